@@ -0,0 +1,24 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "capi")]
+    generate_header();
+}
+
+// Generates the C header of the `capi` module into the build directory, the checked in copy in
+// include/ is compared against it by the tests of the module
+#[cfg(feature = "capi")]
+fn generate_header() {
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    let crate_dir =
+        std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo");
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    let config = cbindgen::Config::from_file(format!("{crate_dir}/cbindgen.toml"))
+        .expect("Unable to read cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Unable to generate the C header")
+        .write_to_file(format!("{out_dir}/evm_disassembler.h"));
+}
@@ -0,0 +1,179 @@
+/* Generated by cbindgen from src/capi.rs with the `capi` feature, do not edit. */
+
+#ifndef EVM_DISASSEMBLER_H
+#define EVM_DISASSEMBLER_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The result of a call
+ */
+typedef enum {
+  /**
+   * The call succeeded
+   */
+  EVMDIS_OK = 0,
+  /**
+   * A required pointer argument was null
+   */
+  EVMDIS_NULL_POINTER = 1,
+  /**
+   * The hex string is not valid UTF-8
+   */
+  EVMDIS_INVALID_UTF8 = 2,
+  /**
+   * The hex string could not be decoded
+   */
+  EVMDIS_INVALID_HEX = 3,
+  /**
+   * The code is an EIP-7702 delegation designator and not disassembled
+   */
+  EVMDIS_DELEGATION = 4,
+  /**
+   * Any other error, including internal panics
+   */
+  EVMDIS_ERROR = 5,
+} EvmdisStatus;
+
+/**
+ * Disassembled code, created by `evmdis_disassemble` or `evmdis_disassemble_hex`
+ */
+typedef struct EvmdisDisassembly EvmdisDisassembly;
+
+/**
+ * An iterator over the instructions of a disassembly, created by `evmdis_iter`
+ */
+typedef struct EvmdisIterator EvmdisIterator;
+
+/**
+ * A decoded instruction
+ *
+ * The pointers are owned by the disassembly and valid until it is freed.
+ */
+typedef struct {
+  /**
+   * The offset in the bytecode
+   */
+  uint32_t offset;
+  /**
+   * The opcode byte
+   */
+  uint8_t opcode;
+  /**
+   * The mnemonic as NUL terminated string
+   */
+  const char *name;
+  /**
+   * The immediate bytes, null if `input_len` is 0
+   */
+  const uint8_t *input;
+  /**
+   * The number of immediate bytes
+   */
+  size_t input_len;
+} EvmdisInstruction;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Disassembles raw bytecode
+ *
+ * On success `*out` is set to a disassembly that has to be released with `evmdis_free`.
+ *
+ * # Safety
+ * `code` must point to `len` readable bytes (or be null if `len` is 0) and `out` must be a
+ * valid pointer to write the result to.
+ */
+EvmdisStatus evmdis_disassemble(const uint8_t *code, size_t len, EvmdisDisassembly **out);
+
+/**
+ * Disassembles a NUL terminated hex string with or without `0x` prefix
+ *
+ * Unlinked library placeholders are decoded as zero addresses. On success `*out` is set to a
+ * disassembly that has to be released with `evmdis_free`.
+ *
+ * # Safety
+ * `hex` must be a valid NUL terminated string and `out` a valid pointer to write the result to.
+ */
+EvmdisStatus evmdis_disassemble_hex(const char *hex, EvmdisDisassembly **out);
+
+/**
+ * Releases a disassembly, null is ignored
+ *
+ * # Safety
+ * `disassembly` must have been returned by this library and not been freed before. Iterators
+ * and instructions of the disassembly must not be used afterwards.
+ */
+void evmdis_free(EvmdisDisassembly *disassembly);
+
+/**
+ * The number of instructions of a disassembly, 0 for null
+ *
+ * # Safety
+ * `disassembly` must be null or a valid disassembly.
+ */
+size_t evmdis_len(const EvmdisDisassembly *disassembly);
+
+/**
+ * Creates an iterator over the instructions of a disassembly, null if `disassembly` is null
+ *
+ * The iterator has to be released with `evmdis_iter_free` before the disassembly is freed.
+ *
+ * # Safety
+ * `disassembly` must be null or a valid disassembly.
+ */
+EvmdisIterator *evmdis_iter(const EvmdisDisassembly *disassembly);
+
+/**
+ * Writes the next instruction to `*out`, returns false at the end or if a pointer is null
+ *
+ * # Safety
+ * `iterator` must be null or a valid iterator whose disassembly is still alive and `out` must be
+ * null or a valid pointer to write the instruction to.
+ */
+bool evmdis_iter_next(EvmdisIterator *iterator, EvmdisInstruction *out);
+
+/**
+ * Releases an iterator, null is ignored
+ *
+ * # Safety
+ * `iterator` must have been returned by `evmdis_iter` and not been freed before.
+ */
+void evmdis_iter_free(EvmdisIterator *iterator);
+
+/**
+ * Formats a disassembly as text, one instruction per line, null if `disassembly` is null
+ *
+ * The string has to be released with `evmdis_string_free`.
+ *
+ * # Safety
+ * `disassembly` must be null or a valid disassembly.
+ */
+char *evmdis_format(const EvmdisDisassembly *disassembly);
+
+/**
+ * Releases a string returned by this library, null is ignored
+ *
+ * # Safety
+ * `string` must have been returned by `evmdis_format` and not been freed before.
+ */
+void evmdis_string_free(char *string);
+
+/**
+ * The message of the last error on the calling thread, empty if there was none
+ *
+ * The string is owned by the library and valid until the next failing call on the same thread.
+ */
+const char *evmdis_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* EVM_DISASSEMBLER_H */
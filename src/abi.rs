@@ -0,0 +1,458 @@
+//! Annotation of the disassembly with a contract ABI
+//!
+//! Functions of the ABI are matched to the dispatcher by selector. Exploring each function from
+//! its entry point resolves the constant call data offsets read by `CALLDATALOAD`, which are
+//! mapped to the parameter encoded at that offset of the ABI head.
+use crate::keccak::keccak256;
+use crate::selectors::{function_entries, FunctionEntry};
+use crate::stack::word_as_u64;
+use crate::symbolic::{explore, BlockIndex};
+use crate::types::{Opcode, Operation};
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// A parameter of a function
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Param {
+    /// The parameter name, empty for unnamed parameters
+    pub name: String,
+    /// The canonical type, e.g. `uint256` or `(address,bytes)[]`
+    pub kind: String,
+    /// Whether the parameter is encoded as an offset to its tail
+    pub dynamic: bool,
+    /// The number of 32 byte words the parameter occupies in the head
+    pub words: u32,
+}
+
+/// A function of the ABI
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    /// The function name
+    pub name: String,
+    /// The input parameters
+    pub inputs: Vec<Param>,
+    /// The 4 byte selector
+    pub selector: [u8; 4],
+}
+
+impl Function {
+    /// The canonical signature, e.g. `transfer(address,uint256)`
+    pub fn signature(&self) -> String {
+        let types: Vec<&str> = self
+            .inputs
+            .iter()
+            .map(|param| param.kind.as_str())
+            .collect();
+        format!("{}({})", self.name, types.join(","))
+    }
+
+    /// The parameter whose head contains the given call data offset
+    ///
+    /// Offsets are counted from the start of the call data, i.e. including the selector.
+    pub fn param_at(&self, offset: u64) -> Option<&Param> {
+        let mut start = 4;
+        for param in &self.inputs {
+            let end = start + 32 * param.words as u64;
+            if (start..end).contains(&offset) {
+                return Some(param);
+            }
+            start = end;
+        }
+        None
+    }
+}
+
+/// A `CALLDATALOAD` reading a parameter at a constant offset
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CalldataRead<'f> {
+    /// The offset of the `CALLDATALOAD` instruction
+    pub offset: u32,
+    /// The call data offset that is read
+    pub calldata_offset: u64,
+    /// The function the read was reached from
+    pub function: &'f Function,
+    /// The index of the parameter in the inputs of the function
+    pub param: usize,
+}
+
+impl CalldataRead<'_> {
+    /// The parameter as `name: type`, unnamed parameters are named by position
+    pub fn label(&self) -> String {
+        let param = &self.function.inputs[self.param];
+        match param.name.as_str() {
+            "" => format!("arg{}: {}", self.param, param.kind),
+            name => format!("{name}: {}", param.kind),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AbiItem {
+    #[serde(rename = "type", default = "function_type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    inputs: Vec<AbiParam>,
+}
+
+#[derive(Deserialize)]
+struct AbiParam {
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    components: Vec<AbiParam>,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+impl AbiParam {
+    // The canonical type with tuples expanded into their components
+    fn canonical(&self) -> String {
+        match self.kind.strip_prefix("tuple") {
+            Some(dimensions) => {
+                let components: Vec<String> =
+                    self.components.iter().map(AbiParam::canonical).collect();
+                format!("({}){dimensions}", components.join(","))
+            }
+            None => self.kind.clone(),
+        }
+    }
+
+    // The size of the head encoding in words, `None` for dynamic types
+    fn static_words(&self) -> Option<u32> {
+        static_words(&self.kind, &self.components)
+    }
+}
+
+fn static_words(kind: &str, components: &[AbiParam]) -> Option<u32> {
+    if let Some(element) = kind.strip_suffix(']') {
+        let (element, length) = element.rsplit_once('[')?;
+        let length: u32 = length.parse().ok()?;
+        return static_words(element, components)?.checked_mul(length);
+    }
+    match kind {
+        "bytes" | "string" => None,
+        "tuple" => components.iter().map(AbiParam::static_words).sum(),
+        _ => Some(1),
+    }
+}
+
+/// Parses the functions of an ABI
+///
+/// Accepts the ABI as JSON array or any object with an `abi` field, such as Foundry and Hardhat
+/// artifacts. Events, errors, constructors and fallback functions are skipped.
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::abi::parse_abi;
+///
+/// let abi = r#"[{"type": "function", "name": "transfer", "inputs": [
+///     {"name": "to", "type": "address"}, {"name": "amount", "type": "uint256"}]}]"#;
+/// let functions = parse_abi(abi).unwrap();
+/// assert_eq!(functions[0].signature(), "transfer(address,uint256)");
+/// assert_eq!(functions[0].selector, [0xa9, 0x05, 0x9c, 0xbb]);
+/// assert_eq!(functions[0].param_at(0x24).unwrap().name, "amount");
+/// ```
+pub fn parse_abi(json: &str) -> Result<Vec<Function>> {
+    let mut value: Value = serde_json::from_str(json)?;
+    if let Some(abi) = value.get_mut("abi") {
+        value = abi.take();
+    }
+    if !value.is_array() {
+        return Err(eyre!(
+            "Expected an ABI array or an object with an abi field"
+        ));
+    }
+    let items: Vec<AbiItem> = serde_json::from_value(value)?;
+    Ok(items
+        .into_iter()
+        .filter(|item| item.kind == "function")
+        .map(|item| {
+            let inputs: Vec<Param> = item
+                .inputs
+                .iter()
+                .map(|input| {
+                    let words = input.static_words();
+                    Param {
+                        name: input.name.clone(),
+                        kind: input.canonical(),
+                        dynamic: words.is_none(),
+                        words: words.unwrap_or(1),
+                    }
+                })
+                .collect();
+            let mut function = Function {
+                name: item.name,
+                inputs,
+                selector: [0; 4],
+            };
+            let hash = keccak256(function.signature().as_bytes());
+            function.selector.copy_from_slice(&hash[..4]);
+            function
+        })
+        .collect())
+}
+
+/// Finds the parameters read by `CALLDATALOAD` with a constant offset
+///
+/// Each function of the ABI found in the dispatcher is explored from its entry point. Reads in
+/// code shared by several functions (e.g. ABI decoding helpers) are reported once per function.
+///
+/// # Arguments
+/// - `operations` - The decoded runtime code
+/// - `functions` - The functions of the ABI
+pub fn calldata_reads<'f>(
+    operations: &[Operation],
+    functions: &'f [Function],
+) -> Vec<CalldataRead<'f>> {
+    let index = BlockIndex::new(operations);
+    let mut by_entry: BTreeMap<u32, Vec<&Function>> = BTreeMap::new();
+    for entry in function_entries(operations) {
+        by_entry
+            .entry(entry.offset)
+            .or_default()
+            .extend(functions.iter().filter(|f| f.selector == entry.selector));
+    }
+    let entries: Vec<u32> = by_entry.keys().copied().collect();
+    let mut reads: BTreeSet<(u32, u64, [u8; 4], usize)> = BTreeSet::new();
+    explore(&index, &entries, |step| {
+        if step.operation.opcode != Opcode::CALLDATALOAD {
+            return;
+        }
+        let Some(calldata_offset) = step.args[0].fold().and_then(word_as_u64) else {
+            return;
+        };
+        for function in &by_entry[&step.entry] {
+            let param = function
+                .param_at(calldata_offset)
+                .and_then(|param| function.inputs.iter().position(|p| std::ptr::eq(p, param)));
+            if let Some(param) = param {
+                reads.insert((
+                    step.operation.offset,
+                    calldata_offset,
+                    function.selector,
+                    param,
+                ));
+            }
+        }
+    });
+    reads
+        .into_iter()
+        .filter_map(|(offset, calldata_offset, selector, param)| {
+            let function = functions.iter().find(|f| f.selector == selector)?;
+            Some(CalldataRead {
+                offset,
+                calldata_offset,
+                function,
+                param,
+            })
+        })
+        .collect()
+}
+
+/// Formats the operations annotated with the functions and parameters of an ABI
+///
+/// The output starts with a summary of the selectors in the dispatcher and the ABI functions with
+/// their entry offsets. Function entries are labeled with their signature and `CALLDATALOAD`s
+/// reading a parameter with the offset and the parameter.
+///
+/// # Arguments
+/// - `operations` - The decoded runtime code
+/// - `abi` - The ABI as JSON, see [`parse_abi`]
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{abi::format_operations_with_abi, disassemble_str};
+///
+/// let abi = r#"[{"type": "function", "name": "withdraw", "inputs": [{"name": "wad", "type": "uint256"}]}]"#;
+/// // PUSH0 CALLDATALOAD PUSH1 0xe0 SHR PUSH4 0x2e1a7d4d EQ PUSH1 0xf JUMPI STOP
+/// // JUMPDEST PUSH1 0x4 CALLDATALOAD STOP
+/// let operations = disassemble_str("5f3560e01c632e1a7d4d14600f57005b60043500").unwrap();
+/// assert_eq!(
+///     format_operations_with_abi(operations, abi).unwrap(),
+///     "// 0x2e1a7d4d -> 0000000f withdraw(uint256)\n\
+///      \n\
+///      00000000: PUSH0\n\
+///      00000001: CALLDATALOAD\n\
+///      00000002: PUSH1 0xe0\n\
+///      00000004: SHR\n\
+///      00000005: PUSH4 0x2e1a7d4d\n\
+///      0000000a: EQ\n\
+///      0000000b: PUSH1 0xf\n\
+///      0000000d: JUMPI\n\
+///      0000000e: STOP\n\
+///      // withdraw(uint256)\n\
+///      0000000f: JUMPDEST\n\
+///      00000010: PUSH1 0x4\n\
+///      00000012: CALLDATALOAD 0x4 // wad: uint256\n\
+///      00000013: STOP\n"
+/// );
+/// ```
+pub fn format_operations_with_abi(operations: Vec<Operation>, abi: &str) -> Result<String> {
+    let functions = parse_abi(abi)?;
+    let entries = function_entries(&operations);
+    let mut formatted = String::new();
+    for FunctionEntry { selector, offset } in &entries {
+        let function = functions.iter().find(|f| f.selector == *selector);
+        let signature = function.map_or("(unknown)".to_string(), Function::signature);
+        writeln!(
+            formatted,
+            "// 0x{} -> {offset:08x} {signature}",
+            hex::encode(selector)
+        )?;
+    }
+    for function in &functions {
+        if entries
+            .iter()
+            .all(|entry| entry.selector != function.selector)
+        {
+            writeln!(
+                formatted,
+                "// 0x{} -> -------- {} (not in dispatcher)",
+                hex::encode(function.selector),
+                function.signature()
+            )?;
+        }
+    }
+    writeln!(formatted)?;
+
+    let mut labels: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+    for read in calldata_reads(&operations, &functions) {
+        let label = format!("{:#x} // {}", read.calldata_offset, read.label());
+        let existing = labels.entry(read.offset).or_default();
+        if !existing.contains(&label) {
+            existing.push(label);
+        }
+    }
+    for operation in &operations {
+        for entry in entries
+            .iter()
+            .filter(|entry| entry.offset == operation.offset)
+        {
+            if let Some(function) = functions.iter().find(|f| f.selector == entry.selector) {
+                writeln!(formatted, "// {}", function.signature())?;
+            }
+        }
+        write!(formatted, "{operation:?}")?;
+        if let Some(labels) = labels.get(&operation.offset) {
+            write!(formatted, " {}", labels.join(", "))?;
+        }
+        writeln!(formatted)?;
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+    use std::fs;
+
+    #[rstest]
+    #[case(r#"{"name": "x", "type": "uint8"}"#, "uint8", false, 1)]
+    #[case(r#"{"name": "x", "type": "bytes"}"#, "bytes", true, 1)]
+    #[case(r#"{"name": "x", "type": "address[3]"}"#, "address[3]", false, 3)]
+    #[case(r#"{"name": "x", "type": "uint256[2][]"}"#, "uint256[2][]", true, 1)]
+    #[case(
+        r#"{"name": "x", "type": "tuple[2]", "components": [
+            {"name": "a", "type": "address"}, {"name": "b", "type": "bytes32[2]"}]}"#,
+        "(address,bytes32[2])[2]",
+        false,
+        6
+    )]
+    #[case(
+        r#"{"name": "x", "type": "tuple", "components": [{"name": "a", "type": "string"}]}"#,
+        "(string)",
+        true,
+        1
+    )]
+    fn encode_parameters(
+        #[case] param: &str,
+        #[case] kind: &str,
+        #[case] dynamic: bool,
+        #[case] words: u32,
+    ) {
+        let abi = format!(r#"[{{"type": "function", "name": "f", "inputs": [{param}]}}]"#);
+        let function = &parse_abi(&abi).unwrap()[0];
+        assert_eq!(
+            function.inputs[0],
+            Param {
+                name: "x".to_string(),
+                kind: kind.to_string(),
+                dynamic,
+                words
+            }
+        );
+    }
+
+    #[rstest]
+    fn skip_non_functions() {
+        let abi = r#"{"abi": [
+            {"type": "constructor", "inputs": []},
+            {"type": "event", "name": "Transfer", "inputs": []},
+            {"type": "fallback"},
+            {"type": "function", "name": "totalSupply", "inputs": [], "outputs": []}
+        ]}"#;
+        let functions = parse_abi(abi).unwrap();
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].selector, [0x18, 0x16, 0x0d, 0xdd]);
+        assert!(parse_abi(r#"{"bytecode": "0x"}"#).is_err());
+    }
+
+    #[rstest]
+    fn annotate_weth() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let abi = r#"[
+            {"type": "function", "name": "withdraw", "inputs": [{"name": "wad", "type": "uint256"}]},
+            {"type": "function", "name": "transferFrom", "inputs": [
+                {"name": "src", "type": "address"},
+                {"name": "dst", "type": "address"},
+                {"name": "wad", "type": "uint256"}
+            ]},
+            {"type": "function", "name": "balanceOf", "inputs": [{"name": "", "type": "address"}]},
+            {"type": "function", "name": "mint", "inputs": []}
+        ]"#;
+        let functions = parse_abi(abi).unwrap();
+        for function in &functions {
+            assert_eq!(function.selector, ethers::utils::id(function.signature()));
+        }
+        let reads = calldata_reads(&operations, &functions);
+        let labels: BTreeSet<(String, u64, String)> = reads
+            .iter()
+            .map(|read| {
+                (
+                    read.function.name.clone(),
+                    read.calldata_offset,
+                    read.label(),
+                )
+            })
+            .collect();
+        assert!(labels.contains(&("withdraw".into(), 4, "wad: uint256".into())));
+        assert!(labels.contains(&("transferFrom".into(), 0x44, "wad: uint256".into())));
+        assert!(labels.contains(&("balanceOf".into(), 4, "arg0: address".into())));
+
+        let formatted = format_operations_with_abi(operations, abi).unwrap();
+        assert!(formatted.contains("// 0x2e1a7d4d -> "));
+        assert!(formatted.contains(" withdraw(uint256)\n"));
+        assert!(formatted.contains("// 0x095ea7b3 -> 00000147 (unknown)\n"));
+        assert!(formatted.contains("-------- mint() (not in dispatcher)\n"));
+        assert!(
+            formatted.contains("// transferFrom(address,address,uint256)\n000001ca: JUMPDEST\n")
+        );
+        assert!(formatted.contains("CALLDATALOAD 0x44 // wad: uint256\n"));
+    }
+}
@@ -0,0 +1,395 @@
+//! Loading of compiler artifacts
+//!
+//! Supports Foundry artifacts (`out/<File>.sol/<Contract>.json`), Hardhat artifacts
+//! (`artifacts/<File>.sol/<Contract>.json`), solc standard JSON output and Hardhat build info
+//! files wrapping it. All formats are read into [`Artifact`]s whose bytecode can be handed to the
+//! disassembler directly.
+use crate::immutables::ImmutableReference;
+use crate::link::link_libraries;
+use crate::{disassemble_str, Operation};
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// A compiled contract
+#[derive(Clone, Debug, PartialEq)]
+pub struct Artifact {
+    /// The contract name
+    pub name: String,
+    /// The source file the contract is defined in, if known
+    pub source: Option<String>,
+    /// The JSON ABI, `null` if the artifact contains none
+    pub abi: Value,
+    /// The creation bytecode, `None` for abstract contracts and interfaces
+    pub creation: Option<Bytecode>,
+    /// The runtime bytecode, `None` for abstract contracts and interfaces
+    pub runtime: Option<Bytecode>,
+}
+
+/// Bytecode with the compiler output describing it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bytecode {
+    /// The hex encoded code without `0x` prefix, addresses of unlinked libraries are placeholders
+    pub object: String,
+    /// The compressed source map, see [`crate::sourcemap`]
+    pub source_map: Option<String>,
+    /// Places where library addresses have to be linked
+    pub link_references: Vec<LinkReference>,
+    /// Places where the constructor fills in immutable values (runtime code only)
+    pub immutable_references: Vec<ImmutableReference>,
+}
+
+/// A place in the bytecode referencing the address of a library
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LinkReference {
+    /// The source file defining the library
+    pub file: String,
+    /// The library name
+    pub library: String,
+    /// Byte offset of the address in the bytecode
+    pub start: u32,
+    /// Length of the address in bytes
+    pub length: u32,
+}
+
+impl Bytecode {
+    /// Whether all library addresses are linked, i.e. the object is valid hex
+    pub fn is_linked(&self) -> bool {
+        self.object.bytes().all(|byte| byte.is_ascii_hexdigit())
+    }
+
+    /// Decodes the hex object
+    ///
+    /// Fails if library addresses are not linked yet.
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        if !self.is_linked() {
+            return Err(eyre!("Bytecode contains unlinked library references"));
+        }
+        Ok(hex::decode(&self.object)?)
+    }
+
+    /// Disassembles the bytecode
+    ///
+    /// Unlinked library addresses are decoded as zero PUSH20 operands marked with their placeholder.
+    pub fn disassemble(&self) -> Result<Vec<Operation>> {
+        disassemble_str(&self.object)
+    }
+
+    /// Links the given libraries, keyed by fully qualified name (`<file>:<library>`)
+    ///
+    /// Placeholders of libraries that are not given are kept.
+    pub fn link(&self, libraries: &BTreeMap<String, [u8; 20]>) -> Bytecode {
+        Bytecode {
+            object: link_libraries(&self.object, libraries),
+            ..self.clone()
+        }
+    }
+}
+
+/// Reads all artifacts from a Foundry, Hardhat or solc standard JSON output file
+///
+/// Foundry artifacts do not always contain the contract name, it then defaults to the file stem.
+pub fn load_artifacts(path: impl AsRef<Path>) -> Result<Vec<Artifact>> {
+    let path = path.as_ref();
+    let mut artifacts = parse_artifacts(&std::fs::read_to_string(path)?)?;
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("");
+    for artifact in artifacts
+        .iter_mut()
+        .filter(|artifact| artifact.name.is_empty())
+    {
+        artifact.name = stem.to_string();
+    }
+    Ok(artifacts)
+}
+
+/// Parses a Foundry, Hardhat or solc standard JSON output artifact
+///
+/// The format is detected from the contents. Standard JSON output and Hardhat build info files
+/// yield one artifact per compiled contract, sorted by source file and name.
+///
+/// # Arguments
+/// - `json` - The contents of the artifact file
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::artifacts::parse_artifacts;
+///
+/// let json = r#"{
+///     "_format": "hh-sol-artifact-1", "contractName": "Stop", "sourceName": "contracts/Stop.sol",
+///     "abi": [], "bytecode": "0x6080", "deployedBytecode": "0x00",
+///     "linkReferences": {}, "deployedLinkReferences": {}
+/// }"#;
+/// let artifacts = parse_artifacts(json).unwrap();
+/// assert_eq!(artifacts[0].name, "Stop");
+/// let operations = artifacts[0].runtime.as_ref().unwrap().disassemble().unwrap();
+/// assert_eq!(format!("{:?}", operations[0]), "00000000: STOP");
+/// ```
+pub fn parse_artifacts(json: &str) -> Result<Vec<Artifact>> {
+    let value: Value = serde_json::from_str(json)?;
+    let output = value.get("output").unwrap_or(&value);
+    if output.get("contracts").is_some() {
+        let output: StandardJsonOutput = serde_json::from_value(output.clone())?;
+        return Ok(output
+            .contracts
+            .into_iter()
+            .flat_map(|(source, contracts)| {
+                contracts.into_iter().map(move |(name, contract)| {
+                    let evm = contract.evm.unwrap_or_default();
+                    Artifact {
+                        name,
+                        source: Some(source.clone()),
+                        abi: contract.abi,
+                        creation: evm.bytecode.and_then(EvmBytecode::into_bytecode),
+                        runtime: evm.deployed_bytecode.and_then(EvmBytecode::into_bytecode),
+                    }
+                })
+            })
+            .collect());
+    }
+    match value.get("bytecode") {
+        Some(Value::String(_)) => {
+            let artifact: HardhatArtifact = serde_json::from_value(value)?;
+            let bytecode = |object: String, links: LinkReferences| {
+                EvmBytecode {
+                    object,
+                    link_references: links,
+                    ..EvmBytecode::default()
+                }
+                .into_bytecode()
+            };
+            Ok(vec![Artifact {
+                name: artifact.contract_name,
+                source: Some(artifact.source_name),
+                abi: artifact.abi,
+                creation: bytecode(artifact.bytecode, artifact.link_references),
+                runtime: bytecode(
+                    artifact.deployed_bytecode,
+                    artifact.deployed_link_references,
+                ),
+            }])
+        }
+        Some(Value::Object(_)) => {
+            let artifact: FoundryArtifact = serde_json::from_value(value)?;
+            // The compilation target maps the source file to the contract name
+            let target = artifact
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.pointer("/settings/compilationTarget"))
+                .and_then(Value::as_object)
+                .and_then(|target| target.iter().next())
+                .map(|(source, name)| (source.clone(), name.as_str().unwrap_or("").to_string()));
+            let (source, name) = match target {
+                Some((source, name)) => (Some(source), name),
+                None => (None, String::new()),
+            };
+            Ok(vec![Artifact {
+                name,
+                source,
+                abi: artifact.abi,
+                creation: artifact.bytecode.and_then(EvmBytecode::into_bytecode),
+                runtime: artifact
+                    .deployed_bytecode
+                    .and_then(EvmBytecode::into_bytecode),
+            }])
+        }
+        _ => Err(eyre!("Unknown artifact format")),
+    }
+}
+
+// File name to library name to places the library is referenced
+type LinkReferences = BTreeMap<String, BTreeMap<String, Vec<ImmutableReference>>>;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EvmBytecode {
+    #[serde(default)]
+    object: String,
+    #[serde(default)]
+    source_map: Option<String>,
+    #[serde(default)]
+    link_references: LinkReferences,
+    #[serde(default)]
+    immutable_references: HashMap<String, Vec<ImmutableReference>>,
+}
+
+impl EvmBytecode {
+    // Empty objects (e.g. of interfaces) are None
+    fn into_bytecode(self) -> Option<Bytecode> {
+        let object = self.object.trim_start_matches("0x").to_string();
+        if object.is_empty() {
+            return None;
+        }
+        let mut link_references: Vec<LinkReference> = self
+            .link_references
+            .into_iter()
+            .flat_map(|(file, libraries)| {
+                libraries.into_iter().flat_map(move |(library, places)| {
+                    let file = file.clone();
+                    places.into_iter().map(move |place| LinkReference {
+                        file: file.clone(),
+                        library: library.clone(),
+                        start: place.start,
+                        length: place.length,
+                    })
+                })
+            })
+            .collect();
+        link_references.sort_by_key(|reference| reference.start);
+        let mut immutable_references: Vec<ImmutableReference> =
+            self.immutable_references.into_values().flatten().collect();
+        immutable_references.sort();
+        Some(Bytecode {
+            object,
+            source_map: self.source_map.filter(|source_map| !source_map.is_empty()),
+            link_references,
+            immutable_references,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FoundryArtifact {
+    #[serde(default)]
+    abi: Value,
+    bytecode: Option<EvmBytecode>,
+    deployed_bytecode: Option<EvmBytecode>,
+    metadata: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HardhatArtifact {
+    contract_name: String,
+    source_name: String,
+    #[serde(default)]
+    abi: Value,
+    bytecode: String,
+    deployed_bytecode: String,
+    #[serde(default)]
+    link_references: LinkReferences,
+    #[serde(default)]
+    deployed_link_references: LinkReferences,
+}
+
+#[derive(Deserialize)]
+struct StandardJsonOutput {
+    contracts: BTreeMap<String, BTreeMap<String, SolcContract>>,
+}
+
+#[derive(Deserialize)]
+struct SolcContract {
+    #[serde(default)]
+    abi: Value,
+    evm: Option<SolcEvm>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SolcEvm {
+    bytecode: Option<EvmBytecode>,
+    deployed_bytecode: Option<EvmBytecode>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    // A library placeholder followed by STOP
+    const LINKED_OBJECT: &str = "73__$8b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b$__00";
+
+    #[rstest]
+    fn parse_foundry_artifact() {
+        let json = format!(
+            r#"{{
+            "abi": [{{"type": "function", "name": "f", "inputs": [], "outputs": []}}],
+            "bytecode": {{"object": "0x6080", "sourceMap": "0:1:0:-", "linkReferences": {{}}}},
+            "deployedBytecode": {{
+                "object": "0x{LINKED_OBJECT}",
+                "sourceMap": "0:10:0:-;;",
+                "linkReferences": {{"src/Lib.sol": {{"Lib": [{{"start": 1, "length": 20}}]}}}},
+                "immutableReferences": {{"7": [{{"start": 40, "length": 32}}]}}
+            }},
+            "metadata": {{"settings": {{"compilationTarget": {{"src/Token.sol": "Token"}}}}}}
+        }}"#
+        );
+        let artifact = &parse_artifacts(&json).unwrap()[0];
+        assert_eq!(artifact.name, "Token");
+        assert_eq!(artifact.source.as_deref(), Some("src/Token.sol"));
+        assert_eq!(artifact.abi[0]["name"], "f");
+        let runtime = artifact.runtime.as_ref().unwrap();
+        assert_eq!(runtime.source_map.as_deref(), Some("0:10:0:-;;"));
+        assert_eq!(
+            runtime.link_references,
+            vec![LinkReference {
+                file: "src/Lib.sol".to_string(),
+                library: "Lib".to_string(),
+                start: 1,
+                length: 20
+            }]
+        );
+        assert_eq!(runtime.immutable_references[0].start, 40);
+        assert!(!runtime.is_linked());
+        assert!(runtime.bytes().is_err());
+        assert!(runtime.disassemble().unwrap()[0].link.is_some());
+        let libraries = BTreeMap::from([("Other.sol:Other".to_string(), [0x11; 20])]);
+        assert_eq!(runtime.link(&libraries), *runtime);
+        assert_eq!(
+            artifact.creation.as_ref().unwrap().bytes().unwrap(),
+            vec![0x60, 0x80]
+        );
+    }
+
+    #[rstest]
+    #[case::standard_json(false)]
+    #[case::build_info(true)]
+    fn parse_standard_json_output(#[case] build_info: bool) {
+        let output = r#"{
+            "contracts": {
+                "B.sol": {"B": {"abi": [], "evm": {"bytecode": {"object": "00"}, "deployedBytecode": {"object": "fe"}}}},
+                "A.sol": {
+                    "IA": {"abi": [], "evm": {"bytecode": {"object": ""}, "deployedBytecode": {"object": ""}}},
+                    "A": {"abi": [], "evm": {"deployedBytecode": {"object": "5f5ff3", "immutableReferences": {}}}}
+                }
+            },
+            "sources": {"A.sol": {"id": 0}, "B.sol": {"id": 1}}
+        }"#;
+        let json = if build_info {
+            format!(r#"{{"_format": "hh-sol-build-info-1", "input": {{}}, "output": {output}}}"#)
+        } else {
+            output.to_string()
+        };
+        let artifacts = parse_artifacts(&json).unwrap();
+        let names: Vec<&str> = artifacts
+            .iter()
+            .map(|artifact| artifact.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["A", "IA", "B"]);
+        assert_eq!(artifacts[0].creation, None);
+        assert_eq!(artifacts[0].runtime.as_ref().unwrap().object, "5f5ff3");
+        assert_eq!(artifacts[1].runtime, None);
+        assert_eq!(artifacts[2].source.as_deref(), Some("B.sol"));
+    }
+
+    #[rstest]
+    fn load_foundry_artifact_without_name() {
+        let path = std::env::temp_dir().join("evm_disassembler_Counter.json");
+        std::fs::write(&path, r#"{"abi": [], "bytecode": {"object": "0x00"}}"#).unwrap();
+        let artifacts = load_artifacts(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(artifacts[0].name, "evm_disassembler_Counter");
+        assert_eq!(artifacts[0].runtime, None);
+    }
+
+    #[rstest]
+    fn reject_unknown_format() {
+        assert!(parse_artifacts(r#"{"abi": []}"#).is_err());
+    }
+}
@@ -0,0 +1,474 @@
+//! Batch disassembly of large collections of bytecode
+//!
+//! Entries are read lazily from an iterator, a directory, a JSONL or a CSV dump and processed in
+//! chunks: identical code is detected by its keccak256 hash (the code hash of the contract) and
+//! only disassembled once, and results are passed to a callback in input order as soon as their
+//! chunk is done. With the `parallel` feature decoding and disassembly of a chunk run on all cores
+//! with rayon.
+use crate::keccak::keccak256;
+use crate::{disassemble, Disassembly};
+use eyre::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// A named bytecode blob of a batch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// The file path, or the address, name or line number of an entry of a dump
+    pub name: String,
+    /// The hex encoded bytecode, or why it could not be read
+    pub code: std::result::Result<String, String>,
+}
+
+impl Entry {
+    /// Creates an entry from hex encoded bytecode
+    pub fn new(name: impl Into<String>, code: impl Into<String>) -> Self {
+        Entry {
+            name: name.into(),
+            code: Ok(code.into()),
+        }
+    }
+
+    fn failed(name: impl Into<String>, error: impl ToString) -> Self {
+        Entry {
+            name: name.into(),
+            code: Err(error.to_string()),
+        }
+    }
+}
+
+/// The result of a single entry of a batch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BatchItem {
+    /// The first entry with this code
+    Disassembled {
+        /// The name of the entry
+        name: String,
+        /// The keccak256 hash of the code
+        hash: [u8; 32],
+        /// The decoded code
+        disassembly: Disassembly,
+    },
+    /// An entry with the same code as an earlier one, which is not disassembled again
+    Duplicate {
+        /// The name of the entry
+        name: String,
+        /// The keccak256 hash of the code
+        hash: [u8; 32],
+        /// The name of the first entry with this code
+        first: String,
+    },
+    /// An entry that could not be read or decoded
+    Failed {
+        /// The name of the entry
+        name: String,
+        /// Why the entry failed
+        error: String,
+    },
+}
+
+impl BatchItem {
+    /// The name of the entry
+    pub fn name(&self) -> &str {
+        match self {
+            BatchItem::Disassembled { name, .. }
+            | BatchItem::Duplicate { name, .. }
+            | BatchItem::Failed { name, .. } => name,
+        }
+    }
+}
+
+/// Options controlling batch disassembly
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// The number of entries read and processed together, bounding memory use
+    pub chunk_size: usize,
+    /// Disassemble identical code only once and report later entries as duplicates
+    pub deduplicate: bool,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            chunk_size: 4096,
+            deduplicate: true,
+        }
+    }
+}
+
+/// Counts of the entries of a batch
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    /// The number of entries
+    pub entries: usize,
+    /// The number of disassembled entries
+    pub disassembled: usize,
+    /// The number of entries with the code of an earlier entry
+    pub duplicates: usize,
+    /// The number of entries that could not be read or decoded
+    pub failed: usize,
+}
+
+#[cfg(feature = "parallel")]
+fn map<T: Send, U: Send>(items: Vec<T>, f: impl Fn(T) -> U + Sync + Send) -> Vec<U> {
+    use rayon::prelude::*;
+    items.into_par_iter().map(f).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn map<T, U>(items: Vec<T>, f: impl Fn(T) -> U) -> Vec<U> {
+    items.into_iter().map(f).collect()
+}
+
+// The decoded bytes of an entry, or the failed item
+type Decoded = std::result::Result<(String, [u8; 32], Vec<u8>), BatchItem>;
+
+fn decode(entry: Entry) -> Decoded {
+    let Entry { name, code } = entry;
+    let bytes = code.and_then(|code| {
+        hex::decode(code.trim().trim_start_matches("0x")).map_err(|e| e.to_string())
+    });
+    match bytes {
+        Ok(bytes) => Ok((name, keccak256(&bytes), bytes)),
+        Err(error) => Err(BatchItem::Failed { name, error }),
+    }
+}
+
+/// Disassembles many bytecode blobs, passing the results to `callback` in input order
+///
+/// Entries are read `options.chunk_size` at a time, so the input is never fully loaded into
+/// memory. The hashes of all seen code are kept to detect duplicates across chunks. Entries that
+/// cannot be read or decoded are reported as [`BatchItem::Failed`]; an error returned by the
+/// callback aborts the batch.
+///
+/// # Arguments
+/// - `entries` - The named bytecode blobs, e.g. from [`read_path`]
+/// - `options` - Options controlling chunking and deduplication
+/// - `callback` - Called with the result of every entry
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::batch::{disassemble_batch, BatchItem, BatchOptions, Entry};
+///
+/// let entries = vec![
+///     Entry::new("a", "0x6080604052"),
+///     Entry::new("b", "6080604052"),
+///     Entry::new("c", "zz"),
+/// ];
+/// let mut names = Vec::new();
+/// let summary = disassemble_batch(entries, &BatchOptions::default(), |item| {
+///     if let BatchItem::Duplicate { name, first, .. } = &item {
+///         names.push(format!("{name} = {first}"));
+///     }
+///     Ok(())
+/// })
+/// .unwrap();
+/// assert_eq!(names, vec!["b = a"]);
+/// assert_eq!((summary.disassembled, summary.duplicates, summary.failed), (1, 1, 1));
+/// ```
+pub fn disassemble_batch(
+    entries: impl IntoIterator<Item = Entry>,
+    options: &BatchOptions,
+    mut callback: impl FnMut(BatchItem) -> Result<()>,
+) -> Result<BatchSummary> {
+    let mut summary = BatchSummary::default();
+    let mut seen: HashMap<[u8; 32], String> = HashMap::new();
+    let mut entries = entries.into_iter().peekable();
+    while entries.peek().is_some() {
+        let chunk: Vec<Entry> = entries.by_ref().take(options.chunk_size.max(1)).collect();
+        summary.entries += chunk.len();
+
+        // Decide which entries are disassembled before running them in parallel, so the first
+        // of several identical entries in a chunk is the one disassembled
+        let mut items: Vec<Option<BatchItem>> = Vec::with_capacity(chunk.len());
+        let mut unique = Vec::new();
+        for decoded in map(chunk, decode) {
+            items.push(match decoded {
+                Err(failed) => Some(failed),
+                Ok((name, hash, _)) if options.deduplicate && seen.contains_key(&hash) => {
+                    Some(BatchItem::Duplicate {
+                        name,
+                        hash,
+                        first: seen[&hash].clone(),
+                    })
+                }
+                Ok((name, hash, bytes)) => {
+                    if options.deduplicate {
+                        seen.insert(hash, name.clone());
+                    }
+                    unique.push((items.len(), name, hash, bytes));
+                    None
+                }
+            });
+        }
+        let disassembled = map(unique, |(index, name, hash, bytes)| {
+            let item = match disassemble(bytes) {
+                Ok(disassembly) => BatchItem::Disassembled {
+                    name,
+                    hash,
+                    disassembly,
+                },
+                Err(e) => BatchItem::Failed {
+                    name,
+                    error: e.to_string(),
+                },
+            };
+            (index, item)
+        });
+        for (index, item) in disassembled {
+            items[index] = Some(item);
+        }
+
+        for item in items.into_iter().flatten() {
+            match item {
+                BatchItem::Disassembled { .. } => summary.disassembled += 1,
+                BatchItem::Duplicate { .. } => summary.duplicates += 1,
+                BatchItem::Failed { .. } => summary.failed += 1,
+            }
+            callback(item)?;
+        }
+    }
+    Ok(summary)
+}
+
+/// Lazily reads the entries of a file, a directory or a dump
+///
+/// - Directories are read recursively in sorted order.
+/// - Files ending in `.jsonl` contain one JSON object per line with the hex encoded bytecode in
+///   `code` or `bytecode`, entries are named by `address` or `name` if present and by line number
+///   otherwise.
+/// - Files ending in `.csv` have an optional header and `address,code` columns, or the columns
+///   named `address`/`name` and `code`/`bytecode` in the header.
+/// - Any other file contains hex encoded bytecode and is named by its path.
+///
+/// Only listing directories and opening dumps can fail, unreadable files and malformed lines are
+/// returned as entries with an error.
+pub fn read_path(path: impl AsRef<Path>) -> Result<Box<dyn Iterator<Item = Entry>>> {
+    let path = path.as_ref();
+    if path.is_dir() {
+        let mut paths = Vec::new();
+        list_files(path, &mut paths)?;
+        return Ok(Box::new(paths.into_iter().flat_map(|path| {
+            read_path(&path).unwrap_or_else(|e| {
+                Box::new(std::iter::once(Entry::failed(
+                    path.display().to_string(),
+                    e,
+                )))
+            })
+        })));
+    }
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("jsonl") => Ok(Box::new(read_jsonl(BufReader::new(File::open(path)?)))),
+        Some("csv") => Ok(Box::new(read_csv(BufReader::new(File::open(path)?)))),
+        _ => {
+            let name = path.display().to_string();
+            Ok(Box::new(std::iter::once(
+                match std::fs::read_to_string(path) {
+                    Ok(code) => Entry::new(name, code),
+                    Err(e) => Entry::failed(name, e),
+                },
+            )))
+        }
+    }
+}
+
+fn list_files(directory: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries: Vec<_> = std::fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            list_files(&entry, paths)?;
+        } else {
+            paths.push(entry);
+        }
+    }
+    Ok(())
+}
+
+// Non-empty lines with their line number, read errors end the input with a failed entry
+fn numbered_lines(reader: impl BufRead) -> impl Iterator<Item = (usize, std::io::Result<String>)> {
+    let mut failed = false;
+    reader
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .take_while(move |(_, line)| !std::mem::replace(&mut failed, line.is_err()))
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+}
+
+/// Lazily reads a JSONL dump, see [`read_path`]
+pub fn read_jsonl(reader: impl BufRead) -> impl Iterator<Item = Entry> {
+    numbered_lines(reader).map(|(number, line)| {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Entry::failed(format!("line {number}"), e),
+        };
+        let entry: Value = serde_json::from_str(&line).unwrap_or(Value::Null);
+        let field = |keys: [&str; 2]| keys.iter().find_map(|key| entry.get(key)?.as_str());
+        let name = field(["address", "name"]).map_or(format!("line {number}"), str::to_string);
+        match field(["code", "bytecode"]) {
+            Some(code) => Entry::new(name, code),
+            None => Entry::failed(name, "Expected a JSON object with code or bytecode"),
+        }
+    })
+}
+
+/// Lazily reads a CSV dump, see [`read_path`]
+///
+/// Fields are separated by commas and may be enclosed in double quotes; hex encoded code and
+/// addresses never contain commas, so quoted commas are not supported.
+pub fn read_csv(reader: impl BufRead) -> impl Iterator<Item = Entry> {
+    let mut columns = None;
+    numbered_lines(reader).filter_map(move |(number, line)| {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Some(Entry::failed(format!("line {number}"), e)),
+        };
+        let fields: Vec<&str> = line
+            .split(',')
+            .map(|field| field.trim().trim_matches('"'))
+            .collect();
+        let position = |keys: [&str; 2]| {
+            fields
+                .iter()
+                .position(|field| keys.iter().any(|key| field.eq_ignore_ascii_case(key)))
+        };
+        // The first line determines the columns and is skipped if it is a header
+        if columns.is_none() {
+            let header =
+                position(["code", "bytecode"]).map(|code| (position(["address", "name"]), code));
+            columns = Some(header.unwrap_or((Some(0), 1)));
+            if header.is_some() {
+                return None;
+            }
+        }
+        let (name_column, code_column) = columns.unwrap_or_default();
+        let name = name_column
+            .and_then(|column| fields.get(column))
+            .map_or(format!("line {number}"), |name| name.to_string());
+        Some(match fields.get(code_column) {
+            Some(code) => Entry::new(name, *code),
+            None => Entry::failed(
+                name,
+                format!("Expected a code column at index {code_column}"),
+            ),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn collect(entries: Vec<Entry>, options: &BatchOptions) -> Vec<BatchItem> {
+        let mut items = Vec::new();
+        disassemble_batch(entries, options, |item| {
+            items.push(item);
+            Ok(())
+        })
+        .unwrap();
+        items
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(2)]
+    #[case(100)]
+    fn deduplicate_across_chunks(#[case] chunk_size: usize) {
+        let entries = vec![
+            Entry::new("a", "6080604052"),
+            Entry::new("b", "0x33ff"),
+            Entry::new("c", "0x6080604052"),
+            Entry::failed("d", "unreadable"),
+            Entry::new("e", "33ff"),
+        ];
+        let options = BatchOptions {
+            chunk_size,
+            ..Default::default()
+        };
+        let items = collect(entries, &options);
+        let names: Vec<&str> = items.iter().map(BatchItem::name).collect();
+        assert_eq!(names, vec!["a", "b", "c", "d", "e"]);
+        let BatchItem::Disassembled {
+            hash, disassembly, ..
+        } = &items[0]
+        else {
+            panic!("Expected a disassembled entry, got {:?}", items[0]);
+        };
+        assert_eq!(
+            *disassembly,
+            Disassembly::Operations(crate::disassemble_str("6080604052").unwrap())
+        );
+        assert_eq!(
+            items[2],
+            BatchItem::Duplicate {
+                name: "c".to_string(),
+                hash: *hash,
+                first: "a".to_string()
+            }
+        );
+        assert!(matches!(&items[3], BatchItem::Failed { error, .. } if error == "unreadable"));
+        assert!(matches!(&items[4], BatchItem::Duplicate { first, .. } if first == "b"));
+    }
+
+    #[rstest]
+    fn keep_duplicates() {
+        let entries = vec![Entry::new("a", "33ff"), Entry::new("b", "33ff")];
+        let options = BatchOptions {
+            deduplicate: false,
+            ..Default::default()
+        };
+        let items = collect(entries, &options);
+        assert!(items
+            .iter()
+            .all(|item| matches!(item, BatchItem::Disassembled { .. })));
+    }
+
+    #[rstest]
+    #[case("address,code\n0x01,0x33ff\n\n0x02,6080\n", vec![("0x01", "0x33ff"), ("0x02", "6080")])]
+    #[case("\"code\",\"address\"\n\"33ff\",\"0x01\"\n", vec![("0x01", "33ff")])]
+    #[case("0x01,33ff\n0x02,6080\n", vec![("0x01", "33ff"), ("0x02", "6080")])]
+    #[case("bytecode\n33ff\n", vec![("line 2", "33ff")])]
+    fn read_csv_columns(#[case] csv: &str, #[case] expected: Vec<(&str, &str)>) {
+        let entries: Vec<Entry> = read_csv(csv.as_bytes()).collect();
+        let expected: Vec<Entry> = expected
+            .into_iter()
+            .map(|(name, code)| Entry::new(name, code))
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[rstest]
+    fn read_directory_and_dumps() {
+        let directory = std::env::temp_dir().join(format!("evm-batch-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("nested")).unwrap();
+        std::fs::write(directory.join("a.txt"), "0x33ff\n").unwrap();
+        std::fs::write(directory.join("nested/b.csv"), "address,code\n0x02,6080\n").unwrap();
+        std::fs::write(
+            directory.join("c.jsonl"),
+            "{\"address\": \"0x03\", \"code\": \"0x33ff\"}\n\n{\"bytecode\": \"60\"}\n[]\n",
+        )
+        .unwrap();
+        let entries: Vec<Entry> = read_path(&directory).unwrap().collect();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let a = directory.join("a.txt").display().to_string();
+        assert_eq!(
+            entries,
+            vec![
+                Entry::new(a, "0x33ff\n"),
+                Entry::new("0x03", "0x33ff"),
+                Entry::new("line 3", "60"),
+                Entry::failed("line 4", "Expected a JSON object with code or bytecode"),
+                Entry::new("0x02", "6080"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! Inventory of external calls, contract creations and self destructs
+use crate::eof::EofContainer;
+use crate::selectors::function_entries;
+use crate::stack::{word_as_u64, Expr};
+use crate::symbolic::{explore, BlockIndex, Step};
+use crate::types::{Opcode, Operation};
+use eyre::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+// Maximum nesting depth of expressions shown in the report
+const REPORT_DEPTH: usize = 3;
+
+/// An operation interacting with other accounts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallSite {
+    /// The offset of the operation in the bytecode
+    pub offset: u32,
+    /// The call, create, `SELFDESTRUCT` or `PAY` operation
+    pub opcode: Opcode,
+    /// Selectors of the public functions from which the site is reachable, empty if it is only
+    /// reachable from the dispatcher (e.g. in the fallback function)
+    pub functions: BTreeSet<[u8; 4]>,
+    /// The called address or the beneficiary, not present for contract creations
+    pub target: Option<Expr>,
+    /// The transferred value, not present for operations that cannot transfer value
+    pub value: Option<Expr>,
+    /// The function selector at the start of the call data, if it is known
+    pub selector: Option<[u8; 4]>,
+}
+
+impl CallSite {
+    /// The target address if it is a constant
+    pub fn target_address(&self) -> Option<[u8; 20]> {
+        let word = self.target.as_ref()?.fold()?;
+        word[..12]
+            .iter()
+            .all(|byte| *byte == 0)
+            .then(|| word[12..].try_into().expect("Slice has 20 bytes"))
+    }
+}
+
+/// Collects all call, create, `SELFDESTRUCT` and `PAY` sites reachable from the start of the code
+///
+/// Every public function found in the dispatcher is explored on its own to attribute sites to the
+/// functions reaching them. Targets, values and call data are resolved by symbolic execution along
+/// the paths from the start of the code, the first path reaching a site determines the reported
+/// expressions.
+///
+/// # Arguments
+/// - `operations` - The decoded runtime code
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{calls::call_sites, disassemble_str, types::Opcode};
+///
+/// // selfdestruct(caller)
+/// let operations = disassemble_str("33ff").unwrap();
+/// let sites = call_sites(&operations);
+/// assert_eq!(sites[0].opcode, Opcode::SELFDESTRUCT);
+/// assert_eq!(sites[0].target.as_ref().unwrap().to_string(), "caller()");
+/// ```
+pub fn call_sites(operations: &[Operation]) -> Vec<CallSite> {
+    sites(operations, &[0])
+}
+
+/// Collects all call, create, `SELFDESTRUCT` and `PAY` sites of the code sections of an EOF
+/// container
+///
+/// Same as [`call_sites`] but every code section is explored from its start, instead of the
+/// container header at offset 0. Offsets are relative to the start of the container.
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{calls::eof_call_sites, eof::EofContainer, types::Opcode};
+///
+/// // One code section with PUSH0 PUSH0 PUSH0 PUSH0 EXTCALL STOP
+/// let bytes = hex::decode("ef000101000402000100060400000000800004 5f5f5f5ff800".replace(' ', ""));
+/// let container = EofContainer::parse(&bytes.unwrap()).unwrap();
+/// let sites = eof_call_sites(&container);
+/// assert_eq!(sites[0].opcode, Opcode::EXTCALL);
+/// assert_eq!(sites[0].offset, 23);
+/// ```
+pub fn eof_call_sites(container: &EofContainer) -> Vec<CallSite> {
+    let entries: Vec<u32> = container
+        .code
+        .iter()
+        .map(|section| section.offset)
+        .collect();
+    sites(&container.code_operations().concat(), &entries)
+}
+
+// Collects the sites reachable from the given entry points
+fn sites(operations: &[Operation], starts: &[u32]) -> Vec<CallSite> {
+    let index = BlockIndex::new(operations);
+    let entries = function_entries(operations);
+    let selectors: BTreeMap<u32, [u8; 4]> = entries
+        .iter()
+        .map(|entry| (entry.offset, entry.selector))
+        .collect();
+    let mut functions: BTreeMap<u32, BTreeSet<[u8; 4]>> = BTreeMap::new();
+    let function_offsets: Vec<u32> = selectors.keys().copied().collect();
+    explore(&index, &function_offsets, |step| {
+        if call_site(step).is_some() {
+            functions
+                .entry(step.operation.offset)
+                .or_default()
+                .extend(selectors.get(&step.entry));
+        }
+    });
+    // Paths through the dispatcher know the memory layout set up before it (e.g. the free memory
+    // pointer), which is needed to find the call data
+    let mut sites: BTreeMap<u32, CallSite> = BTreeMap::new();
+    explore(&index, starts, |step| {
+        if let Some(site) = call_site(step) {
+            let existing = sites.entry(site.offset).or_insert_with(|| site.clone());
+            existing.selector = existing.selector.or(site.selector);
+        }
+    });
+    sites
+        .into_values()
+        .map(|mut site| {
+            site.functions = functions.remove(&site.offset).unwrap_or_default();
+            site
+        })
+        .collect()
+}
+
+/// Formats the call sites, one site per line
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{calls::format_call_sites, disassemble_str};
+///
+/// // selfdestruct(caller)
+/// let operations = disassemble_str("33ff").unwrap();
+/// assert_eq!(
+///     format_call_sites(operations).unwrap(),
+///     "00000001: SELFDESTRUCT functions=[] target=caller()\n"
+/// );
+/// ```
+pub fn format_call_sites(operations: Vec<Operation>) -> Result<String> {
+    format_sites(call_sites(&operations))
+}
+
+/// Formats the call sites of the code sections of an EOF container, one site per line
+pub fn format_eof_call_sites(container: &EofContainer) -> Result<String> {
+    format_sites(eof_call_sites(container))
+}
+
+fn format_sites(sites: Vec<CallSite>) -> Result<String> {
+    let mut formatted = String::new();
+    for site in sites {
+        let functions: Vec<String> = site
+            .functions
+            .iter()
+            .map(|selector| format!("0x{}", hex::encode(selector)))
+            .collect();
+        write!(
+            formatted,
+            "{:08x}: {:?} functions=[{}]",
+            site.offset,
+            site.opcode,
+            functions.join(", ")
+        )?;
+        match (site.target_address(), &site.target) {
+            (Some(address), _) => write!(formatted, " target=0x{}", hex::encode(address))?,
+            (None, Some(target)) => write!(formatted, " target={target:.REPORT_DEPTH$}")?,
+            (None, None) => {}
+        }
+        if let Some(value) = &site.value {
+            write!(formatted, " value={value:.REPORT_DEPTH$}")?;
+        }
+        if let Some(selector) = site.selector {
+            write!(formatted, " selector=0x{}", hex::encode(selector))?;
+        }
+        writeln!(formatted)?;
+    }
+    Ok(formatted)
+}
+
+fn call_site(step: &Step) -> Option<CallSite> {
+    let opcode = step.operation.opcode;
+    // Positions of target, value and call data (offset and size) in the arguments
+    let (target, value, input) = match opcode {
+        Opcode::CALL | Opcode::CALLCODE => (Some(1), Some(2), Some(3)),
+        Opcode::DELEGATECALL | Opcode::STATICCALL => (Some(1), None, Some(2)),
+        Opcode::EXTCALL => (Some(0), Some(3), Some(1)),
+        Opcode::EXTDELEGATECALL | Opcode::EXTSTATICCALL => (Some(0), None, Some(1)),
+        Opcode::CREATE | Opcode::CREATE2 | Opcode::EOFCREATE => (None, Some(0), None),
+        Opcode::TXCREATE => (None, Some(1), None),
+        Opcode::SELFDESTRUCT => (Some(0), None, None),
+        Opcode::PAY => (Some(0), Some(1), None),
+        _ => return None,
+    };
+    let arg = |position: Option<usize>| position.map(|position| step.args[position].clone());
+    let selector = input.and_then(|position| {
+        let (offset, size) = (&step.args[position], &step.args[position + 1]);
+        if word_as_u64(size.fold()?)? < 4 {
+            return None;
+        }
+        let word = step.memory.prefix(offset, 4)?.fold()?;
+        Some(word[..4].try_into().expect("Slice has 4 bytes"))
+    });
+    Some(CallSite {
+        offset: step.operation.offset,
+        opcode,
+        functions: BTreeSet::new(),
+        target: arg(target),
+        value: arg(value),
+        selector,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+    use std::fs;
+
+    #[rstest]
+    fn resolve_constant_call() {
+        // mstore(0x80, shl(0xe0, 0xa9059cbb)) mstore(0x84, 0x1)
+        // call(gas, 0x1111..11, 0x0, 0x80, 0x24, 0x0, 0x0)
+        let code = "63a9059cbb60e01b608052 6001608452 60006000602460806000 \
+                    731111111111111111111111111111111111111111 5af100";
+        let operations = disassemble_str(&code.replace(' ', "")).unwrap();
+        let sites = call_sites(&operations);
+        assert_eq!(sites.len(), 1);
+        assert_eq!(sites[0].opcode, Opcode::CALL);
+        assert_eq!(sites[0].target_address(), Some([0x11; 20]));
+        assert_eq!(sites[0].value, Some(Expr::Constant(vec![0])));
+        assert_eq!(sites[0].selector, Some([0xa9, 0x05, 0x9c, 0xbb]));
+    }
+
+    #[rstest]
+    fn call_without_data_has_no_selector() {
+        // call(gas, caller, callvalue, 0x0, 0x0, 0x0, 0x0)
+        let operations = disassemble_str("600060006000600034335af100").unwrap();
+        let sites = call_sites(&operations);
+        assert_eq!(sites[0].selector, None);
+        assert_eq!(sites[0].value.as_ref().unwrap().to_string(), "callvalue()");
+    }
+
+    #[rstest]
+    // mstore(0xfffffffffffffff5, 0x0) mstore(0xfffffffffffffff5, 0x0)
+    #[case("5f67fffffffffffffff5525f67fffffffffffffff55200")]
+    // mstore(0xffffffffffffffe0, 0x0) sload(sha3(0xffffffffffffffe0, 0x40))
+    #[case("5f67ffffffffffffffe052604067ffffffffffffffe0205400")]
+    fn memory_offsets_near_u64_max(#[case] code: &str) {
+        let operations = disassemble_str(code).unwrap();
+        // Tracking memory words at these offsets must not overflow
+        assert!(call_sites(&operations).is_empty());
+        crate::storage::infer_storage_layout(&operations);
+    }
+
+    #[rstest]
+    fn call_sites_of_eof_code_sections() {
+        // Section 0: PUSH0 PUSH0 PUSH0 PUSH0 EXTCALL STOP
+        // Section 1: PUSH0 PUSH0 PUSH0 EXTSTATICCALL POP RETF
+        let code = "ef0001010008020002000600060400000000800004 00000003 \
+                    5f5f5f5ff800 5f5f5ffb50e4";
+        let bytes = hex::decode(code.replace(' ', "")).unwrap();
+        // Exploring from the header does not reach the code sections
+        assert!(call_sites(&crate::disassemble_bytes(bytes.clone()).unwrap()).is_empty());
+        let container = EofContainer::parse(&bytes).unwrap();
+        let sites: Vec<(u32, Opcode)> = eof_call_sites(&container)
+            .into_iter()
+            .map(|site| (site.offset, site.opcode))
+            .collect();
+        assert_eq!(
+            sites,
+            vec![(29, Opcode::EXTCALL), (34, Opcode::EXTSTATICCALL)]
+        );
+    }
+
+    #[rstest]
+    fn call_sites_of_weth() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let sites = call_sites(&operations);
+        // withdraw(uint256) sends the withdrawn amount to the caller
+        assert_eq!(sites.len(), 1);
+        assert_eq!(sites[0].opcode, Opcode::CALL);
+        assert_eq!(
+            sites[0].functions,
+            BTreeSet::from([[0x2e, 0x1a, 0x7d, 0x4d]])
+        );
+        assert!(sites[0].target.as_ref().unwrap().contains(Opcode::CALLER));
+        assert!(sites[0]
+            .value
+            .as_ref()
+            .unwrap()
+            .contains(Opcode::CALLDATALOAD));
+    }
+
+    #[rstest]
+    fn resolve_selector_of_router_calls() {
+        let code =
+            fs::read_to_string("testdata/0xE592427A0AEce92De3Edee1F18E0157C05861564_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let formatted = format_call_sites(operations).unwrap();
+        // unwrapWETH9(uint256,address) queries the WETH balance and withdraws it
+        assert!(formatted.contains(
+            "STATICCALL functions=[0x49404b7c] target=0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2 \
+             selector=0x70a08231"
+        ));
+        assert!(formatted.contains(
+            "CALL functions=[0x49404b7c] target=0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2 \
+             value=0x0 selector=0x2e1a7d4d"
+        ));
+    }
+}
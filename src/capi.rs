@@ -0,0 +1,413 @@
+//! C interface for linking the disassembler from other languages
+//!
+//! Enabled with the `capi` feature. The header `include/evm_disassembler.h` is generated with
+//! cbindgen into the build directory on every build, the tests check that the copy in `include/`
+//! is up to date. Build a shared or static library with
+//!
+//! ```text
+//! cargo rustc --lib --release --features capi --crate-type cdylib
+//! cargo rustc --lib --release --features capi --crate-type staticlib
+//! ```
+//!
+//! Functions return an [`EvmdisStatus`], the message of the last error on the calling thread is
+//! available from [`evmdis_last_error`]. Instructions are read with an iterator and point into the
+//! disassembly, which owns all memory until it is released with [`evmdis_free`]:
+//!
+//! ```text
+//! EvmdisDisassembly *disassembly;
+//! if (evmdis_disassemble_hex("0x6080604052", &disassembly) != EVMDIS_OK) {
+//!     fprintf(stderr, "%s\n", evmdis_last_error());
+//!     return 1;
+//! }
+//! EvmdisIterator *iterator = evmdis_iter(disassembly);
+//! EvmdisInstruction instruction;
+//! while (evmdis_iter_next(iterator, &instruction)) {
+//!     printf("%08x: %s\n", instruction.offset, instruction.name);
+//! }
+//! evmdis_iter_free(iterator);
+//! evmdis_free(disassembly);
+//! ```
+use crate::types::{Delegation, Operation};
+use crate::{disassemble_bytes, format_operations};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::catch_unwind;
+use std::ptr;
+
+/// The result of a call
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvmdisStatus {
+    /// The call succeeded
+    EVMDIS_OK = 0,
+    /// A required pointer argument was null
+    EVMDIS_NULL_POINTER = 1,
+    /// The hex string is not valid UTF-8
+    EVMDIS_INVALID_UTF8 = 2,
+    /// The hex string could not be decoded
+    EVMDIS_INVALID_HEX = 3,
+    /// The code is an EIP-7702 delegation designator and not disassembled
+    EVMDIS_DELEGATION = 4,
+    /// Any other error, including internal panics
+    EVMDIS_ERROR = 5,
+}
+
+/// A decoded instruction
+///
+/// The pointers are owned by the disassembly and valid until it is freed.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct EvmdisInstruction {
+    /// The offset in the bytecode
+    pub offset: u32,
+    /// The opcode byte
+    pub opcode: u8,
+    /// The mnemonic as NUL terminated string
+    pub name: *const c_char,
+    /// The immediate bytes, null if `input_len` is 0
+    pub input: *const u8,
+    /// The number of immediate bytes
+    pub input_len: usize,
+}
+
+/// Disassembled code, created by `evmdis_disassemble` or `evmdis_disassemble_hex`
+pub struct EvmdisDisassembly {
+    bytes: Vec<u8>,
+    operations: Vec<Operation>,
+    names: Vec<CString>,
+}
+
+/// An iterator over the instructions of a disassembly, created by `evmdis_iter`
+pub struct EvmdisIterator {
+    disassembly: *const EvmdisDisassembly,
+    index: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn fail(status: EvmdisStatus, message: impl ToString) -> EvmdisStatus {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    status
+}
+
+fn disassemble_into(bytes: Vec<u8>, out: *mut *mut EvmdisDisassembly) -> EvmdisStatus {
+    if out.is_null() {
+        return fail(EvmdisStatus::EVMDIS_NULL_POINTER, "out is null");
+    }
+    if let Some(delegation) = Delegation::from_bytes(&bytes) {
+        return fail(
+            EvmdisStatus::EVMDIS_DELEGATION,
+            format!(
+                "Code is an EIP-7702 delegation designator to 0x{}",
+                hex::encode(delegation.address)
+            ),
+        );
+    }
+    let operations = match catch_unwind(|| disassemble_bytes(bytes.clone())) {
+        Ok(Ok(operations)) => operations,
+        Ok(Err(e)) => return fail(EvmdisStatus::EVMDIS_ERROR, e),
+        Err(_) => return fail(EvmdisStatus::EVMDIS_ERROR, "Panic while disassembling"),
+    };
+    let names = operations
+        .iter()
+        .map(|operation| CString::new(format!("{:?}", operation.opcode)).unwrap_or_default())
+        .collect();
+    let disassembly = EvmdisDisassembly {
+        bytes,
+        operations,
+        names,
+    };
+    // SAFETY: `out` was checked to be non-null, the caller guarantees it is writable
+    unsafe { *out = Box::into_raw(Box::new(disassembly)) };
+    EvmdisStatus::EVMDIS_OK
+}
+
+/// Disassembles raw bytecode
+///
+/// On success `*out` is set to a disassembly that has to be released with `evmdis_free`.
+///
+/// # Safety
+/// `code` must point to `len` readable bytes (or be null if `len` is 0) and `out` must be a
+/// valid pointer to write the result to.
+#[no_mangle]
+pub unsafe extern "C" fn evmdis_disassemble(
+    code: *const u8,
+    len: usize,
+    out: *mut *mut EvmdisDisassembly,
+) -> EvmdisStatus {
+    let bytes = match (code.is_null(), len) {
+        (_, 0) => Vec::new(),
+        (true, _) => return fail(EvmdisStatus::EVMDIS_NULL_POINTER, "code is null"),
+        (false, _) => std::slice::from_raw_parts(code, len).to_vec(),
+    };
+    disassemble_into(bytes, out)
+}
+
+/// Disassembles a NUL terminated hex string with or without `0x` prefix
+///
+/// Unlinked library placeholders are decoded as zero addresses. On success `*out` is set to a
+/// disassembly that has to be released with `evmdis_free`.
+///
+/// # Safety
+/// `hex` must be a valid NUL terminated string and `out` a valid pointer to write the result to.
+#[no_mangle]
+pub unsafe extern "C" fn evmdis_disassemble_hex(
+    hex: *const c_char,
+    out: *mut *mut EvmdisDisassembly,
+) -> EvmdisStatus {
+    if hex.is_null() {
+        return fail(EvmdisStatus::EVMDIS_NULL_POINTER, "hex is null");
+    }
+    let code = match CStr::from_ptr(hex).to_str() {
+        Ok(code) => code.trim().trim_start_matches("0x"),
+        Err(e) => return fail(EvmdisStatus::EVMDIS_INVALID_UTF8, e),
+    };
+    let (code, _) = crate::link::zero_placeholders(code);
+    match hex::decode(code) {
+        Ok(bytes) => disassemble_into(bytes, out),
+        Err(e) => fail(EvmdisStatus::EVMDIS_INVALID_HEX, e),
+    }
+}
+
+/// Releases a disassembly, null is ignored
+///
+/// # Safety
+/// `disassembly` must have been returned by this library and not been freed before. Iterators
+/// and instructions of the disassembly must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn evmdis_free(disassembly: *mut EvmdisDisassembly) {
+    if !disassembly.is_null() {
+        drop(Box::from_raw(disassembly));
+    }
+}
+
+/// The number of instructions of a disassembly, 0 for null
+///
+/// # Safety
+/// `disassembly` must be null or a valid disassembly.
+#[no_mangle]
+pub unsafe extern "C" fn evmdis_len(disassembly: *const EvmdisDisassembly) -> usize {
+    disassembly
+        .as_ref()
+        .map_or(0, |disassembly| disassembly.operations.len())
+}
+
+/// Creates an iterator over the instructions of a disassembly, null if `disassembly` is null
+///
+/// The iterator has to be released with `evmdis_iter_free` before the disassembly is freed.
+///
+/// # Safety
+/// `disassembly` must be null or a valid disassembly.
+#[no_mangle]
+pub unsafe extern "C" fn evmdis_iter(disassembly: *const EvmdisDisassembly) -> *mut EvmdisIterator {
+    if disassembly.is_null() {
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(EvmdisIterator {
+        disassembly,
+        index: 0,
+    }))
+}
+
+/// Writes the next instruction to `*out`, returns false at the end or if a pointer is null
+///
+/// # Safety
+/// `iterator` must be null or a valid iterator whose disassembly is still alive and `out` must be
+/// null or a valid pointer to write the instruction to.
+#[no_mangle]
+pub unsafe extern "C" fn evmdis_iter_next(
+    iterator: *mut EvmdisIterator,
+    out: *mut EvmdisInstruction,
+) -> bool {
+    let Some(iterator) = iterator.as_mut() else {
+        return false;
+    };
+    let disassembly = &*iterator.disassembly;
+    let (Some(operation), false) = (disassembly.operations.get(iterator.index), out.is_null())
+    else {
+        return false;
+    };
+    *out = EvmdisInstruction {
+        offset: operation.offset,
+        opcode: disassembly.bytes[operation.offset as usize],
+        name: disassembly.names[iterator.index].as_ptr(),
+        input: match operation.input.is_empty() {
+            true => ptr::null(),
+            false => operation.input.as_ptr(),
+        },
+        input_len: operation.input.len(),
+    };
+    iterator.index += 1;
+    true
+}
+
+/// Releases an iterator, null is ignored
+///
+/// # Safety
+/// `iterator` must have been returned by `evmdis_iter` and not been freed before.
+#[no_mangle]
+pub unsafe extern "C" fn evmdis_iter_free(iterator: *mut EvmdisIterator) {
+    if !iterator.is_null() {
+        drop(Box::from_raw(iterator));
+    }
+}
+
+/// Formats a disassembly as text, one instruction per line, null if `disassembly` is null
+///
+/// The string has to be released with `evmdis_string_free`.
+///
+/// # Safety
+/// `disassembly` must be null or a valid disassembly.
+#[no_mangle]
+pub unsafe extern "C" fn evmdis_format(disassembly: *const EvmdisDisassembly) -> *mut c_char {
+    let Some(disassembly) = disassembly.as_ref() else {
+        return ptr::null_mut();
+    };
+    format_operations(disassembly.operations.clone())
+        .ok()
+        .and_then(|formatted| CString::new(formatted).ok())
+        .map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Releases a string returned by this library, null is ignored
+///
+/// # Safety
+/// `string` must have been returned by `evmdis_format` and not been freed before.
+#[no_mangle]
+pub unsafe extern "C" fn evmdis_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// The message of the last error on the calling thread, empty if there was none
+///
+/// The string is owned by the library and valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn evmdis_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    unsafe fn instructions(
+        disassembly: *const EvmdisDisassembly,
+    ) -> Vec<(u32, u8, String, Vec<u8>)> {
+        let iterator = evmdis_iter(disassembly);
+        let mut instruction = std::mem::MaybeUninit::<EvmdisInstruction>::uninit();
+        let mut instructions = Vec::new();
+        while evmdis_iter_next(iterator, instruction.as_mut_ptr()) {
+            let instruction = instruction.assume_init();
+            let input = match instruction.input.is_null() {
+                true => Vec::new(),
+                false => {
+                    std::slice::from_raw_parts(instruction.input, instruction.input_len).to_vec()
+                }
+            };
+            let name = CStr::from_ptr(instruction.name)
+                .to_str()
+                .unwrap()
+                .to_string();
+            instructions.push((instruction.offset, instruction.opcode, name, input));
+        }
+        evmdis_iter_free(iterator);
+        instructions
+    }
+
+    #[rstest]
+    fn disassemble_and_iterate() {
+        let code = hex::decode("6080604052e7").unwrap();
+        let mut disassembly = ptr::null_mut();
+        unsafe {
+            let status = evmdis_disassemble(code.as_ptr(), code.len(), &mut disassembly);
+            assert_eq!(status, EvmdisStatus::EVMDIS_OK);
+            assert_eq!(evmdis_len(disassembly), 4);
+            assert_eq!(
+                instructions(disassembly),
+                vec![
+                    (0, 0x60, "PUSH1".to_string(), vec![0x80]),
+                    (2, 0x60, "PUSH1".to_string(), vec![0x40]),
+                    (4, 0x52, "MSTORE".to_string(), vec![]),
+                    // Undefined opcodes keep their byte
+                    (5, 0xe7, "INVALID".to_string(), vec![]),
+                ]
+            );
+            let formatted = evmdis_format(disassembly);
+            assert_eq!(
+                CStr::from_ptr(formatted).to_str().unwrap(),
+                "00000000: PUSH1 0x80\n00000002: PUSH1 0x40\n00000004: MSTORE\n00000005: INVALID\n"
+            );
+            evmdis_string_free(formatted);
+            evmdis_free(disassembly);
+        }
+    }
+
+    #[rstest]
+    #[case("0x6080", EvmdisStatus::EVMDIS_OK, "")]
+    #[case(
+        "0xzz",
+        EvmdisStatus::EVMDIS_INVALID_HEX,
+        "Invalid character 'z' at position 0"
+    )]
+    #[case(
+        "ef0100bebebebebebebebebebebebebebebebebebebebe",
+        EvmdisStatus::EVMDIS_DELEGATION,
+        "Code is an EIP-7702 delegation designator to 0xbebebebebebebebebebebebebebebebebebebebe"
+    )]
+    fn report_errors(#[case] hex: &str, #[case] expected: EvmdisStatus, #[case] message: &str) {
+        LAST_ERROR.with(|last| *last.borrow_mut() = CString::default());
+        let hex = CString::new(hex).unwrap();
+        let mut disassembly = ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                evmdis_disassemble_hex(hex.as_ptr(), &mut disassembly),
+                expected
+            );
+            assert_eq!(
+                CStr::from_ptr(evmdis_last_error()).to_str().unwrap(),
+                message
+            );
+            assert_eq!(disassembly.is_null(), expected != EvmdisStatus::EVMDIS_OK);
+            evmdis_free(disassembly);
+        }
+    }
+
+    #[rstest]
+    fn checked_in_header_is_current() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/evm_disassembler.h"));
+        assert!(
+            generated == include_str!("../include/evm_disassembler.h"),
+            "include/evm_disassembler.h is outdated, copy the generated {}/evm_disassembler.h",
+            env!("OUT_DIR")
+        );
+    }
+
+    #[rstest]
+    fn handle_null_pointers() {
+        unsafe {
+            let mut disassembly = ptr::null_mut();
+            assert_eq!(
+                evmdis_disassemble(ptr::null(), 1, &mut disassembly),
+                EvmdisStatus::EVMDIS_NULL_POINTER
+            );
+            assert_eq!(
+                evmdis_disassemble_hex(c"00".as_ptr(), ptr::null_mut()),
+                EvmdisStatus::EVMDIS_NULL_POINTER
+            );
+            assert_eq!(evmdis_len(ptr::null()), 0);
+            assert!(evmdis_iter(ptr::null()).is_null());
+            assert!(!evmdis_iter_next(ptr::null_mut(), ptr::null_mut()));
+            assert!(evmdis_format(ptr::null()).is_null());
+            evmdis_free(ptr::null_mut());
+            evmdis_iter_free(ptr::null_mut());
+            evmdis_string_free(ptr::null_mut());
+        }
+    }
+}
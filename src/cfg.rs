@@ -0,0 +1,232 @@
+//! Splitting decoded operations into basic blocks and recovering the control flow graph
+use crate::symbolic::{explore, BlockIndex};
+use crate::types::{Opcode, Operation};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A maximal sequence of operations that is always executed from start to end
+///
+/// A block starts at the beginning of the code, at a JUMPDEST or after an operation ending the
+/// previous block, and ends with the next block terminator (see [`Opcode::is_block_terminator`]) or
+/// right before the next JUMPDEST.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BasicBlock<'a> {
+    /// The operations of the block
+    pub operations: &'a [Operation],
+}
+
+impl<'a> BasicBlock<'a> {
+    /// The offset of the first operation in the bytecode
+    pub fn offset(&self) -> u32 {
+        self.operations[0].offset
+    }
+
+    /// The last operation of the block
+    pub fn last(&self) -> &'a Operation {
+        &self.operations[self.operations.len() - 1]
+    }
+
+    /// Whether execution can continue with the operation following the block
+    pub fn falls_through(&self) -> bool {
+        let last = self.last().opcode;
+        !last.is_block_terminator()
+            || matches!(last, Opcode::JUMPI | Opcode::RJUMPI | Opcode::RJUMPV)
+    }
+}
+
+/// Splits operations into basic blocks
+///
+/// # Arguments
+/// - `operations` - The decoded operations as returned by `disassemble_str` or `disassemble_bytes`
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{cfg::basic_blocks, disassemble_str};
+///
+/// let operations = disassemble_str("608060405260043610603f57600035").unwrap();
+/// let blocks = basic_blocks(&operations);
+/// assert_eq!(blocks.len(), 2);
+/// assert_eq!(blocks[1].offset(), 0xc);
+/// ```
+pub fn basic_blocks(operations: &[Operation]) -> Vec<BasicBlock<'_>> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (i, operation) in operations.iter().enumerate() {
+        if operation.opcode == Opcode::JUMPDEST && i > start {
+            blocks.push(BasicBlock {
+                operations: &operations[start..i],
+            });
+            start = i;
+        }
+        if operation.opcode.is_block_terminator() {
+            blocks.push(BasicBlock {
+                operations: &operations[start..=i],
+            });
+            start = i + 1;
+        }
+    }
+    if start < operations.len() {
+        blocks.push(BasicBlock {
+            operations: &operations[start..],
+        });
+    }
+    blocks
+}
+
+/// Control flow graph of basic blocks with jump targets resolved by symbolic execution
+///
+/// Only blocks reachable from the entry points are part of the graph. Jumps to targets that are
+/// computed at runtime (e.g. loaded from storage) cannot be resolved and are reported by
+/// [`ControlFlowGraph::has_unresolved_jump`].
+#[derive(Clone, Debug)]
+pub struct ControlFlowGraph<'a> {
+    /// The basic blocks of the code indexed by offset
+    pub index: BlockIndex<'a>,
+    successors: BTreeMap<u32, BTreeSet<u32>>,
+    predecessors: BTreeMap<u32, BTreeSet<u32>>,
+    reached: BTreeSet<u32>,
+    unresolved: BTreeSet<u32>,
+}
+
+impl<'a> ControlFlowGraph<'a> {
+    /// Builds the control flow graph of code starting execution at offset 0
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use evm_disassembler::{cfg::ControlFlowGraph, disassemble_str};
+    ///
+    /// // PUSH1 0x7 PUSH1 0x5 JUMP JUMPDEST JUMP JUMPDEST STOP
+    /// let operations = disassemble_str("60076005565b565b00").unwrap();
+    /// let cfg = ControlFlowGraph::new(&operations);
+    /// assert_eq!(cfg.successors(0x5).collect::<Vec<_>>(), vec![0x7]);
+    /// ```
+    pub fn new(operations: &'a [Operation]) -> Self {
+        Self::with_entries(operations, &[0])
+    }
+
+    /// Builds the control flow graph reachable from the given entry offsets
+    pub fn with_entries(operations: &'a [Operation], entries: &[u32]) -> Self {
+        let index = BlockIndex::new(operations);
+        let exploration = explore(&index, entries, |_| {});
+        let mut successors: BTreeMap<u32, BTreeSet<u32>> = BTreeMap::new();
+        let mut predecessors: BTreeMap<u32, BTreeSet<u32>> = BTreeMap::new();
+        for (from, to) in exploration.edges {
+            successors.entry(from).or_default().insert(to);
+            predecessors.entry(to).or_default().insert(from);
+        }
+        ControlFlowGraph {
+            index,
+            successors,
+            predecessors,
+            reached: exploration.reached,
+            unresolved: exploration.unresolved,
+        }
+    }
+
+    /// Returns the reachable blocks in code order
+    pub fn blocks(&self) -> impl Iterator<Item = &BasicBlock<'a>> {
+        self.index
+            .blocks
+            .iter()
+            .filter(|block| self.reached.contains(&block.offset()))
+    }
+
+    /// Returns the block starting at the given offset
+    pub fn block(&self, offset: u32) -> Option<&BasicBlock<'a>> {
+        self.index.block(offset)
+    }
+
+    /// Offsets of the blocks execution can continue at after the given block
+    pub fn successors(&self, offset: u32) -> impl Iterator<Item = u32> + '_ {
+        self.successors.get(&offset).into_iter().flatten().copied()
+    }
+
+    /// Offsets of the blocks that can continue execution at the given block
+    pub fn predecessors(&self, offset: u32) -> impl Iterator<Item = u32> + '_ {
+        self.predecessors
+            .get(&offset)
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
+    /// Whether the block ends in a jump whose target could not be resolved
+    pub fn has_unresolved_jump(&self, offset: u32) -> bool {
+        self.unresolved.contains(&offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+    use std::fs;
+
+    #[rstest]
+    // PUSH1 0x1 JUMPDEST STOP
+    #[case("60015b00", vec![0, 2])]
+    // PUSH1 0x1 PUSH1 0x5 JUMPI
+    #[case("6001600557", vec![0])]
+    // PUSH1 0x1 PUSH1 0x5 JUMPI JUMPDEST STOP
+    #[case("60016005575b00", vec![0, 5])]
+    // STOP STOP JUMPDEST JUMPDEST
+    #[case("00005b5b", vec![0, 1, 2, 3])]
+    fn block_boundaries(#[case] code: &str, #[case] expected_offsets: Vec<u32>) {
+        let operations = disassemble_str(code).unwrap();
+        let blocks = basic_blocks(&operations);
+        let offsets: Vec<u32> = blocks.iter().map(|block| block.offset()).collect();
+        assert_eq!(offsets, expected_offsets);
+        let total: usize = blocks.iter().map(|block| block.operations.len()).sum();
+        assert_eq!(total, operations.len());
+    }
+
+    #[rstest]
+    fn resolve_return_address_of_internal_function() {
+        // Two calls of the internal function at 0xd, each pushing a different return address:
+        // PUSH1 0x5 PUSH1 0xd JUMP | JUMPDEST PUSH1 0xb PUSH1 0xd JUMP | JUMPDEST STOP |
+        // JUMPDEST JUMP
+        let operations = disassemble_str("6005600d565b600b600d565b005b56").unwrap();
+        let cfg = ControlFlowGraph::new(&operations);
+        assert_eq!(cfg.successors(0xd).collect::<Vec<_>>(), vec![0x5, 0xb]);
+        assert_eq!(cfg.predecessors(0xd).collect::<Vec<_>>(), vec![0x0, 0x5]);
+        assert!(!cfg.has_unresolved_jump(0xd));
+    }
+
+    #[rstest]
+    // PUSH1 0x0 SLOAD JUMP
+    #[case("60005456")]
+    // PUSH5 0x100000007 JUMP JUMPDEST STOP, the target is not truncated to the JUMPDEST at 0x7
+    #[case("640100000007 56 5b00")]
+    fn unresolved_jump(#[case] code: &str) {
+        let operations = disassemble_str(&code.replace(' ', "")).unwrap();
+        let cfg = ControlFlowGraph::new(&operations);
+        assert!(cfg.has_unresolved_jump(0));
+        assert_eq!(cfg.successors(0).count(), 0);
+    }
+
+    #[rstest]
+    fn contract_from_file_is_connected() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let cfg = ControlFlowGraph::new(&operations);
+        let jumpdests = cfg
+            .index
+            .blocks
+            .iter()
+            .filter(|block| block.operations[0].opcode == Opcode::JUMPDEST)
+            .count();
+        let reached_jumpdests = cfg
+            .blocks()
+            .filter(|block| block.operations[0].opcode == Opcode::JUMPDEST)
+            .count();
+        // Every JUMPDEST of the compiled contract is the target of some jump
+        assert_eq!(reached_jumpdests, jumpdests);
+        assert!(cfg
+            .blocks()
+            .all(|block| !cfg.has_unresolved_jump(block.offset())));
+    }
+}
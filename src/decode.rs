@@ -1,5 +1,7 @@
+use crate::error::Result;
 use crate::types::{Opcode, Operation};
-use eyre::Result;
+use alloc::vec;
+use alloc::vec::Vec;
 
 pub fn decode_operation(
     bytes: &mut dyn ExactSizeIterator<Item = u8>,
@@ -57,3 +59,18 @@ pub fn decode_operation(
     };
     Ok((operation, new_offset))
 }
+
+// Decodes operations until the end of the input or a truncated immediate, starting at `offset`
+pub(crate) fn decode_operations(bytes: Vec<u8>, mut offset: u32, is_eof: bool) -> Vec<Operation> {
+    let mut operations = Vec::new();
+    let mut bytes_iter = bytes.into_iter();
+    while bytes_iter.len() > 0 {
+        // A truncated immediate can only occur at the end of the input and is left out
+        let Ok((operation, new_offset)) = decode_operation(&mut bytes_iter, offset, is_eof) else {
+            break;
+        };
+        offset = new_offset;
+        operations.push(operation);
+    }
+    operations
+}
@@ -0,0 +1,576 @@
+//! Decompilation into structured pseudo code
+//!
+//! The control flow graph is split into sections starting at blocks with several predecessors
+//! (merge points and internal functions), at the targets of back edges (loop headers) and at the
+//! entry of every public function.
+//! Within a section, blocks are emitted in execution order with stack shuffles replaced by
+//! expressions, conditional jumps turned into `if` statements and storage accessed as
+//! `storage[slot]`. Jumps to other sections are emitted as `goto` passing the stack items the
+//! target section consumes, which appear as `in0`, `in1`, ... in the target.
+use crate::cfg::ControlFlowGraph;
+use crate::selectors::function_entries;
+use crate::stack::{Expr, SymbolicStack};
+use crate::types::{Opcode, Operation};
+use eyre::Result;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+// Maximum number of sections emitted per function
+const MAX_SECTIONS: usize = 512;
+// Expressions rendered longer than this are bound to a variable
+const MAX_INLINE_LENGTH: usize = 120;
+const INDENT: &str = "    ";
+
+/// Decompiles operations into readable pseudo code
+///
+/// Emits one function for the code entry point and one per public function found in the
+/// dispatcher, named by their selector.
+///
+/// # Arguments
+/// - `operations` - The decoded runtime code
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{decompile::decompile, disassemble_str};
+///
+/// // sstore(0x0, calldataload(0x4))
+/// let operations = disassemble_str("60043560005500").unwrap();
+/// let code = decompile(&operations).unwrap();
+/// assert!(code.contains("storage[0x0] = calldataload(0x4);"));
+/// ```
+pub fn decompile(operations: &[Operation]) -> Result<String> {
+    let cfg = ControlFlowGraph::new(operations);
+    let entries = function_entries(operations);
+    let function_names: HashMap<u32, String> = entries
+        .iter()
+        .map(|entry| (entry.offset, entry.selector_hex()))
+        .collect();
+    let section_starts: HashSet<u32> = cfg
+        .blocks()
+        .map(|block| block.offset())
+        .filter(|offset| cfg.predecessors(*offset).count() > 1)
+        .chain(function_names.keys().copied())
+        .chain(back_edge_targets(&cfg))
+        .chain([0])
+        .collect();
+
+    let mut decompiled = String::new();
+    let mut functions = vec![("__entry".to_string(), 0)];
+    functions.extend(
+        entries
+            .iter()
+            .map(|entry| (entry.selector_hex(), entry.offset)),
+    );
+    for (i, (name, offset)) in functions.iter().enumerate() {
+        if i > 0 {
+            writeln!(decompiled)?;
+        }
+        let mut emitter = FunctionEmitter {
+            cfg: &cfg,
+            section_starts: &section_starts,
+            function_names: &function_names,
+            path: Vec::new(),
+            lines: Vec::new(),
+            next_variable: 0,
+        };
+        writeln!(decompiled, "function {name}() {{")?;
+        for line in emitter.emit_function(*offset) {
+            writeln!(decompiled, "{line}")?;
+        }
+        writeln!(decompiled, "}}")?;
+    }
+    Ok(decompiled)
+}
+
+struct FunctionEmitter<'c, 'a> {
+    cfg: &'c ControlFlowGraph<'a>,
+    section_starts: &'c HashSet<u32>,
+    function_names: &'c HashMap<u32, String>,
+    // The blocks inlined on the way to the block being emitted, jumps back to them are not inlined
+    path: Vec<u32>,
+    lines: Vec<String>,
+    next_variable: usize,
+}
+
+// How emission continues after a block
+enum Continuation {
+    Inline(u32, SymbolicStack),
+    Done,
+}
+
+impl<'c, 'a> FunctionEmitter<'c, 'a> {
+    fn emit_function(&mut self, entry: u32) -> Vec<String> {
+        let mut pending = VecDeque::from([entry]);
+        let mut emitted = HashSet::new();
+        while let Some(section) = pending.pop_front() {
+            if emitted.len() >= MAX_SECTIONS || !emitted.insert(section) {
+                continue;
+            }
+            let mut section_lines = Vec::new();
+            std::mem::swap(&mut self.lines, &mut section_lines);
+            let targets = self.emit_path(section, SymbolicStack::new(), 1);
+            std::mem::swap(&mut self.lines, &mut section_lines);
+
+            let is_loop = targets.contains(&section);
+            if section != entry || is_loop {
+                let inputs = (0..self.inputs_needed(section))
+                    .map(|i| format!("in{i}"))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                self.lines.push(format!("label_{section:#x}({inputs}):"));
+            }
+            if is_loop {
+                self.lines.push(format!("{INDENT}while (true) {{"));
+                let goto = format!("goto label_{section:#x}(");
+                self.lines.extend(section_lines.into_iter().map(|line| {
+                    format!(
+                        "{INDENT}{}",
+                        line.replace(&goto, &format!("continue label_{section:#x}("))
+                    )
+                }));
+                self.lines.push(format!("{INDENT}}}"));
+            } else {
+                self.lines.extend(section_lines);
+            }
+            pending.extend(
+                targets
+                    .into_iter()
+                    .filter(|target| !self.function_names.contains_key(target) || *target == entry),
+            );
+        }
+        std::mem::take(&mut self.lines)
+    }
+
+    // Emits the blocks starting at `offset` until leaving the section, returns the sections jumped to
+    fn emit_path(&mut self, mut offset: u32, mut stack: SymbolicStack, indent: usize) -> Vec<u32> {
+        let mut targets = Vec::new();
+        let depth = self.path.len();
+        loop {
+            self.path.push(offset);
+            match self.emit_block(offset, &mut stack, indent, &mut targets) {
+                Continuation::Inline(next, next_stack) => {
+                    offset = next;
+                    stack = next_stack;
+                }
+                Continuation::Done => break,
+            }
+        }
+        self.path.truncate(depth);
+        targets
+    }
+
+    fn emit_block(
+        &mut self,
+        offset: u32,
+        stack: &mut SymbolicStack,
+        indent: usize,
+        targets: &mut Vec<u32>,
+    ) -> Continuation {
+        let Some(block) = self.cfg.block(offset) else {
+            self.line(indent, format!("invalid(); // no code at {offset:#x}"));
+            return Continuation::Done;
+        };
+        for operation in block.operations {
+            let opcode = operation.opcode;
+            if opcode.is_push() || opcode.is_dup() || opcode.is_swap() {
+                stack.apply(operation);
+                continue;
+            }
+            let (_, outputs) = operation.stack_arity();
+            let args = stack.apply(operation);
+            if outputs > 0 {
+                let value = stack.pop();
+                let value = self.bind_if_needed(opcode, value, indent);
+                stack.push(value);
+                continue;
+            }
+            match opcode {
+                Opcode::JUMPDEST | Opcode::POP => {}
+                Opcode::JUMP => {
+                    if let Some(target) = self.invalid_target(&args[0]) {
+                        self.line(indent, format!("invalid(); // no JUMPDEST at {target:#x}"));
+                        return Continuation::Done;
+                    }
+                    return self.jump(&args[0], stack, indent, targets);
+                }
+                Opcode::JUMPI => {
+                    let next =
+                        self.branch(&args[1], &args[0], block.last(), stack, indent, targets);
+                    return self.jump(&constant(next), stack, indent, targets);
+                }
+                _ => self.line(indent, render_statement(opcode, &args)),
+            }
+        }
+        if block.last().opcode.is_block_terminator() {
+            return Continuation::Done;
+        }
+        match self.cfg.index.next_offset(block) {
+            Some(next) => self.jump(&constant(next), stack, indent, targets),
+            None => Continuation::Done,
+        }
+    }
+
+    // Emits a conditional jump as if statement, returns the offset emission continues at
+    fn branch(
+        &mut self,
+        condition: &Expr,
+        target: &Expr,
+        jumpi: &Operation,
+        stack: &mut SymbolicStack,
+        indent: usize,
+        targets: &mut Vec<u32>,
+    ) -> u32 {
+        let fallthrough = jumpi.offset + 1;
+        let target_offset = target
+            .as_u64()
+            .and_then(|target| u32::try_from(target).ok());
+        let inline_target = target_offset
+            .filter(|target| !self.section_starts.contains(target) && !self.path.contains(target));
+        if let Some(target) = self.invalid_target(target) {
+            self.line(indent, format!("if ({}) {{", render_condition(condition)));
+            self.line(
+                indent + 1,
+                format!("invalid(); // no JUMPDEST at {target:#x}"),
+            );
+            self.line(indent, "}".to_string());
+            return fallthrough;
+        }
+        match inline_target {
+            // The fallthrough halts right away: invert the condition and continue at the target
+            Some(target)
+                if self.is_halting(fallthrough) && !self.section_starts.contains(&fallthrough) =>
+            {
+                self.line(
+                    indent,
+                    format!("if ({}) {{", render_condition(&negate(condition))),
+                );
+                targets.extend(self.emit_path(fallthrough, stack.clone(), indent + 1));
+                self.line(indent, "}".to_string());
+                // Emission continues at the target instead of the fallthrough
+                target
+            }
+            Some(target) => {
+                self.line(indent, format!("if ({}) {{", render_condition(condition)));
+                targets.extend(self.emit_path(target, stack.clone(), indent + 1));
+                self.line(indent, "}".to_string());
+                fallthrough
+            }
+            None => {
+                self.line(indent, format!("if ({}) {{", render_condition(condition)));
+                if let Continuation::Inline(..) = self.jump(target, stack, indent + 1, targets) {
+                    unreachable!("Jumps to section starts and inlined blocks are never inlined");
+                }
+                self.line(indent, "}".to_string());
+                fallthrough
+            }
+        }
+    }
+
+    fn jump(
+        &mut self,
+        target: &Expr,
+        stack: &SymbolicStack,
+        indent: usize,
+        targets: &mut Vec<u32>,
+    ) -> Continuation {
+        let Some(target) = target
+            .as_u64()
+            .and_then(|target| u32::try_from(target).ok())
+        else {
+            self.line(indent, format!("jump {};", render(target)));
+            return Continuation::Done;
+        };
+        if let Some(name) = self.function_names.get(&target) {
+            self.line(indent, format!("goto {name};"));
+            targets.push(target);
+            return Continuation::Done;
+        }
+        if self.section_starts.contains(&target) {
+            let mut stack = stack.clone();
+            let args: Vec<String> = (0..self.inputs_needed(target))
+                .map(|i| render(&stack.peek(i)))
+                .collect();
+            self.line(
+                indent,
+                format!("goto label_{target:#x}({});", args.join(", ")),
+            );
+            targets.push(target);
+            return Continuation::Done;
+        }
+        // A loop the control flow graph does not know about, e.g. through an unresolved jump
+        if self.path.contains(&target) {
+            self.line(indent, format!("jump {target:#x};"));
+            return Continuation::Done;
+        }
+        Continuation::Inline(target, stack.clone())
+    }
+
+    // The constant target of a jump if it is not a JUMPDEST
+    fn invalid_target(&self, target: &Expr) -> Option<u64> {
+        target
+            .as_u64()
+            .filter(|target| !u32::try_from(*target).is_ok_and(|t| self.cfg.index.is_jumpdest(t)))
+    }
+
+    fn bind_if_needed(&mut self, opcode: Opcode, value: Expr, indent: usize) -> Expr {
+        let rendered = render(&value);
+        if !reads_state(opcode) && rendered.len() <= MAX_INLINE_LENGTH {
+            return value;
+        }
+        let variable = self.next_variable;
+        self.next_variable += 1;
+        self.line(indent, format!("uint256 v{variable} = {rendered};"));
+        Expr::Variable(variable)
+    }
+
+    // Number of stack items the block at the given offset consumes
+    fn inputs_needed(&self, offset: u32) -> usize {
+        let mut stack = SymbolicStack::new();
+        if let Some(block) = self.cfg.block(offset) {
+            for operation in block.operations {
+                stack.apply(operation);
+            }
+        }
+        stack.inputs()
+    }
+
+    // Whether the block at the given offset ends execution without any further jump
+    fn is_halting(&self, offset: u32) -> bool {
+        self.cfg.block(offset).is_some_and(|block| {
+            matches!(
+                block.last().opcode,
+                Opcode::STOP
+                    | Opcode::RETURN
+                    | Opcode::REVERT
+                    | Opcode::INVALID
+                    | Opcode::SELFDESTRUCT
+            )
+        })
+    }
+
+    fn line(&mut self, indent: usize, line: String) {
+        self.lines.push(format!("{}{line}", INDENT.repeat(indent)));
+    }
+}
+
+// Offsets of the blocks jumped back to from a block reachable from them, found by a depth first
+// search from the code entry point
+fn back_edge_targets(cfg: &ControlFlowGraph) -> HashSet<u32> {
+    let mut targets = HashSet::new();
+    let mut visited = HashSet::from([0]);
+    let mut on_stack = HashSet::from([0]);
+    let mut stack = vec![(0, cfg.successors(0).collect::<Vec<u32>>())];
+    while let Some((offset, successors)) = stack.last_mut() {
+        let Some(next) = successors.pop() else {
+            on_stack.remove(offset);
+            stack.pop();
+            continue;
+        };
+        if on_stack.contains(&next) {
+            targets.insert(next);
+        } else if visited.insert(next) {
+            on_stack.insert(next);
+            stack.push((next, cfg.successors(next).collect()));
+        }
+    }
+    targets
+}
+
+fn constant(value: u32) -> Expr {
+    Expr::Constant(value.to_be_bytes().to_vec())
+}
+
+// Operations whose result depends on mutable state and therefore has to be bound in order
+fn reads_state(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::SLOAD
+            | Opcode::MLOAD
+            | Opcode::TLOAD
+            | Opcode::SHA3
+            | Opcode::GAS
+            | Opcode::MSIZE
+            | Opcode::PC
+            | Opcode::BALANCE
+            | Opcode::SELFBALANCE
+            | Opcode::EXTCODESIZE
+            | Opcode::EXTCODEHASH
+            | Opcode::RETURNDATASIZE
+            | Opcode::RETURNDATALOAD
+            | Opcode::CALL
+            | Opcode::CALLCODE
+            | Opcode::DELEGATECALL
+            | Opcode::STATICCALL
+            | Opcode::CREATE
+            | Opcode::CREATE2
+            | Opcode::EXTCALL
+            | Opcode::EXTDELEGATECALL
+            | Opcode::EXTSTATICCALL
+            | Opcode::EOFCREATE
+            | Opcode::TXCREATE
+    )
+}
+
+fn negate(condition: &Expr) -> Expr {
+    match condition {
+        Expr::Op(Opcode::ISZERO, args) => args[0].clone(),
+        _ => Expr::op(Opcode::ISZERO, vec![condition.clone()]),
+    }
+}
+
+// Renders a condition without redundant outer parentheses
+fn render_condition(condition: &Expr) -> String {
+    let rendered = render(condition);
+    match condition {
+        Expr::Op(opcode, _) if binary_operator(*opcode).is_some() => {
+            rendered[1..rendered.len() - 1].to_string()
+        }
+        _ => rendered,
+    }
+}
+
+fn binary_operator(opcode: Opcode) -> Option<&'static str> {
+    Some(match opcode {
+        Opcode::ADD => "+",
+        Opcode::SUB => "-",
+        Opcode::MUL => "*",
+        Opcode::DIV => "/",
+        Opcode::MOD => "%",
+        Opcode::EXP => "**",
+        Opcode::LT => "<",
+        Opcode::GT => ">",
+        Opcode::EQ => "==",
+        Opcode::AND => "&",
+        Opcode::OR => "|",
+        Opcode::XOR => "^",
+        _ => return None,
+    })
+}
+
+fn render(expr: &Expr) -> String {
+    let Expr::Op(opcode, args) = expr else {
+        return expr.to_string();
+    };
+    if let Some(operator) = binary_operator(*opcode) {
+        return format!("({} {operator} {})", render(&args[0]), render(&args[1]));
+    }
+    match opcode {
+        // Shifts take the shift amount first
+        Opcode::SHL => format!("({} << {})", render(&args[1]), render(&args[0])),
+        Opcode::SHR => format!("({} >> {})", render(&args[1]), render(&args[0])),
+        Opcode::ISZERO => format!("!{}", render(&args[0])),
+        Opcode::NOT => format!("~{}", render(&args[0])),
+        Opcode::SLOAD => format!("storage[{}]", render(&args[0])),
+        Opcode::TLOAD => format!("transient[{}]", render(&args[0])),
+        Opcode::MLOAD => format!("memory[{}]", render(&args[0])),
+        Opcode::CALLER => "msg.sender".to_string(),
+        Opcode::CALLVALUE => "msg.value".to_string(),
+        Opcode::CALLDATASIZE => "msg.data.length".to_string(),
+        Opcode::ORIGIN => "tx.origin".to_string(),
+        Opcode::GASPRICE => "tx.gasprice".to_string(),
+        Opcode::ADDRESS => "address(this)".to_string(),
+        Opcode::TIMESTAMP => "block.timestamp".to_string(),
+        Opcode::NUMBER => "block.number".to_string(),
+        Opcode::CHAINID => "block.chainid".to_string(),
+        Opcode::COINBASE => "block.coinbase".to_string(),
+        _ => render_call(*opcode, args),
+    }
+}
+
+fn render_call(opcode: Opcode, args: &[Expr]) -> String {
+    let args: Vec<String> = args.iter().map(render).collect();
+    format!(
+        "{}({})",
+        format!("{opcode:?}").to_lowercase(),
+        args.join(", ")
+    )
+}
+
+fn render_statement(opcode: Opcode, args: &[Expr]) -> String {
+    match opcode {
+        Opcode::SSTORE => format!("storage[{}] = {};", render(&args[0]), render(&args[1])),
+        Opcode::TSTORE => format!("transient[{}] = {};", render(&args[0]), render(&args[1])),
+        Opcode::MSTORE => format!("memory[{}] = {};", render(&args[0]), render(&args[1])),
+        _ => format!("{};", render_call(opcode, args)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+    use std::fs;
+
+    #[rstest]
+    fn non_payable_check_becomes_if() {
+        // CALLVALUE ISZERO PUSH1 0x9 JUMPI PUSH1 0x0 DUP1 REVERT JUMPDEST PUSH1 0x1 PUSH1 0x0 SSTORE
+        // STOP
+        let operations = disassemble_str("3415600957600080fd5b600160005500").unwrap();
+        let expected = "\
+function __entry() {
+    if (msg.value) {
+        revert(0x0, 0x0);
+    }
+    storage[0x0] = 0x1;
+    stop();
+}
+";
+        assert_eq!(decompile(&operations).unwrap(), expected);
+    }
+
+    #[rstest]
+    fn storage_reads_are_bound_to_variables() {
+        // SLOAD(0x0) + 1 stored back to slot 0x0
+        let operations = disassemble_str("6000546001016000555f5f55").unwrap();
+        let decompiled = decompile(&operations).unwrap();
+        assert!(decompiled.contains("uint256 v0 = storage[0x0];"));
+        assert!(decompiled.contains("storage[0x0] = (0x1 + v0);"));
+    }
+
+    #[rstest]
+    fn loop_is_recovered() {
+        // i = 0; while (i < 10) i++; (PUSH0 JUMPDEST DUP1 PUSH1 0xa GT ISZERO PUSH1 0x10 JUMPI
+        // PUSH1 0x1 ADD PUSH1 0x1 JUMP JUMPDEST STOP)
+        let operations = disassemble_str(
+            "5f5b80600a1115601057600101600156 5b00"
+                .replace(' ', "")
+                .as_str(),
+        )
+        .unwrap();
+        let decompiled = decompile(&operations).unwrap();
+        assert!(decompiled.contains("label_0x1(in0):"), "{decompiled}");
+        assert!(decompiled.contains("while (true) {"), "{decompiled}");
+        assert!(
+            decompiled.contains("continue label_0x1((0x1 + in0));"),
+            "{decompiled}"
+        );
+    }
+
+    #[rstest]
+    // JUMPDEST PUSH1 0x0 JUMP
+    #[case("5b600056", "continue label_0x0();")]
+    // PUSH1 0x0 JUMP JUMPDEST
+    #[case("6000565b", "invalid(); // no JUMPDEST at 0x0")]
+    fn jumps_back_to_the_entry_terminate(#[case] code: &str, #[case] expected: &str) {
+        let operations = disassemble_str(code).unwrap();
+        let decompiled = decompile(&operations).unwrap();
+        assert!(decompiled.contains(expected), "{decompiled}");
+    }
+
+    #[rstest]
+    fn decompile_weth() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let decompiled = decompile(&operations).unwrap();
+        // One function per selector plus the entry point
+        assert_eq!(decompiled.matches("function ").count(), 12);
+        assert!(decompiled.contains("function 0xa9059cbb() {"));
+        assert!(decompiled.contains("goto 0xa9059cbb;"));
+        assert!(decompiled.contains("msg.sender"));
+        assert!(decompiled.contains("storage["));
+    }
+}
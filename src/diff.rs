@@ -0,0 +1,517 @@
+//! Instruction level diff between two disassembled contracts
+//!
+//! Operations are aligned by opcode and immediate bytes only, so inserted or removed code does not
+//! cause every following instruction to be reported as changed due to shifted offsets.
+use crate::metadata::strip_metadata;
+use crate::{disassemble_bytes, Operation};
+use eyre::Result;
+use std::fmt::Write;
+
+/// Options controlling which differences are reported by [`diff_operations`] and [`diff_bytes`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Strip compiler metadata trailers before disassembling (only applies to [`diff_bytes`])
+    pub ignore_metadata: bool,
+    /// Treat operations with identical opcodes but different immediate bytes as unchanged
+    ///
+    /// Hides differences in immutable values and other PUSH constants. To only ignore immutable
+    /// values, mask them with [`crate::immutables::mask_immutables`] before diffing instead.
+    pub ignore_immediates: bool,
+}
+
+/// A single entry of an instruction level diff
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffEntry {
+    /// The operation is present in both versions
+    Unchanged {
+        /// The operation in the old version
+        old: Operation,
+        /// The operation in the new version
+        new: Operation,
+    },
+    /// The operation is only present in the old version
+    Removed(Operation),
+    /// The operation is only present in the new version
+    Inserted(Operation),
+    /// The opcode is identical but the immediate bytes differ
+    Changed {
+        /// The operation in the old version
+        old: Operation,
+        /// The operation in the new version
+        new: Operation,
+    },
+}
+
+impl DiffEntry {
+    /// Returns true for every entry except [`DiffEntry::Unchanged`]
+    pub fn is_change(&self) -> bool {
+        !matches!(self, DiffEntry::Unchanged { .. })
+    }
+}
+
+/// Disassembles and diffs two raw bytecodes
+///
+/// # Arguments
+/// - `old` - The bytecode of the old version
+/// - `new` - The bytecode of the new version
+/// - `options` - Options controlling which differences are ignored
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::diff::{diff_bytes, DiffOptions};
+///
+/// let old = hex::decode("6080604052").unwrap();
+/// let new = hex::decode("608060405233").unwrap();
+/// let entries = diff_bytes(old, new, &DiffOptions::default()).unwrap();
+/// assert_eq!(entries.iter().filter(|entry| entry.is_change()).count(), 1);
+/// ```
+pub fn diff_bytes(old: Vec<u8>, new: Vec<u8>, options: &DiffOptions) -> Result<Vec<DiffEntry>> {
+    let (old, new) = if options.ignore_metadata {
+        (strip_metadata(&old).to_vec(), strip_metadata(&new).to_vec())
+    } else {
+        (old, new)
+    };
+    let old_operations = disassemble_bytes(old)?;
+    let new_operations = disassemble_bytes(new)?;
+    Ok(diff_operations(&old_operations, &new_operations, options))
+}
+
+/// Aligns two sequences of operations and reports inserted, removed and changed instructions
+///
+/// Every operation of both inputs is contained in the output exactly once, in order.
+///
+/// # Arguments
+/// - `old` - The operations of the old version
+/// - `new` - The operations of the new version
+/// - `options` - Options controlling which differences are ignored
+pub fn diff_operations(
+    old: &[Operation],
+    new: &[Operation],
+    options: &DiffOptions,
+) -> Vec<DiffEntry> {
+    let same = |a: &Operation, b: &Operation| {
+        a.opcode == b.opcode && (options.ignore_immediates || a.input == b.input)
+    };
+
+    let mut entries = Vec::with_capacity(old.len().max(new.len()));
+    let mut removed: Vec<&Operation> = Vec::new();
+    let mut inserted: Vec<&Operation> = Vec::new();
+    for edit in shortest_edit(old, new, same) {
+        match edit {
+            Edit::Equal(i, j) => {
+                flush_hunk(&mut entries, &mut removed, &mut inserted);
+                entries.push(DiffEntry::Unchanged {
+                    old: old[i].clone(),
+                    new: new[j].clone(),
+                });
+            }
+            Edit::Delete(i) => removed.push(&old[i]),
+            Edit::Insert(j) => inserted.push(&new[j]),
+        }
+    }
+    flush_hunk(&mut entries, &mut removed, &mut inserted);
+    entries
+}
+
+/// Formats a diff as side by side text
+///
+/// Each line contains the old operation on the left and the new operation on the right separated by
+/// a marker in the style of `sdiff`: `|` for changed, `<` for removed and `>` for inserted
+/// operations. Only unchanged operations within `context` lines of a change are shown, skipped
+/// regions are replaced with `...`.
+///
+/// # Arguments
+/// - `entries` - The diff as returned by `diff_operations` or `diff_bytes`
+/// - `context` - Number of unchanged operations to show around each change
+pub fn format_diff(entries: &[DiffEntry], context: usize) -> Result<String> {
+    let mut visible = vec![false; entries.len()];
+    for (i, _) in entries.iter().enumerate().filter(|(_, e)| e.is_change()) {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(entries.len());
+        visible[start..end].iter_mut().for_each(|v| *v = true);
+    }
+
+    let rows: Vec<(usize, String, char, String)> = entries
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| visible[*i])
+        .map(|(i, entry)| match entry {
+            DiffEntry::Unchanged { old, new } => (i, format!("{old:?}"), ' ', format!("{new:?}")),
+            DiffEntry::Changed { old, new } => (i, format!("{old:?}"), '|', format!("{new:?}")),
+            DiffEntry::Removed(old) => (i, format!("{old:?}"), '<', String::new()),
+            DiffEntry::Inserted(new) => (i, String::new(), '>', format!("{new:?}")),
+        })
+        .collect();
+    let width = rows
+        .iter()
+        .map(|(_, left, _, _)| left.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut formatted = String::new();
+    let mut previous: Option<usize> = None;
+    for (i, left, marker, right) in rows.iter() {
+        if previous.map_or(*i > 0, |previous| *i > previous + 1) {
+            writeln!(formatted, "...")?;
+        }
+        writeln!(
+            formatted,
+            "{}",
+            format!("{left:<width$} {marker} {right}").trim_end()
+        )?;
+        previous = Some(*i);
+    }
+    if previous.is_some_and(|previous| previous + 1 < entries.len()) {
+        writeln!(formatted, "...")?;
+    }
+    Ok(formatted)
+}
+
+// Pairs up removed and inserted operations of a hunk that share an opcode as changed operations
+fn flush_hunk(
+    entries: &mut Vec<DiffEntry>,
+    removed: &mut Vec<&Operation>,
+    inserted: &mut Vec<&Operation>,
+) {
+    let mut removed_iter = removed.drain(..).peekable();
+    let mut inserted_iter = inserted.drain(..).peekable();
+    loop {
+        match (removed_iter.peek(), inserted_iter.peek()) {
+            (Some(old), Some(new)) if old.opcode == new.opcode => {
+                entries.push(DiffEntry::Changed {
+                    old: (*old).clone(),
+                    new: (*new).clone(),
+                });
+                removed_iter.next();
+                inserted_iter.next();
+            }
+            (Some(old), _) => {
+                entries.push(DiffEntry::Removed((*old).clone()));
+                removed_iter.next();
+            }
+            (None, Some(new)) => {
+                entries.push(DiffEntry::Inserted((*new).clone()));
+                inserted_iter.next();
+            }
+            (None, None) => break,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+// Myers' O((n + m) * d) shortest edit script in linear space
+fn shortest_edit<T>(old: &[T], new: &[T], same: impl Fn(&T, &T) -> bool) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(old.len().max(new.len()));
+    edit_range(old, new, (0, 0), &same, &mut edits);
+    edits
+}
+
+// Appends the edits of `a` and `b` starting at `start` in the full sequences, after trimming the
+// common prefix and suffix the middle snake of the remaining ranges splits them into two halves
+// with about half the edits each
+fn edit_range<T>(
+    a: &[T],
+    b: &[T],
+    start: (usize, usize),
+    same: &impl Fn(&T, &T) -> bool,
+    edits: &mut Vec<Edit>,
+) {
+    let prefix = a
+        .iter()
+        .zip(b.iter())
+        .take_while(|(x, y)| same(x, y))
+        .count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| same(x, y))
+        .count();
+    edits.extend((0..prefix).map(|i| Edit::Equal(start.0 + i, start.1 + i)));
+    let (a_start, b_start) = (start.0 + prefix, start.1 + prefix);
+    let inner_a = &a[prefix..a.len() - suffix];
+    let inner_b = &b[prefix..b.len() - suffix];
+    if inner_a.is_empty() {
+        edits.extend((0..inner_b.len()).map(|j| Edit::Insert(b_start + j)));
+    } else if inner_b.is_empty() {
+        edits.extend((0..inner_a.len()).map(|i| Edit::Delete(a_start + i)));
+    } else {
+        let ((x, y), (u, v)) = middle_snake(inner_a, inner_b, same);
+        edit_range(
+            &inner_a[..x],
+            &inner_b[..y],
+            (a_start, b_start),
+            same,
+            edits,
+        );
+        edits.extend((0..u - x).map(|i| Edit::Equal(a_start + x + i, b_start + y + i)));
+        edit_range(
+            &inner_a[u..],
+            &inner_b[v..],
+            (a_start + u, b_start + v),
+            same,
+            edits,
+        );
+    }
+    let (a_end, b_end) = (start.0 + a.len() - suffix, start.1 + b.len() - suffix);
+    edits.extend((0..suffix).map(|i| Edit::Equal(a_end + i, b_end + i)));
+}
+
+// Start and end of the snake in the middle of a shortest edit script of two non-empty sequences,
+// found by searching forward from the start and backward from the end until the paths overlap
+fn middle_snake<T>(
+    a: &[T],
+    b: &[T],
+    same: &impl Fn(&T, &T) -> bool,
+) -> ((usize, usize), (usize, usize)) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    // Furthest x reached on each diagonal k = x - y, backward in the reversed sequences
+    let mut forward = vec![0isize; 2 * max as usize + 3];
+    let mut backward = vec![0isize; 2 * max as usize + 3];
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let index = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && forward[index - 1] < forward[index + 1]) {
+                forward[index + 1]
+            } else {
+                forward[index - 1] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while x < n && y < m && same(&a[x as usize], &b[y as usize]) {
+                x += 1;
+                y += 1;
+            }
+            forward[index] = x;
+            // The backward paths of round d - 1 end on diagonals delta - k
+            let reverse = delta - k;
+            if delta % 2 != 0 && reverse.abs() < d && x + backward[(offset + reverse) as usize] >= n
+            {
+                return ((x0 as usize, y0 as usize), (x as usize, y as usize));
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let index = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && backward[index - 1] < backward[index + 1]) {
+                backward[index + 1]
+            } else {
+                backward[index - 1] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while x < n && y < m && same(&a[(n - 1 - x) as usize], &b[(m - 1 - y) as usize]) {
+                x += 1;
+                y += 1;
+            }
+            backward[index] = x;
+            let reverse = delta - k;
+            if delta % 2 == 0 && reverse.abs() <= d && x + forward[(offset + reverse) as usize] >= n
+            {
+                return (
+                    ((n - x) as usize, (m - y) as usize),
+                    ((n - x0) as usize, (m - y0) as usize),
+                );
+            }
+        }
+    }
+    unreachable!("The forward and backward paths overlap after at most (n + m) / 2 rounds")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use crate::types::Opcode;
+    use rstest::*;
+    use std::fs;
+
+    fn diff_str(old: &str, new: &str, options: &DiffOptions) -> Vec<DiffEntry> {
+        let old = disassemble_str(old).expect("Unable to decode");
+        let new = disassemble_str(new).expect("Unable to decode");
+        diff_operations(&old, &new, options)
+    }
+
+    #[rstest]
+    fn identical_code_has_no_changes() {
+        let entries = diff_str(
+            "608060405260043610",
+            "608060405260043610",
+            &Default::default(),
+        );
+        assert_eq!(entries.len(), 6);
+        assert!(entries.iter().all(|entry| !entry.is_change()));
+    }
+
+    #[rstest]
+    fn inserted_operation_does_not_shift_following_operations() {
+        // CALLER inserted after the free memory pointer setup
+        let entries = diff_str(
+            "608060405260043610",
+            "60806040523360043610",
+            &Default::default(),
+        );
+        let changes: Vec<&DiffEntry> = entries.iter().filter(|e| e.is_change()).collect();
+        assert_eq!(changes.len(), 1);
+        match changes[0] {
+            DiffEntry::Inserted(operation) => {
+                assert_eq!(operation.opcode, Opcode::CALLER);
+                assert_eq!(operation.offset, 5);
+            }
+            other => panic!("Expected insertion, got {other:?}"),
+        }
+    }
+
+    #[rstest]
+    #[case(false, 1)]
+    #[case(true, 0)]
+    fn changed_immediates(#[case] ignore_immediates: bool, #[case] expected_changes: usize) {
+        let options = DiffOptions {
+            ignore_immediates,
+            ..Default::default()
+        };
+        let entries = diff_str("6080604052", "6080606052", &options);
+        let changes: Vec<&DiffEntry> = entries.iter().filter(|e| e.is_change()).collect();
+        assert_eq!(changes.len(), expected_changes);
+        if let Some(change) = changes.first() {
+            assert!(matches!(change, DiffEntry::Changed { .. }));
+        }
+    }
+
+    #[rstest]
+    fn removed_and_inserted_operations() {
+        let entries = diff_str("600101", "33600102", &Default::default());
+        let markers: Vec<&str> = entries
+            .iter()
+            .map(|entry| match entry {
+                DiffEntry::Unchanged { .. } => "=",
+                DiffEntry::Changed { .. } => "~",
+                DiffEntry::Removed(_) => "-",
+                DiffEntry::Inserted(_) => "+",
+            })
+            .collect();
+        assert_eq!(markers, vec!["+", "=", "-", "+"]);
+    }
+
+    #[rstest]
+    fn ignore_metadata_trailer() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let old = hex::decode(code.trim().trim_start_matches("0x")).unwrap();
+        let mut new = old.clone();
+        // Flip a byte of the swarm hash in the metadata trailer
+        let hash_byte = new.len() - 10;
+        new[hash_byte] ^= 0xff;
+
+        let entries = diff_bytes(old.clone(), new.clone(), &Default::default()).unwrap();
+        assert!(entries.iter().any(|entry| entry.is_change()));
+
+        let options = DiffOptions {
+            ignore_metadata: true,
+            ..Default::default()
+        };
+        let entries = diff_bytes(old, new, &options).unwrap();
+        assert!(entries.iter().all(|entry| !entry.is_change()));
+    }
+
+    // Length of the longest common subsequence by dynamic programming
+    fn lcs_length(a: &[u8], b: &[u8]) -> usize {
+        let mut row = vec![0; b.len() + 1];
+        for x in a {
+            let mut diagonal = 0;
+            for (j, y) in b.iter().enumerate() {
+                let above = row[j + 1];
+                row[j + 1] = if x == y {
+                    diagonal + 1
+                } else {
+                    above.max(row[j])
+                };
+                diagonal = above;
+            }
+        }
+        row[b.len()]
+    }
+
+    #[rstest]
+    fn shortest_edit_matches_longest_common_subsequence() {
+        // Deterministic pseudo random sequences over small alphabets
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = |alphabet: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % alphabet) as u8
+        };
+        for round in 0..500 {
+            let alphabet = 2 + round % 4;
+            let a: Vec<u8> = (0..next(40)).map(|_| next(alphabet)).collect();
+            let b: Vec<u8> = (0..next(40)).map(|_| next(alphabet)).collect();
+            let edits = shortest_edit(&a, &b, |x, y| x == y);
+            let (mut i, mut j) = (0, 0);
+            for edit in &edits {
+                match *edit {
+                    Edit::Equal(x, y) => {
+                        assert_eq!((x, y), (i, j));
+                        assert_eq!(a[x], b[y]);
+                        (i, j) = (i + 1, j + 1);
+                    }
+                    Edit::Delete(x) => {
+                        assert_eq!(x, i);
+                        i += 1;
+                    }
+                    Edit::Insert(y) => {
+                        assert_eq!(y, j);
+                        j += 1;
+                    }
+                }
+            }
+            assert_eq!((i, j), (a.len(), b.len()));
+            let equal = edits
+                .iter()
+                .filter(|edit| matches!(edit, Edit::Equal(..)))
+                .count();
+            assert_eq!(equal, lcs_length(&a, &b), "{a:?} {b:?}");
+        }
+    }
+
+    #[rstest]
+    fn diff_unrelated_code_in_linear_space() {
+        // 5000 operations without any common instruction, storing every round of the search
+        // would take hundreds of megabytes
+        let old = disassemble_str(&"30".repeat(5000)).unwrap();
+        let new = disassemble_str(&"33".repeat(5000)).unwrap();
+        let entries = diff_operations(&old, &new, &Default::default());
+        assert_eq!(entries.len(), 10000);
+        assert!(entries.iter().all(|entry| entry.is_change()));
+    }
+
+    #[rstest]
+    fn format_side_by_side_with_context() {
+        let entries = diff_str(
+            "6001600260036004600560066007",
+            "6001600260036004610005600660076008",
+            &Default::default(),
+        );
+        let formatted = format_diff(&entries, 1).unwrap();
+        let expected = "\
+...
+00000006: PUSH1 0x4   00000006: PUSH1 0x4
+00000008: PUSH1 0x5 <
+                    > 00000008: PUSH2 0x5
+0000000a: PUSH1 0x6   0000000b: PUSH1 0x6
+0000000c: PUSH1 0x7   0000000d: PUSH1 0x7
+                    > 0000000f: PUSH1 0x8
+";
+        assert_eq!(formatted, expected);
+    }
+}
@@ -0,0 +1,290 @@
+//! Splitting EOF containers into their sections
+//!
+//! An EOF container (EIP-3540) starts with `0xef00` and a version byte, followed by a header
+//! listing the sizes of the type, code, subcontainer and data sections, and the sections
+//! themselves. `disassemble_bytes` decodes a whole container including its header as
+//! instructions; [`EofContainer`] instead locates each code section so it can be decoded on its
+//! own, with offsets still relative to the start of the container.
+use crate::decode::decode_operations;
+use crate::error::{eyre, Result};
+use crate::types::Operation;
+use alloc::vec::Vec;
+
+/// The prefix identifying an EOF container
+pub const MAGIC: [u8; 2] = [0xef, 0x00];
+
+const KIND_TYPES: u8 = 0x01;
+const KIND_CODE: u8 = 0x02;
+const KIND_CONTAINER: u8 = 0x03;
+const KIND_DATA: u8 = 0x04;
+const TERMINATOR: u8 = 0x00;
+
+/// The signature of a code section from the type section
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CodeType {
+    /// The number of stack inputs
+    pub inputs: u8,
+    /// The number of stack outputs, `0x80` for sections that never return
+    pub outputs: u8,
+    /// The maximum stack height reached by the section
+    pub max_stack_height: u16,
+}
+
+/// A section of an EOF container
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EofSection {
+    /// The offset of the section in the container
+    pub offset: u32,
+    /// The content of the section
+    pub bytes: Vec<u8>,
+}
+
+/// An EOF container split into its sections
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EofContainer {
+    /// The EOF version
+    pub version: u8,
+    /// The size of the header in bytes
+    pub header_size: u32,
+    /// The signature of each code section
+    pub types: Vec<CodeType>,
+    /// The code sections, the first one is the entry point
+    pub code: Vec<EofSection>,
+    /// The subcontainers used by `EOFCREATE` and `RETURNCONTRACT`, which are EOF containers themselves
+    pub containers: Vec<EofSection>,
+    /// The data section as present in the container
+    pub data: EofSection,
+    /// The data size declared in the header, larger than the data section of initcode whose data
+    /// is appended on deployment
+    pub data_size: u16,
+}
+
+// Reads big endian integers from the header
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, size: usize) -> Result<&[u8]> {
+        let bytes = self
+            .position
+            .checked_add(size)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(|| {
+                eyre!(
+                    "EOF container truncated at byte {}, expected {size} more bytes",
+                    self.position
+                )
+            })?;
+        self.position += size;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes([
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+        ]))
+    }
+
+    fn expect(&mut self, kind: u8, name: &str) -> Result<()> {
+        let position = self.position;
+        match self.u8()? {
+            byte if byte == kind => Ok(()),
+            byte => Err(eyre!(
+                "Expected the {name} kind {kind:#04x} at byte {position}, found {byte:#04x}"
+            )),
+        }
+    }
+
+    fn section(&mut self, size: usize) -> Result<EofSection> {
+        let offset = self.position as u32;
+        Ok(EofSection {
+            offset,
+            bytes: self.take(size)?.to_vec(),
+        })
+    }
+}
+
+impl EofContainer {
+    /// Parses the header of an EOF container and splits the body into its sections
+    ///
+    /// The header has to be complete and consistent with the body, only the data section may be
+    /// shorter than declared. Sections are not validated further.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use evm_disassembler::{eof::EofContainer, Opcode};
+    ///
+    /// // One code section with RJUMP 0 STOP and 2 bytes of data
+    /// let bytes = hex::decode("ef000101000402000100040400020000800000e0000000aabb").unwrap();
+    /// let container = EofContainer::parse(&bytes).unwrap();
+    /// assert_eq!(container.code[0].offset, 19);
+    /// assert_eq!(container.data.bytes, vec![0xaa, 0xbb]);
+    /// let operations = container.code_operations();
+    /// assert_eq!(operations[0][0].opcode, Opcode::RJUMP);
+    /// assert_eq!(operations[0][1].offset, 22);
+    /// ```
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(&MAGIC) {
+            return Err(eyre!("Not an EOF container, expected the prefix 0xef00"));
+        }
+        let mut reader = Reader { bytes, position: 2 };
+        let version = reader.u8()?;
+        reader.expect(KIND_TYPES, "type section")?;
+        let types_size = reader.u16()? as usize;
+        reader.expect(KIND_CODE, "code section")?;
+        let code_sizes = (0..reader.u16()?)
+            .map(|_| reader.u16())
+            .collect::<Result<Vec<_>>>()?;
+        let mut container_sizes = Vec::new();
+        if bytes.get(reader.position) == Some(&KIND_CONTAINER) {
+            reader.u8()?;
+            container_sizes = (0..reader.u16()?)
+                .map(|_| reader.u32())
+                .collect::<Result<Vec<_>>>()?;
+        }
+        reader.expect(KIND_DATA, "data section")?;
+        let data_size = reader.u16()?;
+        reader.expect(TERMINATOR, "header terminator")?;
+        let header_size = reader.position as u32;
+
+        if types_size != code_sizes.len() * 4 {
+            return Err(eyre!(
+                "Type section of {types_size} bytes does not match {} code sections",
+                code_sizes.len()
+            ));
+        }
+        let types = reader
+            .take(types_size)?
+            .chunks(4)
+            .map(|chunk| CodeType {
+                inputs: chunk[0],
+                outputs: chunk[1],
+                max_stack_height: u16::from_be_bytes([chunk[2], chunk[3]]),
+            })
+            .collect();
+        let code = code_sizes
+            .iter()
+            .map(|size| reader.section(*size as usize))
+            .collect::<Result<_>>()?;
+        let containers = container_sizes
+            .iter()
+            .map(|size| reader.section(*size as usize))
+            .collect::<Result<_>>()?;
+        let data = reader.section(bytes.len() - reader.position)?;
+        Ok(EofContainer {
+            version,
+            header_size,
+            types,
+            code,
+            containers,
+            data,
+            data_size,
+        })
+    }
+
+    /// Decodes each code section with EOF opcodes, offsets are relative to the container
+    pub fn code_operations(&self) -> Vec<Vec<Operation>> {
+        self.code
+            .iter()
+            .map(|section| decode_operations(section.bytes.clone(), section.offset, true))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Opcode;
+    use rstest::*;
+
+    // Compiled from Solidity with --evm-version osaka --eofVersion 1, see the EOF tests in lib.rs
+    const SOLC_EOF: &str = "ef000101009c020027004b0004000400030003000b00010006000d001c00020001000b00050007001c000c000800120003001b001c001d0003000200030007000500090003000f0001000a0001000f00050013001300080400430000800003010100020001000100800002008000020200000301010001020000020201000500800004020100020101000102010003020100020001000200800004010000020201000202010005010100020080000302020005008000040080000200010001010100020101000101010001000100010101000202010004010100010101000101010001020000030100000200800002020100030201000360806040526004361015e10003e500175f35e3000180632096525514e1002980633fa4f24514e1001c80635524107714e1000f63771602f714e10003e0ffcee50016e50014e5000fe5000960e01ce4604051e45f80fd5f80fd5f910312e10001e4e50004e4e300069052e4905f60208301920190e30007e434e10015366004e30005e3001ce30002809181e300080390f3e500031ce4e490600802e3000ae3000be454e3000ce45f5f90e3000de434e10015366004e30005e3000ee30002809181e300080390f3e5000380e3000603e10001e45f80fd90503580e30010e4602081830312e100065f01e30011e4e500045f01e434e10014366004e30012e30023e300028080e300130390f3e5000390604082820312e1000f805f8301e3001191602001e30011e4e5000434e10016366004e3001590e30026e30002809181e300080390f3e500035f80fd5fe45f1ce4e30019e3000be454e3001ae4e30018505fe3001be45f1be4905f1990e3001d91811916911617e4e4e30006e3001fe30006e4e490e30020e300218154e3001e9055e45fe30022e4634e487b7160e01b5f52601160045260245ffde3000690e300068101809111e10001e4e50024e3001850e30025e4";
+
+    #[rstest]
+    fn split_solc_container() {
+        let bytes = hex::decode(SOLC_EOF).unwrap();
+        let container = EofContainer::parse(&bytes).unwrap();
+        assert_eq!(container.version, 1);
+        assert_eq!(container.header_size, 91);
+        assert_eq!(container.code.len(), 39);
+        assert_eq!(
+            container.types[..2],
+            [
+                CodeType {
+                    inputs: 0,
+                    outputs: 0x80,
+                    max_stack_height: 3
+                },
+                CodeType {
+                    inputs: 1,
+                    outputs: 1,
+                    max_stack_height: 2
+                }
+            ]
+        );
+        assert_eq!(container.code[0].offset, 91 + 156);
+        assert_eq!(container.code[0].bytes.len(), 75);
+        assert!(container.containers.is_empty());
+        // Data of deployed code is appended later
+        assert_eq!(container.data_size, 0x43);
+        assert!(container.data.bytes.is_empty());
+
+        let operations = container.code_operations();
+        assert_eq!(operations[0][0].opcode, Opcode::PUSH1);
+        assert_eq!(operations[0][0].offset, 247);
+        assert!(operations
+            .iter()
+            .flatten()
+            .any(|operation| operation.opcode == Opcode::CALLF));
+    }
+
+    #[rstest]
+    fn split_subcontainers() {
+        // STOP as the only code section
+        let inner = "ef000101000402000100010400000000800000".to_string() + "00";
+        // PUSH0 PUSH0 PUSH0 PUSH0 EOFCREATE 0 STOP with `inner` as subcontainer and no data
+        let outer =
+            format!("ef000101000402000100070300010000001404000000008000045f5f5f5fec0000{inner}");
+        let bytes = hex::decode(outer).unwrap();
+        let container = EofContainer::parse(&bytes).unwrap();
+        assert_eq!(container.containers.len(), 1);
+        assert_eq!(container.containers[0].bytes, hex::decode(&inner).unwrap());
+        let nested = EofContainer::parse(&container.containers[0].bytes).unwrap();
+        assert_eq!(nested.code[0].bytes, vec![0x00]);
+        assert!(container.data.bytes.is_empty());
+    }
+
+    #[rstest]
+    #[case("6080604052")]
+    #[case("ef0001")]
+    #[case("ef00010100040200010001ff00000000800000")]
+    #[case("ef000101000802000100010400000000800000")]
+    #[case("ef000101000402000100050400000000800000")]
+    fn reject_invalid_containers(#[case] code: &str) {
+        assert!(EofContainer::parse(&hex::decode(code).unwrap()).is_err());
+    }
+
+    #[rstest]
+    fn reject_sizes_beyond_the_address_space() {
+        let mut reader = Reader {
+            bytes: &[0xef, 0x00],
+            position: 1,
+        };
+        assert!(reader.take(usize::MAX).is_err());
+        assert_eq!(reader.position, 1);
+    }
+}
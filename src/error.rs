@@ -0,0 +1,64 @@
+//! Error type of the decoding functions
+//!
+//! With the `std` feature errors are [`eyre::Report`]s, without it a minimal message type that
+//! only needs `alloc`.
+#[cfg(feature = "std")]
+pub(crate) use eyre::eyre;
+#[cfg(feature = "std")]
+pub use eyre::{Report as Error, Result};
+
+#[cfg(not(feature = "std"))]
+use alloc::string::{String, ToString};
+#[cfg(not(feature = "std"))]
+use core::fmt;
+
+/// An error message
+#[cfg(not(feature = "std"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error(String);
+
+#[cfg(not(feature = "std"))]
+impl Error {
+    /// Creates an error from a message
+    pub fn msg(message: impl fmt::Display) -> Self {
+        Error(message.to_string())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::error::Error for Error {}
+
+#[cfg(not(feature = "std"))]
+impl From<hex::FromHexError> for Error {
+    fn from(error: hex::FromHexError) -> Self {
+        Error::msg(error)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl From<fmt::Error> for Error {
+    fn from(error: fmt::Error) -> Self {
+        Error::msg(error)
+    }
+}
+
+/// Result type of the decoding functions
+#[cfg(not(feature = "std"))]
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+// Formats an error message like `eyre::eyre!`
+#[cfg(not(feature = "std"))]
+macro_rules! eyre {
+    ($($arg:tt)*) => {
+        $crate::error::Error::msg(alloc::format!($($arg)*))
+    };
+}
+#[cfg(not(feature = "std"))]
+pub(crate) use eyre;
@@ -0,0 +1,585 @@
+//! Heuristic detection of the compiler that produced a contract
+//!
+//! The metadata trailer identifies solc and Vyper and their exact version when present. Without
+//! it, the shape of the code is used instead: solc starts by initializing the free memory pointer,
+//! Vyper by checking the call data size and Huff contracts commonly extract the selector right
+//! away. Each piece of evidence adds to the score of a compiler, scores are normalized into
+//! confidences.
+use crate::disassemble_bytes;
+use crate::metadata::{decode_cbor, metadata_entries, split_metadata, Cbor};
+use crate::types::{Opcode, Operation};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+// Selector of `Panic(uint256)`, used by solc >= 0.8.0 for failing checks
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+// Base score of unknown compilers and hand written code
+const UNKNOWN_SCORE: f64 = 0.5;
+
+/// A compiler producing EVM bytecode
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Compiler {
+    /// The Solidity compiler
+    Solc,
+    /// The Vyper compiler
+    Vyper,
+    /// The Huff compiler
+    Huff,
+    /// Any other compiler or hand written code
+    Unknown,
+}
+
+impl fmt::Display for Compiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{self:?}").to_lowercase())
+    }
+}
+
+/// A compiler version
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    /// The major version
+    pub major: u64,
+    /// The minor version
+    pub minor: u64,
+    /// The patch version
+    pub patch: u64,
+}
+
+impl Version {
+    /// Creates a version from its components
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    // Parses `<major>.<minor>.<patch>` ignoring prerelease and build suffixes
+    fn parse(version: &str) -> Option<Self> {
+        let version = version.split(['-', '+']).next()?;
+        let mut parts = version.split('.').map(|part| part.parse().ok());
+        let version = Version::new(parts.next()??, parts.next()??, parts.next()??);
+        parts.next().is_none().then_some(version)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// A range of compiler versions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VersionRange {
+    /// The lowest possible version, inclusive
+    pub min: Option<Version>,
+    /// The first version that is no longer possible, exclusive
+    pub max: Option<Version>,
+}
+
+impl VersionRange {
+    /// The range containing only the given version
+    pub fn exact(version: Version) -> Self {
+        VersionRange {
+            min: Some(version),
+            max: Some(Version::new(
+                version.major,
+                version.minor,
+                version.patch + 1,
+            )),
+        }
+    }
+
+    /// The single version in the range, if it is exact
+    pub fn as_exact(&self) -> Option<Version> {
+        let (min, max) = (self.min?, self.max?);
+        (VersionRange::exact(min).max == Some(max)).then_some(min)
+    }
+
+    /// Whether the range contains the given version
+    pub fn contains(&self, version: Version) -> bool {
+        self.min.is_none_or(|min| min <= version) && self.max.is_none_or(|max| version < max)
+    }
+
+    // Narrows the range to the versions also in `other`
+    fn intersect(&self, other: &VersionRange) -> VersionRange {
+        VersionRange {
+            min: self.min.max(other.min),
+            max: match (self.max, other.max) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        matches!((self.min, self.max), (Some(min), Some(max)) if min >= max)
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(version) = self.as_exact() {
+            return write!(f, "{version}");
+        }
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => write!(f, ">={min} <{max}"),
+            (Some(min), None) => write!(f, ">={min}"),
+            (None, Some(max)) => write!(f, "<{max}"),
+            (None, None) => write!(f, "*"),
+        }
+    }
+}
+
+/// A compiler with the confidence that it produced the code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Candidate {
+    /// The compiler
+    pub compiler: Compiler,
+    /// The confidence between 0 and 1, the confidences of all candidates add up to 1
+    pub confidence: f64,
+}
+
+/// Whether the optimizer was likely enabled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OptimizerGuess {
+    /// Whether the optimizer was enabled
+    pub enabled: bool,
+    /// The confidence between 0 and 1
+    pub confidence: f64,
+}
+
+/// The likely compiler and settings of a contract
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fingerprint {
+    /// The candidate compilers, most likely first
+    pub candidates: Vec<Candidate>,
+    /// The possible versions of the most likely compiler, if any evidence constrains them
+    pub version: Option<VersionRange>,
+    /// Whether the optimizer was enabled, if any evidence was found
+    pub optimizer: Option<OptimizerGuess>,
+    /// Human readable descriptions of the evidence found
+    pub evidence: Vec<String>,
+}
+
+impl Fingerprint {
+    /// The most likely compiler
+    pub fn compiler(&self) -> Compiler {
+        self.candidates[0].compiler
+    }
+}
+
+// Accumulates the scores and version constraints while inspecting the code
+#[derive(Default)]
+struct Evidence {
+    scores: BTreeMap<Compiler, f64>,
+    versions: BTreeMap<Compiler, VersionRange>,
+    exact: BTreeMap<Compiler, Version>,
+    descriptions: Vec<String>,
+}
+
+impl Evidence {
+    fn add(&mut self, compiler: Compiler, score: f64, description: String) {
+        *self.scores.entry(compiler).or_default() += score;
+        self.descriptions.push(description);
+    }
+
+    fn constrain(&mut self, compiler: Compiler, range: VersionRange) {
+        let current = self.versions.entry(compiler).or_default();
+        *current = current.intersect(&range);
+    }
+}
+
+/// Guesses the compiler, version range and optimizer settings that produced the runtime code
+///
+/// Metadata trailers are decisive, any other evidence only shifts the confidences. Versions are
+/// exact when the trailer contains them and otherwise narrowed by features of the code, e.g. the
+/// `Panic(uint256)` error introduced in solc 0.8.0. The optimizer guess is only available for
+/// solc.
+///
+/// # Arguments
+/// - `bytes` - The runtime code
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::fingerprint::{fingerprint, Compiler};
+///
+/// // PUSH1 0x80 PUSH1 0x40 MSTORE ... with a metadata trailer for solc 0.8.14
+/// let bytes = hex::decode("6080604052600080fda164736f6c634300080e000a").unwrap();
+/// let fingerprint = fingerprint(&bytes);
+/// assert_eq!(fingerprint.compiler(), Compiler::Solc);
+/// assert_eq!(fingerprint.version.unwrap().to_string(), "0.8.14");
+/// ```
+pub fn fingerprint(bytes: &[u8]) -> Fingerprint {
+    let mut evidence = Evidence::default();
+    let (code, metadata) = split_metadata(bytes);
+    if let Some(metadata) = metadata {
+        inspect_metadata(&metadata[..metadata.len() - 2], &mut evidence);
+    }
+    let operations = disassemble_bytes(code.to_vec()).unwrap_or_default();
+    inspect_code(&operations, metadata.is_some(), &mut evidence);
+
+    let total: f64 = UNKNOWN_SCORE + evidence.scores.values().sum::<f64>();
+    let mut candidates: Vec<Candidate> = [Compiler::Solc, Compiler::Vyper, Compiler::Huff]
+        .into_iter()
+        .map(|compiler| Candidate {
+            compiler,
+            confidence: evidence.scores.get(&compiler).copied().unwrap_or(0.0) / total,
+        })
+        .chain(std::iter::once(Candidate {
+            compiler: Compiler::Unknown,
+            confidence: UNKNOWN_SCORE / total,
+        }))
+        .collect();
+    candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    let compiler = candidates[0].compiler;
+    let version = match evidence.exact.get(&compiler) {
+        Some(version) => Some(VersionRange::exact(*version)),
+        None => evidence
+            .versions
+            .get(&compiler)
+            .filter(|range| !range.is_empty())
+            .copied(),
+    };
+    let optimizer = (compiler == Compiler::Solc)
+        .then(|| guess_optimizer(&operations))
+        .flatten();
+    Fingerprint {
+        candidates,
+        version,
+        optimizer,
+        evidence: evidence.descriptions,
+    }
+}
+
+fn inspect_metadata(cbor: &[u8], evidence: &mut Evidence) {
+    let Some(value) = decode_cbor(cbor, &mut 0, 0) else {
+        return;
+    };
+    let mut entries = BTreeMap::new();
+    metadata_entries(&value, &mut entries);
+    let solc = match entries.get("solc") {
+        Some(Cbor::Bytes(version)) if version.len() == 3 => Some(Version::new(
+            version[0] as u64,
+            version[1] as u64,
+            version[2] as u64,
+        )),
+        Some(Cbor::Text(version)) => Version::parse(version),
+        _ => None,
+    };
+    if let Some(version) = solc {
+        evidence.exact.insert(Compiler::Solc, version);
+        evidence.add(
+            Compiler::Solc,
+            8.0,
+            format!("metadata trailer with solc version {version}"),
+        );
+    } else if entries.contains_key("bzzr0") || entries.contains_key("bzzr1") {
+        // The compiler version is part of the trailer since solc 0.5.9
+        evidence.constrain(
+            Compiler::Solc,
+            VersionRange {
+                min: Some(Version::new(0, 4, 7)),
+                max: Some(Version::new(0, 5, 9)),
+            },
+        );
+        evidence.add(
+            Compiler::Solc,
+            6.0,
+            "metadata trailer with swarm hash and without compiler version".to_string(),
+        );
+    }
+    if let Some(Cbor::Array(version)) = entries.get("vyper") {
+        let parts: Vec<u64> = version
+            .iter()
+            .filter_map(|part| match part {
+                Cbor::Uint(part) => Some(*part),
+                _ => None,
+            })
+            .collect();
+        if let [major, minor, patch] = parts[..] {
+            let version = Version::new(major, minor, patch);
+            evidence.exact.insert(Compiler::Vyper, version);
+            evidence.add(
+                Compiler::Vyper,
+                8.0,
+                format!("metadata trailer with vyper version {version}"),
+            );
+        }
+    }
+}
+
+fn inspect_code(operations: &[Operation], has_metadata: bool, evidence: &mut Evidence) {
+    let opcodes: Vec<Opcode> = operations.iter().map(|op| op.opcode).collect();
+    let push = |i: usize| {
+        operations
+            .get(i)
+            .filter(|op| op.opcode.is_push())
+            .map(|op| op.input.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+    };
+    let free_memory_pointer = opcodes.get(2) == Some(&Opcode::MSTORE) && push(1) == Some(0x40);
+    match push(0) {
+        Some(0x80) if free_memory_pointer => {
+            evidence.constrain(
+                Compiler::Solc,
+                VersionRange {
+                    min: Some(Version::new(0, 4, 22)),
+                    max: None,
+                },
+            );
+            evidence.add(
+                Compiler::Solc,
+                3.0,
+                "free memory pointer initialized to 0x80".to_string(),
+            );
+        }
+        Some(0x60) if free_memory_pointer => {
+            evidence.constrain(
+                Compiler::Solc,
+                VersionRange {
+                    min: None,
+                    max: Some(Version::new(0, 4, 22)),
+                },
+            );
+            evidence.add(
+                Compiler::Solc,
+                3.0,
+                "free memory pointer initialized to 0x60".to_string(),
+            );
+        }
+        _ => {}
+    }
+
+    // Vyper checks for a selector before dispatching: PUSH1 <3 or 4> CALLDATASIZE LT/GT
+    let size_check = matches!(push(0), Some(3..=4))
+        && opcodes.get(1) == Some(&Opcode::CALLDATASIZE)
+        && matches!(opcodes.get(2), Some(Opcode::LT | Opcode::GT));
+    if size_check {
+        evidence.add(
+            Compiler::Vyper,
+            2.0,
+            "starts by comparing the call data size with the selector size".to_string(),
+        );
+    }
+    // Huff contracts commonly start with the dispatcher: PUSH0 CALLDATALOAD PUSH1 0xe0 SHR
+    let selector_first = push(0) == Some(0)
+        && opcodes.get(1) == Some(&Opcode::CALLDATALOAD)
+        && push(2) == Some(0xe0)
+        && opcodes.get(3) == Some(&Opcode::SHR);
+    if selector_first {
+        evidence.add(
+            Compiler::Huff,
+            2.0,
+            "starts by extracting the selector".to_string(),
+        );
+    }
+    if !free_memory_pointer && !has_metadata && !operations.is_empty() {
+        evidence.add(
+            Compiler::Huff,
+            1.0,
+            "neither free memory pointer nor metadata trailer".to_string(),
+        );
+        *evidence.scores.entry(Compiler::Vyper).or_default() += 1.0;
+    }
+
+    let panics = operations
+        .iter()
+        .any(|op| op.opcode == Opcode::PUSH4 && op.input == PANIC_SELECTOR);
+    if panics {
+        evidence.constrain(
+            Compiler::Solc,
+            VersionRange {
+                min: Some(Version::new(0, 8, 0)),
+                max: None,
+            },
+        );
+        evidence.add(
+            Compiler::Solc,
+            1.0,
+            "reverts with Panic(uint256)".to_string(),
+        );
+    }
+    if opcodes.contains(&Opcode::PUSH0) {
+        // PUSH0 is emitted since solc 0.8.20 targeting Shanghai
+        evidence.constrain(
+            Compiler::Solc,
+            VersionRange {
+                min: Some(Version::new(0, 8, 20)),
+                max: None,
+            },
+        );
+        evidence.descriptions.push("uses PUSH0".to_string());
+    }
+}
+
+// Looks for code patterns that only occur with or without the optimizer
+fn guess_optimizer(operations: &[Operation]) -> Option<OptimizerGuess> {
+    let value = |op: &Operation| op.input.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+    let is_push = |op: &Operation, expected: u64| op.opcode.is_push() && value(op) == expected;
+    // The constant optimizer computes the address mask instead of pushing 20 bytes:
+    // PUSH1 0x1 PUSH1 0xa0 PUSH1 0x2 EXP SUB or PUSH1 0x1 PUSH1 0x1 PUSH1 0xa0 SHL SUB
+    let computed_mask = operations.windows(5).any(|w| {
+        let exp = is_push(&w[0], 1)
+            && is_push(&w[1], 0xa0)
+            && is_push(&w[2], 2)
+            && w[3].opcode == Opcode::EXP
+            && w[4].opcode == Opcode::SUB;
+        let shl = is_push(&w[0], 1)
+            && is_push(&w[1], 1)
+            && is_push(&w[2], 0xa0)
+            && w[3].opcode == Opcode::SHL
+            && w[4].opcode == Opcode::SUB;
+        exp || shl
+    });
+    if computed_mask {
+        return Some(OptimizerGuess {
+            enabled: true,
+            confidence: 0.8,
+        });
+    }
+    // Jumps to the directly following instruction are removed by the peephole optimizer
+    let jumps_to_next = operations.windows(3).any(|w| {
+        w[0].opcode.is_push()
+            && w[1].opcode == Opcode::JUMP
+            && w[2].opcode == Opcode::JUMPDEST
+            && value(&w[0]) == w[2].offset as u64
+    });
+    jumps_to_next.then_some(OptimizerGuess {
+        enabled: false,
+        confidence: 0.7,
+    })
+}
+
+/// Formats the fingerprint of the runtime code
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::fingerprint::format_fingerprint;
+///
+/// // PUSH0 CALLDATALOAD PUSH1 0xe0 SHR
+/// let bytes = hex::decode("5f3560e01c").unwrap();
+/// assert!(format_fingerprint(&bytes).unwrap().starts_with("compiler: huff (0.6"));
+/// ```
+pub fn format_fingerprint(bytes: &[u8]) -> eyre::Result<String> {
+    let fingerprint = fingerprint(bytes);
+    let candidates: Vec<String> = fingerprint
+        .candidates
+        .iter()
+        .map(|candidate| format!("{} ({:.2})", candidate.compiler, candidate.confidence))
+        .collect();
+    let mut formatted = String::new();
+    writeln!(formatted, "compiler: {}", candidates.join(", "))?;
+    if let Some(version) = fingerprint.version {
+        writeln!(formatted, "version: {version}")?;
+    }
+    if let Some(optimizer) = fingerprint.optimizer {
+        let enabled = if optimizer.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        };
+        writeln!(
+            formatted,
+            "optimizer: {enabled} ({:.2})",
+            optimizer.confidence
+        )?;
+    }
+    for evidence in &fingerprint.evidence {
+        writeln!(formatted, "- {evidence}")?;
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use std::fs;
+
+    fn read_testdata(address: &str) -> Vec<u8> {
+        let code = fs::read_to_string(format!("testdata/{address}_encoded.txt"))
+            .expect("Unable to read encoded file");
+        hex::decode(code.trim().trim_start_matches("0x")).unwrap()
+    }
+
+    #[rstest]
+    // Seaport, solc 0.8.14 with optimizer
+    #[case("0x00000000006c3852cbEf3e08E8dF289169EdE581", "0.8.14", Some(true))]
+    // WETH, solc 0.4.x with swarm hash and 0x60 free memory pointer
+    #[case("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", ">=0.4.7 <0.4.22", None)]
+    // Uniswap V3 router, solc 0.7.6 without metadata hash
+    #[case("0xE592427A0AEce92De3Edee1F18E0157C05861564", "0.7.6", None)]
+    fn fingerprint_solc_contracts(
+        #[case] address: &str,
+        #[case] version: &str,
+        #[case] optimized: Option<bool>,
+    ) {
+        let fingerprint = fingerprint(&read_testdata(address));
+        assert_eq!(fingerprint.compiler(), Compiler::Solc);
+        assert!(fingerprint.candidates[0].confidence > 0.9);
+        assert_eq!(fingerprint.version.unwrap().to_string(), version);
+        assert_eq!(
+            fingerprint.optimizer.map(|optimizer| optimizer.enabled),
+            optimized
+        );
+    }
+
+    #[rstest]
+    // PUSH1 0x3 CALLDATASIZE GT PUSH2 0x000c JUMPI with the trailer {"vyper": [0, 3, 7]}
+    #[case(
+        "6003361161000c57a165767970657283000307000b",
+        Compiler::Vyper,
+        Some("0.3.7")
+    )]
+    // PUSH1 0x4 CALLDATASIZE LT ISZERO PUSH2 0x000d JUMPI
+    #[case("600436101561000d57", Compiler::Vyper, None)]
+    // PUSH0 CALLDATALOAD PUSH1 0xe0 SHR DUP1 PUSH4 0xa9059cbb EQ
+    #[case("5f3560e01c8063a9059cbb14", Compiler::Huff, None)]
+    #[case("", Compiler::Unknown, None)]
+    fn fingerprint_other_compilers(
+        #[case] code: &str,
+        #[case] compiler: Compiler,
+        #[case] version: Option<&str>,
+    ) {
+        let fingerprint = fingerprint(&hex::decode(code).unwrap());
+        assert_eq!(fingerprint.compiler(), compiler);
+        assert_eq!(
+            fingerprint
+                .version
+                .map(|version| version.to_string())
+                .as_deref(),
+            version
+        );
+        let total: f64 = fingerprint.candidates.iter().map(|c| c.confidence).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+
+    #[rstest]
+    fn reject_deeply_nested_metadata() {
+        // STOP followed by a trailer of 60000 nested single element arrays
+        let mut code = vec![0x00];
+        code.extend([0x81; 60000]);
+        code.extend(60000u16.to_be_bytes());
+        let fingerprint = fingerprint(&code);
+        assert_eq!(fingerprint.version, None);
+    }
+
+    #[rstest]
+    fn detect_unoptimized_jump_to_next() {
+        // PUSH1 0x80 PUSH1 0x40 MSTORE PUSH1 0x8 JUMP JUMPDEST STOP
+        let operations = crate::disassemble_str("60806040526008565b00").unwrap();
+        assert_eq!(
+            guess_optimizer(&operations),
+            Some(OptimizerGuess {
+                enabled: false,
+                confidence: 0.7
+            })
+        );
+    }
+}
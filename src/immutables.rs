@@ -0,0 +1,251 @@
+//! Detection and masking of Solidity immutable variables
+//!
+//! Immutable values are written into the runtime bytecode by the constructor, so two deployments
+//! of the same source generally differ in these bytes. Masking them yields a normalized
+//! disassembly that can be compared across deployments.
+use crate::{disassemble_bytes, Operation};
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// Number of leading runtime bytes used to find candidate template positions in the creation code
+const ANCHOR_LENGTH: usize = 8;
+
+/// A region of the runtime bytecode that is filled in by the constructor
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub struct ImmutableReference {
+    /// Byte offset of the value in the runtime bytecode
+    pub start: u32,
+    /// Length of the value in bytes
+    pub length: u32,
+}
+
+impl ImmutableReference {
+    // References ending beyond the address space are cut off instead of wrapping around
+    fn end(&self) -> u32 {
+        self.start.saturating_add(self.length)
+    }
+}
+
+/// Parses the `immutableReferences` object of a Solidity artifact
+///
+/// The object maps AST ids of immutable variables to the list of places they are referenced in
+/// the runtime bytecode. The returned references are flattened and sorted by their start offset.
+/// References ending beyond `u32::MAX` are rejected.
+///
+/// # Arguments
+/// - `json` - The JSON encoded `immutableReferences` object
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::immutables::parse_immutable_references;
+///
+/// let json = r#"{"3": [{"start": 12, "length": 32}, {"start": 80, "length": 32}]}"#;
+/// let references = parse_immutable_references(json).unwrap();
+/// assert_eq!(references.len(), 2);
+/// ```
+pub fn parse_immutable_references(json: &str) -> Result<Vec<ImmutableReference>> {
+    let by_id: HashMap<String, Vec<ImmutableReference>> = serde_json::from_str(json)?;
+    let mut references: Vec<ImmutableReference> = by_id.into_values().flatten().collect();
+    if let Some(reference) = references
+        .iter()
+        .find(|reference| reference.start.checked_add(reference.length).is_none())
+    {
+        return Err(eyre!(
+            "Immutable reference {}:{} ends beyond the maximum offset",
+            reference.start,
+            reference.length
+        ));
+    }
+    references.sort();
+    Ok(references)
+}
+
+/// Identifies immutable values in deployed runtime code given the creation code
+///
+/// Solidity embeds the runtime code in the creation code as a template with all immutable values
+/// set to zero. Any PUSH immediate that is zero in the template but not in the deployed code has
+/// been filled in by the constructor. Immutables that were assigned zero cannot be detected this
+/// way.
+///
+/// # Arguments
+/// - `creation` - The creation bytecode (including constructor arguments if present)
+/// - `runtime` - The deployed runtime bytecode
+pub fn find_immutables(creation: &[u8], runtime: &[u8]) -> Result<Vec<ImmutableReference>> {
+    let template = find_template(creation, runtime)
+        .ok_or_else(|| eyre!("Runtime code template not found in creation code"))?;
+    let operations = disassemble_bytes(runtime.to_vec())?;
+    Ok(operations
+        .iter()
+        .filter(|operation| !operation.input.is_empty())
+        .filter_map(|operation| {
+            let start = operation.offset as usize + 1;
+            let range = start..start + operation.input.len();
+            let filled = template[range.clone()] != runtime[range.clone()];
+            let zero_in_template = template[range].iter().all(|byte| *byte == 0);
+            (filled && zero_in_template).then_some(ImmutableReference {
+                start: start as u32,
+                length: operation.input.len() as u32,
+            })
+        })
+        .collect())
+}
+
+/// Sets the immediate bytes of all operations covered by the given references to zero
+///
+/// # Arguments
+/// - `operations` - The decoded operations of the runtime bytecode
+/// - `references` - The immutable references as returned by `find_immutables` or
+///   `parse_immutable_references`
+pub fn mask_immutables(
+    operations: &[Operation],
+    references: &[ImmutableReference],
+) -> Vec<Operation> {
+    operations
+        .iter()
+        .map(|operation| {
+            let mut masked = operation.clone();
+            let input_start = operation.offset as usize + 1;
+            let input_end = input_start + masked.input.len();
+            for reference in references {
+                let start = (reference.start as usize).max(input_start);
+                let end = (reference.end() as usize).min(input_end);
+                for position in start..end {
+                    masked.input[position - input_start] = 0;
+                }
+            }
+            masked
+        })
+        .collect()
+}
+
+/// Sets all bytes covered by the given references to zero
+///
+/// Applying this to deployed code restores the template that is embedded in the creation code.
+pub fn mask_immutables_bytes(bytes: &[u8], references: &[ImmutableReference]) -> Vec<u8> {
+    let mut masked = bytes.to_vec();
+    for reference in references {
+        let start = (reference.start as usize).min(masked.len());
+        let end = (reference.end() as usize).min(masked.len());
+        masked[start..end].iter_mut().for_each(|byte| *byte = 0);
+    }
+    masked
+}
+
+// Locates the zeroed runtime template inside the creation code
+fn find_template<'a>(creation: &'a [u8], runtime: &[u8]) -> Option<&'a [u8]> {
+    if runtime.is_empty() || runtime.len() > creation.len() {
+        return None;
+    }
+    let anchor = &runtime[..runtime.len().min(ANCHOR_LENGTH)];
+    (0..=creation.len() - runtime.len())
+        .map(|start| &creation[start..start + runtime.len()])
+        .filter(|candidate| candidate.starts_with(anchor))
+        .find(|candidate| {
+            candidate
+                .iter()
+                .zip(runtime.iter())
+                .all(|(template_byte, runtime_byte)| {
+                    template_byte == runtime_byte || *template_byte == 0
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format_operations;
+    use rstest::*;
+
+    const VALUE: &str = "00000000000000000000000000000000000000000000000000000000deadbeef";
+
+    // Runtime code reading an immutable: PUSH32 <value> PUSH1 0x0 SSTORE STOP
+    fn runtime(value: &str) -> Vec<u8> {
+        hex::decode(format!("6080604052347f{value}60005500")).unwrap()
+    }
+
+    fn creation() -> Vec<u8> {
+        // Constructor copying the template to memory and returning it, followed by the template
+        let constructor = "6080604052602a80600e6000396000f3";
+        hex::decode(constructor.to_owned() + &hex::encode(runtime(&"0".repeat(64)))).unwrap()
+    }
+
+    #[rstest]
+    fn find_immutables_from_creation_code() {
+        let references = find_immutables(&creation(), &runtime(VALUE)).unwrap();
+        assert_eq!(
+            references,
+            vec![ImmutableReference {
+                start: 7,
+                length: 32
+            }]
+        );
+    }
+
+    #[rstest]
+    fn find_immutables_without_template() {
+        assert!(find_immutables(&hex::decode("6080").unwrap(), &runtime(VALUE)).is_err());
+    }
+
+    #[rstest]
+    fn parse_references_sorted() {
+        let json = r#"{"7": [{"start": 80, "length": 32}], "3": [{"start": 12, "length": 32}]}"#;
+        let references = parse_immutable_references(json).unwrap();
+        assert_eq!(
+            references,
+            vec![
+                ImmutableReference {
+                    start: 12,
+                    length: 32
+                },
+                ImmutableReference {
+                    start: 80,
+                    length: 32
+                },
+            ]
+        );
+    }
+
+    #[rstest]
+    fn masked_disassembly_is_identical_across_deployments() {
+        let references = vec![ImmutableReference {
+            start: 7,
+            length: 32,
+        }];
+        let other_value = format!("{:0>64}", "cafe");
+        let first = disassemble_bytes(runtime(VALUE)).unwrap();
+        let second = disassemble_bytes(runtime(&other_value)).unwrap();
+        assert_ne!(first, second);
+
+        let first = mask_immutables(&first, &references);
+        let second = mask_immutables(&second, &references);
+        assert_eq!(first, second);
+        assert!(format_operations(first).unwrap().contains("PUSH32 0x0\n"));
+    }
+
+    #[rstest]
+    fn references_beyond_the_maximum_offset() {
+        let json = format!(r#"{{"3": [{{"start": {}, "length": 32}}]}}"#, u32::MAX - 1);
+        assert!(parse_immutable_references(&json).is_err());
+
+        // Constructed references are cut off at the maximum offset instead of overflowing
+        let references = vec![ImmutableReference {
+            start: u32::MAX - 1,
+            length: 32,
+        }];
+        let operations = disassemble_bytes(runtime(VALUE)).unwrap();
+        assert_eq!(mask_immutables(&operations, &references), operations);
+        assert_eq!(
+            mask_immutables_bytes(&runtime(VALUE), &references),
+            runtime(VALUE)
+        );
+    }
+
+    #[rstest]
+    fn masked_bytes_match_template() {
+        let references = find_immutables(&creation(), &runtime(VALUE)).unwrap();
+        let masked = mask_immutables_bytes(&runtime(VALUE), &references);
+        assert_eq!(masked, runtime(&"0".repeat(64)));
+    }
+}
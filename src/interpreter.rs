@@ -0,0 +1,1061 @@
+//! Concrete execution of decoded operations
+//!
+//! [`execute_operations`] runs decoded operations and [`execute`] raw legacy code against the given
+//! call data, call value and mocked accounts and record every executed operation in a trace. Other
+//! contracts are not executed: calls succeed without return data after transferring their value
+//! and contract creations fail. `DUPN`, `SWAPN` and `EXCHANGE` of operations decoded from EOF code
+//! sections are executed as well, the other EOF only opcodes are invalid.
+//!
+//! Gas follows the Cancun schedule including memory expansion, per word costs and cold and warm
+//! accesses. Gas forwarded to calls, refunds and the call stipend are not modeled.
+use crate::decode::decode_operations;
+use crate::keccak::keccak256;
+use crate::types::{Opcode, Operation};
+use crate::u256::U256;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+// Maximum number of items on the stack
+const MAX_STACK_HEIGHT: usize = 1024;
+// Memory beyond this size always runs out of gas
+const MAX_MEMORY_SIZE: u64 = 1 << 32;
+
+/// A 20 byte account address
+pub type Address = [u8; 20];
+
+/// The transaction and block the code is executed in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Environment {
+    /// The address of the executing account, its storage and balance are taken from the accounts
+    pub address: Address,
+    /// The sender of the call
+    pub caller: Address,
+    /// The sender of the transaction
+    pub origin: Address,
+    /// The value transferred with the call
+    pub value: U256,
+    /// The call data
+    pub calldata: Vec<u8>,
+    /// The gas available to the execution
+    pub gas: u64,
+    /// The gas price of the transaction
+    pub gas_price: U256,
+    /// The beneficiary of the block
+    pub coinbase: Address,
+    /// The block number
+    pub number: U256,
+    /// The block timestamp
+    pub timestamp: U256,
+    /// The block gas limit
+    pub gas_limit: U256,
+    /// The randomness beacon returned by `PREVRANDAO` (`DIFFICULTY`)
+    pub prevrandao: U256,
+    /// The chain id
+    pub chain_id: U256,
+    /// The base fee of the block
+    pub base_fee: U256,
+    /// The blob base fee of the block
+    pub blob_base_fee: U256,
+    /// Executions exceeding this number of steps are aborted
+    pub max_steps: usize,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment {
+            address: [0; 20],
+            caller: [0; 20],
+            origin: [0; 20],
+            value: U256::ZERO,
+            calldata: Vec::new(),
+            gas: 30_000_000,
+            gas_price: U256::ZERO,
+            coinbase: [0; 20],
+            number: U256::ZERO,
+            timestamp: U256::ZERO,
+            gas_limit: U256::from(30_000_000),
+            prevrandao: U256::ZERO,
+            chain_id: U256::ONE,
+            base_fee: U256::ZERO,
+            blob_base_fee: U256::ONE,
+            max_steps: 1_000_000,
+        }
+    }
+}
+
+/// The mocked state of an account
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Account {
+    /// The balance in wei
+    pub balance: U256,
+    /// The deployed code
+    pub code: Vec<u8>,
+    /// The non-zero storage slots
+    pub storage: BTreeMap<U256, U256>,
+}
+
+/// Bytes written to memory by a single operation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryWrite {
+    /// The offset of the first written byte
+    pub offset: u64,
+    /// The written bytes
+    pub data: Vec<u8>,
+}
+
+/// A single executed operation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    /// The offset of the operation
+    pub pc: u32,
+    /// The executed opcode
+    pub opcode: Opcode,
+    /// The gas remaining before the operation
+    pub gas: u64,
+    /// The gas consumed by the operation
+    pub gas_cost: u64,
+    /// The stack before the operation (top of stack last)
+    pub stack: Vec<U256>,
+    /// The bytes written to memory by the operation
+    pub memory: Option<MemoryWrite>,
+}
+
+/// An exceptional halt, consuming all gas and reverting all changes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExecutionError {
+    /// An operation needed more items than are on the stack
+    StackUnderflow,
+    /// The stack grew beyond 1024 items
+    StackOverflow,
+    /// A jump to an offset that is not a `JUMPDEST`
+    InvalidJump(U256),
+    /// The operation cost more gas than remaining
+    OutOfGas,
+    /// `RETURNDATACOPY` read beyond the return data
+    ReturnDataOutOfBounds,
+    /// `INVALID` or an opcode that is not supported in legacy code
+    InvalidOpcode(Opcode),
+    /// The execution exceeded [`Environment::max_steps`]
+    StepLimit,
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::StackUnderflow => write!(f, "stack underflow"),
+            ExecutionError::StackOverflow => write!(f, "stack overflow"),
+            ExecutionError::InvalidJump(target) => write!(f, "invalid jump to {target}"),
+            ExecutionError::OutOfGas => write!(f, "out of gas"),
+            ExecutionError::ReturnDataOutOfBounds => write!(f, "return data out of bounds"),
+            ExecutionError::InvalidOpcode(opcode) => write!(f, "invalid opcode {opcode:?}"),
+            ExecutionError::StepLimit => write!(f, "step limit exceeded"),
+        }
+    }
+}
+
+/// How the execution ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// `STOP` or the end of the code was reached
+    Stop,
+    /// `RETURN` with the returned data
+    Return(Vec<u8>),
+    /// `REVERT` with the revert data
+    Revert(Vec<u8>),
+    /// `SELFDESTRUCT` was executed
+    SelfDestruct,
+    /// The execution halted exceptionally
+    Error(ExecutionError),
+}
+
+/// A log emitted by `LOG0` to `LOG4`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Log {
+    /// The topics of the log
+    pub topics: Vec<U256>,
+    /// The logged data
+    pub data: Vec<u8>,
+}
+
+/// The result of an execution
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Execution {
+    /// How the execution ended
+    pub outcome: Outcome,
+    /// Every executed operation in order
+    pub trace: Vec<TraceStep>,
+    /// The gas consumed
+    pub gas_used: u64,
+    /// The emitted logs, empty unless the execution succeeded
+    pub logs: Vec<Log>,
+}
+
+impl Execution {
+    /// Whether the execution ended without reverting
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self.outcome,
+            Outcome::Stop | Outcome::Return(_) | Outcome::SelfDestruct
+        )
+    }
+}
+
+/// Executes the code of [`Environment::address`]
+///
+/// The code is decoded as legacy code and keeps its original bytes for `CODECOPY` and
+/// `EXTCODECOPY` of the executing account, see [`execute_operations`] to execute operations that
+/// are already decoded. Storage and balances are read from and written to `accounts`, changes are
+/// only kept if the execution succeeds.
+///
+/// # Arguments
+/// - `code` - The bytecode to execute
+/// - `environment` - The call data, value and block the code is executed with
+/// - `accounts` - The mocked state of all accounts
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::interpreter::{execute, Environment, Outcome};
+/// use std::collections::BTreeMap;
+///
+/// // mstore(0x0, add(0x2, 0x3)) return(0x0, 0x20)
+/// let code = hex::decode("600360020160005260206000f3").unwrap();
+/// let execution = execute(&code, &Environment::default(), &mut BTreeMap::new());
+/// let mut expected = vec![0; 32];
+/// expected[31] = 5;
+/// assert_eq!(execution.outcome, Outcome::Return(expected));
+/// assert_eq!(execution.trace.len(), 8);
+/// ```
+pub fn execute(
+    code: &[u8],
+    environment: &Environment,
+    accounts: &mut BTreeMap<Address, Account>,
+) -> Execution {
+    let operations = decode_operations(code.to_vec(), 0, false);
+    execute_with_code(&operations, code, environment, accounts)
+}
+
+/// Executes decoded operations as the code of [`Environment::address`]
+///
+/// Same as [`execute`], except that `CODECOPY` and `EXTCODECOPY` of the executing account read the
+/// code encoded from the operations. Bytes that do not decode to an opcode were replaced by
+/// `INVALID` (`0xfe`) when decoding, execute the original bytes with [`execute`] to read them.
+///
+/// # Arguments
+/// - `operations` - The decoded operations to execute
+/// - `environment` - The call data, value and block the code is executed with
+/// - `accounts` - The mocked state of all accounts
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::disassemble_str;
+/// use evm_disassembler::interpreter::{execute_operations, Environment, Outcome};
+/// use std::collections::BTreeMap;
+///
+/// // return(0x0, calldatasize) after copying the call data
+/// let operations = disassemble_str("365f5f37365ff3").unwrap();
+/// let environment = Environment {
+///     calldata: vec![0x2a],
+///     ..Environment::default()
+/// };
+/// let execution = execute_operations(&operations, &environment, &mut BTreeMap::new());
+/// assert_eq!(execution.outcome, Outcome::Return(vec![0x2a]));
+/// ```
+pub fn execute_operations(
+    operations: &[Operation],
+    environment: &Environment,
+    accounts: &mut BTreeMap<Address, Account>,
+) -> Execution {
+    let code: Vec<u8> = operations
+        .iter()
+        .flat_map(|operation| {
+            std::iter::once(operation.opcode.to_byte()).chain(operation.input.iter().copied())
+        })
+        .collect();
+    execute_with_code(operations, &code, environment, accounts)
+}
+
+// Executes the decoded operations of the code
+fn execute_with_code(
+    operations: &[Operation],
+    code: &[u8],
+    environment: &Environment,
+    accounts: &mut BTreeMap<Address, Account>,
+) -> Execution {
+    let mut machine = Machine::new(operations, code, environment, accounts.clone());
+    let mut trace = Vec::new();
+    let mut position = 0;
+    let outcome = loop {
+        let Some(operation) = operations.get(position) else {
+            break Outcome::Stop;
+        };
+        if trace.len() >= environment.max_steps {
+            break Outcome::Error(ExecutionError::StepLimit);
+        }
+        let (gas, stack) = (machine.gas, machine.stack.clone());
+        let result = machine.step(operation);
+        trace.push(TraceStep {
+            pc: operation.offset,
+            opcode: operation.opcode,
+            gas,
+            gas_cost: gas - machine.gas,
+            stack,
+            memory: machine.written.take(),
+        });
+        match result {
+            Ok(Flow::Next) => position += 1,
+            Ok(Flow::Jump(target)) => position = target,
+            Ok(Flow::Halt(outcome)) => break outcome,
+            Err(error) => break Outcome::Error(error),
+        }
+    };
+    let mut execution = Execution {
+        outcome,
+        trace,
+        gas_used: environment.gas - machine.gas,
+        logs: Vec::new(),
+    };
+    match execution.outcome {
+        Outcome::Error(_) => execution.gas_used = environment.gas,
+        Outcome::Revert(_) => {}
+        _ => {
+            *accounts = machine.accounts;
+            execution.logs = machine.logs;
+        }
+    }
+    execution
+}
+
+enum Flow {
+    Next,
+    // Continue at the operation with the given position
+    Jump(usize),
+    Halt(Outcome),
+}
+
+struct Machine<'a> {
+    environment: &'a Environment,
+    // Positions of the JUMPDEST operations by offset
+    jumpdests: HashMap<u32, usize>,
+    code: Vec<u8>,
+    accounts: BTreeMap<Address, Account>,
+    // Storage of the executing account before the execution
+    original: BTreeMap<U256, U256>,
+    transient: BTreeMap<U256, U256>,
+    warm_addresses: BTreeSet<Address>,
+    warm_slots: BTreeSet<U256>,
+    stack: Vec<U256>,
+    memory: Vec<u8>,
+    return_data: Vec<u8>,
+    gas: u64,
+    logs: Vec<Log>,
+    written: Option<MemoryWrite>,
+}
+
+impl<'a> Machine<'a> {
+    fn new(
+        operations: &[Operation],
+        code: &[u8],
+        environment: &'a Environment,
+        accounts: BTreeMap<Address, Account>,
+    ) -> Self {
+        let jumpdests = operations
+            .iter()
+            .enumerate()
+            .filter(|(_, operation)| operation.opcode == Opcode::JUMPDEST)
+            .map(|(position, operation)| (operation.offset, position))
+            .collect();
+        let original = accounts
+            .get(&environment.address)
+            .map(|account| account.storage.clone())
+            .unwrap_or_default();
+        Machine {
+            environment,
+            jumpdests,
+            code: code.to_vec(),
+            accounts,
+            original,
+            transient: BTreeMap::new(),
+            warm_addresses: BTreeSet::from([
+                environment.address,
+                environment.caller,
+                environment.origin,
+            ]),
+            warm_slots: BTreeSet::new(),
+            stack: Vec::new(),
+            memory: Vec::new(),
+            return_data: Vec::new(),
+            gas: environment.gas,
+            logs: Vec::new(),
+            written: None,
+        }
+    }
+
+    fn step(&mut self, operation: &Operation) -> Result<Flow, ExecutionError> {
+        use Opcode::*;
+        let opcode = operation.opcode;
+        self.charge(base_gas(opcode))?;
+        let environment = self.environment;
+        match opcode {
+            STOP => return Ok(Flow::Halt(Outcome::Stop)),
+            ADD => self.binary(|a, b| a + b)?,
+            MUL => self.binary(|a, b| a * b)?,
+            SUB => self.binary(|a, b| a - b)?,
+            DIV => self.binary(|a, b| a.div_rem(b).0)?,
+            SDIV => self.binary(|a, b| a.signed_div_rem(b).0)?,
+            MOD => self.binary(|a, b| a.div_rem(b).1)?,
+            SMOD => self.binary(|a, b| a.signed_div_rem(b).1)?,
+            ADDMOD => {
+                let [a, b, modulus] = self.pop()?;
+                self.push(a.add_mod(b, modulus))?;
+            }
+            MULMOD => {
+                let [a, b, modulus] = self.pop()?;
+                self.push(a.mul_mod(b, modulus))?;
+            }
+            EXP => {
+                let [base, exponent] = self.pop()?;
+                self.charge(50 * exponent.bits().div_ceil(8) as u64)?;
+                self.push(base.pow(exponent))?;
+            }
+            SIGNEXTEND => self.binary(|index, value| value.sign_extend(index))?,
+            LT => self.binary(|a, b| U256::from(a < b))?,
+            GT => self.binary(|a, b| U256::from(a > b))?,
+            SLT => self.binary(|a, b| U256::from(a.signed_cmp(&b).is_lt()))?,
+            SGT => self.binary(|a, b| U256::from(a.signed_cmp(&b).is_gt()))?,
+            EQ => self.binary(|a, b| U256::from(a == b))?,
+            ISZERO => self.unary(|a| U256::from(a.is_zero()))?,
+            AND => self.binary(|a, b| a & b)?,
+            OR => self.binary(|a, b| a | b)?,
+            XOR => self.binary(|a, b| a ^ b)?,
+            NOT => self.unary(|a| !a)?,
+            BYTE => self.binary(|index, value| value.byte(index))?,
+            SHL => self.binary(|shift, value| value << shift.as_shift())?,
+            SHR => self.binary(|shift, value| value >> shift.as_shift())?,
+            SAR => self.binary(|shift, value| value.arithmetic_shr(shift.as_shift()))?,
+            CLZ => self.unary(|a| U256::from(256 - a.bits() as u64))?,
+            SHA3 => {
+                let [offset, size] = self.pop()?;
+                let (offset, size) = self.expand(offset, size)?;
+                self.charge(6 * words(size))?;
+                let hash = keccak256(&self.memory[offset..offset + size]);
+                self.push(U256::from_be_bytes(hash))?;
+            }
+            ADDRESS => self.push(from_address(environment.address))?,
+            BALANCE => {
+                let [address] = self.pop()?;
+                let address = to_address(address);
+                self.access(address)?;
+                self.push(self.account(address).balance)?;
+            }
+            ORIGIN => self.push(from_address(environment.origin))?,
+            CALLER => self.push(from_address(environment.caller))?,
+            CALLVALUE => self.push(environment.value)?,
+            CALLDATALOAD => {
+                let [offset] = self.pop()?;
+                let word = padded_slice(&environment.calldata, offset, 32);
+                self.push(U256::from_be_slice(&word))?;
+            }
+            CALLDATASIZE => self.push(U256::from(environment.calldata.len() as u64))?,
+            CALLDATACOPY => self.copy(&environment.calldata.clone())?,
+            CODESIZE => self.push(U256::from(self.code.len() as u64))?,
+            CODECOPY => self.copy(&self.code.clone())?,
+            GASPRICE => self.push(environment.gas_price)?,
+            EXTCODESIZE => {
+                let [address] = self.pop()?;
+                let address = to_address(address);
+                self.access(address)?;
+                self.push(U256::from(self.code_of(address).len() as u64))?;
+            }
+            EXTCODECOPY => {
+                let [address] = self.pop()?;
+                let address = to_address(address);
+                self.access(address)?;
+                self.copy(&self.code_of(address))?;
+            }
+            RETURNDATASIZE => self.push(U256::from(self.return_data.len() as u64))?,
+            RETURNDATACOPY => {
+                let [destination, offset, size] = self.pop()?;
+                let end = offset.overflowing_add(size);
+                if end.1 || end.0 > U256::from(self.return_data.len() as u64) {
+                    return Err(ExecutionError::ReturnDataOutOfBounds);
+                }
+                self.stack.extend([size, offset, destination]);
+                self.copy(&self.return_data.clone())?;
+            }
+            EXTCODEHASH => {
+                let [address] = self.pop()?;
+                let address = to_address(address);
+                self.access(address)?;
+                let hash = match self.accounts.get(&address) {
+                    Some(account) => U256::from_be_bytes(keccak256(&account.code)),
+                    None => U256::ZERO,
+                };
+                self.push(hash)?;
+            }
+            // Hashes of previous blocks are unknown
+            BLOCKHASH | BLOBHASH => self.unary(|_| U256::ZERO)?,
+            COINBASE => self.push(from_address(environment.coinbase))?,
+            TIMESTAMP => self.push(environment.timestamp)?,
+            NUMBER => self.push(environment.number)?,
+            DIFFICULTY => self.push(environment.prevrandao)?,
+            GASLIMIT => self.push(environment.gas_limit)?,
+            CHAINID => self.push(environment.chain_id)?,
+            SELFBALANCE => self.push(self.account(environment.address).balance)?,
+            BASEFEE => self.push(environment.base_fee)?,
+            BLOBBASEFEE => self.push(environment.blob_base_fee)?,
+            POP => {
+                self.pop::<1>()?;
+            }
+            MLOAD => {
+                let [offset] = self.pop()?;
+                let (offset, _) = self.expand(offset, U256::from(32))?;
+                self.push(U256::from_be_slice(&self.memory[offset..offset + 32]))?;
+            }
+            MSTORE => {
+                let [offset, value] = self.pop()?;
+                self.store(offset, &value.to_be_bytes())?;
+            }
+            MSTORE8 => {
+                let [offset, value] = self.pop()?;
+                self.store(offset, &[value.low_u64() as u8])?;
+            }
+            SLOAD => {
+                let [slot] = self.pop()?;
+                let cost = if self.warm_slots.insert(slot) {
+                    2100
+                } else {
+                    100
+                };
+                self.charge(cost)?;
+                let value = self
+                    .account(environment.address)
+                    .storage
+                    .get(&slot)
+                    .copied();
+                self.push(value.unwrap_or_default())?;
+            }
+            SSTORE => {
+                // Storing requires more than the call stipend (EIP-2200)
+                if self.gas <= 2300 {
+                    return Err(ExecutionError::OutOfGas);
+                }
+                let [slot, value] = self.pop()?;
+                self.sstore(slot, value)?;
+            }
+            JUMP => {
+                let [target] = self.pop()?;
+                return Ok(Flow::Jump(self.jump_target(target)?));
+            }
+            JUMPI => {
+                let [target, condition] = self.pop()?;
+                if !condition.is_zero() {
+                    return Ok(Flow::Jump(self.jump_target(target)?));
+                }
+            }
+            PC => self.push(U256::from(operation.offset as u64))?,
+            MSIZE => self.push(U256::from(self.memory.len() as u64))?,
+            GAS => self.push(U256::from(self.gas))?,
+            JUMPDEST => {}
+            MCOPY => {
+                let [destination, source, size] = self.pop()?;
+                let (source, length) = self.expand(source, size)?;
+                let data = self.memory[source..source + length].to_vec();
+                self.charge(3 * words(length))?;
+                self.store(destination, &data)?;
+            }
+            TLOAD => {
+                let [slot] = self.pop()?;
+                self.push(self.transient.get(&slot).copied().unwrap_or_default())?;
+            }
+            TSTORE => {
+                let [slot, value] = self.pop()?;
+                self.transient.insert(slot, value);
+            }
+            LOG0 | LOG1 | LOG2 | LOG3 | LOG4 => {
+                let [offset, size] = self.pop()?;
+                let mut topics = Vec::new();
+                for _ in 0..opcode.to_byte() - 0xa0 {
+                    let [topic] = self.pop()?;
+                    topics.push(topic);
+                }
+                let (offset, size) = self.expand(offset, size)?;
+                self.charge(375 * topics.len() as u64 + 8 * size as u64)?;
+                let data = self.memory[offset..offset + size].to_vec();
+                self.logs.push(Log { topics, data });
+            }
+            CREATE | CREATE2 => {
+                let [_, offset, size] = self.pop()?;
+                if opcode == CREATE2 {
+                    self.pop::<1>()?;
+                }
+                let (_, size) = self.expand(offset, size)?;
+                let hashing = if opcode == CREATE2 { 6 } else { 0 };
+                self.charge((2 + hashing) * words(size))?;
+                self.return_data.clear();
+                self.push(U256::ZERO)?;
+            }
+            CALL | CALLCODE | DELEGATECALL | STATICCALL => {
+                let [_, address] = self.pop()?;
+                let value = match opcode {
+                    CALL | CALLCODE => self.pop::<1>()?[0],
+                    _ => U256::ZERO,
+                };
+                let [input_offset, input_size, output_offset, output_size] = self.pop()?;
+                self.expand(input_offset, input_size)?;
+                self.expand(output_offset, output_size)?;
+                let address = to_address(address);
+                self.access(address)?;
+                if !value.is_zero() {
+                    self.charge(9000)?;
+                }
+                self.return_data.clear();
+                let success = match opcode {
+                    CALL => self.transfer(address, value),
+                    CALLCODE => self.account(environment.address).balance >= value,
+                    _ => true,
+                };
+                self.push(U256::from(success))?;
+            }
+            RETURN | REVERT => {
+                let [offset, size] = self.pop()?;
+                let (offset, size) = self.expand(offset, size)?;
+                let data = self.memory[offset..offset + size].to_vec();
+                let outcome = if opcode == RETURN {
+                    Outcome::Return(data)
+                } else {
+                    Outcome::Revert(data)
+                };
+                return Ok(Flow::Halt(outcome));
+            }
+            SELFDESTRUCT => {
+                let [beneficiary] = self.pop()?;
+                let beneficiary = to_address(beneficiary);
+                if self.warm_addresses.insert(beneficiary) {
+                    self.charge(2600)?;
+                }
+                let balance = self.account(environment.address).balance;
+                self.transfer(beneficiary, balance);
+                return Ok(Flow::Halt(Outcome::SelfDestruct));
+            }
+            _ if opcode.is_push() => self.push(U256::from_be_slice(&operation.input))?,
+            DUPN | SWAPN | EXCHANGE => {
+                // Depths of the exchanged items below the top of the stack (EIP-663)
+                let immediate = operation.input.first().copied().unwrap_or(0) as usize;
+                let (first, second) = match opcode {
+                    DUPN => (immediate + 1, 0),
+                    SWAPN => (0, immediate + 1),
+                    _ => {
+                        let n = (immediate >> 4) + 1;
+                        (n, n + (immediate & 0x0f) + 1)
+                    }
+                };
+                let top = self.stack.len();
+                if top < operation.stack_arity().0 {
+                    return Err(ExecutionError::StackUnderflow);
+                }
+                if opcode == DUPN {
+                    self.push(self.stack[top - first])?;
+                } else {
+                    self.stack.swap(top - 1 - first, top - 1 - second);
+                }
+            }
+            _ if (0x80..=0x8f).contains(&opcode.to_byte()) => {
+                let depth = (opcode.to_byte() - 0x7f) as usize;
+                let Some(index) = self.stack.len().checked_sub(depth) else {
+                    return Err(ExecutionError::StackUnderflow);
+                };
+                self.push(self.stack[index])?;
+            }
+            _ if (0x90..=0x9f).contains(&opcode.to_byte()) => {
+                let depth = (opcode.to_byte() - 0x8f) as usize;
+                let top = self.stack.len();
+                if top <= depth {
+                    return Err(ExecutionError::StackUnderflow);
+                }
+                self.stack.swap(top - 1, top - 1 - depth);
+            }
+            _ => return Err(ExecutionError::InvalidOpcode(opcode)),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn charge(&mut self, gas: u64) -> Result<(), ExecutionError> {
+        self.gas = self.gas.checked_sub(gas).ok_or(ExecutionError::OutOfGas)?;
+        Ok(())
+    }
+
+    fn pop<const N: usize>(&mut self) -> Result<[U256; N], ExecutionError> {
+        if self.stack.len() < N {
+            return Err(ExecutionError::StackUnderflow);
+        }
+        let mut items = [U256::ZERO; N];
+        for item in items.iter_mut() {
+            *item = self.stack.pop().expect("Stack height was checked");
+        }
+        Ok(items)
+    }
+
+    fn push(&mut self, value: U256) -> Result<(), ExecutionError> {
+        if self.stack.len() >= MAX_STACK_HEIGHT {
+            return Err(ExecutionError::StackOverflow);
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn unary(&mut self, f: impl Fn(U256) -> U256) -> Result<(), ExecutionError> {
+        let [a] = self.pop()?;
+        self.push(f(a))
+    }
+
+    fn binary(&mut self, f: impl Fn(U256, U256) -> U256) -> Result<(), ExecutionError> {
+        let [a, b] = self.pop()?;
+        self.push(f(a, b))
+    }
+
+    fn jump_target(&self, target: U256) -> Result<usize, ExecutionError> {
+        target
+            .as_u64()
+            .and_then(|offset| self.jumpdests.get(&u32::try_from(offset).ok()?))
+            .copied()
+            .ok_or(ExecutionError::InvalidJump(target))
+    }
+
+    // Charges for expanding memory to cover the given range and returns it as offset and size
+    fn expand(&mut self, offset: U256, size: U256) -> Result<(usize, usize), ExecutionError> {
+        if size.is_zero() {
+            return Ok((0, 0));
+        }
+        let (Some(offset), Some(size)) = (offset.as_u64(), size.as_u64()) else {
+            return Err(ExecutionError::OutOfGas);
+        };
+        let end = offset.saturating_add(size);
+        if end > MAX_MEMORY_SIZE {
+            return Err(ExecutionError::OutOfGas);
+        }
+        let (current, required) = (words(self.memory.len()), end.div_ceil(32));
+        if required > current {
+            self.charge(memory_cost(required) - memory_cost(current))?;
+            self.memory.resize(required as usize * 32, 0);
+        }
+        Ok((offset as usize, size as usize))
+    }
+
+    fn store(&mut self, offset: U256, data: &[u8]) -> Result<(), ExecutionError> {
+        let (offset, size) = self.expand(offset, U256::from(data.len() as u64))?;
+        self.memory[offset..offset + size].copy_from_slice(data);
+        if size > 0 {
+            self.written = Some(MemoryWrite {
+                offset: offset as u64,
+                data: data.to_vec(),
+            });
+        }
+        Ok(())
+    }
+
+    // Copies a zero padded slice of the source to memory as done by the *COPY operations
+    fn copy(&mut self, source: &[u8]) -> Result<(), ExecutionError> {
+        let [destination, offset, size] = self.pop()?;
+        let (_, length) = self.expand(destination, size)?;
+        self.charge(3 * words(length))?;
+        self.store(destination, &padded_slice(source, offset, length))
+    }
+
+    fn access(&mut self, address: Address) -> Result<(), ExecutionError> {
+        let cost = if self.warm_addresses.insert(address) {
+            2600
+        } else {
+            100
+        };
+        self.charge(cost)
+    }
+
+    fn sstore(&mut self, slot: U256, value: U256) -> Result<(), ExecutionError> {
+        if self.warm_slots.insert(slot) {
+            self.charge(2100)?;
+        }
+        let address = self.environment.address;
+        let current = self.account(address).storage.get(&slot).copied();
+        let (current, original) = (
+            current.unwrap_or_default(),
+            self.original.get(&slot).copied().unwrap_or_default(),
+        );
+        let cost = if current == value || original != current {
+            100
+        } else if original.is_zero() {
+            20000
+        } else {
+            2900
+        };
+        self.charge(cost)?;
+        let storage = &mut self.accounts.entry(address).or_default().storage;
+        if value.is_zero() {
+            storage.remove(&slot);
+        } else {
+            storage.insert(slot, value);
+        }
+        Ok(())
+    }
+
+    // Moves value from the executing account, fails if its balance is insufficient
+    fn transfer(&mut self, to: Address, value: U256) -> bool {
+        let from = self.environment.address;
+        if self.account(from).balance < value {
+            return false;
+        }
+        self.accounts.entry(from).or_default().balance = self.account(from).balance - value;
+        self.accounts.entry(to).or_default().balance = self.account(to).balance + value;
+        true
+    }
+
+    fn account(&self, address: Address) -> Account {
+        self.accounts.get(&address).cloned().unwrap_or_default()
+    }
+
+    // The executing account runs the given code regardless of its mocked code
+    fn code_of(&self, address: Address) -> Vec<u8> {
+        if address == self.environment.address {
+            self.code.clone()
+        } else {
+            self.account(address).code
+        }
+    }
+}
+
+// Static gas of the opcode, dynamic costs are charged during execution
+fn base_gas(opcode: Opcode) -> u64 {
+    use Opcode::*;
+    match opcode {
+        STOP | RETURN | REVERT | INVALID => 0,
+        JUMPDEST => 1,
+        ADDRESS | ORIGIN | CALLER | CALLVALUE | CALLDATASIZE | CODESIZE | GASPRICE
+        | RETURNDATASIZE | COINBASE | TIMESTAMP | NUMBER | DIFFICULTY | GASLIMIT | CHAINID
+        | BASEFEE | BLOBBASEFEE | POP | PC | MSIZE | GAS | PUSH0 => 2,
+        ADD | SUB | NOT | LT | GT | SLT | SGT | EQ | ISZERO | AND | OR | XOR | BYTE | SHL | SHR
+        | SAR | CALLDATALOAD | MLOAD | MSTORE | MSTORE8 | CALLDATACOPY | CODECOPY
+        | RETURNDATACOPY | MCOPY | BLOBHASH => 3,
+        MUL | DIV | SDIV | MOD | SMOD | SIGNEXTEND | SELFBALANCE | CLZ => 5,
+        ADDMOD | MULMOD | JUMP => 8,
+        JUMPI | EXP => 10,
+        BLOCKHASH => 20,
+        SHA3 => 30,
+        TLOAD | TSTORE => 100,
+        LOG0 | LOG1 | LOG2 | LOG3 | LOG4 => 375,
+        SELFDESTRUCT => 5000,
+        CREATE | CREATE2 => 32000,
+        DUPN | SWAPN | EXCHANGE => 3,
+        _ if opcode.is_push() || opcode.is_dup() || opcode.is_swap() => 3,
+        // Account and storage accesses only cost their cold or warm access cost
+        _ => 0,
+    }
+}
+
+fn words(size: usize) -> u64 {
+    size.div_ceil(32) as u64
+}
+
+fn memory_cost(words: u64) -> u64 {
+    3 * words + words * words / 512
+}
+
+// The bytes at the given offset of the data, padded with zeros beyond its end
+fn padded_slice(data: &[u8], offset: U256, length: usize) -> Vec<u8> {
+    let mut slice = vec![0; length];
+    if let Some(start) = offset.as_u64().filter(|start| *start < data.len() as u64) {
+        let available = &data[start as usize..];
+        let copied = available.len().min(length);
+        slice[..copied].copy_from_slice(&available[..copied]);
+    }
+    slice
+}
+
+fn to_address(word: U256) -> Address {
+    word.to_be_bytes()[12..]
+        .try_into()
+        .expect("Slice has 20 bytes")
+}
+
+fn from_address(address: Address) -> U256 {
+    U256::from_be_slice(&address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_bytes;
+    use crate::selectors::function_entries;
+    use rstest::*;
+    use std::fs;
+
+    fn run(code: &str, environment: &Environment) -> Execution {
+        let code = hex::decode(code.replace(' ', "")).unwrap();
+        execute(&code, environment, &mut BTreeMap::new())
+    }
+
+    fn read_testdata(address: &str) -> Vec<u8> {
+        let code = fs::read_to_string(format!("testdata/{address}_encoded.txt"))
+            .expect("Unable to read encoded file");
+        hex::decode(code.trim().trim_start_matches("0x")).unwrap()
+    }
+
+    #[rstest]
+    // jump(0x4) invalid jumpdest stop
+    #[case("6004 56 fe 5b 00", Outcome::Stop)]
+    // jump(0x3)
+    #[case(
+        "6003 56 00",
+        Outcome::Error(ExecutionError::InvalidJump(U256::from(3)))
+    )]
+    // revert(0x0, 0x0)
+    #[case("5f5f fd", Outcome::Revert(vec![]))]
+    #[case("01", Outcome::Error(ExecutionError::StackUnderflow))]
+    // jumpdest jump(0x0)
+    #[case("5b 5f 56", Outcome::Error(ExecutionError::StepLimit))]
+    fn outcomes(#[case] code: &str, #[case] expected: Outcome) {
+        let environment = Environment {
+            max_steps: 1000,
+            ..Environment::default()
+        };
+        assert_eq!(run(code, &environment).outcome, expected);
+    }
+
+    #[rstest]
+    // DUPN 1 duplicates the second item
+    #[case(Opcode::DUPN, 1, Ok(vec![1, 2, 3, 2]))]
+    // SWAPN 1 swaps the top with the third item
+    #[case(Opcode::SWAPN, 1, Ok(vec![3, 2, 1]))]
+    // EXCHANGE 0x00 swaps the second and third item
+    #[case(Opcode::EXCHANGE, 0x00, Ok(vec![2, 1, 3]))]
+    #[case(Opcode::DUPN, 3, Err(ExecutionError::StackUnderflow))]
+    #[case(Opcode::SWAPN, 2, Err(ExecutionError::StackUnderflow))]
+    #[case(Opcode::EXCHANGE, 0x01, Err(ExecutionError::StackUnderflow))]
+    fn eof_stack_operations(
+        #[case] opcode: Opcode,
+        #[case] immediate: u8,
+        #[case] expected: Result<Vec<u64>, ExecutionError>,
+    ) {
+        // PUSH1 0x1 PUSH1 0x2 PUSH1 0x3 followed by the operation and STOP
+        let mut operations = disassemble_bytes(hex::decode("600160026003").unwrap()).unwrap();
+        operations.push(Operation {
+            input: vec![immediate],
+            ..Operation::new(opcode, 6)
+        });
+        operations.push(Operation::new(Opcode::STOP, 8));
+        let execution =
+            execute_operations(&operations, &Environment::default(), &mut BTreeMap::new());
+        match expected {
+            Ok(stack) => {
+                assert_eq!(execution.outcome, Outcome::Stop);
+                let stack: Vec<U256> = stack.into_iter().map(U256::from).collect();
+                assert_eq!(execution.trace.last().unwrap().stack, stack);
+                assert_eq!(execution.trace[3].gas_cost, 3);
+            }
+            Err(error) => assert_eq!(execution.outcome, Outcome::Error(error)),
+        }
+    }
+
+    #[rstest]
+    fn read_encoded_operations() {
+        // codecopy(0x0, 0x0, codesize) return(0x0, codesize)
+        let operations = disassemble_bytes(hex::decode("385f5f39385ff3").unwrap()).unwrap();
+        let execution =
+            execute_operations(&operations, &Environment::default(), &mut BTreeMap::new());
+        assert_eq!(
+            execution.outcome,
+            Outcome::Return(hex::decode("385f5f39385ff3").unwrap())
+        );
+    }
+
+    #[rstest]
+    // codecopy(0x0, 0x0, codesize) return(0x0, codesize) followed by an undefined byte and a
+    // truncated PUSH2
+    #[case("385f5f39 385ff3 0c 61ff", hex::decode("385f5f39385ff30c61ff").unwrap())]
+    // mstore(0x0, extcodesize(address)) return(0x1f, 0x1) followed by an undefined byte
+    #[case("303b5f52 6001601ff3 0c", vec![10])]
+    fn read_original_code(#[case] code: &str, #[case] expected: Vec<u8>) {
+        assert_eq!(
+            run(code, &Environment::default()).outcome,
+            Outcome::Return(expected)
+        );
+    }
+
+    #[rstest]
+    fn trace_records_stack_memory_and_gas() {
+        // mstore(0x0, 0x2a) stop
+        let execution = run("602a 5f 52 00", &Environment::default());
+        let mstore = &execution.trace[2];
+        assert_eq!(mstore.pc, 3);
+        assert_eq!(mstore.stack, vec![U256::from(0x2a), U256::ZERO]);
+        // 3 for MSTORE and 3 for expanding memory by one word
+        assert_eq!(mstore.gas_cost, 6);
+        assert_eq!(mstore.memory.as_ref().unwrap().offset, 0);
+        assert_eq!(mstore.memory.as_ref().unwrap().data[31], 0x2a);
+        assert_eq!(execution.gas_used, 3 + 2 + 6);
+    }
+
+    #[rstest]
+    fn storage_changes_are_reverted_on_failure() {
+        // sstore(0x0, 0x1) revert(0x0, 0x0)
+        let code = hex::decode("60015f555f5ffd").unwrap();
+        let mut accounts = BTreeMap::new();
+        let execution = execute(&code, &Environment::default(), &mut accounts);
+        assert!(!execution.is_success());
+        assert!(accounts.is_empty());
+        // sstore(0x0, 0x1) stop
+        let code = hex::decode("60015f5500").unwrap();
+        execute(&code, &Environment::default(), &mut accounts);
+        assert_eq!(accounts[&[0; 20]].storage[&U256::ZERO], U256::ONE);
+    }
+
+    #[rstest]
+    fn weth_balance_of() {
+        let code = read_testdata("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let owner = [0x11; 20];
+        // balanceOf is mapping 0x3
+        let mut key = [0u8; 64];
+        key[12..32].copy_from_slice(&owner);
+        key[63] = 3;
+        let slot = U256::from_be_bytes(keccak256(&key));
+        let mut accounts = BTreeMap::from([(
+            [0xee; 20],
+            Account {
+                storage: BTreeMap::from([(slot, U256::from(42))]),
+                ..Account::default()
+            },
+        )]);
+        let mut calldata = hex::decode("70a08231").unwrap();
+        calldata.extend(&key[..32]);
+        let environment = Environment {
+            address: [0xee; 20],
+            calldata,
+            ..Environment::default()
+        };
+        let execution = execute(&code, &environment, &mut accounts);
+        assert_eq!(
+            execution.outcome,
+            Outcome::Return(U256::from(42).to_be_bytes().to_vec())
+        );
+    }
+
+    #[rstest]
+    fn dispatcher_reaches_every_function_entry() {
+        let code = read_testdata("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let operations = disassemble_bytes(code.clone()).unwrap();
+        for entry in function_entries(&operations) {
+            let mut calldata = entry.selector.to_vec();
+            calldata.resize(4 + 3 * 32, 0);
+            let environment = Environment {
+                calldata,
+                ..Environment::default()
+            };
+            let execution = execute(&code, &environment, &mut BTreeMap::new());
+            assert!(
+                execution.trace.iter().any(|step| step.pc == entry.offset),
+                "{}",
+                entry.selector_hex()
+            );
+        }
+    }
+}
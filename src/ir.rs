@@ -0,0 +1,719 @@
+//! Intermediate representation in static single assignment form
+//!
+//! Every value computed by an operation is assigned to a numbered variable exactly once and stack
+//! shuffles (`PUSH`, `DUP`, `SWAP`, `POP`) disappear. Stack items flowing into a block from several
+//! predecessors are merged by phi nodes, storage and memory accesses are explicit loads and stores.
+//!
+//! ```text
+//! block 0x1:
+//!     v0 = phi(0x0: 0x0, 0xa: v3)
+//!     v1 = gt(0xa, v0)
+//!     v2 = iszero(v1)
+//!     branch v2 ? 0x10 : 0xa
+//! ```
+use crate::cfg::ControlFlowGraph;
+use crate::stack::{Expr, SymbolicStack};
+use crate::symbolic::relative_targets;
+use crate::types::{Opcode, Operation};
+use eyre::Result;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Maximum number of stack items passed between blocks
+const MAX_STACK_HEIGHT: usize = 1024;
+
+/// An operand of an instruction
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Value {
+    /// A constant pushed by a PUSH instruction (big endian)
+    Constant(Vec<u8>),
+    /// The variable with the given number
+    Variable(usize),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Constant(bytes) => write!(f, "{}", Expr::Constant(bytes.clone())),
+            Value::Variable(n) => write!(f, "v{n}"),
+        }
+    }
+}
+
+/// A merge of the values of a stack item flowing into a block from its predecessors
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Phi {
+    /// The variable the merged value is assigned to
+    pub result: usize,
+    /// The value for each predecessor block, identified by its offset
+    pub incoming: Vec<(u32, Value)>,
+}
+
+/// A data location accessed by loads and stores
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Space {
+    /// Memory words (`MLOAD`, `MSTORE`)
+    Memory,
+    /// Single bytes of memory (`MSTORE8`)
+    MemoryByte,
+    /// Persistent storage (`SLOAD`, `SSTORE`)
+    Storage,
+    /// Transient storage (`TLOAD`, `TSTORE`)
+    Transient,
+}
+
+impl Space {
+    fn name(&self) -> &'static str {
+        match self {
+            Space::Memory => "memory",
+            Space::MemoryByte => "memory8",
+            Space::Storage => "storage",
+            Space::Transient => "transient",
+        }
+    }
+}
+
+/// A non control flow instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// Assigns the result of an operation to a variable
+    Compute {
+        /// The assigned variable
+        result: usize,
+        /// The executed operation
+        opcode: Opcode,
+        /// The arguments (top of stack first)
+        args: Vec<Value>,
+    },
+    /// Reads a word from memory or storage
+    Load {
+        /// The assigned variable
+        result: usize,
+        /// The accessed location
+        space: Space,
+        /// The memory offset or storage slot
+        address: Value,
+    },
+    /// Writes to memory or storage
+    Store {
+        /// The accessed location
+        space: Space,
+        /// The memory offset or storage slot
+        address: Value,
+        /// The written value
+        value: Value,
+    },
+    /// An operation without result, e.g. `LOG` or `CALLDATACOPY`
+    Effect {
+        /// The executed operation
+        opcode: Opcode,
+        /// The arguments (top of stack first)
+        args: Vec<Value>,
+    },
+}
+
+/// The control flow at the end of a block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Terminator {
+    /// Continues at the following block
+    Fallthrough(u32),
+    /// Jumps to the given target, see [`Block::successors`] for the resolved targets
+    Jump(Value),
+    /// Jumps to the target if the condition is non zero and continues at `fallthrough` otherwise
+    Branch {
+        /// The jump condition
+        condition: Value,
+        /// The jump target
+        target: Value,
+        /// The offset of the next block
+        fallthrough: u32,
+    },
+    /// Jumps to the target selected by the index, continuing at `fallthrough` if out of range
+    Switch {
+        /// The index into the jump table
+        index: Value,
+        /// The offsets of the jump table
+        targets: Vec<u32>,
+        /// The offset of the next block
+        fallthrough: u32,
+    },
+    /// Ends execution of the code (or the EOF code section), e.g. `RETURN` or `RETF`
+    Exit {
+        /// The executed operation
+        opcode: Opcode,
+        /// The arguments (top of stack first)
+        args: Vec<Value>,
+    },
+}
+
+/// A basic block in SSA form
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    /// The offset of the first operation of the block
+    pub offset: u32,
+    /// The stack items merged at the start of the block
+    pub phis: Vec<Phi>,
+    /// The instructions of the block in execution order
+    pub instructions: Vec<Instruction>,
+    /// The control flow at the end of the block
+    pub terminator: Terminator,
+    /// Offsets of the blocks execution can continue at, as resolved by the control flow graph
+    pub successors: Vec<u32>,
+}
+
+/// The SSA representation of code reachable from offset 0
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ir {
+    /// The reachable blocks in code order
+    pub blocks: Vec<Block>,
+}
+
+/// Translates operations into SSA form
+///
+/// Only blocks reachable in the control flow graph are translated. Phi nodes that merge a single
+/// distinct value are removed and variables are numbered in order of definition.
+///
+/// # Arguments
+/// - `operations` - The decoded operations
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, ir::lift};
+///
+/// // PUSH1 0x4 CALLDATALOAD PUSH1 0x0 SSTORE STOP
+/// let operations = disassemble_str("60043560005500").unwrap();
+/// let ir = lift(&operations);
+/// assert_eq!(
+///     ir.to_string(),
+///     "block 0x0:\n    v0 = calldataload(0x4)\n    storage[0x0] = v0\n    stop()\n"
+/// );
+/// ```
+pub fn lift(operations: &[Operation]) -> Ir {
+    let cfg = ControlFlowGraph::new(operations);
+    let heights = entry_heights(&cfg);
+
+    let mut next_variable = 0;
+    let mut blocks = Vec::new();
+    let mut exits: HashMap<u32, Vec<Value>> = HashMap::new();
+    for block in cfg.blocks() {
+        let offset = block.offset();
+        let phis: Vec<Phi> = (0..heights[&offset])
+            .map(|_| {
+                next_variable += 1;
+                Phi {
+                    result: next_variable - 1,
+                    incoming: Vec::new(),
+                }
+            })
+            .collect();
+        let mut stack = SymbolicStack::new();
+        for phi in phis.iter().rev() {
+            stack.push(Expr::Variable(phi.result));
+        }
+
+        let mut instructions = Vec::new();
+        let mut terminator = None;
+        for operation in block.operations {
+            let opcode = operation.opcode;
+            if opcode.is_push() || opcode.is_dup() || opcode.is_swap() {
+                stack.apply(operation);
+                continue;
+            }
+            let (_, outputs) = operation.stack_arity();
+            let args: Vec<Value> = stack.apply(operation).iter().map(to_value).collect();
+            if outputs > 0 {
+                stack.pop();
+                stack.push(Expr::Variable(next_variable));
+                instructions.push(instruction(Some(next_variable), opcode, args));
+                next_variable += 1;
+                continue;
+            }
+            let fallthrough = operation.offset + 1 + operation.input.len() as u32;
+            match opcode {
+                Opcode::JUMPDEST | Opcode::POP => {}
+                Opcode::JUMP => terminator = Some(Terminator::Jump(args[0].clone())),
+                Opcode::JUMPI => {
+                    terminator = Some(Terminator::Branch {
+                        condition: args[1].clone(),
+                        target: args[0].clone(),
+                        fallthrough,
+                    })
+                }
+                Opcode::RJUMP => {
+                    let target = relative_targets(operation).first().copied().unwrap_or(0);
+                    terminator = Some(Terminator::Jump(constant(target)));
+                }
+                Opcode::RJUMPI => {
+                    let target = relative_targets(operation).first().copied().unwrap_or(0);
+                    terminator = Some(Terminator::Branch {
+                        condition: args[0].clone(),
+                        target: constant(target),
+                        fallthrough,
+                    })
+                }
+                Opcode::RJUMPV => {
+                    terminator = Some(Terminator::Switch {
+                        index: args[0].clone(),
+                        targets: relative_targets(operation),
+                        fallthrough,
+                    })
+                }
+                _ if opcode.is_block_terminator() => {
+                    terminator = Some(Terminator::Exit { opcode, args })
+                }
+                _ => instructions.push(instruction(None, opcode, args)),
+            }
+        }
+        let terminator = terminator.unwrap_or_else(|| match cfg.index.next_offset(block) {
+            Some(next) => Terminator::Fallthrough(next),
+            // Execution stops when running past the end of the code
+            None => Terminator::Exit {
+                opcode: Opcode::STOP,
+                args: Vec::new(),
+            },
+        });
+        exits.insert(offset, stack.items().map(to_value).collect());
+        blocks.push(Block {
+            offset,
+            phis,
+            instructions,
+            terminator,
+            successors: cfg.successors(offset).collect(),
+        });
+    }
+
+    for block in blocks.iter_mut() {
+        let predecessors: Vec<u32> = cfg.predecessors(block.offset).collect();
+        for (slot, phi) in block.phis.iter_mut().enumerate() {
+            phi.incoming = predecessors
+                .iter()
+                .filter_map(|predecessor| {
+                    let value = exits[predecessor].get(slot)?;
+                    Some((*predecessor, value.clone()))
+                })
+                .collect();
+        }
+    }
+
+    let mut ir = Ir { blocks };
+    ir.remove_trivial_phis();
+    ir.renumber();
+    ir
+}
+
+/// Converts operations into their SSA representation as string
+///
+/// See [`lift`] for details.
+pub fn format_ir(operations: Vec<Operation>) -> Result<String> {
+    Ok(lift(&operations).to_string())
+}
+
+impl Ir {
+    // Replaces phis merging a single value (apart from themselves) by that value
+    fn remove_trivial_phis(&mut self) {
+        let mut replacements: HashMap<usize, Value> = HashMap::new();
+        loop {
+            let mut changed = false;
+            for phi in self.blocks.iter().flat_map(|block| block.phis.iter()) {
+                if replacements.contains_key(&phi.result) {
+                    continue;
+                }
+                let mut values = phi
+                    .incoming
+                    .iter()
+                    .map(|(_, value)| resolve(&replacements, value))
+                    .filter(|value| *value != Value::Variable(phi.result));
+                let Some(first) = values.next() else {
+                    continue;
+                };
+                if values.all(|value| value == first) {
+                    replacements.insert(phi.result, first);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        for block in self.blocks.iter_mut() {
+            block
+                .phis
+                .retain(|phi| !replacements.contains_key(&phi.result));
+        }
+        self.map_values(|value| resolve(&replacements, value), |result| result);
+    }
+
+    // Numbers variables consecutively in order of definition
+    fn renumber(&mut self) {
+        let mut numbers: HashMap<usize, usize> = HashMap::new();
+        for block in &self.blocks {
+            let phis = block.phis.iter().map(|phi| phi.result);
+            let instructions =
+                block
+                    .instructions
+                    .iter()
+                    .filter_map(|instruction| match instruction {
+                        Instruction::Compute { result, .. } | Instruction::Load { result, .. } => {
+                            Some(*result)
+                        }
+                        _ => None,
+                    });
+            for result in phis.chain(instructions) {
+                let number = numbers.len();
+                numbers.insert(result, number);
+            }
+        }
+        self.map_values(
+            |value| match value {
+                Value::Variable(n) => Value::Variable(numbers.get(n).copied().unwrap_or(*n)),
+                _ => value.clone(),
+            },
+            |result| numbers[&result],
+        );
+    }
+
+    // Rewrites every used value and every defined variable
+    fn map_values(&mut self, value: impl Fn(&Value) -> Value, result: impl Fn(usize) -> usize) {
+        let values = |args: &mut Vec<Value>| {
+            for arg in args.iter_mut() {
+                *arg = value(arg);
+            }
+        };
+        for block in self.blocks.iter_mut() {
+            for phi in block.phis.iter_mut() {
+                phi.result = result(phi.result);
+                for (_, incoming) in phi.incoming.iter_mut() {
+                    *incoming = value(incoming);
+                }
+            }
+            for instruction in block.instructions.iter_mut() {
+                match instruction {
+                    Instruction::Compute {
+                        result: r, args, ..
+                    } => {
+                        *r = result(*r);
+                        values(args);
+                    }
+                    Instruction::Load {
+                        result: r, address, ..
+                    } => {
+                        *r = result(*r);
+                        *address = value(address);
+                    }
+                    Instruction::Store {
+                        address, value: v, ..
+                    } => {
+                        *address = value(address);
+                        *v = value(v);
+                    }
+                    Instruction::Effect { args, .. } => values(args),
+                }
+            }
+            match &mut block.terminator {
+                Terminator::Fallthrough(_) => {}
+                Terminator::Jump(target) => *target = value(target),
+                Terminator::Branch {
+                    condition, target, ..
+                } => {
+                    *condition = value(condition);
+                    *target = value(target);
+                }
+                Terminator::Switch { index, .. } => *index = value(index),
+                Terminator::Exit { args, .. } => values(args),
+            }
+        }
+    }
+}
+
+impl fmt::Display for Phi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let incoming: Vec<String> = self
+            .incoming
+            .iter()
+            .map(|(predecessor, value)| format!("{predecessor:#x}: {value}"))
+            .collect();
+        write!(f, "v{} = phi({})", self.result, incoming.join(", "))
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Compute {
+                result,
+                opcode,
+                args,
+            } => write!(f, "v{result} = {}", call(*opcode, args)),
+            Instruction::Load {
+                result,
+                space,
+                address,
+            } => write!(f, "v{result} = {}[{address}]", space.name()),
+            Instruction::Store {
+                space,
+                address,
+                value,
+            } => write!(f, "{}[{address}] = {value}", space.name()),
+            Instruction::Effect { opcode, args } => write!(f, "{}", call(*opcode, args)),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Fallthrough(next) => write!(f, "jump {next:#x}"),
+            Terminator::Jump(target) => write!(f, "jump {target}"),
+            Terminator::Branch {
+                condition,
+                target,
+                fallthrough,
+            } => write!(f, "branch {condition} ? {target} : {fallthrough:#x}"),
+            Terminator::Switch {
+                index,
+                targets,
+                fallthrough,
+            } => {
+                let targets: Vec<String> = targets
+                    .iter()
+                    .map(|target| format!("{target:#x}"))
+                    .collect();
+                write!(
+                    f,
+                    "switch {index} [{}] : {fallthrough:#x}",
+                    targets.join(", ")
+                )
+            }
+            Terminator::Exit { opcode, args } => write!(f, "{}", call(*opcode, args)),
+        }
+    }
+}
+
+/// Prints the blocks separated by empty lines
+///
+/// Jumps to targets computed at runtime are followed by the resolved successors as comment.
+impl fmt::Display for Ir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, block) in self.blocks.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "block {:#x}:", block.offset)?;
+            for phi in &block.phis {
+                writeln!(f, "    {phi}")?;
+            }
+            for instruction in &block.instructions {
+                writeln!(f, "    {instruction}")?;
+            }
+            write!(f, "    {}", block.terminator)?;
+            if let Terminator::Jump(Value::Variable(_)) = block.terminator {
+                let successors: Vec<String> = block
+                    .successors
+                    .iter()
+                    .map(|successor| format!("{successor:#x}"))
+                    .collect();
+                write!(f, " // {}", successors.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+// Number of stack items on the stack when entering each reachable block
+//
+// Execution starts with an empty stack. Blocks entered with different stack heights (e.g. internal
+// functions called from different depths) use the largest height, blocks consuming more items than
+// present underflow and start with the items they consume.
+fn entry_heights(cfg: &ControlFlowGraph) -> HashMap<u32, usize> {
+    // Items consumed and items left on the stack when executing the block on an unknown stack
+    let effects: HashMap<u32, (usize, usize)> = cfg
+        .blocks()
+        .map(|block| {
+            let mut stack = SymbolicStack::new();
+            for operation in block.operations {
+                stack.apply(operation);
+            }
+            (block.offset(), (stack.inputs(), stack.len()))
+        })
+        .collect();
+    let mut heights: HashMap<u32, usize> = effects
+        .iter()
+        .map(|(offset, (inputs, _))| (*offset, *inputs))
+        .collect();
+    let mut visited = HashSet::new();
+    let mut worklist = vec![0];
+    while let Some(offset) = worklist.pop() {
+        visited.insert(offset);
+        let Some((inputs, outputs)) = effects.get(&offset) else {
+            continue;
+        };
+        let exit = (heights[&offset] - inputs + outputs).min(MAX_STACK_HEIGHT);
+        for successor in cfg.successors(offset) {
+            let Some(height) = heights.get_mut(&successor) else {
+                continue;
+            };
+            if exit > *height || !visited.contains(&successor) {
+                *height = exit.max(*height);
+                worklist.push(successor);
+            }
+        }
+    }
+    heights
+}
+
+fn resolve(replacements: &HashMap<usize, Value>, value: &Value) -> Value {
+    let mut value = value.clone();
+    while let Value::Variable(n) = value {
+        match replacements.get(&n) {
+            Some(replacement) => value = replacement.clone(),
+            None => break,
+        }
+    }
+    value
+}
+
+fn instruction(result: Option<usize>, opcode: Opcode, args: Vec<Value>) -> Instruction {
+    let space = match opcode {
+        Opcode::MLOAD | Opcode::MSTORE => Space::Memory,
+        Opcode::MSTORE8 => Space::MemoryByte,
+        Opcode::SLOAD | Opcode::SSTORE => Space::Storage,
+        Opcode::TLOAD | Opcode::TSTORE => Space::Transient,
+        _ => {
+            return match result {
+                Some(result) => Instruction::Compute {
+                    result,
+                    opcode,
+                    args,
+                },
+                None => Instruction::Effect { opcode, args },
+            }
+        }
+    };
+    let mut args = args.into_iter();
+    let address = args.next().expect("Loads and stores take an address");
+    match (result, args.next()) {
+        (Some(result), _) => Instruction::Load {
+            result,
+            space,
+            address,
+        },
+        (None, Some(value)) => Instruction::Store {
+            space,
+            address,
+            value,
+        },
+        (None, None) => unreachable!("Stores take a value"),
+    }
+}
+
+fn to_value(expr: &Expr) -> Value {
+    match expr {
+        Expr::Constant(bytes) => Value::Constant(bytes.clone()),
+        Expr::Variable(n) => Value::Variable(*n),
+        // Blocks start with all stack items they access and every result is bound to a variable
+        _ => unreachable!("Stack items of the IR are constants or variables"),
+    }
+}
+
+fn constant(value: u32) -> Value {
+    Value::Constant(value.to_be_bytes().to_vec())
+}
+
+fn call(opcode: Opcode, args: &[Value]) -> String {
+    let args: Vec<String> = args.iter().map(Value::to_string).collect();
+    format!(
+        "{}({})",
+        format!("{opcode:?}").to_lowercase(),
+        args.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+    use std::fs;
+
+    #[rstest]
+    fn loop_counter_is_merged_by_phi() {
+        // PUSH0 JUMPDEST DUP1 PUSH1 0xa GT ISZERO PUSH1 0x10 JUMPI PUSH1 0x1 ADD PUSH1 0x1 JUMP
+        // JUMPDEST STOP
+        let operations = disassemble_str("5f5b80600a11156010576001016001565b00").unwrap();
+        let expected = "\
+block 0x0:
+    jump 0x1
+
+block 0x1:
+    v0 = phi(0x0: 0x0, 0xa: v3)
+    v1 = gt(0xa, v0)
+    v2 = iszero(v1)
+    branch v2 ? 0x10 : 0xa
+
+block 0xa:
+    v3 = add(0x1, v0)
+    jump 0x1
+
+block 0x10:
+    stop()
+";
+        assert_eq!(lift(&operations).to_string(), expected);
+    }
+
+    #[rstest]
+    fn return_address_is_merged_by_phi() {
+        // Internal function at 0xd called with return addresses 0x5 and 0xb:
+        // PUSH1 0x5 PUSH1 0xd JUMP | JUMPDEST PUSH1 0xb PUSH1 0xd JUMP | JUMPDEST STOP |
+        // JUMPDEST JUMP
+        let operations = disassemble_str("6005600d565b600b600d565b005b56").unwrap();
+        let ir = lift(&operations);
+        let function = ir.blocks.iter().find(|block| block.offset == 0xd).unwrap();
+        assert_eq!(function.phis[0].to_string(), "v0 = phi(0x0: 0x5, 0x5: 0xb)");
+        assert_eq!(function.terminator, Terminator::Jump(Value::Variable(0)));
+        assert_eq!(function.successors, vec![0x5, 0xb]);
+    }
+
+    #[rstest]
+    // PUSH1 0x80 PUSH1 0x40 MSTORE
+    #[case("6080604052", "memory[0x40] = 0x80")]
+    // PUSH1 0x1 PUSH1 0x0 MSTORE8
+    #[case("6001600053", "memory8[0x0] = 0x1")]
+    // PUSH1 0x0 SLOAD
+    #[case("600054", "v0 = storage[0x0]")]
+    // PUSH1 0x1 PUSH1 0x0 TSTORE
+    #[case("600160005d", "transient[0x0] = 0x1")]
+    // PUSH1 0x20 PUSH1 0x0 PUSH1 0x0 CALLDATACOPY
+    #[case("60206000600037", "calldatacopy(0x0, 0x0, 0x20)")]
+    fn explicit_memory_and_storage(#[case] code: &str, #[case] expected: &str) {
+        let operations = disassemble_str(code).unwrap();
+        let ir = lift(&operations);
+        assert_eq!(ir.blocks[0].instructions[0].to_string(), expected);
+    }
+
+    #[rstest]
+    fn lift_contract_from_file() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let ir = lift(&operations);
+        // Every variable is defined exactly once
+        let mut definitions = Vec::new();
+        for block in &ir.blocks {
+            definitions.extend(block.phis.iter().map(|phi| phi.result));
+            definitions.extend(block.instructions.iter().filter_map(
+                |instruction| match instruction {
+                    Instruction::Compute { result, .. } | Instruction::Load { result, .. } => {
+                        Some(*result)
+                    }
+                    _ => None,
+                },
+            ));
+        }
+        assert_eq!(definitions, (0..definitions.len()).collect::<Vec<_>>());
+        // Internal functions returning to several call sites merge their return address
+        assert!(ir.blocks.iter().any(|block| block.phis.len() > 1));
+    }
+}
@@ -0,0 +1,129 @@
+//! Keccak-256 as used by the `SHA3` opcode and for function selectors
+//!
+//! This is the original Keccak padding used by Ethereum, not the padding standardized as SHA3-256.
+
+// Bytes absorbed per permutation for a 256 bit output
+const RATE: usize = 136;
+
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001,
+    0x0000000000008082,
+    0x800000000000808a,
+    0x8000000080008000,
+    0x000000000000808b,
+    0x0000000080000001,
+    0x8000000080008081,
+    0x8000000000008009,
+    0x000000000000008a,
+    0x0000000000000088,
+    0x0000000080008009,
+    0x000000008000000a,
+    0x000000008000808b,
+    0x800000000000008b,
+    0x8000000000008089,
+    0x8000000000008003,
+    0x8000000000008002,
+    0x8000000000000080,
+    0x000000000000800a,
+    0x800000008000000a,
+    0x8000000080008081,
+    0x8000000000008080,
+    0x0000000080000001,
+    0x8000000080008008,
+];
+
+// Rotation offsets and lane positions of the combined rho and pi steps
+const ROTATIONS: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+const LANES: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+/// Computes the Keccak-256 hash of the data
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::keccak::keccak256;
+///
+/// assert_eq!(keccak256(b"transfer(address,uint256)")[..4], [0xa9, 0x05, 0x9c, 0xbb]);
+/// ```
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut state = [0u64; 25];
+    let mut padded = data.to_vec();
+    padded.push(0x01);
+    padded.resize(padded.len().div_ceil(RATE) * RATE, 0);
+    *padded.last_mut().expect("Padding is not empty") |= 0x80;
+    for block in padded.chunks(RATE) {
+        for (lane, bytes) in state.iter_mut().zip(block.chunks(8)) {
+            *lane ^= u64::from_le_bytes(bytes.try_into().expect("Lanes have 8 bytes"));
+        }
+        permute(&mut state);
+    }
+    let mut hash = [0u8; 32];
+    for (bytes, lane) in hash.chunks_mut(8).zip(state) {
+        bytes.copy_from_slice(&lane.to_le_bytes());
+    }
+    hash
+}
+
+// The Keccak-f[1600] permutation, lanes are indexed by x + 5 * y
+fn permute(state: &mut [u64; 25]) {
+    for round_constant in ROUND_CONSTANTS {
+        // Theta
+        let mut columns = [0u64; 5];
+        for (x, column) in columns.iter_mut().enumerate() {
+            *column = (0..5).fold(0, |parity, y| parity ^ state[x + 5 * y]);
+        }
+        for x in 0..5 {
+            let d = columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                state[x + 5 * y] ^= d;
+            }
+        }
+        // Rho and pi
+        let mut moved = state[1];
+        for (rotation, lane) in ROTATIONS.iter().zip(LANES) {
+            let next = state[lane];
+            state[lane] = moved.rotate_left(*rotation);
+            moved = next;
+        }
+        // Chi
+        for y in 0..5 {
+            let row: [u64; 5] = state[5 * y..5 * y + 5]
+                .try_into()
+                .expect("Rows have 5 lanes");
+            for x in 0..5 {
+                state[x + 5 * y] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+            }
+        }
+        // Iota
+        state[0] ^= round_constant;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case(b"".to_vec(), "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470")]
+    #[case(b"abc".to_vec(), "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45")]
+    fn known_hashes(#[case] data: Vec<u8>, #[case] expected: &str) {
+        assert_eq!(hex::encode(keccak256(&data)), expected);
+    }
+
+    #[rstest]
+    fn matches_reference_implementation_across_block_boundaries() {
+        for length in [1, 135, 136, 137, 271, 272, 273, 1000] {
+            let data: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            assert_eq!(
+                keccak256(&data),
+                ethers::utils::keccak256(&data),
+                "{length}"
+            );
+        }
+    }
+}
@@ -23,22 +23,99 @@
 //! println!("{}", format_operations(instructions_from_bytes).unwrap());
 //!
 //! ```
+//!
+//! # Features
+//! - `std` (default) - The analysis modules and the command line tool. Without it the crate is
+//!   `no_std` and only needs `alloc`: decoding and formatting, [`Opcode`], [`Operation`], the
+//!   [`link`] and [`keccak`] modules remain, and errors are a minimal [`Error`] message instead
+//!   of an `eyre::Report`.
+//! - `parallel` - Disassemble batches on all cores with rayon, see `batch`.
+//! - `wasm` - wasm-bindgen exports for use from JavaScript, see `wasm`.
+//! - `python` - PyO3 bindings for a Python extension module built with maturin, see `python`.
+//! - `capi` - A C interface and generated header for linking from other languages, see `capi`.
+#![cfg_attr(not(feature = "std"), no_std)]
 #![warn(missing_docs)]
-use crate::decode::decode_operation;
-use std::fmt::Write;
+extern crate alloc;
 
-use eyre::Result;
+use crate::decode::decode_operations;
+use crate::error::eyre;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
 
 mod decode;
+mod error;
 
+#[cfg(feature = "std")]
+pub mod abi;
+#[cfg(feature = "std")]
+pub mod artifacts;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod calls;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "std")]
+pub mod cfg;
+#[cfg(feature = "std")]
+pub mod decompile;
+#[cfg(feature = "std")]
+pub mod diff;
+pub mod eof;
+#[cfg(feature = "std")]
+pub mod fingerprint;
+#[cfg(feature = "std")]
+pub mod immutables;
+#[cfg(feature = "std")]
+pub mod interpreter;
+#[cfg(feature = "std")]
+pub mod ir;
+pub mod keccak;
+pub mod link;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod metadata;
+#[cfg(feature = "std")]
+pub mod pattern;
+#[cfg(feature = "std")]
+pub mod proxy;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "std")]
+pub mod rules;
+#[cfg(feature = "std")]
+pub mod selectors;
+#[cfg(feature = "std")]
+pub mod similarity;
+#[cfg(feature = "std")]
+pub mod sourcemap;
+#[cfg(feature = "std")]
+pub mod stack;
+#[cfg(feature = "std")]
+pub mod storage;
+#[cfg(feature = "std")]
+pub mod symbolic;
+#[cfg(feature = "std")]
+pub mod trace;
 pub mod types;
-pub use types::{Opcode, Operation};
+#[cfg(feature = "std")]
+pub mod u256;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub use error::{Error, Result};
+pub use types::{Delegation, Disassembly, Opcode, Operation};
 
 #[cfg(test)]
 mod test_utils;
 
 /// Disassemble a hex encoded string into a vector of instructions / operations
 ///
+/// Unlinked library addresses (`__$<hash>$__` or legacy `__<name>__` placeholders) are decoded as
+/// zero PUSH20 operands with the placeholder in [`Operation::link`], see [`link`] to link them.
+/// Placeholders that are not the operand of a PUSH20 are rejected with an error.
+///
 /// # Arguments
 /// - `input` - A hex encoded string representing the bytecode to disassemble
 ///
@@ -52,17 +129,48 @@ mod test_utils;
 /// ```
 pub fn disassemble_str(input: &str) -> Result<Vec<Operation>> {
     let input = input.trim_start_matches("0x");
+    let (input, placeholders) = link::zero_placeholders(input);
     let bytes = hex::decode(input)?;
-    disassemble_bytes(bytes)
+    let mut operations = disassemble_bytes(bytes)?;
+    link::attach_placeholders(&mut operations, placeholders)?;
+    Ok(operations)
+}
+
+/// Disassemble the code of an account, detecting EIP-7702 delegation designators
+///
+/// Delegation designators (`0xef0100` followed by an address) are returned as
+/// [`Disassembly::Delegation`] without attempting to decode them, any other code is disassembled
+/// as in `disassemble_bytes`.
+///
+/// # Arguments
+/// - `bytes` - A vector of bytes representing the code of the account
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble, Disassembly};
+///
+/// let bytes = hex::decode("ef0100bebebebebebebebebebebebebebebebebebebebe").unwrap();
+/// match disassemble(bytes).unwrap() {
+///     Disassembly::Delegation(delegation) => assert_eq!(delegation.address, [0xbe; 20]),
+///     Disassembly::Operations(_) => unreachable!(),
+/// }
+/// ```
+pub fn disassemble(bytes: Vec<u8>) -> Result<Disassembly> {
+    match Delegation::from_bytes(&bytes) {
+        Some(delegation) => Ok(Disassembly::Delegation(delegation)),
+        None => Ok(Disassembly::Operations(disassemble_bytes(bytes)?)),
+    }
 }
 
 /// Disassemble a vector of bytes into a vector of decoded Operations
 ///
 /// Will stop disassembling when it encounters a push instruction with a size greater than
-/// remaining bytes in the input.
+/// remaining bytes in the input. The truncated instruction is left out without any output.
 ///
 /// Automatically detects EOF containers (starting with 0xef00) and decodes EOF-specific
-/// opcodes only when appropriate.
+/// opcodes only when appropriate. Returns an error for EIP-7702 delegation designators, use
+/// `disassemble` to handle those.
 ///
 /// # Arguments
 /// - `bytes` - A vector of bytes representing the encoded bytecode
@@ -77,24 +185,17 @@ pub fn disassemble_str(input: &str) -> Result<Vec<Operation>> {
 /// let instructions_from_bytes = disassemble_bytes(bytes).unwrap();
 /// ```
 pub fn disassemble_bytes(bytes: Vec<u8>) -> Result<Vec<Operation>> {
+    if let Some(delegation) = Delegation::from_bytes(&bytes) {
+        return Err(eyre!(
+            "Bytecode is an EIP-7702 delegation designator to 0x{}",
+            hex::encode(delegation.address)
+        ));
+    }
+
     // Detect EOF container: starts with 0xef00
     let is_eof = bytes.len() >= 2 && bytes[0] == 0xef && bytes[1] == 0x00;
 
-    let mut operations = Vec::new();
-    let mut new_operation: Operation;
-    let mut offset = 0;
-    let mut bytes_iter = bytes.into_iter();
-    while bytes_iter.len() > 0 {
-        (new_operation, offset) = match decode_operation(&mut bytes_iter, offset, is_eof) {
-            Ok((operation, new_offset)) => (operation, new_offset),
-            Err(e) => {
-                println!("Stop decoding at offset {offset} due to error : {e}");
-                break;
-            }
-        };
-        operations.push(new_operation);
-    }
-    Ok(operations)
+    Ok(decode_operations(bytes, 0, is_eof))
 }
 
 /// Converts a vector of decoded operations into a human readable formatted string
@@ -219,6 +320,30 @@ mod tests {
         );
     }
 
+    #[rstest]
+    fn disassemble_delegation_designator() {
+        let bytes = hex::decode("ef0100bebebebebebebebebebebebebebebebebebebebe").unwrap();
+        assert_eq!(
+            disassemble(bytes.clone()).unwrap(),
+            Disassembly::Delegation(Delegation {
+                address: [0xbe; 20]
+            })
+        );
+        assert!(disassemble_bytes(bytes).is_err());
+    }
+
+    #[rstest]
+    #[case("ef0100bebebebebebebebebebebebebebebebebebebe")] // Address too short
+    #[case("ef0100bebebebebebebebebebebebebebebebebebebebebe")] // Address too long
+    #[case("ef0000bebebebebebebebebebebebebebebebebebebebe")] // Not the delegation magic
+    fn disassemble_non_delegation(#[case] code: &str) {
+        let bytes = hex::decode(code).unwrap();
+        assert!(matches!(
+            disassemble(bytes).unwrap(),
+            Disassembly::Operations(_)
+        ));
+    }
+
     // EOF container tests
     // EOF format: ef0001 [header] [types] [code] [data]
     // Header: 01 XXXX (type section) 02 YYYY ZZZZ (code section) 04 WWWW (data section) 00 (terminator)
@@ -0,0 +1,236 @@
+//! Library link placeholders in unlinked Solidity bytecode
+//!
+//! Until libraries are linked, solc emits a 40 character placeholder instead of the hex encoded
+//! library address, either `__$<hash>$__` with the first 34 hex characters of the keccak256 hash of
+//! the fully qualified library name (solc 0.5 and later) or `__<name>__` with the name padded
+//! with underscores (earlier versions).
+use crate::error::{eyre, Result};
+use crate::keccak::keccak256;
+use crate::types::{Opcode, Operation};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+// Length of a placeholder in hex characters (the size of an address)
+const PLACEHOLDER_LENGTH: usize = 40;
+
+/// A placeholder for the address of a library
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LinkPlaceholder {
+    /// The first 34 hex characters of the hash of the fully qualified library name
+    Hash(String),
+    /// The library name, truncated to 36 characters
+    Name(String),
+}
+
+impl LinkPlaceholder {
+    /// Parses a 40 character placeholder
+    pub fn parse(placeholder: &str) -> Option<Self> {
+        if placeholder.len() != PLACEHOLDER_LENGTH || !placeholder.starts_with("__") {
+            return None;
+        }
+        let hash = placeholder
+            .strip_prefix("__$")
+            .and_then(|rest| rest.strip_suffix("$__"));
+        match hash {
+            Some(hash) if hash.bytes().all(|byte| byte.is_ascii_hexdigit()) => {
+                Some(LinkPlaceholder::Hash(hash.to_lowercase()))
+            }
+            _ => {
+                let name = placeholder[2..].trim_end_matches('_');
+                (!name.is_empty()).then(|| LinkPlaceholder::Name(name.to_string()))
+            }
+        }
+    }
+
+    /// Whether the placeholder stands for the library with the given fully qualified name
+    /// (`<file>:<library>`) or, for name placeholders, library name
+    pub fn matches(&self, library: &str) -> bool {
+        match self {
+            LinkPlaceholder::Hash(hash) => {
+                hex::encode(keccak256(library.as_bytes())).starts_with(hash)
+            }
+            LinkPlaceholder::Name(name) => {
+                let truncated: String = library.chars().take(PLACEHOLDER_LENGTH - 4).collect();
+                *name == truncated || library.rsplit(':').next() == Some(name.as_str())
+            }
+        }
+    }
+}
+
+impl fmt::Display for LinkPlaceholder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkPlaceholder::Hash(hash) => write!(f, "__${hash}$__"),
+            LinkPlaceholder::Name(name) => write!(f, "__{name:_<38}"),
+        }
+    }
+}
+
+/// Finds all link placeholders in hex encoded bytecode
+///
+/// Returns the byte offset of each placeholder in the decoded bytecode.
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::link::{find_placeholders, LinkPlaceholder};
+///
+/// let code = "73__$0123456789abcdef0123456789abcdef01$__00";
+/// let placeholders = find_placeholders(code);
+/// assert_eq!(placeholders[0].0, 1);
+/// assert!(matches!(placeholders[0].1, LinkPlaceholder::Hash(_)));
+/// ```
+pub fn find_placeholders(code: &str) -> Vec<(u32, LinkPlaceholder)> {
+    let code = code.trim_start_matches("0x");
+    let mut placeholders = Vec::new();
+    let mut position = 0;
+    while position + PLACEHOLDER_LENGTH <= code.len() {
+        let window = code.get(position..position + PLACEHOLDER_LENGTH);
+        match window.and_then(LinkPlaceholder::parse) {
+            Some(placeholder) => {
+                placeholders.push(((position / 2) as u32, placeholder));
+                position += PLACEHOLDER_LENGTH;
+            }
+            None => position += 2,
+        }
+    }
+    placeholders
+}
+
+/// Replaces link placeholders with the addresses of the given libraries
+///
+/// Libraries are identified by their fully qualified name (`<file>:<library>`), placeholders of
+/// libraries that are not given are kept.
+///
+/// # Arguments
+/// - `code` - The hex encoded unlinked bytecode
+/// - `libraries` - The addresses of the libraries by fully qualified name
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::link::link_libraries;
+/// use std::collections::BTreeMap;
+///
+/// let code = format!("73{:_<40}00", "__Lib");
+/// let libraries = BTreeMap::from([("Lib.sol:Lib".to_string(), [0x11; 20])]);
+/// let linked = link_libraries(&code, &libraries);
+/// assert_eq!(linked, format!("73{}00", "11".repeat(20)));
+/// ```
+pub fn link_libraries(code: &str, libraries: &BTreeMap<String, [u8; 20]>) -> String {
+    let mut linked = code.trim_start_matches("0x").to_string();
+    for (offset, placeholder) in find_placeholders(&linked) {
+        let address = libraries
+            .iter()
+            .find(|(library, _)| placeholder.matches(library))
+            .map(|(_, address)| address);
+        if let Some(address) = address {
+            let start = offset as usize * 2;
+            linked.replace_range(start..start + PLACEHOLDER_LENGTH, &hex::encode(address));
+        }
+    }
+    linked
+}
+
+// Replaces placeholders with zero addresses so the code can be hex decoded
+pub(crate) fn zero_placeholders(code: &str) -> (String, Vec<(u32, LinkPlaceholder)>) {
+    let placeholders = find_placeholders(code);
+    let mut zeroed = code.to_string();
+    for (offset, _) in &placeholders {
+        let start = *offset as usize * 2;
+        zeroed.replace_range(
+            start..start + PLACEHOLDER_LENGTH,
+            &"0".repeat(PLACEHOLDER_LENGTH),
+        );
+    }
+    (zeroed, placeholders)
+}
+
+// Marks the PUSH20 operations whose operand is a placeholder
+pub(crate) fn attach_placeholders(
+    operations: &mut [Operation],
+    placeholders: Vec<(u32, LinkPlaceholder)>,
+) -> Result<()> {
+    for (offset, placeholder) in placeholders {
+        let operation = operations
+            .iter_mut()
+            .find(|operation| operation.opcode == Opcode::PUSH20 && operation.offset + 1 == offset)
+            .ok_or_else(|| {
+                eyre!("Link placeholder {placeholder} at byte {offset} is not a PUSH20 operand")
+            })?;
+        operation.link = Some(placeholder);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+
+    fn placeholder(library: &str) -> String {
+        format!(
+            "__${}$__",
+            &hex::encode(keccak256(library.as_bytes()))[..34]
+        )
+    }
+
+    #[rstest]
+    fn disassemble_unlinked_code() {
+        // PUSH20 <Lib> DELEGATECALL STOP
+        let code = format!("0x73{}f400", placeholder("src/Lib.sol:Lib"));
+        let operations = disassemble_str(&code).unwrap();
+        assert_eq!(operations.len(), 3);
+        assert_eq!(operations[0].input, vec![0; 20]);
+        assert!(operations[0]
+            .link
+            .as_ref()
+            .unwrap()
+            .matches("src/Lib.sol:Lib"));
+        assert_eq!(
+            format!("{:?}", operations[0]),
+            format!("00000000: PUSH20 {}", placeholder("src/Lib.sol:Lib"))
+        );
+        assert_eq!(operations[1].offset, 21);
+    }
+
+    #[rstest]
+    fn link_only_given_libraries() {
+        let code = format!(
+            "73{}73{}00",
+            placeholder("src/A.sol:A"),
+            placeholder("src/B.sol:B")
+        );
+        let libraries = BTreeMap::from([("src/B.sol:B".to_string(), [0xbb; 20])]);
+        let linked = link_libraries(&code, &libraries);
+        assert_eq!(
+            linked,
+            format!("73{}73{}00", placeholder("src/A.sol:A"), "bb".repeat(20))
+        );
+        let operations = disassemble_str(&linked).unwrap();
+        assert!(operations[0].link.is_some());
+        assert_eq!(operations[1].link, None);
+        assert_eq!(operations[1].input, vec![0xbb; 20]);
+    }
+
+    #[rstest]
+    #[case("__$0123456789abcdef0123456789abcdef01$__", Some(LinkPlaceholder::Hash("0123456789abcdef0123456789abcdef01".to_string())))]
+    #[case("__contracts/Lib.sol:Lib_________________", Some(LinkPlaceholder::Name("contracts/Lib.sol:Lib".to_string())))]
+    #[case("0000000000000000000000000000000000000000", None)]
+    #[case("________________________________________", None)]
+    fn parse_placeholders(#[case] placeholder: &str, #[case] expected: Option<LinkPlaceholder>) {
+        assert_eq!(LinkPlaceholder::parse(placeholder), expected);
+        if let Some(expected) = expected {
+            assert_eq!(expected.to_string(), placeholder);
+        }
+    }
+
+    #[rstest]
+    fn reject_placeholder_outside_of_push20() {
+        let code = format!("60{}", placeholder("A.sol:A"));
+        assert!(disassemble_str(&code).is_err());
+    }
+}
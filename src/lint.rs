@@ -0,0 +1,561 @@
+//! Security lints over decoded operations
+//!
+//! Lints implement [`Lint`] and are driven by a single symbolic exploration of the code: every
+//! executed operation is passed to [`Lint::visit`] together with its symbolic arguments, and
+//! [`Lint::finish`] is called with the control flow graph once exploration is done. The built-in
+//! lints are returned by [`default_lints`].
+use crate::cfg::ControlFlowGraph;
+use crate::eof::EofContainer;
+use crate::stack::Expr;
+use crate::symbolic::{explore, Step};
+use crate::types::{Opcode, Operation};
+use eyre::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt::{self, Write};
+use std::ops::RangeInclusive;
+
+// Maximum depth at which uses of a call result are searched in the arguments of operations
+const MAX_USE_DEPTH: usize = 4;
+
+/// How severe a finding is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Worth knowing when reviewing the code
+    Info,
+    /// Unlikely to be exploitable on its own
+    Low,
+    /// Potentially exploitable depending on the context
+    Medium,
+    /// Likely exploitable or destructive
+    High,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{self:?}").to_lowercase())
+    }
+}
+
+/// An issue reported by a lint
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    /// The name of the lint reporting the finding
+    pub lint: &'static str,
+    /// How severe the finding is
+    pub severity: Severity,
+    /// The offset of the offending instruction
+    pub offset: u32,
+    /// Offsets of the first and last instruction involved, in code order
+    pub span: RangeInclusive<u32>,
+    /// A description of the finding
+    pub message: String,
+}
+
+impl Finding {
+    /// Creates a finding involving a single instruction
+    pub fn new(lint: &'static str, severity: Severity, offset: u32, message: &str) -> Self {
+        Finding {
+            lint,
+            severity,
+            offset,
+            span: offset..=offset,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:08x}: [{}] {}: {} ({:08x}..{:08x})",
+            self.offset,
+            self.severity,
+            self.lint,
+            self.message,
+            self.span.start(),
+            self.span.end()
+        )
+    }
+}
+
+/// A check reporting findings about the code
+pub trait Lint {
+    /// The name identifying the lint in findings
+    fn name(&self) -> &'static str;
+
+    /// Called for every operation executed during exploration
+    ///
+    /// Operations reached in several stack contexts are visited once per context, duplicate
+    /// findings at the same offset are dropped.
+    fn visit(&mut self, _step: &Step, _findings: &mut Vec<Finding>) {}
+
+    /// Called once after exploration with the control flow graph of the code
+    fn finish(&mut self, _cfg: &ControlFlowGraph, _findings: &mut Vec<Finding>) {}
+}
+
+/// The built-in lints
+pub fn default_lints() -> Vec<Box<dyn Lint>> {
+    vec![
+        Box::new(ReachableSelfdestruct),
+        Box::new(ControlledDelegatecall),
+        Box::new(OriginComparison),
+        Box::new(UncheckedCall::default()),
+        Box::new(StoreAfterCall::default()),
+        Box::new(CalldataJump),
+    ]
+}
+
+/// Runs the given lints over the code reachable from its start
+///
+/// Findings are sorted by offset and deduplicated by lint and offset.
+///
+/// # Arguments
+/// - `operations` - The decoded runtime code
+/// - `lints` - The lints to run
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, lint::{default_lints, run_lints, Severity}};
+///
+/// // selfdestruct(caller)
+/// let operations = disassemble_str("33ff").unwrap();
+/// let findings = run_lints(&operations, &mut default_lints());
+/// assert_eq!(findings[0].lint, "reachable-selfdestruct");
+/// assert_eq!(findings[0].severity, Severity::High);
+/// ```
+pub fn run_lints(operations: &[Operation], lints: &mut [Box<dyn Lint>]) -> Vec<Finding> {
+    lint_from(operations, &[0], lints)
+}
+
+/// Runs the given lints over the code sections of an EOF container
+///
+/// Same as [`run_lints`] but every code section is explored from its start, instead of the
+/// container header at offset 0. Offsets are relative to the start of the container.
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{eof::EofContainer, lint::{default_lints, run_eof_lints}};
+///
+/// // One code section with PUSH0 PUSH0 PUSH0 CALLDATALOAD EXTDELEGATECALL POP STOP
+/// let bytes = hex::decode("ef000101000402000100070400000000800003 5f5f5f35f95000".replace(' ', ""));
+/// let container = EofContainer::parse(&bytes.unwrap()).unwrap();
+/// let findings = run_eof_lints(&container, &mut default_lints());
+/// assert_eq!(findings[0].lint, "controlled-delegatecall");
+/// assert_eq!(findings[0].offset, 23);
+/// ```
+pub fn run_eof_lints(container: &EofContainer, lints: &mut [Box<dyn Lint>]) -> Vec<Finding> {
+    let starts: Vec<u32> = container
+        .code
+        .iter()
+        .map(|section| section.offset)
+        .collect();
+    lint_from(&container.code_operations().concat(), &starts, lints)
+}
+
+// Runs the lints over the code reachable from the given entry points
+fn lint_from(
+    operations: &[Operation],
+    starts: &[u32],
+    lints: &mut [Box<dyn Lint>],
+) -> Vec<Finding> {
+    let cfg = ControlFlowGraph::with_entries(operations, starts);
+    let mut findings = Vec::new();
+    explore(&cfg.index, starts, |step| {
+        for lint in lints.iter_mut() {
+            lint.visit(step, &mut findings);
+        }
+    });
+    for lint in lints.iter_mut() {
+        lint.finish(&cfg, &mut findings);
+    }
+    let mut unique: BTreeMap<(u32, &'static str), Finding> = BTreeMap::new();
+    for finding in findings {
+        unique
+            .entry((finding.offset, finding.lint))
+            .or_insert(finding);
+    }
+    unique.into_values().collect()
+}
+
+/// Runs the built-in lints and formats the findings, one per line
+pub fn format_findings(operations: Vec<Operation>) -> Result<String> {
+    format_all(run_lints(&operations, &mut default_lints()))
+}
+
+/// Runs the built-in lints over the code sections of an EOF container and formats the findings,
+/// one per line
+pub fn format_eof_findings(container: &EofContainer) -> Result<String> {
+    format_all(run_eof_lints(container, &mut default_lints()))
+}
+
+fn format_all(findings: Vec<Finding>) -> Result<String> {
+    let mut formatted = String::new();
+    for finding in findings {
+        writeln!(formatted, "{finding}")?;
+    }
+    Ok(formatted)
+}
+
+/// Reports reachable `SELFDESTRUCT` instructions
+pub struct ReachableSelfdestruct;
+
+impl Lint for ReachableSelfdestruct {
+    fn name(&self) -> &'static str {
+        "reachable-selfdestruct"
+    }
+
+    fn visit(&mut self, step: &Step, findings: &mut Vec<Finding>) {
+        if step.operation.opcode == Opcode::SELFDESTRUCT {
+            findings.push(Finding::new(
+                self.name(),
+                Severity::High,
+                step.operation.offset,
+                "SELFDESTRUCT is reachable",
+            ));
+        }
+    }
+}
+
+/// Reports `DELEGATECALL` and `CALLCODE` to targets read from the call data
+pub struct ControlledDelegatecall;
+
+impl Lint for ControlledDelegatecall {
+    fn name(&self) -> &'static str {
+        "controlled-delegatecall"
+    }
+
+    fn visit(&mut self, step: &Step, findings: &mut Vec<Finding>) {
+        let target = match step.operation.opcode {
+            Opcode::DELEGATECALL | Opcode::CALLCODE => &step.args[1],
+            Opcode::EXTDELEGATECALL => &step.args[0],
+            _ => return,
+        };
+        if target.contains(Opcode::CALLDATALOAD) {
+            findings.push(Finding::new(
+                self.name(),
+                Severity::High,
+                step.operation.offset,
+                "Code of an address taken from the call data is executed in this context",
+            ));
+        }
+    }
+}
+
+/// Reports comparisons involving `ORIGIN`, e.g. authorization via `tx.origin`
+pub struct OriginComparison;
+
+impl Lint for OriginComparison {
+    fn name(&self) -> &'static str {
+        "origin-comparison"
+    }
+
+    fn visit(&mut self, step: &Step, findings: &mut Vec<Finding>) {
+        let is_comparison = matches!(
+            step.operation.opcode,
+            Opcode::EQ | Opcode::LT | Opcode::GT | Opcode::SLT | Opcode::SGT
+        );
+        if is_comparison && step.args.iter().any(|arg| arg.contains(Opcode::ORIGIN)) {
+            findings.push(Finding::new(
+                self.name(),
+                Severity::Medium,
+                step.operation.offset,
+                "ORIGIN is used in a comparison",
+            ));
+        }
+    }
+}
+
+/// Reports calls whose success flag is discarded
+#[derive(Default)]
+pub struct UncheckedCall {
+    // Results of each call site
+    results: BTreeMap<u32, Vec<Expr>>,
+    // Call results used by any operation other than POP
+    used: HashSet<Expr>,
+}
+
+impl Lint for UncheckedCall {
+    fn name(&self) -> &'static str {
+        "unchecked-call"
+    }
+
+    fn visit(&mut self, step: &Step, _findings: &mut Vec<Finding>) {
+        let opcode = step.operation.opcode;
+        if opcode.is_call() {
+            if let Some(result) = &step.result {
+                let results = self.results.entry(step.operation.offset).or_default();
+                if !results.contains(result) {
+                    results.push(result.clone());
+                }
+            }
+        }
+        if opcode != Opcode::POP && !opcode.is_dup() && !opcode.is_swap() {
+            for arg in &step.args {
+                collect_call_results(arg, MAX_USE_DEPTH, &mut self.used);
+            }
+        }
+    }
+
+    fn finish(&mut self, _cfg: &ControlFlowGraph, findings: &mut Vec<Finding>) {
+        for (offset, results) in &self.results {
+            if !results.iter().any(|result| self.used.contains(result)) {
+                findings.push(Finding::new(
+                    self.name(),
+                    Severity::Medium,
+                    *offset,
+                    "The success of the call is never checked",
+                ));
+            }
+        }
+    }
+}
+
+fn collect_call_results(expr: &Expr, depth: usize, results: &mut HashSet<Expr>) {
+    let Expr::Op(opcode, args) = expr else {
+        return;
+    };
+    if opcode.is_call() {
+        results.insert(expr.clone());
+    } else if depth > 0 {
+        for arg in args.iter() {
+            collect_call_results(arg, depth - 1, results);
+        }
+    }
+}
+
+/// Reports `SSTORE` reachable after a state changing external call, which may allow reentrancy
+///
+/// Reachability follows the edges of the control flow graph, so paths through internal functions
+/// may combine calls and stores of different public functions.
+#[derive(Default)]
+pub struct StoreAfterCall {
+    calls: BTreeSet<u32>,
+    stores: BTreeSet<u32>,
+}
+
+impl Lint for StoreAfterCall {
+    fn name(&self) -> &'static str {
+        "sstore-after-call"
+    }
+
+    fn visit(&mut self, step: &Step, _findings: &mut Vec<Finding>) {
+        match step.operation.opcode {
+            Opcode::CALL
+            | Opcode::CALLCODE
+            | Opcode::DELEGATECALL
+            | Opcode::EXTCALL
+            | Opcode::EXTDELEGATECALL => {
+                self.calls.insert(step.operation.offset);
+            }
+            Opcode::SSTORE => {
+                self.stores.insert(step.operation.offset);
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self, cfg: &ControlFlowGraph, findings: &mut Vec<Finding>) {
+        let block_of: HashMap<u32, u32> = cfg
+            .blocks()
+            .flat_map(|block| {
+                block
+                    .operations
+                    .iter()
+                    .map(move |operation| (operation.offset, block.offset()))
+            })
+            .collect();
+        let mut reported = BTreeSet::new();
+        for call in &self.calls {
+            let Some(start) = block_of.get(call) else {
+                continue;
+            };
+            let mut visited = HashSet::from([*start]);
+            let mut queue = VecDeque::from([*start]);
+            let mut first = true;
+            while let Some(offset) = queue.pop_front() {
+                let Some(block) = cfg.block(offset) else {
+                    continue;
+                };
+                for operation in block.operations {
+                    let after_call = !first || operation.offset > *call;
+                    if after_call
+                        && self.stores.contains(&operation.offset)
+                        && reported.insert(operation.offset)
+                    {
+                        findings.push(Finding {
+                            span: (*call).min(operation.offset)..=(*call).max(operation.offset),
+                            ..Finding::new(
+                                self.name(),
+                                Severity::Medium,
+                                operation.offset,
+                                &format!("Storage is written after the call at {call:#x}"),
+                            )
+                        });
+                    }
+                }
+                first = false;
+                for successor in cfg.successors(offset) {
+                    if visited.insert(successor) {
+                        queue.push_back(successor);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reports jumps to targets read from the call data
+pub struct CalldataJump;
+
+impl Lint for CalldataJump {
+    fn name(&self) -> &'static str {
+        "calldata-jump"
+    }
+
+    fn visit(&mut self, step: &Step, findings: &mut Vec<Finding>) {
+        let is_jump = matches!(step.operation.opcode, Opcode::JUMP | Opcode::JUMPI);
+        if is_jump && step.args[0].contains(Opcode::CALLDATALOAD) {
+            findings.push(Finding::new(
+                self.name(),
+                Severity::High,
+                step.operation.offset,
+                "The jump target is taken from the call data",
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+    use std::fs;
+
+    fn lints(code: &str) -> Vec<(&'static str, u32)> {
+        let operations = disassemble_str(&code.replace(' ', "")).unwrap();
+        run_lints(&operations, &mut default_lints())
+            .into_iter()
+            .map(|finding| (finding.lint, finding.offset))
+            .collect()
+    }
+
+    #[rstest]
+    // selfdestruct(caller)
+    #[case("33 ff", vec![("reachable-selfdestruct", 0x1)])]
+    // pop(delegatecall(gas, calldataload(0x4), 0x0, 0x0, 0x0, 0x0))
+    #[case(
+        "6000600060006000 600435 5a f4 50 00",
+        vec![("controlled-delegatecall", 0xc), ("unchecked-call", 0xc)]
+    )]
+    // if (eq(caller, origin)) { stop } else { stop }
+    #[case("3233 14 6008 57 00 00 5b00", vec![("origin-comparison", 0x2)])]
+    // pop(call(gas, caller, callvalue, 0x0, 0x0, 0x0, 0x0)) sstore(0x0, 0x1)
+    #[case(
+        "60006000600060003433 5a f1 50 6001600055 00",
+        vec![("unchecked-call", 0xb), ("sstore-after-call", 0x11)]
+    )]
+    // if (iszero(call(gas, caller, callvalue, 0x0, 0x0, 0x0, 0x0))) { revert }
+    #[case("60006000600060003433 5a f1 15 6011 57 00 5b 5f5f fd", vec![])]
+    // jump(calldataload(0x0))
+    #[case("600035 56", vec![("calldata-jump", 0x3)])]
+    fn builtin_lints(#[case] code: &str, #[case] expected: Vec<(&str, u32)>) {
+        assert_eq!(lints(code), expected);
+    }
+
+    #[rstest]
+    fn sstore_after_call_in_successor_block() {
+        // pop(call(...)) jump(0x10) jumpdest sstore(0x0, 0x1)
+        let findings = lints("60006000600060003433 5a f1 50 6010 56 5b 6001600055 00");
+        assert_eq!(
+            findings,
+            vec![("unchecked-call", 0xb), ("sstore-after-call", 0x15)]
+        );
+    }
+
+    #[rstest]
+    fn custom_lint() {
+        struct Timestamp;
+        impl Lint for Timestamp {
+            fn name(&self) -> &'static str {
+                "timestamp"
+            }
+            fn visit(&mut self, step: &Step, findings: &mut Vec<Finding>) {
+                if step.operation.opcode == Opcode::TIMESTAMP {
+                    findings.push(Finding::new(
+                        self.name(),
+                        Severity::Info,
+                        step.operation.offset,
+                        "TIMESTAMP is used",
+                    ));
+                }
+            }
+        }
+        let operations = disassemble_str("5f425500").unwrap();
+        let findings = run_lints(&operations, &mut [Box::new(Timestamp) as Box<dyn Lint>]);
+        assert_eq!(
+            findings[0].to_string(),
+            "00000001: [info] timestamp: TIMESTAMP is used (00000001..00000001)"
+        );
+    }
+
+    #[rstest]
+    fn lint_eof_code_sections() {
+        // Section 0: PUSH0 CALLF 1 STOP
+        // Section 1: PUSH0 PUSH0 DUP3 EXTDELEGATECALL POP RETF
+        let code = "ef000101000802000200050006040000000080000101000004 \
+                    5fe3000100 5f5f82f950e4";
+        let bytes = hex::decode(code.replace(' ', "")).unwrap();
+        let container = EofContainer::parse(&bytes).unwrap();
+        let findings: Vec<(&str, u32)> = run_eof_lints(&container, &mut default_lints())
+            .into_iter()
+            .map(|finding| (finding.lint, finding.offset))
+            .collect();
+        // The argument of section 1 is unknown, only the unchecked result is reported
+        assert_eq!(findings, vec![("unchecked-call", 33)]);
+        // Exploring from the header does not reach the code sections
+        assert!(run_lints(
+            &crate::disassemble_bytes(bytes).unwrap(),
+            &mut default_lints()
+        )
+        .is_empty());
+    }
+
+    #[rstest]
+    fn lint_store_after_call_in_eof() {
+        // PUSH0 PUSH0 PUSH0 PUSH0 EXTCALL POP PUSH0 PUSH0 SSTORE STOP
+        let bytes =
+            hex::decode("ef0001010004020001000a04000000008000045f5f5f5ff8505f5f5500").unwrap();
+        let container = EofContainer::parse(&bytes).unwrap();
+        let findings: Vec<(&str, u32)> = run_eof_lints(&container, &mut default_lints())
+            .into_iter()
+            .map(|finding| (finding.lint, finding.offset))
+            .collect();
+        // Offsets are relative to the container, the code section starts after the 19 byte header
+        assert_eq!(
+            findings,
+            vec![("unchecked-call", 0x17), ("sstore-after-call", 0x1b)]
+        );
+    }
+
+    #[rstest]
+    fn lint_truncated_rjumpv() {
+        // PUSH0 RJUMPV without its jump table at the end of the only code section
+        let bytes = hex::decode("ef0001010004020001000204000000008000015fe2").unwrap();
+        let container = EofContainer::parse(&bytes).unwrap();
+        assert!(run_eof_lints(&container, &mut default_lints()).is_empty());
+    }
+
+    #[rstest]
+    fn weth_is_clean() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let findings = run_lints(&operations, &mut default_lints());
+        assert!(findings.is_empty(), "{findings:?}");
+    }
+}
@@ -0,0 +1,318 @@
+use evm_disassembler::abi::format_operations_with_abi;
+use evm_disassembler::artifacts::load_artifacts;
+use evm_disassembler::batch::{disassemble_batch, read_path, BatchItem, BatchOptions};
+use evm_disassembler::calls::{format_call_sites, format_eof_call_sites};
+use evm_disassembler::decompile::decompile;
+use evm_disassembler::diff::{diff_operations, format_diff, DiffOptions};
+use evm_disassembler::eof::{EofContainer, MAGIC};
+use evm_disassembler::fingerprint::format_fingerprint;
+use evm_disassembler::ir::format_ir;
+use evm_disassembler::link::find_placeholders;
+use evm_disassembler::lint::{format_eof_findings, format_findings};
+use evm_disassembler::metadata::strip_metadata;
+use evm_disassembler::pattern::format_matches;
+use evm_disassembler::rules::{format_scan_results, load_rules, scan_path};
+use evm_disassembler::stack::format_operations_with_stack;
+use evm_disassembler::storage::format_storage_layout;
+use evm_disassembler::trace::format_trace;
+use evm_disassembler::{disassemble, disassemble_str, format_operations, Disassembly, Operation};
+use eyre::{eyre, Result};
+use std::io::Write;
+use std::path::Path;
+
+const USAGE: &str = "\
+Usage:
+  evm-disassembler [--stack | --decompile | --ir | --storage | --calls | --lint] <bytecode>
+  evm-disassembler --abi <abi.json> <bytecode>
+  evm-disassembler --fingerprint <bytecode>
+  evm-disassembler diff [--ignore-metadata] [--ignore-immediates] [--context <n>] <old> <new>
+  evm-disassembler trace <bytecode> <trace.json>
+  evm-disassembler find <pattern> <bytecode>
+  evm-disassembler scan <rules> <file | directory | dump.jsonl | dump.csv>
+  evm-disassembler batch [--keep-duplicates] <file | directory | dump.jsonl | dump.csv>
+
+Bytecode arguments are either hex strings, paths to files containing hex encoded bytecode or
+Foundry, Hardhat and solc standard JSON artifacts (`<file>.json` or `<file>.json:<contract>`),
+of which the runtime code is used. Unlinked library placeholders (`__$<hash>$__`) are kept as
+PUSH20 operands. ABI files contain either the ABI array or an artifact. At most one of the
+options of the first form, `--abi` and `--fingerprint` can be given.";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("Error: {e}\n\n{USAGE}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("diff") => run_diff(&args[1..]),
+        Some("trace") => run_trace(&args[1..]),
+        Some("find") => run_find(&args[1..]),
+        Some("scan") => run_scan(&args[1..]),
+        Some("batch") => run_batch(&args[1..]),
+        Some("-h") | Some("--help") => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        Some(_) => run_disassemble(args),
+        None => Err(eyre!("Missing bytecode argument")),
+    }
+}
+
+fn run_disassemble(args: &[String]) -> Result<()> {
+    let mut mode = None;
+    let mut abi = None;
+    let mut inputs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            option @ ("--stack" | "--decompile" | "--ir" | "--storage" | "--calls" | "--lint"
+            | "--fingerprint") => set_mode(&mut mode, option)?,
+            "--abi" => {
+                set_mode(&mut mode, "--abi")?;
+                let path = args.next().ok_or_else(|| eyre!("Missing file for --abi"))?;
+                abi = Some(std::fs::read_to_string(path)?);
+            }
+            input => inputs.push(input),
+        }
+    }
+    let [input] = inputs[..] else {
+        return Err(eyre!("Expected exactly one bytecode argument"));
+    };
+    let code = read_bytecode(input)?;
+    if mode == Some("--fingerprint") {
+        print!("{}", format_fingerprint(&unlinked_bytes(&code)?)?);
+        return Ok(());
+    }
+    // The code sections of EOF containers are analysed instead of the whole container
+    let container = if matches!(mode, Some("--calls" | "--lint")) {
+        eof_container(&unlinked_bytes(&code)?)?
+    } else {
+        None
+    };
+    let operations = match disassemble_code(&code)? {
+        Disassembly::Operations(operations) => operations,
+        Disassembly::Delegation(delegation) => {
+            println!(
+                "EIP-7702 delegation to 0x{}",
+                hex::encode(delegation.address)
+            );
+            return Ok(());
+        }
+    };
+    let formatted = match (mode, container) {
+        (Some("--abi"), _) => {
+            format_operations_with_abi(operations, abi.as_deref().unwrap_or_default())?
+        }
+        (Some("--lint"), Some(container)) => format_eof_findings(&container)?,
+        (Some("--lint"), None) => format_findings(operations)?,
+        (Some("--calls"), Some(container)) => format_eof_call_sites(&container)?,
+        (Some("--calls"), None) => format_call_sites(operations)?,
+        (Some("--storage"), _) => format_storage_layout(operations)?,
+        (Some("--ir"), _) => format_ir(operations)?,
+        (Some("--decompile"), _) => decompile(&operations)?,
+        (Some("--stack"), _) => format_operations_with_stack(operations)?,
+        _ => format_operations(operations)?,
+    };
+    print!("{formatted}");
+    Ok(())
+}
+
+// Selects the output of the disassembly, only one option can be given
+fn set_mode<'a>(mode: &mut Option<&'a str>, option: &'a str) -> Result<()> {
+    match mode {
+        Some(previous) if *previous != option => {
+            Err(eyre!("Conflicting options {previous} and {option}"))
+        }
+        _ => {
+            *mode = Some(option);
+            Ok(())
+        }
+    }
+}
+
+fn eof_container(bytes: &[u8]) -> Result<Option<EofContainer>> {
+    bytes
+        .starts_with(&MAGIC)
+        .then(|| EofContainer::parse(bytes))
+        .transpose()
+}
+
+// Disassembles hex encoded code, unlinked library placeholders are decoded by `disassemble_str`
+fn disassemble_code(code: &str) -> Result<Disassembly> {
+    if find_placeholders(code).is_empty() {
+        disassemble(hex::decode(code)?)
+    } else {
+        Ok(Disassembly::Operations(disassemble_str(code)?))
+    }
+}
+
+// Decodes hex encoded code with unlinked library placeholders replaced by the zero address
+fn unlinked_bytes(code: &str) -> Result<Vec<u8>> {
+    let mut zeroed = code.to_owned();
+    for (offset, _) in find_placeholders(code) {
+        let start = offset as usize * 2;
+        zeroed.replace_range(start..start + 40, &"0".repeat(40));
+    }
+    Ok(hex::decode(zeroed)?)
+}
+
+// Disassembles hex encoded code for diffing, optionally without its metadata trailer
+fn diff_operations_of(code: &str, ignore_metadata: bool) -> Result<Vec<Operation>> {
+    let code = if ignore_metadata {
+        &code[..strip_metadata(&unlinked_bytes(code)?).len() * 2]
+    } else {
+        code
+    };
+    disassemble_str(code)
+}
+
+fn run_diff(args: &[String]) -> Result<()> {
+    let mut options = DiffOptions::default();
+    let mut context = 3;
+    let mut inputs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ignore-metadata" => options.ignore_metadata = true,
+            "--ignore-immediates" => options.ignore_immediates = true,
+            "--context" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| eyre!("Missing value for --context"))?;
+                context = value.parse()?;
+            }
+            input => inputs.push(input),
+        }
+    }
+    let [old, new] = inputs[..] else {
+        return Err(eyre!("Expected exactly two bytecode arguments"));
+    };
+    let old = diff_operations_of(&read_bytecode(old)?, options.ignore_metadata)?;
+    let new = diff_operations_of(&read_bytecode(new)?, options.ignore_metadata)?;
+    let entries = diff_operations(&old, &new, &options);
+    print!("{}", format_diff(&entries, context)?);
+    Ok(())
+}
+
+fn run_trace(args: &[String]) -> Result<()> {
+    let [bytecode, trace] = args else {
+        return Err(eyre!("Expected a bytecode and a trace file argument"));
+    };
+    let Disassembly::Operations(operations) = disassemble_code(&read_bytecode(bytecode)?)? else {
+        return Err(eyre!("Cannot annotate traces of delegations"));
+    };
+    print!(
+        "{}",
+        format_trace(operations, &std::fs::read_to_string(trace)?)?
+    );
+    Ok(())
+}
+
+fn run_find(args: &[String]) -> Result<()> {
+    let [pattern, bytecode] = args else {
+        return Err(eyre!("Expected a pattern and a bytecode argument"));
+    };
+    let Disassembly::Operations(operations) = disassemble_code(&read_bytecode(bytecode)?)? else {
+        return Err(eyre!("Cannot search delegations"));
+    };
+    print!("{}", format_matches(operations, pattern)?);
+    Ok(())
+}
+
+fn run_scan(args: &[String]) -> Result<()> {
+    let [rules, path] = args else {
+        return Err(eyre!("Expected a rule file and a path to scan"));
+    };
+    let rules = load_rules(rules)?;
+    print!("{}", format_scan_results(&scan_path(&rules, path)?)?);
+    Ok(())
+}
+
+fn run_batch(args: &[String]) -> Result<()> {
+    let mut options = BatchOptions::default();
+    let mut inputs = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--keep-duplicates" => options.deduplicate = false,
+            input => inputs.push(input),
+        }
+    }
+    let [path] = inputs[..] else {
+        return Err(eyre!("Expected exactly one path to disassemble"));
+    };
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let summary = disassemble_batch(read_path(path)?, &options, |item| {
+        match item {
+            BatchItem::Disassembled {
+                name,
+                hash,
+                disassembly: Disassembly::Operations(operations),
+            } => writeln!(
+                out,
+                "// {name} 0x{}\n{}",
+                hex::encode(hash),
+                format_operations(operations)?
+            )?,
+            BatchItem::Disassembled {
+                name,
+                hash,
+                disassembly: Disassembly::Delegation(delegation),
+            } => writeln!(
+                out,
+                "// {name} 0x{}\nEIP-7702 delegation to 0x{}\n",
+                hex::encode(hash),
+                hex::encode(delegation.address)
+            )?,
+            BatchItem::Duplicate { name, hash, first } => writeln!(
+                out,
+                "// {name} 0x{} same code as {first}\n",
+                hex::encode(hash)
+            )?,
+            BatchItem::Failed { name, error } => writeln!(out, "// {name} error: {error}\n")?,
+        }
+        Ok(())
+    })?;
+    out.flush()?;
+    eprintln!(
+        "{} entries: {} disassembled, {} duplicates, {} failed",
+        summary.entries, summary.disassembled, summary.duplicates, summary.failed
+    );
+    Ok(())
+}
+
+// Reads hex encoded bytecode either from a file, the runtime code of a compiler artifact or
+// directly from the argument, without a `0x` prefix
+fn read_bytecode(input: &str) -> Result<String> {
+    let (path, contract) = match input.split_once(".json:") {
+        Some((path, contract)) => (format!("{path}.json"), Some(contract)),
+        None => (input.to_owned(), None),
+    };
+    if path.ends_with(".json") && Path::new(&path).is_file() {
+        return read_artifact(&path, contract);
+    }
+    let encoded = if Path::new(input).is_file() {
+        std::fs::read_to_string(input)?
+    } else {
+        input.to_owned()
+    };
+    Ok(encoded.trim().trim_start_matches("0x").to_owned())
+}
+
+fn read_artifact(path: &str, contract: Option<&str>) -> Result<String> {
+    let artifacts = load_artifacts(path)?;
+    let matching: Vec<_> = artifacts
+        .iter()
+        .filter(|artifact| contract.is_none_or(|name| artifact.name == name))
+        .filter_map(|artifact| artifact.runtime.as_ref())
+        .collect();
+    let [runtime] = matching[..] else {
+        return Err(eyre!(
+            "Expected exactly one contract with runtime code in {path}, select it with \
+             {path}:<contract>"
+        ));
+    };
+    Ok(runtime.object.clone())
+}
@@ -0,0 +1,216 @@
+//! Detection of compiler metadata trailers appended to contract bytecode
+use std::collections::BTreeMap;
+
+// Keys of the metadata emitted by solc and Vyper
+const KEYS: [&str; 5] = ["ipfs", "bzzr0", "bzzr1", "solc", "vyper"];
+// Metadata maps nest at most a few levels, deeper values are rejected instead of recursing
+const MAX_CBOR_DEPTH: usize = 8;
+
+/// Splits bytecode into the executable code and the CBOR encoded metadata trailer
+///
+/// Solidity (and recent Vyper versions) append a CBOR encoded metadata blob to the runtime code,
+/// followed by its length as a two byte big endian integer. The returned trailer includes these two
+/// length bytes. If no trailer can be detected the full input is returned as code.
+///
+/// # Arguments
+/// - `bytes` - The bytecode to split
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::metadata::split_metadata;
+///
+/// let bytes = hex::decode("6080604052fea165627a7a7230582000000000000000000000000000000000000000000000000000000000000000000029").unwrap();
+/// let (code, metadata) = split_metadata(&bytes);
+/// assert_eq!(code, hex::decode("6080604052fe").unwrap());
+/// assert_eq!(metadata.unwrap().len(), 43);
+/// ```
+pub fn split_metadata(bytes: &[u8]) -> (&[u8], Option<&[u8]>) {
+    match metadata_length(bytes) {
+        Some(length) => {
+            let (code, metadata) = bytes.split_at(bytes.len() - length);
+            (code, Some(metadata))
+        }
+        None => (bytes, None),
+    }
+}
+
+/// Returns the bytecode with any detected metadata trailer removed
+pub fn strip_metadata(bytes: &[u8]) -> &[u8] {
+    split_metadata(bytes).0
+}
+
+/// Finds the start of a metadata trailer by its first CBOR key
+///
+/// Unlike [`split_metadata`] this does not rely on the trailing length bytes, so it also finds
+/// trailers of re-encoded operations whose last bytes were lost to a truncated PUSH. Recognizes
+/// maps starting with the keys emitted by solc (`ipfs`, `bzzr0`, `bzzr1`, `solc`) and Vyper.
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::metadata::find_metadata_start;
+///
+/// let bytes = hex::decode("6080604052fea165627a7a72305820ffff").unwrap();
+/// assert_eq!(find_metadata_start(&bytes), Some(6));
+/// ```
+pub fn find_metadata_start(bytes: &[u8]) -> Option<usize> {
+    (0..bytes.len()).rev().find(|&start| {
+        let header = &bytes[start..];
+        matches!(header.first(), Some(0xa1..=0xa3))
+            && KEYS.iter().any(|key| {
+                header.get(1) == Some(&(0x60 + key.len() as u8))
+                    && header.get(2..2 + key.len()) == Some(key.as_bytes())
+            })
+    })
+}
+
+// Length of the metadata trailer including the two length bytes, if one is present
+fn metadata_length(bytes: &[u8]) -> Option<usize> {
+    if bytes.len() < 2 {
+        return None;
+    }
+    let cbor_length = u16::from_be_bytes([bytes[bytes.len() - 2], bytes[bytes.len() - 1]]) as usize;
+    let total_length = cbor_length + 2;
+    if cbor_length == 0 || total_length > bytes.len() {
+        return None;
+    }
+    // The trailer has to be a single CBOR map (solc, Vyper) or an array holding the map (newer
+    // Vyper versions) of exactly the given length with a known key. Checking the first byte alone
+    // would also accept code ending in arbitrary bytes after a DUP, SWAP or LOG.
+    let cbor = &bytes[bytes.len() - total_length..bytes.len() - 2];
+    let mut position = 0;
+    let value = decode_cbor(cbor, &mut position, 0)?;
+    let mut entries = BTreeMap::new();
+    metadata_entries(&value, &mut entries);
+    (position == cbor.len() && KEYS.iter().any(|key| entries.contains_key(*key)))
+        .then_some(total_length)
+}
+
+// A decoded CBOR value of the metadata trailer
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Cbor {
+    Uint(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Bool(bool),
+}
+
+// Decodes the definite length CBOR subset used in metadata trailers, `depth` is the number of
+// enclosing arrays and maps
+pub(crate) fn decode_cbor(bytes: &[u8], position: &mut usize, depth: usize) -> Option<Cbor> {
+    let initial = *bytes.get(*position)?;
+    *position += 1;
+    let (major, info) = (initial >> 5, initial & 0x1f);
+    let argument = match info {
+        0..=23 => info as u64,
+        24..=27 => {
+            let length = 1 << (info - 24);
+            let bytes = bytes.get(*position..*position + length)?;
+            *position += length;
+            bytes.iter().fold(0, |acc, byte| (acc << 8) | *byte as u64)
+        }
+        _ => return None,
+    };
+    let mut take = |length: u64| {
+        let end = position.checked_add(usize::try_from(length).ok()?)?;
+        let data = bytes.get(*position..end)?.to_vec();
+        *position = end;
+        Some(data)
+    };
+    if matches!(major, 4 | 5) && depth >= MAX_CBOR_DEPTH {
+        return None;
+    }
+    match major {
+        0 => Some(Cbor::Uint(argument)),
+        2 => take(argument).map(Cbor::Bytes),
+        3 => String::from_utf8(take(argument)?).ok().map(Cbor::Text),
+        4 => (0..argument)
+            .map(|_| decode_cbor(bytes, position, depth + 1))
+            .collect::<Option<_>>()
+            .map(Cbor::Array),
+        5 => (0..argument)
+            .map(|_| {
+                Some((
+                    decode_cbor(bytes, position, depth + 1)?,
+                    decode_cbor(bytes, position, depth + 1)?,
+                ))
+            })
+            .collect::<Option<_>>()
+            .map(Cbor::Map),
+        7 if argument == 20 || argument == 21 => Some(Cbor::Bool(argument == 21)),
+        _ => None,
+    }
+}
+
+// Collects the entries of all maps with text keys, including maps nested in arrays
+pub(crate) fn metadata_entries(value: &Cbor, entries: &mut BTreeMap<String, Cbor>) {
+    match value {
+        Cbor::Map(items) => {
+            for (key, value) in items {
+                if let Cbor::Text(key) = key {
+                    entries.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        Cbor::Array(items) => items
+            .iter()
+            .for_each(|item| metadata_entries(item, entries)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    // Appends the length of the trailer like solc and Vyper
+    fn with_trailer(code: &str, cbor: &str) -> Vec<u8> {
+        let mut bytes = hex::decode(format!("{code}{cbor}")).unwrap();
+        bytes.extend((cbor.len() as u16 / 2).to_be_bytes());
+        bytes
+    }
+
+    #[rstest]
+    // {"ipfs": <34 bytes>, "solc": 0.8.20}
+    #[case(&format!("a2646970667358221220{}64736f6c6343000814", "12".repeat(32)), true)]
+    // {"bzzr0": <32 bytes>}
+    #[case(&format!("a165627a7a72305820{}", "00".repeat(32)), true)]
+    // [1, [], 0, {"vyper": [0, 4, 0]}]
+    #[case("84018000a165767970657283000400", true)]
+    // {"a": 1} has no known key
+    #[case("a1616101", false)]
+    // {"solc": 0.8.20} followed by a byte not covered by the map
+    #[case("a164736f6c634300081400", false)]
+    fn detect_trailers(#[case] cbor: &str, #[case] detected: bool) {
+        let bytes = with_trailer("6080604052fe", cbor);
+        let (code, metadata) = split_metadata(&bytes);
+        assert_eq!(metadata.is_some(), detected);
+        assert_eq!(code.len(), if detected { 6 } else { bytes.len() });
+    }
+
+    #[rstest]
+    fn keep_code_ending_like_a_length() {
+        // DUP1 followed by JUMPDESTs, the last two bytes STOP REVERT read as a length of 253
+        let mut bytes = vec![0x80];
+        bytes.extend([0x5b; 252]);
+        bytes.extend([0x00, 0xfd]);
+        assert_eq!(split_metadata(&bytes), (&bytes[..], None));
+    }
+
+    #[rstest]
+    fn decode_nested_values() {
+        // {"a": [[1]]} is within the depth limit
+        assert_eq!(
+            decode_cbor(&hex::decode("a16161818101").unwrap(), &mut 0, 0),
+            Some(Cbor::Map(vec![(
+                Cbor::Text("a".to_string()),
+                Cbor::Array(vec![Cbor::Array(vec![Cbor::Uint(1)])])
+            )]))
+        );
+        assert_eq!(decode_cbor(&[0x81; 60000], &mut 0, 0), None);
+    }
+}
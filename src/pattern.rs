@@ -0,0 +1,484 @@
+//! Search for instruction sequences with a small pattern language
+//!
+//! A pattern is a whitespace separated sequence of instructions, each optionally followed by an
+//! immediate:
+//!
+//! - `CALLER`, `sha3` - an opcode by name (case insensitive, `KECCAK256` and `PREVRANDAO` accepted)
+//! - `DUP*`, `LOG*` - any opcode whose name starts with the prefix
+//! - `PUSH<=4`, `SWAP>8` - an opcode of a numbered family (`<`, `<=`, `>`, `>=`)
+//! - `CALL|STATICCALL` - any of several opcodes or classes
+//! - `.` - any single instruction
+//! - `...` - up to [`MAX_GAP`] arbitrary instructions
+//!
+//! Immediates follow their instruction: `?` or `*` match any value, a hex number (`0x40`) matches
+//! that value ignoring leading zeros and `$name` matches any value and captures it.
+use crate::types::{Opcode, Operation};
+use eyre::{eyre, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// The maximum number of instructions skipped by a `...` gap
+pub const MAX_GAP: usize = 32;
+
+// Matches a single opcode, prefixes and families are resolved to the byte ranges of the opcodes
+// they match when parsing
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum OpcodeMatcher {
+    Exact(Opcode),
+    Bytes(Vec<RangeInclusive<u8>>),
+    Any,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+// Matches the immediate bytes of an instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum ImmediateMatcher {
+    Any,
+    Value(Vec<u8>),
+    Capture(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Element {
+    Instruction {
+        alternatives: Vec<OpcodeMatcher>,
+        immediate: Option<ImmediateMatcher>,
+    },
+    Gap,
+}
+
+/// A compiled instruction pattern
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    elements: Vec<Element>,
+}
+
+/// An immediate captured by `$name`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capture {
+    /// The offset of the instruction the immediate belongs to
+    pub offset: u32,
+    /// The immediate bytes
+    pub value: Vec<u8>,
+}
+
+/// A match of a pattern
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    /// The offset of the first matched instruction
+    pub start: u32,
+    /// The offset of the last matched instruction
+    pub end: u32,
+    /// The captured immediates by name
+    pub captures: BTreeMap<String, Capture>,
+}
+
+impl OpcodeMatcher {
+    fn parse(token: &str) -> Result<Self> {
+        let token = token.to_uppercase();
+        if token == "." {
+            return Ok(OpcodeMatcher::Any);
+        }
+        if let Some(prefix) = token.strip_suffix('*') {
+            return Ok(OpcodeMatcher::matching_names(|name| {
+                name.starts_with(prefix)
+            }));
+        }
+        if let Some(position) = token.find(['<', '>']) {
+            let (prefix, rest) = token.split_at(position);
+            let (comparison, number) = match rest.as_bytes() {
+                [b'<', b'=', ..] => (Comparison::LessOrEqual, &rest[2..]),
+                [b'>', b'=', ..] => (Comparison::GreaterOrEqual, &rest[2..]),
+                [b'<', ..] => (Comparison::Less, &rest[1..]),
+                _ => (Comparison::Greater, &rest[1..]),
+            };
+            let number: u32 = number
+                .parse()
+                .map_err(|_| eyre!("Invalid opcode family comparison {token}"))?;
+            return Ok(OpcodeMatcher::matching_names(|name| {
+                let Some(Ok(actual)) = name.strip_prefix(prefix).map(str::parse::<u32>) else {
+                    return false;
+                };
+                match comparison {
+                    Comparison::Less => actual < number,
+                    Comparison::LessOrEqual => actual <= number,
+                    Comparison::Greater => actual > number,
+                    Comparison::GreaterOrEqual => actual >= number,
+                }
+            }));
+        }
+        Opcode::from_name(&token)
+            .map(OpcodeMatcher::Exact)
+            .ok_or_else(|| eyre!("Unknown opcode {token}"))
+    }
+
+    // Matches the legacy and EOF opcodes whose name satisfies the predicate
+    fn matching_names(predicate: impl Fn(&str) -> bool) -> Self {
+        let mut bytes: Vec<u8> = (0..=u8::MAX)
+            .flat_map(|byte| [Opcode::from_byte(byte), Opcode::from_byte_eof(byte)])
+            .filter(|opcode| predicate(&format!("{opcode:?}")))
+            .map(|opcode| opcode.to_byte())
+            .collect();
+        bytes.sort_unstable();
+        bytes.dedup();
+        let mut ranges: Vec<RangeInclusive<u8>> = Vec::new();
+        for byte in bytes {
+            match ranges.last_mut() {
+                Some(range) if *range.end() as u16 + 1 == byte as u16 => {
+                    *range = *range.start()..=byte
+                }
+                _ => ranges.push(byte..=byte),
+            }
+        }
+        OpcodeMatcher::Bytes(ranges)
+    }
+
+    fn matches(&self, opcode: Opcode) -> bool {
+        match self {
+            OpcodeMatcher::Exact(expected) => opcode == *expected,
+            OpcodeMatcher::Bytes(ranges) => {
+                let byte = opcode.to_byte();
+                ranges.iter().any(|range| range.contains(&byte))
+            }
+            OpcodeMatcher::Any => true,
+        }
+    }
+}
+
+impl ImmediateMatcher {
+    // Parses an immediate token, `None` if the token is an instruction
+    fn parse(token: &str) -> Option<Result<Self>> {
+        match token {
+            "?" | "*" => Some(Ok(ImmediateMatcher::Any)),
+            _ if token.starts_with('$') => Some(match &token[1..] {
+                "" => Err(eyre!("Missing capture name after $")),
+                name => Ok(ImmediateMatcher::Capture(name.to_string())),
+            }),
+            _ if token.starts_with("0x") => {
+                let digits = token[2..].trim_start_matches('0');
+                let padded = format!("{}{digits}", "0".repeat(digits.len() % 2));
+                Some(
+                    hex::decode(padded)
+                        .map(ImmediateMatcher::Value)
+                        .map_err(|_| eyre!("Invalid immediate {token}")),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    fn matches(&self, input: &[u8]) -> bool {
+        match self {
+            ImmediateMatcher::Value(value) => {
+                let leading = input.iter().take_while(|byte| **byte == 0).count();
+                input[leading..] == value[..]
+            }
+            ImmediateMatcher::Any | ImmediateMatcher::Capture(_) => true,
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = eyre::Report;
+
+    fn from_str(pattern: &str) -> Result<Self> {
+        let mut elements = Vec::new();
+        for token in pattern.split_whitespace() {
+            if let Some(immediate) = ImmediateMatcher::parse(token) {
+                let immediate = immediate?;
+                match elements.last_mut() {
+                    Some(Element::Instruction {
+                        immediate: slot @ None,
+                        ..
+                    }) => *slot = Some(immediate),
+                    _ => return Err(eyre!("Immediate {token} does not follow an instruction")),
+                }
+            } else if token == "..." {
+                elements.push(Element::Gap);
+            } else {
+                let alternatives = token
+                    .split('|')
+                    .map(OpcodeMatcher::parse)
+                    .collect::<Result<_>>()?;
+                elements.push(Element::Instruction {
+                    alternatives,
+                    immediate: None,
+                });
+            }
+        }
+        match (elements.first(), elements.last()) {
+            (None, _) => Err(eyre!("Empty pattern")),
+            (Some(Element::Gap), _) | (_, Some(Element::Gap)) => {
+                Err(eyre!("Patterns cannot start or end with a gap"))
+            }
+            _ => Ok(Pattern { elements }),
+        }
+    }
+}
+
+impl Pattern {
+    /// Compiles a pattern
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use evm_disassembler::pattern::Pattern;
+    ///
+    /// assert!(Pattern::parse("DUP* PUSH<=4 $selector EQ").is_ok());
+    /// assert!(Pattern::parse("PUSH4 ? NOTANOPCODE").is_err());
+    /// ```
+    pub fn parse(pattern: &str) -> Result<Self> {
+        pattern.parse()
+    }
+
+    /// Finds the matches starting at every instruction
+    ///
+    /// Gaps match as few instructions as possible, so each start yields at most one match.
+    ///
+    /// # Arguments
+    /// - `operations` - The decoded code to search
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use evm_disassembler::{disassemble_str, pattern::Pattern};
+    ///
+    /// // DUP1 PUSH4 0xa9059cbb EQ PUSH2 0x0041 JUMPI
+    /// let operations = disassemble_str("8063a9059cbb1461004157").unwrap();
+    /// let pattern = Pattern::parse("PUSH4 $selector EQ PUSH2 ? JUMPI").unwrap();
+    /// let matches = pattern.find_all(&operations);
+    /// assert_eq!((matches[0].start, matches[0].end), (1, 10));
+    /// assert_eq!(matches[0].captures["selector"].value, vec![0xa9, 0x05, 0x9c, 0xbb]);
+    /// ```
+    pub fn find_all(&self, operations: &[Operation]) -> Vec<Match> {
+        let mut matches = Vec::new();
+        let mut failed = HashSet::new();
+        for start in 0..operations.len() {
+            let mut captures = Vec::new();
+            if let Some(end) = self.match_at(operations, 0, start, &mut captures, &mut failed) {
+                matches.push(Match {
+                    start: operations[start].offset,
+                    end: operations[end - 1].offset,
+                    captures: captures.into_iter().collect(),
+                });
+            }
+        }
+        matches
+    }
+
+    /// Whether the pattern matches anywhere in the code
+    pub fn is_match(&self, operations: &[Operation]) -> bool {
+        let mut failed = HashSet::new();
+        (0..operations.len()).any(|start| {
+            self.match_at(operations, 0, start, &mut Vec::new(), &mut failed)
+                .is_some()
+        })
+    }
+
+    // Matches the elements from `element` on at operation `index`, returns the end of the match.
+    // Whether the remaining elements match does not depend on the captures so far, so the failed
+    // `(element, index)` pairs are remembered in `failed` instead of retrying them from every gap
+    // length.
+    fn match_at(
+        &self,
+        operations: &[Operation],
+        element: usize,
+        index: usize,
+        captures: &mut Vec<(String, Capture)>,
+        failed: &mut HashSet<(usize, usize)>,
+    ) -> Option<usize> {
+        let Some(current) = self.elements.get(element) else {
+            return Some(index);
+        };
+        if failed.contains(&(element, index)) {
+            return None;
+        }
+        let end = self.match_element(operations, current, element, index, captures, failed);
+        if end.is_none() {
+            failed.insert((element, index));
+        }
+        end
+    }
+
+    fn match_element(
+        &self,
+        operations: &[Operation],
+        current: &Element,
+        element: usize,
+        index: usize,
+        captures: &mut Vec<(String, Capture)>,
+        failed: &mut HashSet<(usize, usize)>,
+    ) -> Option<usize> {
+        match current {
+            Element::Gap => (0..=MAX_GAP)
+                .take_while(|skip| index + skip <= operations.len())
+                .find_map(|skip| {
+                    self.match_at(operations, element + 1, index + skip, captures, failed)
+                }),
+            Element::Instruction {
+                alternatives,
+                immediate,
+            } => {
+                let operation = operations.get(index)?;
+                if !alternatives
+                    .iter()
+                    .any(|matcher| matcher.matches(operation.opcode))
+                {
+                    return None;
+                }
+                let captured = captures.len();
+                match immediate {
+                    Some(immediate) if !immediate.matches(&operation.input) => return None,
+                    Some(ImmediateMatcher::Capture(name)) => captures.push((
+                        name.clone(),
+                        Capture {
+                            offset: operation.offset,
+                            value: operation.input.clone(),
+                        },
+                    )),
+                    _ => {}
+                }
+                let end = self.match_at(operations, element + 1, index + 1, captures, failed);
+                if end.is_none() {
+                    captures.truncate(captured);
+                }
+                end
+            }
+        }
+    }
+}
+
+/// Formats all matches of a pattern, one match per line with its captures
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, pattern::format_matches};
+///
+/// // CALLER PUSH20 <owner> EQ
+/// let operations = disassemble_str(&format!("3373{}14", "11".repeat(20))).unwrap();
+/// assert_eq!(
+///     format_matches(operations, "CALLER PUSH20 $owner EQ").unwrap(),
+///     format!("00000000..00000016 owner=0x{}\n", "11".repeat(20))
+/// );
+/// ```
+pub fn format_matches(operations: Vec<Operation>, pattern: &str) -> Result<String> {
+    let pattern = Pattern::parse(pattern)?;
+    let mut formatted = String::new();
+    for found in pattern.find_all(&operations) {
+        write!(formatted, "{:08x}..{:08x}", found.start, found.end)?;
+        for (name, capture) in &found.captures {
+            write!(formatted, " {name}=0x{}", hex::encode(&capture.value))?;
+        }
+        writeln!(formatted)?;
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use crate::selectors::function_entries;
+    use rstest::*;
+    use std::fs;
+
+    #[rstest]
+    #[case("DUP*", "80", true)]
+    #[case("dup*", "8f", true)]
+    #[case("DUP*", "90", false)]
+    #[case("PUSH<=4", "5f", true)]
+    #[case("PUSH<=4", "6300000000", true)]
+    #[case("PUSH<=4", "640000000000", false)]
+    #[case("SWAP>8", "98", true)]
+    #[case("SWAP>8", "97", false)]
+    #[case("RJUMP*", "e0", false)]
+    #[case("INVALID*", "e0", true)]
+    #[case("LOG>=4", "a4", true)]
+    #[case("CALL|STATICCALL", "fa", true)]
+    #[case("CALL|STATICCALL", "f4", false)]
+    #[case("KECCAK256", "20", true)]
+    #[case(".", "00", true)]
+    #[case("PUSH2 0x40", "610040", true)]
+    #[case("PUSH2 0x0040", "610041", false)]
+    #[case("PUSH1 ?", "6000", true)]
+    fn match_single_instruction(#[case] pattern: &str, #[case] code: &str, #[case] expected: bool) {
+        let operations = disassemble_str(code).unwrap();
+        assert_eq!(
+            Pattern::parse(pattern).unwrap().is_match(&operations),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("? PUSH1")]
+    #[case("PUSH1 ? ?")]
+    #[case("... CALLER")]
+    #[case("CALLER ...")]
+    #[case("PUSH<=x")]
+    #[case("PUSH1 0xzz")]
+    #[case("PUSH1 $")]
+    fn reject_invalid_patterns(#[case] pattern: &str) {
+        assert!(Pattern::parse(pattern).is_err());
+    }
+
+    #[rstest]
+    fn match_gaps() {
+        // CALLER PUSH1 0x0 SLOAD EQ ISZERO PUSH1 0x0 JUMPI
+        let operations = disassemble_str("336000541415600057").unwrap();
+        let matches = Pattern::parse("CALLER ... SLOAD ... JUMPI")
+            .unwrap()
+            .find_all(&operations);
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].start, matches[0].end), (0, 8));
+        assert!(Pattern::parse("CALLER . SLOAD")
+            .unwrap()
+            .is_match(&operations));
+        assert!(!Pattern::parse("CALLER SLOAD")
+            .unwrap()
+            .is_match(&operations));
+    }
+
+    #[rstest]
+    fn match_many_gaps_without_backtracking() {
+        // 2000 times PUSH1 0x0 without a CALLER, every gap length would be retried at every gap
+        let operations = disassemble_str(&"6000".repeat(2000)).unwrap();
+        let pattern = Pattern::parse("PUSH1 ... PUSH1 ... PUSH1 ... PUSH1 ... PUSH1 ... CALLER");
+        assert!(pattern.unwrap().find_all(&operations).is_empty());
+    }
+
+    #[rstest]
+    fn find_dispatcher_of_weth() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let pattern = Pattern::parse("PUSH4 $selector EQ PUSH2 $target JUMPI").unwrap();
+        let found: Vec<([u8; 4], u32)> = pattern
+            .find_all(&operations)
+            .iter()
+            .map(|found| {
+                let target = &found.captures["target"].value;
+                (
+                    found.captures["selector"].value.clone().try_into().unwrap(),
+                    u32::from_be_bytes([0, 0, target[0], target[1]]),
+                )
+            })
+            .collect();
+        let expected: Vec<([u8; 4], u32)> = function_entries(&operations)
+            .iter()
+            .map(|entry| (entry.selector, entry.offset))
+            .collect();
+        assert_eq!(found, expected);
+    }
+}
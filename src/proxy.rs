@@ -0,0 +1,361 @@
+//! Recognition of common proxy patterns
+//!
+//! Classifies decoded operations as one of the widely deployed proxy kinds and extracts where the
+//! implementation is stored, either as an address embedded in the code or as a storage slot.
+use crate::types::{Delegation, Opcode, Operation};
+use crate::{disassemble_bytes, disassemble_str};
+use eyre::Result;
+use std::sync::OnceLock;
+
+// EIP-1167 minimal proxy and its known variants, split around the embedded implementation address
+const MINIMAL_PROXIES: [(MinimalProxyVariant, &str, &str); 4] = [
+    (
+        MinimalProxyVariant::Eip1167,
+        "363d3d373d3d3d363d73",
+        "5af43d82803e903d91602b57fd5bf3",
+    ),
+    (
+        MinimalProxyVariant::ZeroAge,
+        "3d3d3d3d363d3d37363d73",
+        "5af43d3d93803e602a57fd5bf3",
+    ),
+    (
+        MinimalProxyVariant::Push0,
+        "365f5f375f5f365f73",
+        "5af43d5f5f3e5f3d91602a57fd5bf3",
+    ),
+    (
+        MinimalProxyVariant::Vyper,
+        "366000600037611000600036600073",
+        "5af4602c57600080fd5b6110006000f3",
+    ),
+];
+
+// bytes32(uint256(keccak256("eip1967.proxy.implementation")) - 1)
+const EIP1967_IMPLEMENTATION_SLOT: &str =
+    "360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc";
+// bytes32(uint256(keccak256("eip1967.proxy.admin")) - 1)
+const EIP1967_ADMIN_SLOT: &str = "b53127684a568b3173ae13b9f8a6016e243e63b6e8ee1178d6a717850b5d6103";
+// bytes32(uint256(keccak256("eip1967.proxy.beacon")) - 1)
+const EIP1967_BEACON_SLOT: &str =
+    "a3f0ad74e5423aebfd80d3ef4346578335a9a72aeaee59ff6cb3582b35133d50";
+// keccak256("org.zeppelinos.proxy.implementation"), used before EIP-1967 was finalized
+const ZEPPELINOS_IMPLEMENTATION_SLOT: &str =
+    "7050c9e0f4ca769c69bd3a8ef740bc37934f8e2c036e5a723fd8ee048ed3f8c3";
+// keccak256("org.zeppelinos.proxy.admin")
+const ZEPPELINOS_ADMIN_SLOT: &str =
+    "10d6a54a4754c8869d6886b5f5d7fbfa5b4522237ea5c60d11bc4e7a1ff9390b";
+// keccak256("diamond.standard.diamond.storage")
+const DIAMOND_STORAGE_SLOT: &str =
+    "c8fcad8db84d3cc18b4c41d551ea0ee66dd599cde068d998e57d5e09332c131c";
+// The `masterCopy()` selector left aligned in a word, as checked by the Safe proxy fallback
+const SAFE_MASTER_COPY_SELECTOR: &str =
+    "a619486e00000000000000000000000000000000000000000000000000000000";
+
+/// The known variants of minimal proxies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MinimalProxyVariant {
+    /// The original EIP-1167 minimal proxy
+    Eip1167,
+    /// The optimized minimal proxy by 0age (as used by Solady `LibClone`)
+    ZeroAge,
+    /// The PUSH0 based minimal proxy (ERC-7511)
+    Push0,
+    /// The forwarder deployed by Vyper's `create_forwarder_to`
+    Vyper,
+}
+
+/// The kind of a recognized proxy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyKind {
+    /// A minimal proxy with the implementation address embedded in the code
+    MinimalProxy(MinimalProxyVariant),
+    /// An EIP-1967 (or ZeppelinOS) proxy with admin logic in the proxy itself
+    Transparent,
+    /// An EIP-1967 proxy with the upgrade logic in the implementation (EIP-1822)
+    Uups,
+    /// An EIP-1967 proxy retrieving its implementation from a beacon
+    Beacon,
+    /// An EIP-2535 diamond dispatching to multiple facets
+    Diamond,
+    /// A Gnosis Safe proxy delegating to the master copy stored in slot 0
+    GnosisSafe,
+    /// An EIP-7702 delegation designator
+    Eip7702Delegation,
+}
+
+/// Where a proxy keeps the address of its implementation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Implementation {
+    /// The implementation address is embedded in the code
+    Address([u8; 20]),
+    /// The implementation (or beacon / facet mapping) is read from this storage slot
+    Slot([u8; 32]),
+}
+
+/// A recognized proxy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Proxy {
+    /// The kind of proxy
+    pub kind: ProxyKind,
+    /// The implementation address or the storage slot containing it
+    pub implementation: Implementation,
+}
+
+/// Classifies raw bytecode as a proxy
+///
+/// In addition to the patterns recognized by `classify_proxy` this detects EIP-7702 delegation
+/// designators, which are not valid code and can therefore only be recognized from raw bytes.
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::proxy::{classify_proxy_bytes, Implementation, ProxyKind};
+///
+/// let code = hex::decode("363d3d373d3d3d363d73bebebebebebebebebebebebebebebebebebebebe5af43d82803e903d91602b57fd5bf3").unwrap();
+/// let proxy = classify_proxy_bytes(&code).unwrap().unwrap();
+/// assert!(matches!(proxy.kind, ProxyKind::MinimalProxy(_)));
+/// assert_eq!(proxy.implementation, Implementation::Address([0xbe; 20]));
+/// ```
+pub fn classify_proxy_bytes(bytes: &[u8]) -> Result<Option<Proxy>> {
+    if let Some(delegation) = Delegation::from_bytes(bytes) {
+        return Ok(Some(Proxy {
+            kind: ProxyKind::Eip7702Delegation,
+            implementation: Implementation::Address(delegation.address),
+        }));
+    }
+    let operations = disassemble_bytes(bytes.to_vec())?;
+    Ok(classify_proxy(&operations))
+}
+
+/// Classifies decoded operations as a proxy
+///
+/// Minimal proxies have to match a known template instruction by instruction without any further
+/// code, all other kinds are recognized by the storage slot constants they use together with a
+/// DELEGATECALL forwarding the calldata.
+///
+/// # Arguments
+/// - `operations` - The decoded runtime code
+pub fn classify_proxy(operations: &[Operation]) -> Option<Proxy> {
+    if let Some(proxy) = classify_minimal_proxy(operations) {
+        return Some(proxy);
+    }
+    if !forwards_calldata(operations) {
+        return None;
+    }
+    let has_constant = |constant: &str| {
+        operations.iter().any(|operation| {
+            operation.opcode == Opcode::PUSH32 && hex::encode(&operation.input) == constant
+        })
+    };
+    let slot = |constant: &str| {
+        let mut slot = [0u8; 32];
+        hex::decode_to_slice(constant, &mut slot).expect("Invalid slot constant");
+        Implementation::Slot(slot)
+    };
+
+    let (kind, implementation) = if has_constant(SAFE_MASTER_COPY_SELECTOR) {
+        (ProxyKind::GnosisSafe, Implementation::Slot([0u8; 32]))
+    } else if has_constant(DIAMOND_STORAGE_SLOT) {
+        (ProxyKind::Diamond, slot(DIAMOND_STORAGE_SLOT))
+    } else if has_constant(EIP1967_BEACON_SLOT) {
+        (ProxyKind::Beacon, slot(EIP1967_BEACON_SLOT))
+    } else if has_constant(EIP1967_IMPLEMENTATION_SLOT) {
+        let kind = if has_constant(EIP1967_ADMIN_SLOT) {
+            ProxyKind::Transparent
+        } else {
+            ProxyKind::Uups
+        };
+        (kind, slot(EIP1967_IMPLEMENTATION_SLOT))
+    } else if has_constant(ZEPPELINOS_IMPLEMENTATION_SLOT) && has_constant(ZEPPELINOS_ADMIN_SLOT) {
+        (ProxyKind::Transparent, slot(ZEPPELINOS_IMPLEMENTATION_SLOT))
+    } else {
+        return None;
+    };
+    Some(Proxy {
+        kind,
+        implementation,
+    })
+}
+
+// The minimal proxies decoded with a zero implementation address, decoded on first use
+fn minimal_proxy_templates() -> &'static [(MinimalProxyVariant, Vec<Operation>)] {
+    static TEMPLATES: OnceLock<Vec<(MinimalProxyVariant, Vec<Operation>)>> = OnceLock::new();
+    TEMPLATES.get_or_init(|| {
+        MINIMAL_PROXIES
+            .iter()
+            .map(|(variant, prefix, suffix)| {
+                let template = disassemble_str(&format!("{prefix}{}{suffix}", "00".repeat(20)))
+                    .expect("Invalid minimal proxy template");
+                (*variant, template)
+            })
+            .collect()
+    })
+}
+
+// Minimal proxies are deployed as is, code around a template is not a minimal proxy
+fn classify_minimal_proxy(operations: &[Operation]) -> Option<Proxy> {
+    minimal_proxy_templates()
+        .iter()
+        .find_map(|(variant, template)| {
+            if operations.len() != template.len() {
+                return None;
+            }
+            let mut implementation = None;
+            for (operation, expected) in operations.iter().zip(template.iter()) {
+                if operation.opcode != expected.opcode {
+                    return None;
+                }
+                if operation.opcode == Opcode::PUSH20 {
+                    implementation = operation.input.clone().try_into().ok();
+                } else if operation.input != expected.input {
+                    return None;
+                }
+            }
+            Some(Proxy {
+                kind: ProxyKind::MinimalProxy(*variant),
+                implementation: Implementation::Address(implementation?),
+            })
+        })
+}
+
+// Whether the code copies the calldata to memory and later forwards it with DELEGATECALL
+fn forwards_calldata(operations: &[Operation]) -> bool {
+    operations.iter().enumerate().any(|(i, operation)| {
+        operation.opcode == Opcode::CALLDATACOPY
+            && operations[i.saturating_sub(4)..i]
+                .iter()
+                .any(|operation| operation.opcode == Opcode::CALLDATASIZE)
+            && operations[i..(i + 20).min(operations.len())]
+                .iter()
+                .any(|operation| operation.opcode == Opcode::DELEGATECALL)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use std::fs;
+
+    const IMPLEMENTATION: &str = "bebebebebebebebebebebebebebebebebebebebe";
+
+    #[rstest]
+    #[case(MinimalProxyVariant::Eip1167)]
+    #[case(MinimalProxyVariant::ZeroAge)]
+    #[case(MinimalProxyVariant::Push0)]
+    #[case(MinimalProxyVariant::Vyper)]
+    fn minimal_proxy_variants(#[case] variant: MinimalProxyVariant) {
+        let (_, prefix, suffix) = MINIMAL_PROXIES
+            .iter()
+            .find(|(candidate, _, _)| *candidate == variant)
+            .unwrap();
+        let operations = disassemble_str(&format!("{prefix}{IMPLEMENTATION}{suffix}")).unwrap();
+        let proxy = classify_proxy(&operations).expect("Not classified as proxy");
+        assert_eq!(proxy.kind, ProxyKind::MinimalProxy(variant));
+        assert_eq!(proxy.implementation, Implementation::Address([0xbe; 20]));
+    }
+
+    #[rstest]
+    fn gnosis_safe_proxy() {
+        let code = "608060405273ffffffffffffffffffffffffffffffffffffffff600054167fa619486e0000000000000000000000000000000000000000000000000000000060003514156050578060005260206000f35b3660008037600080366000845af43d6000803e60008114156070573d6000fd5b3d6000f3fe";
+        let proxy = classify_proxy(&disassemble_str(code).unwrap()).unwrap();
+        assert_eq!(proxy.kind, ProxyKind::GnosisSafe);
+        assert_eq!(proxy.implementation, Implementation::Slot([0u8; 32]));
+    }
+
+    #[rstest]
+    fn minimal_proxy_with_trailing_code() {
+        let (_, prefix, suffix) = MINIMAL_PROXIES[0];
+        let code = format!("{prefix}{IMPLEMENTATION}{suffix}00");
+        assert_eq!(classify_proxy(&disassemble_str(&code).unwrap()), None);
+    }
+
+    // Hand assembled proxies sharing the forwarding of OpenZeppelin's `_delegate`, with the
+    // implementation on the stack:
+    //   CALLDATASIZE PUSH1 0x0 DUP1 CALLDATACOPY
+    //   PUSH1 0x0 DUP1 CALLDATASIZE PUSH1 0x0 DUP5 GAS DELEGATECALL
+    //   RETURNDATASIZE PUSH1 0x0 DUP1 RETURNDATACOPY DUP1 ISZERO PUSH2 revert JUMPI
+    //   RETURNDATASIZE PUSH1 0x0 RETURN revert: JUMPDEST RETURNDATASIZE PUSH1 0x0 REVERT
+    #[rstest]
+    // Transparent: if caller == sload(ADMIN_SLOT) jump to the admin branch storing calldata[4:36]
+    // as implementation, otherwise forward to sload(IMPLEMENTATION_SLOT)
+    #[case(
+        format!(
+            "7f{EIP1967_ADMIN_SLOT}54331461006c577f{EIP1967_IMPLEMENTATION_SLOT}54\
+             3660008037600080366000845af43d6000803e8015610067573d6000f35b3d6000fd\
+             5b6004357f{EIP1967_IMPLEMENTATION_SLOT}5500"
+        ),
+        ProxyKind::Transparent,
+        EIP1967_IMPLEMENTATION_SLOT
+    )]
+    // UUPS: forward to sload(IMPLEMENTATION_SLOT), the upgrade logic lives in the implementation
+    #[case(
+        format!(
+            "7f{EIP1967_IMPLEMENTATION_SLOT}54\
+             3660008037600080366000845af43d6000803e801561003f573d6000f35b3d6000fd"
+        ),
+        ProxyKind::Uups,
+        EIP1967_IMPLEMENTATION_SLOT
+    )]
+    // Beacon: staticcall implementation() on sload(BEACON_SLOT) and forward to the result
+    #[case(
+        format!(
+            "635c60da1b60e01b60005260206000600460007f{EIP1967_BEACON_SLOT}545afa15610061576000\
+             51\
+             3660008037600080366000845af43d6000803e801561005c573d6000f35b3d6000fd\
+             5b600080fd"
+        ),
+        ProxyKind::Beacon,
+        EIP1967_BEACON_SLOT
+    )]
+    // Diamond: load the facet of msg.sig from the selector mapping at DIAMOND_STORAGE_SLOT and
+    // revert if there is none
+    #[case(
+        format!(
+            "60003560e01c60e01b6000527f{DIAMOND_STORAGE_SLOT}60205260406000205473{}16801561007457\
+             3660008037600080366000845af43d6000803e801561006f573d6000f35b3d6000fd\
+             5b600080fd",
+            "ff".repeat(20)
+        ),
+        ProxyKind::Diamond,
+        DIAMOND_STORAGE_SLOT
+    )]
+    fn storage_slot_proxies(
+        #[case] code: String,
+        #[case] expected: ProxyKind,
+        #[case] expected_slot: &str,
+    ) {
+        let proxy = classify_proxy(&disassemble_str(&code).unwrap()).unwrap();
+        assert_eq!(proxy.kind, expected);
+        assert_eq!(
+            proxy.implementation,
+            Implementation::Slot(hex::decode(expected_slot).unwrap().try_into().unwrap())
+        );
+    }
+
+    #[rstest]
+    fn eip7702_delegation() {
+        let code = hex::decode(format!("ef0100{IMPLEMENTATION}")).unwrap();
+        let proxy = classify_proxy_bytes(&code).unwrap().unwrap();
+        assert_eq!(proxy.kind, ProxyKind::Eip7702Delegation);
+        assert_eq!(proxy.implementation, Implementation::Address([0xbe; 20]));
+    }
+
+    #[rstest]
+    #[case(
+        "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+        Some(ProxyKind::Transparent)
+    )] // USDC
+    #[case("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", None)] // Weth
+    #[case("0x00000000006c3852cbEf3e08E8dF289169EdE581", None)] // Seaport
+    fn classify_contracts_from_file(#[case] address: &str, #[case] expected: Option<ProxyKind>) {
+        let code = fs::read_to_string(format!("testdata/{address}_encoded.txt"))
+            .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        assert_eq!(
+            classify_proxy(&operations).map(|proxy| proxy.kind),
+            expected
+        );
+    }
+}
@@ -0,0 +1,447 @@
+//! Python bindings via PyO3
+//!
+//! Enabled with the `python` feature, the extension module is built with maturin from the
+//! `pyproject.toml` in the repository root:
+//!
+//! ```text
+//! maturin develop --release
+//! ```
+//!
+//! ```text
+//! >>> import evm_disassembler as evm
+//! >>> evm.disassemble("0x6080604052")
+//! [00000000: PUSH1 0x80, 00000002: PUSH1 0x40, 00000004: MSTORE]
+//! >>> print(evm.format(bytes.fromhex("6080604052")), end="")
+//! 00000000: PUSH1 0x80
+//! 00000002: PUSH1 0x40
+//! 00000004: MSTORE
+//! ```
+//!
+//! Bytecode arguments are either hex strings, with or without `0x` prefix, or `bytes`.
+use crate::eof::{EofContainer, MAGIC};
+use crate::fingerprint::fingerprint as fingerprint_bytes;
+use crate::lint::{default_lints, run_eof_lints, run_lints};
+use crate::metadata::split_metadata as split_metadata_bytes;
+use crate::selectors::function_entries as find_function_entries;
+use crate::types::{Opcode, Operation};
+use crate::{disassemble_bytes, disassemble_str, format_operations};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::fmt::Display;
+
+fn value_error(error: impl Display) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+// Bytecode passed from Python
+#[derive(FromPyObject)]
+enum Code {
+    Hex(String),
+    Bytes(Vec<u8>),
+}
+
+impl Code {
+    fn operations(self) -> PyResult<Vec<Operation>> {
+        match self {
+            Code::Hex(code) => disassemble_str(code.trim()),
+            Code::Bytes(bytes) => disassemble_bytes(bytes),
+        }
+        .map_err(value_error)
+    }
+
+    // The parsed container if the code is EOF, whose code sections are analysed on their own
+    fn eof_container(&self) -> PyResult<Option<EofContainer>> {
+        let bytes = match self {
+            Code::Hex(code) => hex::decode(code.trim().trim_start_matches("0x")).ok(),
+            Code::Bytes(bytes) => Some(bytes.clone()),
+        };
+        match bytes {
+            Some(bytes) if bytes.starts_with(&MAGIC) => {
+                EofContainer::parse(&bytes).map(Some).map_err(value_error)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn bytes(self) -> PyResult<Vec<u8>> {
+        match self {
+            Code::Hex(code) => {
+                hex::decode(code.trim().trim_start_matches("0x")).map_err(value_error)
+            }
+            Code::Bytes(bytes) => Ok(bytes),
+        }
+    }
+}
+
+/// An EVM opcode with its stack effect
+#[pyclass(name = "Opcode", module = "evm_disassembler", frozen, eq, hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct PyOpcode(Opcode);
+
+#[pymethods]
+impl PyOpcode {
+    /// Looks up an opcode by its case insensitive mnemonic
+    #[staticmethod]
+    fn from_name(name: &str) -> PyResult<Self> {
+        Opcode::from_name(name)
+            .map(PyOpcode)
+            .ok_or_else(|| value_error(format!("Unknown opcode {name}")))
+    }
+
+    /// Decodes an opcode byte, with the EOF opcodes if `eof` is true
+    #[staticmethod]
+    #[pyo3(signature = (byte, eof = false))]
+    fn from_byte(byte: u8, eof: bool) -> Self {
+        PyOpcode(match eof {
+            true => Opcode::from_byte_eof(byte),
+            false => Opcode::from_byte(byte),
+        })
+    }
+
+    /// The mnemonic
+    #[getter]
+    fn name(&self) -> String {
+        format!("{:?}", self.0)
+    }
+
+    /// The byte encoding the opcode
+    #[getter]
+    fn byte(&self) -> u8 {
+        self.0.to_byte()
+    }
+
+    /// The number of stack items consumed
+    #[getter]
+    fn inputs(&self) -> usize {
+        self.0.stack_arity().0
+    }
+
+    /// The number of stack items produced
+    #[getter]
+    fn outputs(&self) -> usize {
+        self.0.stack_arity().1
+    }
+
+    /// Whether the opcode is one of PUSH0 to PUSH32
+    #[getter]
+    fn is_push(&self) -> bool {
+        self.0.is_push()
+    }
+
+    /// Whether the opcode calls another contract
+    #[getter]
+    fn is_call(&self) -> bool {
+        self.0.is_call()
+    }
+
+    /// Whether the opcode creates a contract
+    #[getter]
+    fn is_create(&self) -> bool {
+        self.0.is_create()
+    }
+
+    /// Whether the opcode ends a basic block
+    #[getter]
+    fn is_block_terminator(&self) -> bool {
+        self.0.is_block_terminator()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Opcode.{:?}", self.0)
+    }
+
+    fn __str__(&self) -> String {
+        self.name()
+    }
+}
+
+/// A decoded instruction
+#[pyclass(name = "Operation", module = "evm_disassembler", frozen)]
+#[derive(Clone)]
+struct PyOperation(Operation);
+
+#[pymethods]
+impl PyOperation {
+    /// The offset in the bytecode
+    #[getter]
+    fn offset(&self) -> u32 {
+        self.0.offset
+    }
+
+    /// The opcode
+    #[getter]
+    fn opcode(&self) -> PyOpcode {
+        PyOpcode(self.0.opcode)
+    }
+
+    /// The immediate bytes, empty for instructions without immediate
+    #[getter]
+    fn input<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.input)
+    }
+
+    /// The library placeholder of unlinked bytecode replaced by `input`
+    #[getter]
+    fn link(&self) -> Option<String> {
+        self.0.link.as_ref().map(ToString::to_string)
+    }
+
+    /// The size of the instruction in bytes
+    #[getter]
+    fn size(&self) -> usize {
+        1 + self.0.input.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+/// A public function found in the dispatcher
+#[pyclass(name = "FunctionEntry", module = "evm_disassembler", frozen, get_all)]
+struct PyFunctionEntry {
+    /// The selector as hex string with `0x` prefix
+    selector: String,
+    /// The offset the dispatcher jumps to when the selector matches
+    offset: u32,
+}
+
+/// An issue reported by the built-in lints
+#[pyclass(name = "Finding", module = "evm_disassembler", frozen, get_all)]
+struct PyFinding {
+    /// The name of the lint
+    lint: String,
+    /// `info`, `low`, `medium` or `high`
+    severity: String,
+    /// The offset of the offending instruction
+    offset: u32,
+    /// A description of the finding
+    message: String,
+}
+
+/// The likely compiler and settings of a contract
+#[pyclass(name = "Fingerprint", module = "evm_disassembler", frozen, get_all)]
+struct PyFingerprint {
+    /// The most likely compiler: `solc`, `vyper`, `huff` or `unknown`
+    compiler: String,
+    /// All candidate compilers with their confidence, most likely first
+    candidates: Vec<(String, f64)>,
+    /// The possible versions, e.g. `>=0.8.20 <0.9.0`
+    version: Option<String>,
+    /// Whether the optimizer was enabled with the confidence
+    optimizer: Option<(bool, f64)>,
+    /// Descriptions of the evidence found
+    evidence: Vec<String>,
+}
+
+/// An EOF container split into its sections
+#[pyclass(name = "EofContainer", module = "evm_disassembler", frozen, get_all)]
+struct PyEofContainer {
+    /// The EOF version
+    version: u8,
+    /// The inputs, outputs and maximum stack height of each code section
+    types: Vec<(u8, u8, u16)>,
+    /// The decoded code sections, offsets are relative to the container
+    code: Vec<Vec<PyOperation>>,
+    /// The subcontainers
+    containers: Vec<Vec<u8>>,
+    /// The data section
+    data: Vec<u8>,
+}
+
+/// Disassembles bytecode into a list of operations
+#[pyfunction]
+#[pyo3(name = "disassemble")]
+fn py_disassemble(code: Code) -> PyResult<Vec<PyOperation>> {
+    Ok(code.operations()?.into_iter().map(PyOperation).collect())
+}
+
+/// Disassembles bytecode into text, one instruction per line as formatted by pyevmasm
+#[pyfunction]
+#[pyo3(name = "format")]
+fn py_format(code: Code) -> PyResult<String> {
+    format_operations(code.operations()?).map_err(value_error)
+}
+
+/// Splits bytecode into the code and the metadata trailer, `None` if there is no trailer
+#[pyfunction]
+fn split_metadata(code: Code) -> PyResult<(Vec<u8>, Option<Vec<u8>>)> {
+    let bytes = code.bytes()?;
+    let (code, metadata) = split_metadata_bytes(&bytes);
+    Ok((code.to_vec(), metadata.map(<[u8]>::to_vec)))
+}
+
+/// Splits an EOF container into its sections
+#[pyfunction]
+fn split_eof(code: Code) -> PyResult<PyEofContainer> {
+    let container = EofContainer::parse(&code.bytes()?).map_err(value_error)?;
+    Ok(PyEofContainer {
+        version: container.version,
+        types: container
+            .types
+            .iter()
+            .map(|code_type| {
+                (
+                    code_type.inputs,
+                    code_type.outputs,
+                    code_type.max_stack_height,
+                )
+            })
+            .collect(),
+        code: container
+            .code_operations()
+            .into_iter()
+            .map(|operations| operations.into_iter().map(PyOperation).collect())
+            .collect(),
+        containers: container
+            .containers
+            .into_iter()
+            .map(|section| section.bytes)
+            .collect(),
+        data: container.data.bytes,
+    })
+}
+
+/// Extracts the function selectors and entry offsets from the dispatcher
+#[pyfunction]
+fn function_entries(code: Code) -> PyResult<Vec<PyFunctionEntry>> {
+    Ok(find_function_entries(&code.operations()?)
+        .iter()
+        .map(|entry| PyFunctionEntry {
+            selector: entry.selector_hex(),
+            offset: entry.offset,
+        })
+        .collect())
+}
+
+/// Runs the built-in security lints
+#[pyfunction]
+fn lint(code: Code) -> PyResult<Vec<PyFinding>> {
+    let findings = match code.eof_container()? {
+        Some(container) => run_eof_lints(&container, &mut default_lints()),
+        None => run_lints(&code.operations()?, &mut default_lints()),
+    };
+    Ok(findings
+        .into_iter()
+        .map(|finding| PyFinding {
+            lint: finding.lint.to_string(),
+            severity: finding.severity.to_string(),
+            offset: finding.offset,
+            message: finding.message,
+        })
+        .collect())
+}
+
+/// Guesses the compiler, its version and whether the optimizer was enabled
+#[pyfunction]
+fn fingerprint(code: Code) -> PyResult<PyFingerprint> {
+    let fingerprint = fingerprint_bytes(&code.bytes()?);
+    Ok(PyFingerprint {
+        compiler: fingerprint.compiler().to_string(),
+        candidates: fingerprint
+            .candidates
+            .iter()
+            .map(|candidate| (candidate.compiler.to_string(), candidate.confidence))
+            .collect(),
+        version: fingerprint.version.map(|version| version.to_string()),
+        optimizer: fingerprint
+            .optimizer
+            .map(|optimizer| (optimizer.enabled, optimizer.confidence)),
+        evidence: fingerprint.evidence,
+    })
+}
+
+/// Disassemble EVM bytecode into individual instructions
+#[pymodule]
+fn evm_disassembler(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyOpcode>()?;
+    module.add_class::<PyOperation>()?;
+    module.add_class::<PyFunctionEntry>()?;
+    module.add_class::<PyFinding>()?;
+    module.add_class::<PyFingerprint>()?;
+    module.add_class::<PyEofContainer>()?;
+    module.add_function(wrap_pyfunction!(py_disassemble, module)?)?;
+    module.add_function(wrap_pyfunction!(py_format, module)?)?;
+    module.add_function(wrap_pyfunction!(split_metadata, module)?)?;
+    module.add_function(wrap_pyfunction!(split_eof, module)?)?;
+    module.add_function(wrap_pyfunction!(function_entries, module)?)?;
+    module.add_function(wrap_pyfunction!(lint, module)?)?;
+    module.add_function(wrap_pyfunction!(fingerprint, module)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+    use rstest::*;
+    use std::ffi::CString;
+
+    // Runs Python code with the module imported as `evm`
+    fn run_python(code: &str) -> PyResult<()> {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("evm", pyo3::wrap_pymodule!(evm_disassembler)(py))?;
+            py.run(&CString::new(code)?, Some(&globals), None)
+        })
+    }
+
+    #[rstest]
+    #[case(
+        r#"
+ops = evm.disassemble("0x6080604052")
+assert [op.opcode.name for op in ops] == ["PUSH1", "PUSH1", "MSTORE"]
+assert ops[1].offset == 2 and ops[1].input == b"\x40" and ops[1].size == 2
+assert repr(ops[0]) == "00000000: PUSH1 0x80"
+assert evm.format(bytes.fromhex("6080604052")) == "".join(repr(op) + "\n" for op in ops)
+"#
+    )]
+    #[case(
+        r#"
+opcode = evm.Opcode.from_name("call")
+assert opcode == evm.Opcode.from_byte(0xf1) and opcode.byte == 0xf1
+assert (opcode.inputs, opcode.outputs, opcode.is_call) == (7, 1, True)
+assert evm.Opcode.from_byte(0xe0).name == "INVALID"
+assert evm.Opcode.from_byte(0xe0, eof=True).name == "RJUMP"
+"#
+    )]
+    #[case(
+        r#"
+entries = evm.function_entries("8063a9059cbb1461004157")
+assert [(entry.selector, entry.offset) for entry in entries] == [("0xa9059cbb", 0x41)]
+eof = evm.split_eof("ef000101000402000100040400020000800000e0000000aabb")
+assert eof.types == [(0, 0x80, 0)] and eof.data == b"\xaa\xbb"
+assert [op.offset for op in eof.code[0]] == [19, 22]
+assert evm.split_metadata("6080") == (b"\x60\x80", None)
+assert evm.fingerprint("6080604052").compiler == "solc"
+"#
+    )]
+    #[case(
+        r#"
+assert [(f.lint, f.offset) for f in evm.lint("33ff")] == [("reachable-selfdestruct", 1)]
+eof = "ef000101000402000100070400000000800003" + "5f5f5f35f95000"
+findings = evm.lint(bytes.fromhex(eof))
+assert ("controlled-delegatecall", 23) in [(f.lint, f.offset) for f in findings]
+"#
+    )]
+    fn call_from_python(#[case] code: &str) {
+        run_python(code).unwrap();
+    }
+
+    #[rstest]
+    fn raise_value_errors() {
+        run_python(
+            r#"
+for call in [lambda: evm.disassemble("zz"), lambda: evm.Opcode.from_name("NOPE")]:
+    try:
+        call()
+        raise AssertionError("expected a ValueError")
+    except ValueError:
+        pass
+"#,
+        )
+        .unwrap();
+    }
+}
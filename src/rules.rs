@@ -0,0 +1,632 @@
+//! YARA-like rules for scanning collections of bytecode
+//!
+//! A rule file contains any number of rules. Each rule names instruction patterns (see
+//! [`crate::pattern`]) and byte patterns and combines them in a condition:
+//!
+//! ```text
+//! // Hardcoded owner that can destroy the contract
+//! rule destructible_by_owner {
+//!     meta:
+//!         description = "Compares the caller with a hardcoded address and can selfdestruct"
+//!     opcodes:
+//!         $owner = "CALLER PUSH20 * EQ"
+//!     bytes:
+//!         $destruct = { 33 ff }
+//!     condition:
+//!         $owner and #destruct >= 1
+//! }
+//! ```
+//!
+//! Byte patterns are hex bytes with `??` matching any byte. Conditions combine `$name` (the
+//! pattern matches), `#name` compared with a number (the number of matches), `any of them`,
+//! `all of them`, `true` and `false` with `and`, `or`, `not` and parentheses.
+use crate::batch::read_path;
+use crate::pattern::Pattern;
+use crate::types::Operation;
+use crate::{disassemble, Disassembly};
+use eyre::{eyre, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+
+/// A byte sequence with wildcards
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BytePattern {
+    bytes: Vec<Option<u8>>,
+}
+
+impl BytePattern {
+    /// The number of (possibly overlapping) occurrences in the bytes
+    pub fn count(&self, bytes: &[u8]) -> usize {
+        bytes
+            .windows(self.bytes.len())
+            .filter(|window| {
+                window
+                    .iter()
+                    .zip(&self.bytes)
+                    .all(|(byte, expected)| expected.is_none_or(|expected| expected == *byte))
+            })
+            .count()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Condition {
+    Constant(bool),
+    Matches(String),
+    Count(String, Comparison, usize),
+    AnyOfThem,
+    AllOfThem,
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    fn evaluate(&self, counts: &HashMap<&str, usize>) -> bool {
+        match self {
+            Condition::Constant(value) => *value,
+            Condition::Matches(name) => counts[name.as_str()] > 0,
+            Condition::Count(name, comparison, expected) => {
+                let count = counts[name.as_str()];
+                match comparison {
+                    Comparison::Equal => count == *expected,
+                    Comparison::NotEqual => count != *expected,
+                    Comparison::Less => count < *expected,
+                    Comparison::LessOrEqual => count <= *expected,
+                    Comparison::Greater => count > *expected,
+                    Comparison::GreaterOrEqual => count >= *expected,
+                }
+            }
+            Condition::AnyOfThem => counts.values().any(|count| *count > 0),
+            Condition::AllOfThem => counts.values().all(|count| *count > 0),
+            Condition::Not(condition) => !condition.evaluate(counts),
+            Condition::And(a, b) => a.evaluate(counts) && b.evaluate(counts),
+            Condition::Or(a, b) => a.evaluate(counts) || b.evaluate(counts),
+        }
+    }
+
+    // The pattern names referenced by the condition
+    fn names(&self) -> Vec<&str> {
+        match self {
+            Condition::Matches(name) | Condition::Count(name, _, _) => vec![name],
+            Condition::Not(condition) => condition.names(),
+            Condition::And(a, b) | Condition::Or(a, b) => {
+                a.names().into_iter().chain(b.names()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A named combination of instruction and byte patterns
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    /// The rule name
+    pub name: String,
+    /// The entries of the `meta` section, e.g. `description`
+    pub meta: BTreeMap<String, String>,
+    opcodes: Vec<(String, Pattern)>,
+    bytes: Vec<(String, BytePattern)>,
+    condition: Condition,
+}
+
+impl Rule {
+    /// The description from the `meta` section
+    pub fn description(&self) -> Option<&str> {
+        self.meta.get("description").map(String::as_str)
+    }
+
+    /// Whether the rule matches the code
+    ///
+    /// # Arguments
+    /// - `bytes` - The raw bytecode, searched by byte patterns
+    /// - `operations` - The decoded bytecode, searched by instruction patterns
+    pub fn matches(&self, bytes: &[u8], operations: &[Operation]) -> bool {
+        let counts: HashMap<&str, usize> = self
+            .opcodes
+            .iter()
+            .map(|(name, pattern)| (name.as_str(), pattern.find_all(operations).len()))
+            .chain(
+                self.bytes
+                    .iter()
+                    .map(|(name, pattern)| (name.as_str(), pattern.count(bytes))),
+            )
+            .collect();
+        self.condition.evaluate(&counts)
+    }
+}
+
+// Recursive descent parser over the characters of a rule file
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> eyre::Report {
+        let line = 1 + self.source[..self.position].matches('\n').count();
+        eyre!("Line {line}: {message}")
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    // Skips whitespace and `//` comments
+    fn skip(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.position += rest.len() - trimmed.len();
+            if !trimmed.starts_with("//") {
+                return;
+            }
+            self.position += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip();
+        self.rest().chars().next()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.position += token.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected {token}")))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<&'a str> {
+        self.skip();
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return Err(self.error("Expected an identifier"));
+        }
+        self.position += length;
+        Ok(&rest[..length])
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect("\"")?;
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += i + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        Err(self.error("Unterminated string"))
+    }
+
+    fn byte_pattern(&mut self) -> Result<BytePattern> {
+        self.expect("{")?;
+        let end = self
+            .rest()
+            .find('}')
+            .ok_or_else(|| self.error("Unterminated byte pattern"))?;
+        let hex: String = self.rest()[..end].split_whitespace().collect();
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| match hex.get(i..i + 2) {
+                Some("??") => Ok(None),
+                Some(byte) => u8::from_str_radix(byte, 16).map(Some).map_err(|_| ()),
+                None => Err(()),
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| self.error("Invalid byte pattern"))?;
+        if bytes.is_empty() {
+            return Err(self.error("Empty byte pattern"));
+        }
+        self.position += end + 1;
+        Ok(BytePattern { bytes })
+    }
+
+    fn rule(&mut self) -> Result<Rule> {
+        if self.identifier()? != "rule" {
+            return Err(self.error("Expected rule"));
+        }
+        let name = self.identifier()?.to_string();
+        self.expect("{")?;
+        let mut rule = Rule {
+            name,
+            meta: BTreeMap::new(),
+            opcodes: Vec::new(),
+            bytes: Vec::new(),
+            condition: Condition::Constant(false),
+        };
+        let mut section = "";
+        let mut condition = None;
+        while !self.eat("}") {
+            if self.eat("$") {
+                let name = self.identifier()?.to_string();
+                if rule.opcodes.iter().any(|(n, _)| *n == name)
+                    || rule.bytes.iter().any(|(n, _)| *n == name)
+                {
+                    return Err(self.error(&format!("Duplicate pattern ${name}")));
+                }
+                self.expect("=")?;
+                match section {
+                    "opcodes" => {
+                        let pattern = Pattern::parse(&self.string()?)
+                            .map_err(|e| self.error(&e.to_string()))?;
+                        rule.opcodes.push((name, pattern));
+                    }
+                    "bytes" => rule.bytes.push((name, self.byte_pattern()?)),
+                    _ => return Err(self.error("Patterns belong in opcodes or bytes sections")),
+                }
+                continue;
+            }
+            let word = self.identifier()?;
+            if self.eat(":") {
+                section = word;
+                match section {
+                    "condition" => condition = Some(self.or()?),
+                    "meta" | "opcodes" | "bytes" => {}
+                    _ => return Err(self.error(&format!("Unknown section {section}"))),
+                }
+            } else if section == "meta" {
+                self.expect("=")?;
+                rule.meta.insert(word.to_string(), self.string()?);
+            } else {
+                return Err(self.error(&format!("Unexpected {word}")));
+            }
+        }
+        rule.condition = condition.ok_or_else(|| self.error("Missing condition"))?;
+        for name in rule.condition.names() {
+            let defined = rule.opcodes.iter().any(|(n, _)| n == name)
+                || rule.bytes.iter().any(|(n, _)| n == name);
+            if !defined {
+                return Err(eyre!("Rule {} references undefined ${name}", rule.name));
+            }
+        }
+        Ok(rule)
+    }
+
+    fn or(&mut self) -> Result<Condition> {
+        let mut condition = self.and()?;
+        while self.keyword("or") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition> {
+        let mut condition = self.not()?;
+        while self.keyword("and") {
+            condition = Condition::And(Box::new(condition), Box::new(self.not()?));
+        }
+        Ok(condition)
+    }
+
+    fn not(&mut self) -> Result<Condition> {
+        if self.keyword("not") {
+            return Ok(Condition::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Condition> {
+        if self.eat("(") {
+            let condition = self.or()?;
+            self.expect(")")?;
+            return Ok(condition);
+        }
+        if self.eat("$") {
+            return Ok(Condition::Matches(self.identifier()?.to_string()));
+        }
+        if self.eat("#") {
+            let name = self.identifier()?.to_string();
+            let comparison = [
+                ("==", Comparison::Equal),
+                ("!=", Comparison::NotEqual),
+                ("<=", Comparison::LessOrEqual),
+                (">=", Comparison::GreaterOrEqual),
+                ("<", Comparison::Less),
+                (">", Comparison::Greater),
+            ]
+            .into_iter()
+            .find(|(token, _)| self.eat(token))
+            .map(|(_, comparison)| comparison)
+            .ok_or_else(|| self.error("Expected a comparison"))?;
+            let count = self
+                .identifier()?
+                .parse()
+                .map_err(|_| self.error("Expected a number"))?;
+            return Ok(Condition::Count(name, comparison, count));
+        }
+        match self.identifier()? {
+            "true" => Ok(Condition::Constant(true)),
+            "false" => Ok(Condition::Constant(false)),
+            quantifier @ ("any" | "all") => {
+                if !self.keyword("of") || !self.keyword("them") {
+                    return Err(self.error("Expected of them"));
+                }
+                Ok(match quantifier {
+                    "any" => Condition::AnyOfThem,
+                    _ => Condition::AllOfThem,
+                })
+            }
+            word => Err(self.error(&format!("Unexpected {word} in condition"))),
+        }
+    }
+
+    // Consumes a keyword that is not the prefix of a longer identifier
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.skip();
+        let rest = self.rest();
+        let found = rest.starts_with(keyword)
+            && !rest[keyword.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+        if found {
+            self.position += keyword.len();
+        }
+        found
+    }
+}
+
+/// Parses a rule file
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, rules::parse_rules};
+///
+/// let rules = parse_rules(r#"
+///     rule caller_destruct {
+///         meta:
+///             description = "Sends the balance to the caller"
+///         bytes:
+///             $destruct = { 33 ff }
+///         condition:
+///             $destruct
+///     }
+/// "#).unwrap();
+/// assert_eq!(rules[0].description(), Some("Sends the balance to the caller"));
+/// let bytes = hex::decode("6000543315600a5733ff").unwrap();
+/// assert!(rules[0].matches(&bytes, &disassemble_str("6000543315600a5733ff").unwrap()));
+/// ```
+pub fn parse_rules(source: &str) -> Result<Vec<Rule>> {
+    let mut parser = Parser {
+        source,
+        position: 0,
+    };
+    let mut rules: Vec<Rule> = Vec::new();
+    while parser.peek().is_some() {
+        let rule = parser.rule()?;
+        if rules.iter().any(|existing| existing.name == rule.name) {
+            return Err(eyre!("Duplicate rule {}", rule.name));
+        }
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+/// Reads and parses a rule file
+pub fn load_rules(path: impl AsRef<Path>) -> Result<Vec<Rule>> {
+    parse_rules(&std::fs::read_to_string(path)?)
+}
+
+/// The rules matching a single contract
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanResult {
+    /// The file path, or the address, name or line number of an entry of a JSONL dump
+    pub target: String,
+    /// The names of the matching rules
+    pub rules: Vec<String>,
+    /// Why the target could not be scanned
+    pub error: Option<String>,
+}
+
+/// Returns the names of the rules matching the bytecode
+///
+/// The bytecode is disassembled once for all rules. Instruction patterns never match EIP-7702
+/// delegation designators.
+pub fn scan_bytes(rules: &[Rule], bytes: &[u8]) -> Vec<String> {
+    let operations = match disassemble(bytes.to_vec()) {
+        Ok(Disassembly::Operations(operations)) => operations,
+        _ => Vec::new(),
+    };
+    rules
+        .iter()
+        .filter(|rule| rule.matches(bytes, &operations))
+        .map(|rule| rule.name.clone())
+        .collect()
+}
+
+fn scan_hex(rules: &[Rule], target: String, hex: &str) -> ScanResult {
+    match hex::decode(hex.trim().trim_start_matches("0x")) {
+        Ok(bytes) => ScanResult {
+            target,
+            rules: scan_bytes(rules, &bytes),
+            error: None,
+        },
+        Err(e) => ScanResult {
+            target,
+            rules: Vec::new(),
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+/// Scans a file, a directory or a JSONL or CSV dump of bytecode
+///
+/// The path is read with [`read_path`], which documents the supported formats. Targets that
+/// cannot be read or decoded are reported with an error instead of aborting the scan.
+///
+/// # Arguments
+/// - `rules` - The rules to match
+/// - `path` - The file or directory to scan
+pub fn scan_path(rules: &[Rule], path: impl AsRef<Path>) -> Result<Vec<ScanResult>> {
+    Ok(read_path(path)?
+        .map(|entry| match entry.code {
+            Ok(code) => scan_hex(rules, entry.name, &code),
+            Err(error) => ScanResult {
+                target: entry.name,
+                rules: Vec::new(),
+                error: Some(error),
+            },
+        })
+        .collect())
+}
+
+/// Formats the results of a scan, one line per target that matched or failed
+pub fn format_scan_results(results: &[ScanResult]) -> Result<String> {
+    let mut formatted = String::new();
+    for result in results {
+        match &result.error {
+            Some(error) => writeln!(formatted, "{}: error: {error}", result.target)?,
+            None if !result.rules.is_empty() => {
+                writeln!(formatted, "{}: {}", result.target, result.rules.join(", "))?
+            }
+            None => {}
+        }
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    const RULES: &str = r#"
+        // Hardcoded owner that can destroy the contract
+        rule destructible_by_owner {
+            meta:
+                description = "Compares the caller with a \"hardcoded\" address"
+                author = "monitoring"
+            opcodes:
+                $owner = "CALLER PUSH20 * EQ"
+                $destruct = "SELFDESTRUCT"
+            condition:
+                $owner and $destruct
+        }
+
+        rule many_sstores {
+            opcodes:
+                $sstore = "SSTORE"
+            bytes:
+                $fmp = { 60 80 60 ?? 52 }
+            condition:
+                #sstore >= 2 and not $fmp
+        }
+
+        rule anything { bytes: $stop = { 00 } condition: any of them }
+    "#;
+
+    fn scan(code: &str) -> Vec<String> {
+        let rules = parse_rules(RULES).unwrap();
+        scan_bytes(&rules, &hex::decode(code).unwrap())
+    }
+
+    #[rstest]
+    fn parse_rule_file() {
+        let rules = parse_rules(RULES).unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(
+            rules[0].description(),
+            Some("Compares the caller with a \"hardcoded\" address")
+        );
+        assert_eq!(rules[0].meta["author"], "monitoring");
+    }
+
+    #[rstest]
+    // CALLER PUSH20 <owner> EQ PUSH1 0x1b JUMPI STOP JUMPDEST CALLER SELFDESTRUCT
+    #[case(&format!("3373{}14601b57005b33ff", "11".repeat(20)), vec!["destructible_by_owner", "anything"])]
+    // PUSH1 0x1 PUSH0 SSTORE PUSH1 0x1 PUSH1 0x1 SSTORE
+    #[case("60015f556001600155", vec!["many_sstores"])]
+    // PUSH1 0x80 PUSH1 0x40 MSTORE PUSH1 0x1 PUSH0 SSTORE PUSH1 0x1 PUSH1 0x1 SSTORE
+    #[case("608060405260015f556001600155", vec![])]
+    fn match_rules(#[case] code: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(scan(code), expected);
+    }
+
+    #[rstest]
+    #[case("rule a { condition: $missing }")]
+    #[case("rule a { opcodes: $x = \"NOTANOPCODE\" condition: $x }")]
+    #[case("rule a { bytes: $x = { 6 } condition: $x }")]
+    #[case("rule a { bytes: $x = { 60 } $x = { 61 } condition: $x }")]
+    #[case("rule a { bytes: $x = { 60 } }")]
+    #[case("rule a { bytes: $x = { 60 } condition: #x 2 }")]
+    #[case("rule a { condition: true } rule a { condition: false }")]
+    #[case("rule a { strings: condition: true }")]
+    fn reject_invalid_rules(#[case] source: &str) {
+        assert!(parse_rules(source).is_err());
+    }
+
+    #[rstest]
+    fn scan_directory_and_jsonl() {
+        let directory = std::env::temp_dir().join(format!("evm-rules-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("nested")).unwrap();
+        std::fs::write(directory.join("a.txt"), "0x60015f556001600155").unwrap();
+        std::fs::write(directory.join("nested/b.txt"), "zz").unwrap();
+        std::fs::write(
+            directory.join("dump.jsonl"),
+            "{\"address\": \"0x01\", \"code\": \"0x33ff\"}\n\n{\"bytecode\": \"60015f556001600155\"}\n[]\n",
+        )
+        .unwrap();
+        let rules = parse_rules(RULES).unwrap();
+        let results = scan_path(&rules, &directory).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let summary: Vec<(String, Vec<String>, bool)> = results
+            .into_iter()
+            .map(|result| {
+                let target = result
+                    .target
+                    .rsplit(['/', '\\'])
+                    .next()
+                    .unwrap()
+                    .to_string();
+                (target, result.rules, result.error.is_some())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("a.txt".to_string(), vec!["many_sstores".to_string()], false),
+                ("0x01".to_string(), vec![], false),
+                (
+                    "line 3".to_string(),
+                    vec!["many_sstores".to_string()],
+                    false
+                ),
+                ("line 4".to_string(), vec![], true),
+                ("b.txt".to_string(), vec![], true),
+            ]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Extraction of function selectors from the dispatcher
+use crate::types::{Opcode, Operation};
+use crate::u256::U256;
+
+// Maximum distance between the PUSH4 of a selector and the EQ comparing it
+const MAX_SELECTOR_DISTANCE: usize = 2;
+
+/// A public function found in the dispatcher
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FunctionEntry {
+    /// The 4 byte function selector
+    pub selector: [u8; 4],
+    /// The offset the dispatcher jumps to when the selector matches
+    pub offset: u32,
+}
+
+impl FunctionEntry {
+    /// The selector as hex string with `0x` prefix
+    pub fn selector_hex(&self) -> String {
+        format!("0x{}", hex::encode(self.selector))
+    }
+}
+
+/// Extracts the function selectors and entry offsets from the dispatcher
+///
+/// Recognizes the selector comparisons emitted by solc (`PUSH4 <selector> EQ PUSH <offset>
+/// JUMPI`, with the selector either before or after a `DUP`). Each selector is reported once, in
+/// order of appearance.
+///
+/// # Arguments
+/// - `operations` - The decoded runtime code
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, selectors::function_entries};
+///
+/// // DUP1 PUSH4 0xa9059cbb EQ PUSH2 0x0041 JUMPI
+/// let operations = disassemble_str("8063a9059cbb1461004157").unwrap();
+/// let entries = function_entries(&operations);
+/// assert_eq!(entries[0].selector, [0xa9, 0x05, 0x9c, 0xbb]);
+/// assert_eq!(entries[0].offset, 0x41);
+/// ```
+pub fn function_entries(operations: &[Operation]) -> Vec<FunctionEntry> {
+    let mut entries: Vec<FunctionEntry> = Vec::new();
+    for (i, operation) in operations.iter().enumerate() {
+        if operation.opcode != Opcode::JUMPI || i < 3 {
+            continue;
+        }
+        let target = &operations[i - 1];
+        if !target.opcode.is_push() || operations[i - 2].opcode != Opcode::EQ {
+            continue;
+        }
+        let selector = operations[i.saturating_sub(3 + MAX_SELECTOR_DISTANCE)..i - 2]
+            .iter()
+            .rev()
+            .find(|operation| operation.opcode == Opcode::PUSH4)
+            .and_then(|operation| operation.input.clone().try_into().ok());
+        let Some(selector) = selector else {
+            continue;
+        };
+        // Targets beyond the code size limit are not function entries
+        let offset = U256::from_be_slice(&target.input)
+            .as_u64()
+            .and_then(|offset| u32::try_from(offset).ok());
+        let Some(offset) = offset else {
+            continue;
+        };
+        if entries.iter().all(|entry| entry.selector != selector) {
+            entries.push(FunctionEntry { selector, offset });
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+    use std::fs;
+
+    #[rstest]
+    // DUP1 PUSH4 <selector> EQ PUSH2 <offset> JUMPI
+    #[case("80636fdde0031461004157")]
+    // PUSH4 <selector> DUP2 EQ PUSH2 <offset> JUMPI
+    #[case("636fdde0038114610041 57")]
+    fn dispatcher_patterns(#[case] code: &str) {
+        let operations = disassemble_str(&code.replace(' ', "")).unwrap();
+        assert_eq!(
+            function_entries(&operations),
+            vec![FunctionEntry {
+                selector: [0x6f, 0xdd, 0xe0, 0x03],
+                offset: 0x41
+            }]
+        );
+    }
+
+    #[rstest]
+    fn skip_targets_beyond_u32() {
+        // DUP1 PUSH4 <selector> EQ PUSH5 0x100000041 JUMPI
+        let operations =
+            disassemble_str("80636fdde00314640100000041 57".replace(' ', "").as_str()).unwrap();
+        assert!(function_entries(&operations).is_empty());
+    }
+
+    #[rstest]
+    fn selectors_of_weth() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let selectors: Vec<String> = function_entries(&operations)
+            .iter()
+            .map(FunctionEntry::selector_hex)
+            .collect();
+        assert_eq!(
+            selectors,
+            vec![
+                "0x06fdde03", // name()
+                "0x095ea7b3", // approve(address,uint256)
+                "0x18160ddd", // totalSupply()
+                "0x23b872dd", // transferFrom(address,address,uint256)
+                "0x2e1a7d4d", // withdraw(uint256)
+                "0x313ce567", // decimals()
+                "0x70a08231", // balanceOf(address)
+                "0x95d89b41", // symbol()
+                "0xa9059cbb", // transfer(address,uint256)
+                "0xd0e30db0", // deposit()
+                "0xdd62ed3e", // allowance(address,address)
+            ]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! Normalized hashes and fuzzy similarity of decoded code
+//!
+//! Operations are normalized into tokens of the opcode and, depending on [`PushValues`], the
+//! immediate value, after stripping the metadata trailer. The keccak256 hash of the tokens
+//! identifies exact clones that only differ in metadata or masked constants, a simhash over
+//! n-grams of tokens finds near clones by Hamming distance.
+use crate::keccak::keccak256;
+use crate::metadata::{find_metadata_start, split_metadata};
+use crate::types::Operation;
+use std::collections::HashSet;
+
+// FNV-1a parameters used to hash tokens and n-grams
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// How immediate values of PUSH instructions are normalized
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PushValues {
+    /// Keep all values
+    Keep,
+    /// Keep single byte values (e.g. memory offsets and shift widths) and mask larger ones (jump
+    /// targets, addresses, selectors, hashes)
+    #[default]
+    Bucket,
+    /// Mask all values, only the opcodes remain
+    Mask,
+}
+
+/// Options controlling normalization and fuzzy hashing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SimilarityOptions {
+    /// How immediate values are normalized
+    pub push_values: PushValues,
+    /// The number of consecutive tokens hashed together for the simhash and n-gram similarity
+    pub ngram: usize,
+}
+
+impl Default for SimilarityOptions {
+    fn default() -> Self {
+        SimilarityOptions {
+            push_values: PushValues::Bucket,
+            ngram: 4,
+        }
+    }
+}
+
+/// The normalized hashes of decoded code
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CodeFingerprint {
+    /// The keccak256 hash of the normalized tokens, equal for exact clones
+    pub hash: [u8; 32],
+    /// The simhash of the n-grams of normalized tokens, close for near clones
+    pub simhash: u64,
+    /// The number of instructions without the metadata trailer
+    pub instructions: usize,
+}
+
+impl CodeFingerprint {
+    /// The similarity of the simhashes between 0 (unrelated) and 1 (identical)
+    pub fn similarity(&self, other: &CodeFingerprint) -> f64 {
+        simhash_similarity(self.simhash, other.simhash)
+    }
+}
+
+/// Normalizes operations into one token per instruction
+///
+/// Instructions decoded from the metadata trailer are dropped. The trailer is detected in the
+/// re-encoded operations, so any output of `disassemble_bytes` can be passed directly.
+///
+/// # Arguments
+/// - `operations` - The decoded code
+/// - `push_values` - How immediate values are normalized
+pub fn normalize(operations: &[Operation], push_values: PushValues) -> Vec<u64> {
+    let bytes: Vec<u8> = operations
+        .iter()
+        .flat_map(|operation| {
+            std::iter::once(operation.opcode.to_byte()).chain(operation.input.iter().copied())
+        })
+        .collect();
+    // Trailers decoded into a truncated PUSH lose their length bytes
+    let code_length = match split_metadata(&bytes) {
+        (code, Some(_)) => code.len(),
+        (code, None) => find_metadata_start(code).unwrap_or(code.len()),
+    };
+    let start = operations.first().map_or(0, |operation| operation.offset) as usize;
+    operations
+        .iter()
+        .take_while(|operation| (operation.offset as usize - start) < code_length)
+        .map(|operation| {
+            let keep = match push_values {
+                PushValues::Keep => true,
+                PushValues::Bucket => operation.input.len() <= 1,
+                PushValues::Mask => false,
+            };
+            let mut token = fnv(FNV_OFFSET, &[operation.opcode.to_byte()]);
+            if keep {
+                token = fnv(token, &operation.input);
+            }
+            token
+        })
+        .collect()
+}
+
+fn fnv(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// Hashes of all windows of `n` consecutive tokens, the whole sequence for shorter code
+fn ngrams(tokens: &[u64], n: usize) -> Vec<u64> {
+    let n = n.clamp(1, tokens.len().max(1));
+    tokens
+        .windows(n)
+        .map(|window| {
+            window
+                .iter()
+                .fold(FNV_OFFSET, |hash, token| fnv(hash, &token.to_le_bytes()))
+        })
+        .collect()
+}
+
+/// Computes the exact and fuzzy hashes of decoded code
+///
+/// # Arguments
+/// - `operations` - The decoded code, e.g. the output of `disassemble_bytes`
+/// - `options` - Options controlling normalization and fuzzy hashing
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, similarity::{fingerprint_operations, SimilarityOptions}};
+///
+/// // Two contracts calling different addresses: PUSH20 <address> EXTCODESIZE
+/// let a = disassemble_str(&format!("73{}3b", "11".repeat(20))).unwrap();
+/// let b = disassemble_str(&format!("73{}3b", "22".repeat(20))).unwrap();
+/// let options = SimilarityOptions::default();
+/// assert_eq!(
+///     fingerprint_operations(&a, &options).hash,
+///     fingerprint_operations(&b, &options).hash
+/// );
+/// ```
+pub fn fingerprint_operations(
+    operations: &[Operation],
+    options: &SimilarityOptions,
+) -> CodeFingerprint {
+    let tokens = normalize(operations, options.push_values);
+    let bytes: Vec<u8> = tokens
+        .iter()
+        .flat_map(|token| token.to_le_bytes())
+        .collect();
+    let mut weights = [0i64; 64];
+    for ngram in ngrams(&tokens, options.ngram) {
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if ngram >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+    let simhash = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .fold(0, |hash, (bit, _)| hash | 1 << bit);
+    CodeFingerprint {
+        hash: keccak256(&bytes),
+        simhash,
+        instructions: tokens.len(),
+    }
+}
+
+/// The similarity of two simhashes between 0 and 1, one minus the share of differing bits
+pub fn simhash_similarity(a: u64, b: u64) -> f64 {
+    1.0 - (a ^ b).count_ones() as f64 / 64.0
+}
+
+/// The Jaccard similarity of the sets of normalized n-grams between 0 and 1
+///
+/// Exact but slower than comparing simhashes, useful to confirm candidates found by simhash.
+///
+/// # Arguments
+/// - `a` - The first decoded code
+/// - `b` - The second decoded code
+/// - `options` - Options controlling normalization and the n-gram size
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, similarity::{ngram_similarity, SimilarityOptions}};
+///
+/// let options = SimilarityOptions { ngram: 2, ..Default::default() };
+/// // CALLER SLOAD CALLVALUE ADD vs CALLER SLOAD CALLVALUE SUB
+/// let a = disassemble_str("33543401").unwrap();
+/// let b = disassemble_str("33543403").unwrap();
+/// assert_eq!(ngram_similarity(&a, &b, &options), 0.5);
+/// ```
+pub fn ngram_similarity(a: &[Operation], b: &[Operation], options: &SimilarityOptions) -> f64 {
+    let set = |operations: &[Operation]| -> HashSet<u64> {
+        ngrams(&normalize(operations, options.push_values), options.ngram)
+            .into_iter()
+            .collect()
+    };
+    let (a, b) = (set(a), set(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+    use std::fs;
+
+    fn read_testdata(address: &str) -> Vec<Operation> {
+        let code = fs::read_to_string(format!("testdata/{address}_encoded.txt"))
+            .expect("Unable to read encoded file");
+        disassemble_str(code.trim()).unwrap()
+    }
+
+    const WETH: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+    const USDC: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+
+    #[rstest]
+    // PUSH1 0x20 PUSH2 0x0100
+    #[case(PushValues::Keep, "6020610100", "6020610200", false)]
+    #[case(PushValues::Bucket, "6020610100", "6020610200", true)]
+    #[case(PushValues::Bucket, "6020610100", "6040610100", false)]
+    #[case(PushValues::Mask, "6020610100", "6040610200", true)]
+    fn normalize_push_values(
+        #[case] push_values: PushValues,
+        #[case] a: &str,
+        #[case] b: &str,
+        #[case] equal: bool,
+    ) {
+        let (a, b) = (disassemble_str(a).unwrap(), disassemble_str(b).unwrap());
+        assert_eq!(
+            normalize(&a, push_values) == normalize(&b, push_values),
+            equal
+        );
+    }
+
+    #[rstest]
+    fn ignore_metadata_trailer() {
+        let code = fs::read_to_string(format!("testdata/{WETH}_encoded.txt")).unwrap();
+        let code = code.trim().trim_start_matches("0x");
+        // Replace the swarm hash of the trailer
+        let (body, trailer) = code.split_at(code.len() - 86);
+        let other = format!(
+            "{body}{}{}{}",
+            &trailer[..18],
+            "ab".repeat(32),
+            &trailer[82..]
+        );
+        assert_ne!(code, other);
+        let options = SimilarityOptions::default();
+        let a = fingerprint_operations(&disassemble_str(code).unwrap(), &options);
+        let b = fingerprint_operations(&disassemble_str(&other).unwrap(), &options);
+        assert_eq!(a, b);
+        assert!(a.instructions < disassemble_str(code).unwrap().len());
+    }
+
+    #[rstest]
+    fn find_near_clones() {
+        let options = SimilarityOptions::default();
+        let weth = read_testdata(WETH);
+        // Insert a CALLER instruction in the middle of the code
+        let code = fs::read_to_string(format!("testdata/{WETH}_encoded.txt")).unwrap();
+        let code = code.trim().trim_start_matches("0x");
+        let middle = weth[weth.len() / 2].offset as usize * 2;
+        let modified = format!("{}33{}", &code[..middle], &code[middle..]);
+        let modified = disassemble_str(&modified).unwrap();
+        let usdc = read_testdata(USDC);
+
+        let original = fingerprint_operations(&weth, &options);
+        let clone = fingerprint_operations(&modified, &options);
+        let unrelated = fingerprint_operations(&usdc, &options);
+        assert_ne!(original.hash, clone.hash);
+        assert_eq!(clone.instructions, original.instructions + 1);
+        assert!(original.similarity(&clone) > 0.9);
+        assert!(original.similarity(&clone) > original.similarity(&unrelated));
+        assert!(ngram_similarity(&weth, &modified, &options) > 0.95);
+        assert!(ngram_similarity(&weth, &usdc, &options) < 0.5);
+    }
+}
@@ -0,0 +1,363 @@
+//! Solidity source maps
+//!
+//! Solc emits a compressed source map with one `s:l:f:j:m` entry per instruction, where empty
+//! fields repeat the value of the previous entry. [`attach_sources`] decompresses the map and
+//! attaches every decoded operation to its source file, line range and jump type.
+use crate::types::Operation;
+use eyre::{eyre, Result};
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::ops::RangeInclusive;
+
+/// The jump type of an instruction in the source map
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Jump {
+    /// A jump into a function (`i`)
+    Into,
+    /// A jump returning from a function (`o`)
+    Out,
+    /// Any other instruction (`-`)
+    #[default]
+    Regular,
+}
+
+impl fmt::Display for Jump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Jump::Into => "i",
+            Jump::Out => "o",
+            Jump::Regular => "-",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+/// A decompressed entry of a source map
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SourceMapEntry {
+    /// The byte offset of the source range
+    ///
+    /// Compiler generated code without source uses `-1` for offset, length and file index, negative
+    /// offsets and lengths are stored as 0.
+    pub start: u32,
+    /// The length of the source range in bytes
+    pub length: u32,
+    /// The index of the source file, `None` for instructions without source (index `-1`)
+    pub file: Option<u32>,
+    /// The jump type
+    pub jump: Jump,
+    /// The depth of the modifier the instruction belongs to
+    pub modifier_depth: u32,
+}
+
+/// A source file referenced by the source map
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceFile {
+    /// The source index assigned by the compiler
+    pub id: u32,
+    /// The name or path of the file
+    pub name: String,
+    /// The contents of the file
+    pub content: String,
+}
+
+/// The source range of an operation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation<'s> {
+    /// The source file
+    pub file: &'s SourceFile,
+    /// The byte offset of the range in the file
+    pub start: u32,
+    /// The length of the range in bytes
+    pub length: u32,
+    /// The lines covered by the range, starting at 1
+    pub lines: RangeInclusive<usize>,
+}
+
+/// An operation with its source location
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourcedOperation<'a, 's> {
+    /// The decoded operation
+    pub operation: &'a Operation,
+    /// The source range, `None` for compiler generated code, sources that were not passed in and
+    /// trailing data without entries
+    pub location: Option<SourceLocation<'s>>,
+    /// The jump type
+    pub jump: Jump,
+}
+
+/// Decompresses a solc source map
+///
+/// # Arguments
+/// - `source_map` - The compressed source map, e.g. `sourceMap` of solc's `evm.deployedBytecode`
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::sourcemap::{parse_source_map, Jump};
+///
+/// let entries = parse_source_map("0:20:0:-;;5:3::i").unwrap();
+/// assert_eq!(entries[1].length, 20);
+/// assert_eq!((entries[2].start, entries[2].length, entries[2].file), (5, 3, Some(0)));
+/// assert_eq!(entries[2].jump, Jump::Into);
+/// ```
+pub fn parse_source_map(source_map: &str) -> Result<Vec<SourceMapEntry>> {
+    let mut entries = Vec::new();
+    let mut entry = SourceMapEntry::default();
+    for (i, compressed) in source_map.trim().split(';').enumerate() {
+        let invalid = || eyre!("Invalid source map entry {i}: {compressed:?}");
+        for (field, value) in compressed.split(':').enumerate() {
+            if value.is_empty() {
+                continue;
+            }
+            match field {
+                0 => entry.start = index(value).ok_or_else(invalid)?.unwrap_or(0),
+                1 => entry.length = index(value).ok_or_else(invalid)?.unwrap_or(0),
+                2 => entry.file = index(value).ok_or_else(invalid)?,
+                3 => {
+                    entry.jump = match value {
+                        "i" => Jump::Into,
+                        "o" => Jump::Out,
+                        "-" => Jump::Regular,
+                        _ => return Err(invalid()),
+                    }
+                }
+                4 => entry.modifier_depth = value.parse().map_err(|_| invalid())?,
+                _ => return Err(invalid()),
+            }
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// Parses an offset, length or file index, `-1` denotes a missing value
+fn index(value: &str) -> Option<Option<u32>> {
+    let number: i64 = value.parse().ok()?;
+    Some(u32::try_from(number).ok())
+}
+
+/// Attaches every operation to its source location
+///
+/// Source map entries correspond to operations in order. Operations beyond the end of the source
+/// map (e.g. the metadata trailer decoded as instructions) have no location, as do operations of
+/// source files that are not passed in (e.g. the generated `#utility.yul` of solc).
+///
+/// # Arguments
+/// - `operations` - The decoded code the source map was generated for
+/// - `source_map` - The compressed source map
+/// - `sources` - The source files referenced by index in the source map, files with the same index
+///   as an earlier file are ignored
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, sourcemap::{attach_sources, SourceFile}};
+///
+/// let sources = [SourceFile { id: 0, name: "A.sol".into(), content: "contract A {\n}\n".into() }];
+/// let operations = disassemble_str("6080604052").unwrap();
+/// let sourced = attach_sources(&operations, "0:15:0:-;;13:1", &sources).unwrap();
+/// assert_eq!(sourced[0].location.as_ref().unwrap().lines, 1..=2);
+/// assert_eq!(sourced[2].location.as_ref().unwrap().lines, 2..=2);
+/// ```
+pub fn attach_sources<'a, 's>(
+    operations: &'a [Operation],
+    source_map: &str,
+    sources: &'s [SourceFile],
+) -> Result<Vec<SourcedOperation<'a, 's>>> {
+    let entries = parse_source_map(source_map)?;
+    if entries.len() > operations.len() {
+        return Err(eyre!(
+            "Source map has {} entries but the code only {} instructions",
+            entries.len(),
+            operations.len()
+        ));
+    }
+    // The files by index with the offsets at which their lines start
+    let mut files: HashMap<u32, (&SourceFile, Vec<usize>)> = HashMap::new();
+    for file in sources {
+        files
+            .entry(file.id)
+            .or_insert_with(|| (file, line_starts(&file.content)));
+    }
+    let mut sourced = Vec::with_capacity(operations.len());
+    for (i, operation) in operations.iter().enumerate() {
+        let Some(entry) = entries.get(i) else {
+            sourced.push(SourcedOperation {
+                operation,
+                location: None,
+                jump: Jump::Regular,
+            });
+            continue;
+        };
+        let location = match entry.file.and_then(|id| files.get(&id)) {
+            Some((file, line_starts)) => {
+                Some(location(file, line_starts, entry.start, entry.length)?)
+            }
+            None => None,
+        };
+        sourced.push(SourcedOperation {
+            operation,
+            location,
+            jump: entry.jump,
+        });
+    }
+    Ok(sourced)
+}
+
+fn line_starts(content: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(offset, _)| offset + 1))
+        .collect()
+}
+
+fn location<'s>(
+    file: &'s SourceFile,
+    line_starts: &[usize],
+    start: u32,
+    length: u32,
+) -> Result<SourceLocation<'s>> {
+    let end = start as usize + length as usize;
+    if end > file.content.len() {
+        return Err(eyre!(
+            "Source range {start}:{length} is outside of {}",
+            file.name
+        ));
+    }
+    // Lines starting at or before the offset
+    let line = |offset: usize| line_starts.partition_point(|start| *start <= offset);
+    // The last line of a range ending in a newline is the line of that newline
+    let last = if length == 0 { end } else { end - 1 };
+    Ok(SourceLocation {
+        file,
+        start,
+        length,
+        lines: line(start as usize)..=line(last),
+    })
+}
+
+/// Formats the operations interleaved with the source lines they were compiled from
+///
+/// Whenever the source range changes, the first line of the new range is printed as a comment
+/// before the operation. Jumps into and out of functions are marked with `[in]` and `[out]`.
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, sourcemap::{format_operations_with_source, SourceFile}};
+///
+/// let sources = [SourceFile { id: 0, name: "A.sol".into(), content: "contract A {\n}\n".into() }];
+/// let operations = disassemble_str("6080604052").unwrap();
+/// assert_eq!(
+///     format_operations_with_source(operations, "0:15:0:-;;13:1", &sources).unwrap(),
+///     "// A.sol:1-2: contract A {\n\
+///      00000000: PUSH1 0x80\n\
+///      00000002: PUSH1 0x40\n\
+///      // A.sol:2: }\n\
+///      00000004: MSTORE\n"
+/// );
+/// ```
+pub fn format_operations_with_source(
+    operations: Vec<Operation>,
+    source_map: &str,
+    sources: &[SourceFile],
+) -> Result<String> {
+    let mut formatted = String::new();
+    let mut previous = None;
+    for sourced in attach_sources(&operations, source_map, sources)? {
+        let range = sourced
+            .location
+            .as_ref()
+            .map(|location| (location.file.id, location.lines.clone()));
+        if range != previous {
+            if let Some(location) = &sourced.location {
+                let (first, last) = (*location.lines.start(), *location.lines.end());
+                let text = location.file.content.lines().nth(first - 1).unwrap_or("");
+                write!(formatted, "// {}:{first}", location.file.name)?;
+                if last != first {
+                    write!(formatted, "-{last}")?;
+                }
+                writeln!(formatted, ": {}", text.trim())?;
+            }
+            previous = range;
+        }
+        write!(formatted, "{:?}", sourced.operation)?;
+        match sourced.jump {
+            Jump::Into => write!(formatted, " [in]")?,
+            Jump::Out => write!(formatted, " [out]")?,
+            Jump::Regular => {}
+        }
+        writeln!(formatted)?;
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+
+    const SOURCE: &str = "contract Counter {\n    uint256 count;\n    function inc() public {\n        count += 1;\n    }\n}\n";
+
+    fn sources() -> Vec<SourceFile> {
+        vec![SourceFile {
+            id: 3,
+            name: "Counter.sol".to_string(),
+            content: SOURCE.to_string(),
+        }]
+    }
+
+    #[rstest]
+    #[case("1:2:3:i:1", SourceMapEntry { start: 1, length: 2, file: Some(3), jump: Jump::Into, modifier_depth: 1 })]
+    // Omitted and empty fields repeat the previous entry
+    #[case("1:2:3:i:1;;:5;:::o", SourceMapEntry { start: 1, length: 5, file: Some(3), jump: Jump::Out, modifier_depth: 1 })]
+    #[case("1:2:3;-1:-1:-1", SourceMapEntry { start: 0, length: 0, file: None, ..Default::default() })]
+    fn decompress_entries(#[case] source_map: &str, #[case] last: SourceMapEntry) {
+        let entries = parse_source_map(source_map).unwrap();
+        assert_eq!(*entries.last().unwrap(), last);
+    }
+
+    #[rstest]
+    #[case("1:2:3:x")]
+    #[case("a:2")]
+    #[case("1:2:3:-:0:9")]
+    fn reject_invalid_entries(#[case] source_map: &str) {
+        assert!(parse_source_map(source_map).is_err());
+    }
+
+    #[rstest]
+    fn attach_lines_and_jumps() {
+        let count = SOURCE.find("count += 1").unwrap();
+        // PUSH1 0x1 PUSH0 SLOAD ADD PUSH0 SSTORE JUMP INVALID (metadata)
+        let operations = disassemble_str("60015f54015f5556fe").unwrap();
+        let source_map = format!("{count}:10:3:-;;;;;;32:55::o");
+        let sources = sources();
+        let sourced = attach_sources(&operations, &source_map, &sources).unwrap();
+        assert_eq!(sourced[0].location.as_ref().unwrap().lines, 4..=4);
+        assert_eq!(sourced[6].location.as_ref().unwrap().lines, 2..=5);
+        assert_eq!(sourced[6].jump, Jump::Out);
+        assert_eq!(sourced[7].location, None);
+        let formatted = format_operations_with_source(operations, &source_map, &sources).unwrap();
+        assert!(formatted.starts_with("// Counter.sol:4: count += 1;\n00000000: PUSH1 0x1\n"));
+        assert!(formatted.contains("// Counter.sol:2-5: uint256 count;\n00000007: JUMP [out]\n"));
+    }
+
+    #[rstest]
+    fn skip_missing_sources() {
+        // PUSH1 0x1 STOP with the first instruction in a generated Yul source with index 7
+        let operations = disassemble_str("600100").unwrap();
+        let sources = sources();
+        let sourced = attach_sources(&operations, "0:10:7;0:8:3", &sources).unwrap();
+        assert_eq!(sourced[0].location, None);
+        assert_eq!(sourced[1].location.as_ref().unwrap().lines, 1..=1);
+    }
+
+    #[rstest]
+    #[case("0:1000:3")]
+    #[case("0:1;0:1;0:1;0:1")]
+    fn reject_inconsistent_maps(#[case] source_map: &str) {
+        let operations = disassemble_str("600100").unwrap();
+        assert!(attach_sources(&operations, source_map, &sources()).is_err());
+    }
+}
@@ -0,0 +1,464 @@
+//! Symbolic stack simulation within basic blocks
+//!
+//! Values are tracked as expressions over constants, the unknown stack items present at the start
+//! of the basic block and the results of previous operations. This allows annotating each
+//! operation with a readable view of the stack, e.g. `[calldataload(0x4), 0x20, ...]`.
+use crate::cfg::basic_blocks;
+use crate::types::{Opcode, Operation};
+use eyre::Result;
+use std::fmt::{self, Write};
+use std::rc::Rc;
+
+// Maximum depth of expressions folded into constants
+const MAX_FOLD_DEPTH: usize = 16;
+// Maximum nesting depth of expressions shown in stack annotations
+const ANNOTATION_DEPTH: usize = 3;
+
+/// A symbolic stack value
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Expr {
+    /// A constant pushed by a PUSH instruction (big endian, without the PUSH width semantics)
+    Constant(Vec<u8>),
+    /// The n-th item from the top of the stack at the start of the basic block
+    Input(usize),
+    /// The result of an operation applied to the given arguments (top of stack first)
+    Op(Opcode, Rc<[Expr]>),
+    /// A named intermediate value, used by analyses binding results to variables
+    Variable(usize),
+    /// The keccak256 hash of the concatenated 32 byte words, for SHA3 over known memory contents
+    Keccak(Rc<[Expr]>),
+}
+
+impl Expr {
+    /// Creates an expression for the result of an operation
+    pub fn op(opcode: Opcode, args: Vec<Expr>) -> Self {
+        Expr::Op(opcode, args.into())
+    }
+
+    /// Returns the value of a constant as u64 if it fits
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Expr::Constant(bytes) => {
+                let significant: Vec<u8> = bytes.iter().copied().skip_while(|b| *b == 0).collect();
+                if significant.len() > 8 {
+                    return None;
+                }
+                Some(
+                    significant
+                        .iter()
+                        .fold(0u64, |acc, b| (acc << 8) | *b as u64),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the expression contains the result of the given opcode
+    pub fn contains(&self, opcode: Opcode) -> bool {
+        match self {
+            Expr::Op(op, args) => *op == opcode || args.iter().any(|arg| arg.contains(opcode)),
+            Expr::Keccak(words) => {
+                opcode == Opcode::SHA3 || words.iter().any(|word| word.contains(opcode))
+            }
+            _ => false,
+        }
+    }
+
+    /// Evaluates the expression to a 256 bit word if it only depends on constants
+    ///
+    /// Supports the bitwise operations, `ADD`, `SUB` and multiplications, divisions and shifts by
+    /// whole bytes, which covers the offsets, slots and masks emitted by compilers.
+    pub fn fold(&self) -> Option<[u8; 32]> {
+        self.fold_depth(MAX_FOLD_DEPTH)
+    }
+
+    fn fold_depth(&self, depth: usize) -> Option<[u8; 32]> {
+        let (opcode, args) = match self {
+            Expr::Constant(bytes) if bytes.len() <= 32 => {
+                let mut word = [0u8; 32];
+                word[32 - bytes.len()..].copy_from_slice(bytes);
+                return Some(word);
+            }
+            Expr::Op(opcode, args) if depth > 0 && !args.is_empty() => (opcode, args),
+            _ => return None,
+        };
+        let a = args[0].fold_depth(depth - 1)?;
+        if *opcode == Opcode::NOT {
+            return Some(a.map(|byte| !byte));
+        }
+        let b = args.get(1)?.fold_depth(depth - 1)?;
+        let shift = |word: [u8; 32], bytes: usize, left: bool| {
+            let mut shifted = [0u8; 32];
+            for i in 0..32usize.saturating_sub(bytes) {
+                if left {
+                    shifted[i] = word[i + bytes];
+                } else {
+                    shifted[i + bytes] = word[i];
+                }
+            }
+            shifted
+        };
+        let bitwise = |f: fn(u8, u8) -> u8| {
+            let mut word = [0u8; 32];
+            for i in 0..32 {
+                word[i] = f(a[i], b[i]);
+            }
+            word
+        };
+        Some(match opcode {
+            Opcode::AND => bitwise(|x, y| x & y),
+            Opcode::OR => bitwise(|x, y| x | y),
+            Opcode::XOR => bitwise(|x, y| x ^ y),
+            Opcode::ADD | Opcode::SUB => {
+                let mut word = [0u8; 32];
+                let mut carry = 0i16;
+                for i in (0..32).rev() {
+                    let sum = match opcode {
+                        Opcode::ADD => a[i] as i16 + b[i] as i16 + carry,
+                        _ => a[i] as i16 - b[i] as i16 + carry,
+                    };
+                    word[i] = sum.rem_euclid(256) as u8;
+                    carry = sum.div_euclid(256);
+                }
+                word
+            }
+            Opcode::SHL | Opcode::SHR => {
+                let bits = word_as_u64(a)?;
+                if bits % 8 != 0 {
+                    return None;
+                }
+                shift(b, (bits / 8).min(32) as usize, *opcode == Opcode::SHL)
+            }
+            Opcode::EXP if power_of_256(&a) == Some(1) => {
+                let exponent = word_as_u64(b)?;
+                shift(
+                    Expr::Constant(vec![1]).fold_depth(0)?,
+                    exponent.min(32) as usize,
+                    true,
+                )
+            }
+            Opcode::MUL => match (power_of_256(&a), power_of_256(&b)) {
+                (Some(bytes), _) => shift(b, bytes, true),
+                (_, Some(bytes)) => shift(a, bytes, true),
+                _ => return None,
+            },
+            Opcode::DIV => shift(a, power_of_256(&b)?, false),
+            _ => return None,
+        })
+    }
+
+    fn fmt_depth(&self, f: &mut fmt::Formatter<'_>, depth: Option<usize>) -> fmt::Result {
+        match self {
+            Expr::Constant(bytes) => {
+                let encoded = hex::encode(bytes);
+                let trimmed = encoded.trim_start_matches('0');
+                write!(f, "0x{}", if trimmed.is_empty() { "0" } else { trimmed })
+            }
+            Expr::Input(n) => write!(f, "in{n}"),
+            Expr::Variable(n) => write!(f, "v{n}"),
+            Expr::Op(_, _) | Expr::Keccak(_) if depth == Some(0) => write!(f, ".."),
+            Expr::Op(opcode, args) => {
+                write!(f, "{}(", format!("{opcode:?}").to_lowercase())?;
+                Self::fmt_args(f, args, depth)
+            }
+            Expr::Keccak(words) => {
+                write!(f, "keccak(")?;
+                Self::fmt_args(f, words, depth)
+            }
+        }
+    }
+
+    fn fmt_args(f: &mut fmt::Formatter<'_>, args: &[Expr], depth: Option<usize>) -> fmt::Result {
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            arg.fmt_depth(f, depth.map(|depth| depth - 1))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Formats the expression, using the precision (e.g. `{:.2}`) as maximum nesting depth
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_depth(f, f.precision())
+    }
+}
+
+// The exponent if the word is a power of 256
+pub(crate) fn power_of_256(word: &[u8; 32]) -> Option<usize> {
+    let position = word.iter().rev().position(|b| *b != 0)?;
+    let is_power = word[31 - position] == 1 && word[..31 - position].iter().all(|b| *b == 0);
+    is_power.then_some(position)
+}
+
+pub(crate) fn word_as_u64(word: [u8; 32]) -> Option<u64> {
+    Expr::Constant(word.to_vec()).as_u64()
+}
+
+/// A stack of symbolic values
+///
+/// Items below the known part of the stack are materialized as [`Expr::Input`] on demand.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolicStack {
+    // Known items, top of the stack last
+    items: Vec<Expr>,
+    // Number of input items materialized so far
+    inputs: usize,
+}
+
+impl SymbolicStack {
+    /// Creates an empty stack whose items are all unknown inputs
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The known items of the stack, top of the stack first
+    pub fn items(&self) -> impl Iterator<Item = &Expr> {
+        self.items.iter().rev()
+    }
+
+    /// Number of known items on the stack
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Whether no item on the stack is known
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Number of unknown input items that have been materialized so far
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    /// Returns the n-th item from the top of the stack
+    pub fn peek(&mut self, n: usize) -> Expr {
+        self.ensure(n + 1);
+        self.items[self.items.len() - 1 - n].clone()
+    }
+
+    /// Pushes a value on top of the stack
+    pub fn push(&mut self, value: Expr) {
+        self.items.push(value);
+    }
+
+    /// Removes and returns the top of the stack
+    pub fn pop(&mut self) -> Expr {
+        self.ensure(1);
+        self.items.pop().expect("Stack ensured to be non empty")
+    }
+
+    /// Applies an operation to the stack and returns its arguments (top of stack first)
+    ///
+    /// The result of operations producing a value is pushed as [`Expr::Op`] of the arguments.
+    pub fn apply(&mut self, operation: &Operation) -> Vec<Expr> {
+        let (inputs, outputs) = operation.stack_arity();
+        let opcode = operation.opcode;
+        if opcode.is_push() {
+            self.push(Expr::Constant(operation.input.clone()));
+            return Vec::new();
+        }
+        match opcode {
+            _ if opcode.is_dup() => {
+                let value = self.peek(inputs - 1);
+                self.push(value);
+                Vec::new()
+            }
+            Opcode::EXCHANGE => {
+                let immediate = operation.input.first().copied().unwrap_or(0) as usize;
+                let first = (immediate >> 4) + 1;
+                let second = first + (immediate & 0x0f) + 1;
+                self.swap(first, second);
+                Vec::new()
+            }
+            _ if opcode.is_swap() => {
+                self.swap(0, inputs - 1);
+                Vec::new()
+            }
+            _ => {
+                let args: Vec<Expr> = (0..inputs).map(|_| self.pop()).collect();
+                if outputs > 0 {
+                    self.push(Expr::op(opcode, args.clone()));
+                }
+                args
+            }
+        }
+    }
+
+    // Swaps the n-th and m-th item from the top of the stack
+    fn swap(&mut self, n: usize, m: usize) {
+        self.ensure(n.max(m) + 1);
+        let len = self.items.len();
+        self.items.swap(len - 1 - n, len - 1 - m);
+    }
+
+    // Materializes input items until at least `depth` items are known
+    fn ensure(&mut self, depth: usize) {
+        while self.items.len() < depth {
+            self.materialize_input();
+        }
+    }
+
+    fn materialize_input(&mut self) {
+        self.items.insert(0, Expr::Input(self.inputs));
+        self.inputs += 1;
+    }
+}
+
+/// Formats the stack top first, with `...` denoting the unknown items below
+impl fmt::Display for SymbolicStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for item in self.items() {
+            match f.precision() {
+                Some(depth) => write!(f, "{item:.depth$}, ")?,
+                None => write!(f, "{item}, ")?,
+            }
+        }
+        write!(f, "...]")
+    }
+}
+
+/// The symbolic stack before and after an operation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StackAnnotation {
+    /// The stack before executing the operation
+    pub before: SymbolicStack,
+    /// The stack after executing the operation
+    pub after: SymbolicStack,
+}
+
+/// Computes the symbolic stack before and after each operation
+///
+/// The simulation restarts with an unknown stack at the start of every basic block.
+///
+/// # Arguments
+/// - `operations` - The decoded operations
+pub fn annotate_stack(operations: &[Operation]) -> Vec<StackAnnotation> {
+    let mut annotations = Vec::with_capacity(operations.len());
+    for block in basic_blocks(operations) {
+        let mut stack = SymbolicStack::new();
+        for operation in block.operations {
+            let mut before = stack.clone();
+            stack.apply(operation);
+            // Show the inputs consumed by the operation on the stack before it
+            while before.inputs < stack.inputs {
+                before.materialize_input();
+            }
+            annotations.push(StackAnnotation {
+                before,
+                after: stack.clone(),
+            });
+        }
+    }
+    annotations
+}
+
+/// Converts operations into a human readable string annotated with the symbolic stack
+///
+/// Each line contains the operation formatted as in `format_operations`, followed by the stack
+/// before and after the operation (top of stack first).
+///
+/// # Examples
+/// ```rust
+/// use evm_disassembler::{disassemble_str, stack::format_operations_with_stack};
+///
+/// let operations = disassemble_str("6004356020").unwrap();
+/// let formatted = format_operations_with_stack(operations).unwrap();
+/// assert_eq!(
+///     formatted.lines().last().unwrap(),
+///     "00000003: PUSH1 0x20    [calldataload(0x4), ...] => [0x20, calldataload(0x4), ...]"
+/// );
+/// ```
+pub fn format_operations_with_stack(operations: Vec<Operation>) -> Result<String> {
+    let annotations = annotate_stack(&operations);
+    let lines: Vec<String> = operations
+        .iter()
+        .map(|operation| format!("{operation:?}"))
+        .collect();
+    let width = lines.iter().map(String::len).max().unwrap_or(0);
+    let mut formatted = String::new();
+    for (line, annotation) in lines.iter().zip(annotations.iter()) {
+        writeln!(
+            formatted,
+            "{line:<width$}  {:.depth$} => {:.depth$}",
+            annotation.before,
+            annotation.after,
+            depth = ANNOTATION_DEPTH
+        )?;
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+    use std::fs;
+
+    fn stack_after(code: &str) -> String {
+        let operations = disassemble_str(code).unwrap();
+        let annotations = annotate_stack(&operations);
+        annotations.last().unwrap().after.to_string()
+    }
+
+    #[rstest]
+    // PUSH1 0x4 CALLDATALOAD PUSH1 0x20
+    #[case("6004356020", "[0x20, calldataload(0x4), ...]")]
+    // ADD on unknown stack
+    #[case("01", "[add(in0, in1), ...]")]
+    // DUP2
+    #[case("81", "[in1, in0, in1, ...]")]
+    // SWAP2
+    #[case("91", "[in2, in1, in0, ...]")]
+    // PUSH1 0x1 PUSH1 0x2 SWAP1 SUB
+    #[case("6001600290 03", "[sub(0x1, 0x2), ...]")]
+    // MSTORE consumes both items
+    #[case("6080604052", "[...]")]
+    // PUSH0 POP
+    #[case("5f50", "[...]")]
+    fn symbolic_stack(#[case] code: &str, #[case] expected: &str) {
+        assert_eq!(stack_after(&code.replace(' ', "")), expected);
+    }
+
+    #[rstest]
+    fn stack_resets_at_block_boundaries() {
+        // PUSH1 0x1 JUMPDEST PUSH1 0x2
+        let operations = disassemble_str("60015b6002").unwrap();
+        let annotations = annotate_stack(&operations);
+        assert_eq!(annotations[1].before.to_string(), "[...]");
+        assert_eq!(annotations[2].after.to_string(), "[0x2, ...]");
+    }
+
+    #[rstest]
+    fn nested_expressions_are_truncated() {
+        let expr = Expr::op(
+            Opcode::ADD,
+            vec![
+                Expr::op(Opcode::MLOAD, vec![Expr::Constant(vec![0x40])]),
+                Expr::Input(0),
+            ],
+        );
+        assert_eq!(format!("{expr}"), "add(mload(0x40), in0)");
+        assert_eq!(format!("{expr:.1}"), "add(.., in0)");
+    }
+
+    #[rstest]
+    fn annotate_contract_from_file() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let count = operations.len();
+        let formatted = format_operations_with_stack(operations).unwrap();
+        assert_eq!(formatted.lines().count(), count);
+        // The selector is extracted by dividing the first calldata word
+        assert!(formatted.contains(
+            "[div(calldataload(0x0), 0x100000000000000000000000000000000000000000000000000000000), ...]"
+        ));
+    }
+}
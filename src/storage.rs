@@ -0,0 +1,402 @@
+//! Inference of the storage layout from SLOAD and SSTORE patterns
+//!
+//! Storage slots accessed along all paths are classified by the way they are computed:
+//!
+//! - constant slots hold state variables
+//! - `keccak(key, slot)` is an entry of the mapping at `slot`
+//! - `keccak(slot) + index` is an element of the dynamic array (or long string) at `slot`
+//! - a constant added to one of the above is a member of a struct stored there
+//!
+//! Masks applied to loaded words and cleared before stores reveal variables packed into a slot.
+use crate::stack::{power_of_256, word_as_u64, Expr};
+use crate::symbolic::{explore, BlockIndex};
+use crate::types::{Opcode, Operation};
+use eyre::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+
+// Maximum depth at which the stored value is searched for a cleared field
+const MAX_STORE_DEPTH: usize = 4;
+
+/// The way a storage slot is computed
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Slot {
+    /// A constant slot (big endian)
+    Constant([u8; 32]),
+    /// An entry of the mapping stored at the given slot
+    Mapping(Box<Slot>),
+    /// An element of the dynamic array stored at the given slot
+    Array(Box<Slot>),
+    /// The slot at a constant offset from the given slot, e.g. a struct member
+    Member(Box<Slot>, u64),
+    /// A slot computed in any other way
+    Dynamic,
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Slot::Constant(slot) => write!(f, "{}", Expr::Constant(slot.to_vec())),
+            Slot::Mapping(base) => write!(f, "mapping({base})"),
+            Slot::Array(base) => write!(f, "array({base})"),
+            Slot::Member(base, offset) => write!(f, "{base} + {offset:#x}"),
+            Slot::Dynamic => write!(f, "dynamic"),
+        }
+    }
+}
+
+/// A range of bytes within a storage slot holding a packed variable
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Field {
+    /// Offset of the lowest byte of the field, counted from the least significant byte
+    pub offset: u8,
+    /// Size of the field in bytes
+    pub size: u8,
+}
+
+/// A storage location and how it is accessed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageVariable {
+    /// The slot of the variable
+    pub slot: Slot,
+    /// Whether the slot is read with SLOAD
+    pub read: bool,
+    /// Whether the slot is written with SSTORE
+    pub written: bool,
+    /// The packed fields found in the slot, empty if the slot is only accessed as a whole
+    pub fields: BTreeSet<Field>,
+}
+
+/// Infers the storage layout of runtime code
+///
+/// All paths from the start of the code are explored and every storage access is classified by
+/// its slot (see [`Slot`]). Accesses of mapping entries and array elements with different keys
+/// are merged into a single variable. Packed fields observed with different masks are split at
+/// every observed field boundary.
+///
+/// # Arguments
+/// - `operations` - The decoded runtime code
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, storage::{infer_storage_layout, Slot}};
+///
+/// // mstore(0x0, caller) mstore(0x20, 0x3) sload(sha3(0x0, 0x40))
+/// let operations = disassemble_str("33600052600360205260406000205400").unwrap();
+/// let layout = infer_storage_layout(&operations);
+/// assert_eq!(layout[0].slot.to_string(), "mapping(0x3)");
+/// ```
+pub fn infer_storage_layout(operations: &[Operation]) -> Vec<StorageVariable> {
+    let index = BlockIndex::new(operations);
+    let mut variables: BTreeMap<Slot, StorageVariable> = BTreeMap::new();
+    explore(&index, &[0], |step| match step.operation.opcode {
+        Opcode::SLOAD => variable(&mut variables, classify_slot(&step.args[0])).read = true,
+        Opcode::SSTORE => {
+            let stored = variable(&mut variables, classify_slot(&step.args[0]));
+            stored.written = true;
+            if let Some(field) = cleared_field(&step.args[1], &step.args[0], MAX_STORE_DEPTH) {
+                stored.fields.insert(field);
+            }
+        }
+        Opcode::AND => {
+            if let Some((slot, field)) = masked_field(&step.args[0], &step.args[1]) {
+                variable(&mut variables, classify_slot(&slot))
+                    .fields
+                    .insert(field);
+            }
+        }
+        _ => {}
+    });
+    variables
+        .into_values()
+        .map(|mut variable| {
+            variable.fields = split_fields(&variable.fields);
+            variable
+        })
+        .collect()
+}
+
+// Splits overlapping fields at all field boundaries, e.g. a mask clearing two adjacent fields at
+// once and masks reading each of them result in the two fields
+fn split_fields(fields: &BTreeSet<Field>) -> BTreeSet<Field> {
+    let boundaries: BTreeSet<u8> = fields
+        .iter()
+        .flat_map(|field| [field.offset, field.offset + field.size])
+        .collect();
+    let boundaries: Vec<u8> = boundaries.into_iter().collect();
+    boundaries
+        .windows(2)
+        .map(|range| Field {
+            offset: range[0],
+            size: range[1] - range[0],
+        })
+        .filter(|part| {
+            fields.iter().any(|field| {
+                field.offset <= part.offset && part.offset + part.size <= field.offset + field.size
+            })
+        })
+        .collect()
+}
+
+fn variable(variables: &mut BTreeMap<Slot, StorageVariable>, slot: Slot) -> &mut StorageVariable {
+    variables
+        .entry(slot.clone())
+        .or_insert_with(|| StorageVariable {
+            slot,
+            read: false,
+            written: false,
+            fields: BTreeSet::new(),
+        })
+}
+
+/// Formats the inferred storage layout, one variable per line
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, storage::format_storage_layout};
+///
+/// // sstore(0x1, and(sload(0x0), 0xff))
+/// let operations = disassemble_str("60ff60005416600155").unwrap();
+/// assert_eq!(
+///     format_storage_layout(operations).unwrap(),
+///     "0x0  read        bytes 0..1\n0x1       write\n"
+/// );
+/// ```
+pub fn format_storage_layout(operations: Vec<Operation>) -> Result<String> {
+    let layout = infer_storage_layout(&operations);
+    let slots: Vec<String> = layout
+        .iter()
+        .map(|variable| variable.slot.to_string())
+        .collect();
+    let width = slots.iter().map(String::len).max().unwrap_or(0);
+    let mut formatted = String::new();
+    for (slot, variable) in slots.iter().zip(layout.iter()) {
+        let read = if variable.read { "read" } else { "" };
+        let written = if variable.written { "write" } else { "" };
+        let fields: Vec<String> = variable
+            .fields
+            .iter()
+            .map(|field| format!("{}..{}", field.offset, field.offset + field.size))
+            .collect();
+        let line = if fields.is_empty() {
+            format!("{slot:<width$}  {read:<4} {written}")
+        } else {
+            format!(
+                "{slot:<width$}  {read:<4} {written:<5}  bytes {}",
+                fields.join(", ")
+            )
+        };
+        writeln!(formatted, "{}", line.trim_end())?;
+    }
+    Ok(formatted)
+}
+
+/// Classifies the expression computing a storage slot
+pub fn classify_slot(slot: &Expr) -> Slot {
+    if let Some(constant) = slot.fold() {
+        return Slot::Constant(constant);
+    }
+    match slot {
+        Expr::Keccak(words) if words.len() == 2 => {
+            Slot::Mapping(Box::new(classify_slot(&words[1])))
+        }
+        Expr::Keccak(words) if words.len() == 1 => Slot::Array(Box::new(classify_slot(&words[0]))),
+        Expr::Op(Opcode::ADD, args) => {
+            for (base, offset) in [(&args[0], &args[1]), (&args[1], &args[0])] {
+                let base = classify_slot(base);
+                match (&base, offset.fold().and_then(word_as_u64)) {
+                    (Slot::Constant(_) | Slot::Dynamic, _) => continue,
+                    (_, Some(offset)) => return Slot::Member(Box::new(base), offset),
+                    // A variable index into an array addresses one of its elements
+                    (Slot::Array(_), None) => return base,
+                    _ => continue,
+                }
+            }
+            Slot::Dynamic
+        }
+        _ => Slot::Dynamic,
+    }
+}
+
+// The field read by masking a (shifted) loaded word, together with the loaded slot
+fn masked_field(a: &Expr, b: &Expr) -> Option<(Expr, Field)> {
+    let (mask, value) = match a.fold() {
+        Some(mask) => (mask, b),
+        None => (b.fold()?, a),
+    };
+    let (offset, size) = contiguous_bytes(&mask, 0xff)?;
+    let (slot, shift) = match value {
+        Expr::Op(Opcode::SLOAD, args) => (args[0].clone(), 0),
+        Expr::Op(Opcode::SHR, args) => {
+            let bits = word_as_u64(args[0].fold()?)?;
+            let Expr::Op(Opcode::SLOAD, load) = &args[1] else {
+                return None;
+            };
+            (load[0].clone(), (bits % 8 == 0).then_some(bits / 8)?)
+        }
+        Expr::Op(Opcode::DIV, args) => {
+            let Expr::Op(Opcode::SLOAD, load) = &args[0] else {
+                return None;
+            };
+            (load[0].clone(), power_of_256(&args[1].fold()?)? as u64)
+        }
+        _ => return None,
+    };
+    let offset = shift + offset as u64;
+    if offset + size as u64 > 32 || (offset == 0 && size == 32) {
+        return None;
+    }
+    Some((
+        slot,
+        Field {
+            offset: offset as u8,
+            size,
+        },
+    ))
+}
+
+// The field cleared in the loaded word before storing the updated word at the same slot
+fn cleared_field(value: &Expr, slot: &Expr, depth: usize) -> Option<Field> {
+    let Expr::Op(opcode, args) = value else {
+        return None;
+    };
+    if *opcode == Opcode::AND {
+        for (mask, loaded) in [(&args[0], &args[1]), (&args[1], &args[0])] {
+            let Expr::Op(Opcode::SLOAD, load) = loaded else {
+                continue;
+            };
+            if load[0] != *slot {
+                continue;
+            }
+            let (offset, size) = contiguous_bytes(&mask.fold()?, 0x00)?;
+            return (size < 32).then_some(Field { offset, size });
+        }
+    }
+    if depth == 0 {
+        return None;
+    }
+    args.iter()
+        .find_map(|arg| cleared_field(arg, slot, depth - 1))
+}
+
+// The position and length of the only run of bytes equal to `byte` (counted from the least
+// significant byte), if all other bytes are equal to its complement
+fn contiguous_bytes(word: &[u8; 32], byte: u8) -> Option<(u8, u8)> {
+    let low = word.iter().rev().take_while(|b| **b != byte).count();
+    let size = word
+        .iter()
+        .rev()
+        .skip(low)
+        .take_while(|b| **b == byte)
+        .count();
+    let other = !byte;
+    let is_run = size > 0 && word.iter().filter(|b| **b == other).count() == 32 - size;
+    is_run.then_some((low as u8, size as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+    use std::fs;
+
+    fn layout(code: &str) -> Vec<(String, Vec<(u8, u8)>)> {
+        let operations = disassemble_str(&code.replace(' ', "")).unwrap();
+        infer_storage_layout(&operations)
+            .into_iter()
+            .map(|variable| {
+                let fields = variable
+                    .fields
+                    .iter()
+                    .map(|field| (field.offset, field.size))
+                    .collect();
+                (variable.slot.to_string(), fields)
+            })
+            .collect()
+    }
+
+    #[rstest]
+    // and(sload(0x1), 0xff..ff (20 bytes)) and(shr(0xa0, sload(0x1)), 0xff)
+    #[case(
+        "600154 73ffffffffffffffffffffffffffffffffffffffff 16 50 600154 60a0 1c 60ff 16 00",
+        vec![("0x1", vec![(0, 20), (20, 1)])]
+    )]
+    // and(div(sload(0x0), 0x100), 0xffff)
+    #[case("61ffff 610100 600054 04 16 00", vec![("0x0", vec![(1, 2)])])]
+    // sstore(0x0, or(and(sload(0x0), not(0xff00)), 0x100))
+    #[case("610100 61ff00 19 600054 16 17 600055 00", vec![("0x0", vec![(1, 1)])])]
+    fn packed_fields(#[case] code: &str, #[case] expected: Vec<(&str, Vec<(u8, u8)>)>) {
+        let expected: Vec<(String, Vec<(u8, u8)>)> = expected
+            .into_iter()
+            .map(|(slot, fields)| (slot.to_string(), fields))
+            .collect();
+        assert_eq!(layout(code), expected);
+    }
+
+    #[rstest]
+    // mstore(0x0, caller) mstore(0x20, 0x3) sload(sha3(0x0, 0x40))
+    #[case("33600052 6003602052 6040600020 54 00", "mapping(0x3)")]
+    // sload(sha3(0x0, 0x40)) after mstore(0x0, calldataload(0x4)) and mstore(0x20, <slot of the
+    // inner mapping entry for caller>)
+    #[case(
+        "33600052 6004602052 6040600020 6020 52 6004 35 600052 6040600020 54 00",
+        "mapping(mapping(0x4))"
+    )]
+    // sload(add(sha3(0x0, 0x20), calldataload(0x4))) with mstore(0x0, 0x2)
+    #[case("6002600052 600435 6020600020 01 54 00", "array(0x2)")]
+    // sload(add(sha3(0x0, 0x40), 0x1)) for a struct in a mapping
+    #[case("33600052 6005602052 6001 6040600020 01 54 00", "mapping(0x5) + 0x1")]
+    // sload(calldataload(0x4))
+    #[case("600435 54 00", "dynamic")]
+    fn slot_classification(#[case] code: &str, #[case] expected: &str) {
+        let layout = layout(code);
+        assert_eq!(layout.len(), 1);
+        assert_eq!(layout[0].0, expected);
+    }
+
+    #[rstest]
+    fn layout_of_weth() {
+        let code =
+            fs::read_to_string("testdata/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let formatted = format_storage_layout(operations).unwrap();
+        let slots: Vec<&str> = formatted
+            .lines()
+            .map(|line| line.split("  ").next().unwrap())
+            .collect();
+        // name, symbol, decimals, balanceOf, allowance and the data of the name and symbol strings
+        for slot in [
+            "0x0",
+            "0x1",
+            "0x2",
+            "mapping(0x3)",
+            "mapping(mapping(0x4))",
+            "array(0x0)",
+            "array(0x1)",
+        ] {
+            assert!(slots.contains(&slot), "{slot} missing in\n{formatted}");
+        }
+        // decimals is a uint8
+        assert!(formatted
+            .lines()
+            .any(|line| line.starts_with("0x2 ") && line.ends_with("bytes 0..1")));
+    }
+
+    #[rstest]
+    fn packed_struct_of_seaport() {
+        let code =
+            fs::read_to_string("testdata/0x00000000006c3852cbEf3e08E8dF289169EdE581_encoded.txt")
+                .expect("Unable to read encoded file");
+        let operations = disassemble_str(code.trim()).unwrap();
+        let formatted = format_storage_layout(operations).unwrap();
+        // OrderStatus { bool isValidated; bool isCancelled; uint120 numerator; uint120 denominator }
+        assert!(
+            formatted.contains("mapping(0x2)  read write  bytes 0..1, 1..2, 2..17, 17..32"),
+            "{formatted}"
+        );
+    }
+}
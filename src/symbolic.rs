@@ -0,0 +1,343 @@
+//! Path sensitive symbolic execution over decoded operations
+//!
+//! Starting from an entry point, every reachable path is executed on a [`SymbolicStack`] that is
+//! carried across basic blocks. This resolves jump targets that were pushed in earlier blocks
+//! (e.g. return addresses of internal functions) and provides the symbolic arguments of every
+//! executed operation to analyses built on top.
+//!
+//! Paths are deduplicated by the jump destinations present on the stack, so loops and repeated
+//! calls of the same internal function with the same return address are only executed once.
+//!
+//! Memory words written at constant offsets are tracked along each path as well. Loading a known
+//! word yields the stored expression and hashing known words yields [`Expr::Keccak`], which
+//! exposes e.g. the key and slot of mapping accesses.
+use crate::cfg::{basic_blocks, BasicBlock};
+use crate::stack::{word_as_u64, Expr, SymbolicStack};
+use crate::types::{Opcode, Operation};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
+
+// Maximum number of distinct stack contexts in which a single block is executed
+const MAX_VISITS_PER_BLOCK: usize = 64;
+// Stacks deeper than this are not valid EVM states
+const MAX_STACK_HEIGHT: usize = 1024;
+// Hashes of more memory words than this are not resolved
+const MAX_HASHED_WORDS: u64 = 16;
+
+/// An operation executed during symbolic exploration
+#[derive(Clone, Debug)]
+pub struct Step<'a, 'm> {
+    /// The executed operation
+    pub operation: &'a Operation,
+    /// The arguments consumed from the stack (top of stack first)
+    pub args: Vec<Expr>,
+    /// The value pushed by the operation, if any
+    pub result: Option<Expr>,
+    /// The offset of the entry point the path started at
+    pub entry: u32,
+    /// The known memory contents before the operation writes to memory
+    pub memory: &'m SymbolicMemory,
+}
+
+/// Memory words written at constant offsets
+///
+/// A word partially overwritten by a later write keeps its leading bytes that were not overwritten
+/// (e.g. a function selector followed by the call arguments). Writes at offsets that are not
+/// constant may overwrite any word and therefore forget all known contents.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolicMemory {
+    // Stored words with the number of leading bytes that are still valid
+    words: BTreeMap<u64, (Expr, u64)>,
+}
+
+impl SymbolicMemory {
+    /// Creates a memory without known contents
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the word stored at the given offset, if known
+    pub fn load(&self, offset: &Expr) -> Option<Expr> {
+        self.prefix(offset, 32)
+    }
+
+    /// Returns the word stored at the given offset if at least its first `length` bytes are known
+    pub fn prefix(&self, offset: &Expr, length: u64) -> Option<Expr> {
+        let (value, valid) = self.words.get(&constant(offset)?)?;
+        (*valid >= length).then(|| value.clone())
+    }
+
+    /// Returns the known words of the given memory range, if it consists of whole known words
+    pub fn words(&self, offset: &Expr, size: &Expr) -> Option<Vec<Expr>> {
+        let (offset, size) = (constant(offset)?, constant(size)?);
+        if size == 0 || size % 32 != 0 || size / 32 > MAX_HASHED_WORDS {
+            return None;
+        }
+        (0..size / 32)
+            .map(|i| {
+                let word = offset.checked_add(i * 32)?;
+                self.load(&Expr::Constant(word.to_be_bytes().to_vec()))
+            })
+            .collect()
+    }
+
+    /// Stores a word at the given offset
+    pub fn store(&mut self, offset: &Expr, value: Expr) {
+        self.invalidate(offset, &Expr::Constant(vec![32]));
+        if let Some(offset) = constant(offset) {
+            self.words.insert(offset, (value, 32));
+        }
+    }
+
+    /// Forgets the bytes of the given memory range
+    pub fn invalidate(&mut self, offset: &Expr, size: &Expr) {
+        match (constant(offset), constant(size)) {
+            (_, Some(0)) => {}
+            (Some(start), Some(size)) => {
+                let end = start.saturating_add(size);
+                self.words.retain(|word, (_, valid)| {
+                    if *word >= end || word.saturating_add(32) <= start {
+                        return true;
+                    }
+                    // Keep the leading bytes in front of the range
+                    *valid = (*valid).min(start.saturating_sub(*word));
+                    *valid > 0
+                });
+            }
+            _ => self.words.clear(),
+        }
+    }
+
+    // The value produced by an operation reading known memory contents
+    fn read(&self, opcode: Opcode, args: &[Expr]) -> Option<Expr> {
+        match opcode {
+            Opcode::MLOAD => self.load(&args[0]),
+            Opcode::SHA3 => self
+                .words(&args[0], &args[1])
+                .map(|words| Expr::Keccak(Rc::from(words))),
+            _ => None,
+        }
+    }
+
+    // Updates memory for the writes of an executed operation
+    fn write(&mut self, opcode: Opcode, args: &[Expr]) {
+        match opcode {
+            Opcode::MSTORE => self.store(&args[0], args[1].clone()),
+            Opcode::MSTORE8 => self.invalidate(&args[0], &Expr::Constant(vec![1])),
+            Opcode::CALLDATACOPY | Opcode::CODECOPY | Opcode::RETURNDATACOPY | Opcode::MCOPY => {
+                self.invalidate(&args[0], &args[2])
+            }
+            Opcode::EXTCODECOPY => self.invalidate(&args[1], &args[3]),
+            Opcode::CALL | Opcode::CALLCODE => self.invalidate(&args[5], &args[6]),
+            Opcode::DELEGATECALL | Opcode::STATICCALL => self.invalidate(&args[4], &args[5]),
+            _ => {}
+        }
+    }
+}
+
+/// The result of executing a basic block on a symbolic stack
+#[derive(Clone, Debug)]
+pub struct BlockExit {
+    /// Offsets of the blocks that execution may continue at
+    pub successors: Vec<u32>,
+    /// Whether a jump target could not be resolved to a constant JUMPDEST
+    pub unresolved: bool,
+}
+
+/// Index of basic blocks by offset, shared by all path based analyses
+#[derive(Clone, Debug)]
+pub struct BlockIndex<'a> {
+    /// The basic blocks in code order
+    pub blocks: Vec<BasicBlock<'a>>,
+    by_offset: HashMap<u32, usize>,
+}
+
+impl<'a> BlockIndex<'a> {
+    /// Splits the operations into basic blocks and indexes them by offset
+    pub fn new(operations: &'a [Operation]) -> Self {
+        let blocks = basic_blocks(operations);
+        let by_offset = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (block.offset(), i))
+            .collect();
+        BlockIndex { blocks, by_offset }
+    }
+
+    /// Returns the block starting at the given offset
+    pub fn block(&self, offset: u32) -> Option<&BasicBlock<'a>> {
+        self.by_offset.get(&offset).map(|i| &self.blocks[*i])
+    }
+
+    /// Whether a JUMP to the given offset is valid
+    pub fn is_jumpdest(&self, offset: u32) -> bool {
+        self.block(offset)
+            .is_some_and(|block| block.operations[0].opcode == Opcode::JUMPDEST)
+    }
+
+    /// Returns the offset of the block following the given block in code order
+    pub fn next_offset(&self, block: &BasicBlock<'a>) -> Option<u32> {
+        let i = self.by_offset.get(&block.offset())?;
+        self.blocks.get(i + 1).map(|block| block.offset())
+    }
+
+    /// Executes a block on the given stack and memory and reports where execution may continue
+    ///
+    /// `visit` is called for every executed operation.
+    pub fn execute(
+        &self,
+        block: &BasicBlock<'a>,
+        stack: &mut SymbolicStack,
+        memory: &mut SymbolicMemory,
+        entry: u32,
+        visit: &mut impl FnMut(&Step<'a, '_>),
+    ) -> BlockExit {
+        let mut exit = BlockExit {
+            successors: Vec::new(),
+            unresolved: false,
+        };
+        let mut jump_target = None;
+        for operation in block.operations {
+            let args = stack.apply(operation);
+            let opcode = operation.opcode;
+            if let Some(value) = memory.read(opcode, &args) {
+                stack.pop();
+                stack.push(value);
+            }
+            let produces_value =
+                operation.stack_arity().1 == 1 && !opcode.is_dup() && !opcode.is_swap();
+            let result = produces_value.then(|| stack.peek(0));
+            if matches!(opcode, Opcode::JUMP | Opcode::JUMPI) {
+                jump_target = Some(args[0].clone());
+            }
+            let step = Step {
+                operation,
+                args,
+                result,
+                entry,
+                memory,
+            };
+            visit(&step);
+            memory.write(opcode, &step.args);
+        }
+
+        let last = block.last();
+        match last.opcode {
+            Opcode::JUMP | Opcode::JUMPI => match jump_target
+                .as_ref()
+                .and_then(Expr::as_u64)
+                .and_then(|target| u32::try_from(target).ok())
+            {
+                Some(target) if self.is_jumpdest(target) => exit.successors.push(target),
+                _ => exit.unresolved = true,
+            },
+            Opcode::RJUMP | Opcode::RJUMPI | Opcode::RJUMPV => {
+                exit.successors.extend(relative_targets(last));
+            }
+            _ => {}
+        }
+        if block.falls_through() {
+            exit.successors.extend(self.next_offset(block));
+        }
+        exit
+    }
+}
+
+/// The control flow observed while exploring
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Exploration {
+    /// All taken edges between blocks as pairs of block offsets
+    pub edges: BTreeSet<(u32, u32)>,
+    /// Offsets of blocks ending in a jump whose target could not be resolved in some context
+    pub unresolved: BTreeSet<u32>,
+    /// Offsets of all executed blocks
+    pub reached: BTreeSet<u32>,
+}
+
+/// Explores all paths reachable from the given entry points
+///
+/// Every entry point starts with an unknown stack and is explored independently. `visit` is
+/// called for every executed operation, operations in blocks reached in several stack contexts
+/// are visited once per context.
+///
+/// # Arguments
+/// - `index` - The basic blocks of the code
+/// - `entries` - Offsets of the blocks to start exploring at
+/// - `visit` - Callback for every executed operation
+pub fn explore<'a>(
+    index: &BlockIndex<'a>,
+    entries: &[u32],
+    mut visit: impl FnMut(&Step<'a, '_>),
+) -> Exploration {
+    let mut exploration = Exploration::default();
+    for entry in entries {
+        let mut visited: HashSet<(u32, Vec<Option<u32>>)> = HashSet::new();
+        let mut visits: HashMap<u32, usize> = HashMap::new();
+        let mut worklist = vec![(*entry, SymbolicStack::new(), SymbolicMemory::new())];
+        while let Some((offset, mut stack, mut memory)) = worklist.pop() {
+            let Some(block) = index.block(offset) else {
+                continue;
+            };
+            if stack.len() > MAX_STACK_HEIGHT {
+                continue;
+            }
+            let key = (offset, jumpdest_projection(index, &stack));
+            let count = visits.entry(offset).or_default();
+            if *count >= MAX_VISITS_PER_BLOCK || !visited.insert(key) {
+                continue;
+            }
+            *count += 1;
+            exploration.reached.insert(offset);
+            let exit = index.execute(block, &mut stack, &mut memory, *entry, &mut visit);
+            if exit.unresolved {
+                exploration.unresolved.insert(offset);
+            }
+            for successor in exit.successors {
+                exploration.edges.insert((offset, successor));
+                worklist.push((successor, stack.clone(), memory.clone()));
+            }
+        }
+    }
+    exploration
+}
+
+// The jump destinations on the stack, which determine the paths that can be taken from here
+fn jumpdest_projection(index: &BlockIndex, stack: &SymbolicStack) -> Vec<Option<u32>> {
+    stack
+        .items()
+        .map(|item| {
+            item.as_u64()
+                .and_then(|value| u32::try_from(value).ok())
+                .filter(|value| index.is_jumpdest(*value))
+        })
+        .collect()
+}
+
+// The value of an expression that only depends on constants
+fn constant(expr: &Expr) -> Option<u64> {
+    expr.fold().and_then(word_as_u64)
+}
+
+// Targets of EOF relative jumps, relative to the end of the instruction
+pub(crate) fn relative_targets(operation: &Operation) -> Vec<u32> {
+    let end = operation.offset as i64 + 1 + operation.input.len() as i64;
+    let relative_offsets: Vec<i16> = match operation.opcode {
+        Opcode::RJUMPV => operation
+            .input
+            .get(1..)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|bytes| i16::from_be_bytes([bytes[0], bytes[1]]))
+            .collect(),
+        _ if operation.input.len() == 2 => {
+            vec![i16::from_be_bytes([operation.input[0], operation.input[1]])]
+        }
+        _ => Vec::new(),
+    };
+    relative_offsets
+        .into_iter()
+        .map(|relative| end + relative as i64)
+        .filter(|target| *target >= 0)
+        .map(|target| target as u32)
+        .collect()
+}
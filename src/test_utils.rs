@@ -1,3 +1,4 @@
+#![allow(dead_code)]
 use ethers::providers::{Http, Middleware, Provider};
 
 pub fn pad_word(input: &str) -> [u8; 32] {
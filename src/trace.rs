@@ -0,0 +1,318 @@
+//! Annotation of execution traces with the decoded operations
+//!
+//! Supports the struct logs returned by geth's `debug_traceTransaction` with the default tracer
+//! (optionally wrapped in a JSON-RPC response) and flat arrays of opcode steps. Every step of the
+//! traced contract is mapped back to the operation at its pc and checked against it.
+use crate::types::{Opcode, Operation};
+use eyre::{eyre, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// A single step of an execution trace
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// The program counter
+    pub pc: u32,
+    /// The opcode name as given in the trace
+    pub name: String,
+    /// The opcode, if the name or byte in the trace is known
+    pub opcode: Option<Opcode>,
+    /// The call depth, starting at 1
+    pub depth: u32,
+    /// The gas remaining before the step
+    pub gas: Option<u64>,
+    /// The gas cost of the step
+    pub gas_cost: Option<u64>,
+}
+
+/// How a trace step relates to the decoded code
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Annotation<'a> {
+    /// The traced opcode matches the decoded operation at the pc
+    Matched(&'a Operation),
+    /// The decoded operation at the pc has a different opcode
+    Mismatch(&'a Operation),
+    /// No decoded operation starts at the pc
+    UnknownPc,
+    /// The step executes in a nested call, i.e. most likely in the code of another contract
+    Nested,
+}
+
+/// A trace step annotated with the decoded operation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnnotatedStep<'a> {
+    /// The step as given in the trace
+    pub entry: TraceEntry,
+    /// The decoded operation at the pc of the step
+    pub annotation: Annotation<'a>,
+    /// The target of a taken `JUMP` or `JUMPI`
+    pub jump: Option<u32>,
+}
+
+/// Parses the steps of a JSON execution trace
+///
+/// Accepts geth struct logs (`{"structLogs": [...]}`, also inside a JSON-RPC `result`) and arrays
+/// of steps. Each step needs a `pc` and an `op`, given either as name or as opcode byte, `depth`,
+/// `gas` and `gasCost` are optional. Numbers may be given as JSON numbers or hex strings.
+///
+/// Traces of the `callTracer` only contain call frames without program counters and are rejected.
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{trace::parse_trace, types::Opcode};
+///
+/// let trace = r#"{"structLogs": [{"pc": 0, "op": "PUSH1", "gas": 100, "gasCost": 3, "depth": 1}]}"#;
+/// let entries = parse_trace(trace).unwrap();
+/// assert_eq!(entries[0].opcode, Some(Opcode::PUSH1));
+/// assert_eq!(entries[0].gas_cost, Some(3));
+/// ```
+pub fn parse_trace(json: &str) -> Result<Vec<TraceEntry>> {
+    let value: Value = serde_json::from_str(json)?;
+    let value = value.get("result").unwrap_or(&value);
+    let steps = match value {
+        Value::Array(steps) => steps,
+        Value::Object(object) => match object.get("structLogs") {
+            Some(Value::Array(steps)) => steps,
+            _ if object.contains_key("calls") || object.contains_key("type") => {
+                return Err(eyre!(
+                    "callTracer traces contain no program counters, trace with the default \
+                     struct logger instead"
+                ))
+            }
+            _ => return Err(eyre!("Trace contains no structLogs")),
+        },
+        _ => return Err(eyre!("Unsupported trace format")),
+    };
+    steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| parse_step(step).ok_or_else(|| eyre!("Invalid trace step {i}")))
+        .collect()
+}
+
+fn parse_step(step: &Value) -> Option<TraceEntry> {
+    let pc = u32::try_from(number(step.get("pc")?)?).ok()?;
+    let (name, opcode) = match step.get("op")? {
+        Value::String(name) if !name.starts_with("0x") => (name.clone(), Opcode::from_name(name)),
+        op => {
+            let opcode = Opcode::from_byte(u8::try_from(number(op)?).ok()?);
+            (format!("{opcode:?}"), Some(opcode))
+        }
+    };
+    Some(TraceEntry {
+        pc,
+        name,
+        opcode,
+        depth: step.get("depth").map_or(Some(1), number)? as u32,
+        gas: step.get("gas").and_then(number),
+        gas_cost: step.get("gasCost").and_then(number),
+    })
+}
+
+// A number given as JSON number or (hex) string
+fn number(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(string) => match string.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => string.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+/// Maps every trace step to the decoded operation at its pc
+///
+/// Steps at the call depth of the first step are considered to execute the decoded code, steps at
+/// other depths are marked as [`Annotation::Nested`].
+///
+/// # Arguments
+/// - `operations` - The decoded code of the traced contract
+/// - `entries` - The trace steps
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, trace::{annotate_trace, parse_trace, Annotation}};
+///
+/// let operations = disassemble_str("600100").unwrap();
+/// let entries = parse_trace(r#"[{"pc": 0, "op": "PUSH1"}, {"pc": 2, "op": "ADD"}]"#).unwrap();
+/// let steps = annotate_trace(&operations, &entries);
+/// assert_eq!(steps[0].annotation, Annotation::Matched(&operations[0]));
+/// assert_eq!(steps[1].annotation, Annotation::Mismatch(&operations[1]));
+/// ```
+pub fn annotate_trace<'a>(
+    operations: &'a [Operation],
+    entries: &[TraceEntry],
+) -> Vec<AnnotatedStep<'a>> {
+    let by_offset: HashMap<u32, &Operation> = operations
+        .iter()
+        .map(|operation| (operation.offset, operation))
+        .collect();
+    let root = entries.first().map_or(1, |entry| entry.depth);
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let annotation = match by_offset.get(&entry.pc) {
+                _ if entry.depth != root => Annotation::Nested,
+                Some(operation) if entry.opcode == Some(operation.opcode) => {
+                    Annotation::Matched(operation)
+                }
+                Some(operation) => Annotation::Mismatch(operation),
+                None => Annotation::UnknownPc,
+            };
+            let next = entries.get(i + 1).filter(|next| next.depth == entry.depth);
+            let jump = match (entry.opcode, next) {
+                (Some(Opcode::JUMP), Some(next)) => Some(next.pc),
+                (Some(Opcode::JUMPI), Some(next)) if next.pc != entry.pc + 1 => Some(next.pc),
+                _ => None,
+            };
+            AnnotatedStep {
+                entry: entry.clone(),
+                annotation,
+                jump,
+            }
+        })
+        .collect()
+}
+
+/// Annotates a JSON trace and formats it, one step per line
+///
+/// Steps in nested calls are indented by their call depth, taken jumps show their target and
+/// steps not matching the decoded code are marked with `!!`.
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::{disassemble_str, trace::format_trace};
+///
+/// // PUSH1 0x4 JUMP INVALID JUMPDEST STOP
+/// let operations = disassemble_str("600456fe5b00").unwrap();
+/// let trace = r#"[{"pc": 0, "op": "PUSH1"}, {"pc": 2, "op": "JUMP"}, {"pc": 4, "op": "JUMPDEST"}]"#;
+/// assert_eq!(
+///     format_trace(operations, trace).unwrap(),
+///     "00000000: PUSH1 0x4\n00000002: JUMP -> 00000004\n00000004: JUMPDEST\n"
+/// );
+/// ```
+pub fn format_trace(operations: Vec<Operation>, json: &str) -> Result<String> {
+    let entries = parse_trace(json)?;
+    let root = entries.first().map_or(1, |entry| entry.depth);
+    let mut formatted = String::new();
+    for step in annotate_trace(&operations, &entries) {
+        let entry = &step.entry;
+        let indent = "  ".repeat(entry.depth.saturating_sub(root) as usize);
+        write!(formatted, "{indent}{:08x}: ", entry.pc)?;
+        match step.annotation {
+            Annotation::Matched(operation) => write!(formatted, "{}", instruction(operation))?,
+            Annotation::Mismatch(operation) => write!(
+                formatted,
+                "{} !! decoded as {}",
+                entry.name,
+                instruction(operation)
+            )?,
+            Annotation::UnknownPc => write!(formatted, "{} !! no instruction at pc", entry.name)?,
+            Annotation::Nested => write!(formatted, "{} [depth {}]", entry.name, entry.depth)?,
+        }
+        if let Some(gas) = entry.gas {
+            write!(formatted, " gas={gas}")?;
+        }
+        if let Some(gas_cost) = entry.gas_cost {
+            write!(formatted, " cost={gas_cost}")?;
+        }
+        if let Some(target) = step.jump {
+            write!(formatted, " -> {target:08x}")?;
+        }
+        writeln!(formatted)?;
+    }
+    Ok(formatted)
+}
+
+// The operation as formatted by `format_operations` without its offset
+fn instruction(operation: &Operation) -> String {
+    let formatted = format!("{operation:?}");
+    match formatted.split_once(": ") {
+        Some((_, instruction)) => instruction.to_string(),
+        None => formatted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_str;
+    use rstest::*;
+
+    // PUSH1 0x1 PUSH1 0x7 JUMPI INVALID INVALID JUMPDEST PUSH0 PUSH0 CALL STOP
+    const CODE: &str = "6001600757fefe5b5f5ff100";
+
+    #[rstest]
+    fn annotate_struct_logs_with_nested_call() {
+        let trace = r#"{"jsonrpc": "2.0", "id": 1, "result": {"gas": 100, "failed": false,
+            "returnValue": "", "structLogs": [
+            {"pc": 0, "op": "PUSH1", "gas": 1000, "gasCost": 3, "depth": 1, "stack": []},
+            {"pc": 2, "op": "PUSH1", "gas": 997, "gasCost": 3, "depth": 1, "stack": ["0x1"]},
+            {"pc": 4, "op": "JUMPI", "gas": 994, "gasCost": 10, "depth": 1},
+            {"pc": 7, "op": "JUMPDEST", "gas": 984, "gasCost": 1, "depth": 1},
+            {"pc": 8, "op": "PUSH0", "gas": 983, "gasCost": 2, "depth": 1},
+            {"pc": 9, "op": "PUSH0", "gas": 981, "gasCost": 2, "depth": 1},
+            {"pc": 10, "op": "CALL", "gas": 979, "gasCost": 100, "depth": 1},
+            {"pc": 0, "op": "STOP", "gas": 500, "gasCost": 0, "depth": 2},
+            {"pc": 11, "op": "STOP", "gas": 879, "gasCost": 0, "depth": 1}
+        ]}}"#;
+        let operations = disassemble_str(CODE).unwrap();
+        let formatted = format_trace(operations, trace).unwrap();
+        let lines: Vec<&str> = formatted.lines().collect();
+        assert_eq!(lines[2], "00000004: JUMPI gas=994 cost=10 -> 00000007");
+        assert_eq!(lines[7], "  00000000: STOP [depth 2] gas=500 cost=0");
+        assert_eq!(lines[8], "0000000b: STOP gas=879 cost=0");
+    }
+
+    #[rstest]
+    // Opcodes given as bytes and numbers as hex strings
+    #[case(r#"[{"pc": "0x0", "op": 96}, {"pc": "0x2", "op": "0x60"}]"#, true)]
+    // Aliases used by geth
+    #[case(r#"[{"pc": 0, "op": "PUSH1"}, {"pc": 11, "op": "stop"}]"#, true)]
+    #[case(r#"[{"pc": 0, "op": "PUSH1"}, {"pc": 2, "op": "KECCAK256"}]"#, false)]
+    fn verify_opcodes(#[case] trace: &str, #[case] matches: bool) {
+        let operations = disassemble_str(CODE).unwrap();
+        let entries = parse_trace(trace).unwrap();
+        let steps = annotate_trace(&operations, &entries);
+        assert_eq!(
+            steps
+                .iter()
+                .all(|step| matches!(step.annotation, Annotation::Matched(_))),
+            matches
+        );
+    }
+
+    #[rstest]
+    fn unknown_pc_and_aliases() {
+        assert_eq!(Opcode::from_name("KECCAK256"), Some(Opcode::SHA3));
+        assert_eq!(Opcode::from_name("PREVRANDAO"), Some(Opcode::DIFFICULTY));
+        assert_eq!(Opcode::from_name("RJUMP"), Some(Opcode::RJUMP));
+        assert_eq!(Opcode::from_name("NOPE"), None);
+        for opcode in
+            (0..=u8::MAX).flat_map(|byte| [Opcode::from_byte(byte), Opcode::from_byte_eof(byte)])
+        {
+            assert_eq!(Opcode::from_name(&format!("{opcode:?}")), Some(opcode));
+            assert_eq!(
+                Opcode::from_name(&format!("{opcode:?}").to_lowercase()),
+                Some(opcode)
+            );
+        }
+        let operations = disassemble_str(CODE).unwrap();
+        // pc 1 is the immediate of PUSH1
+        let formatted = format_trace(operations, r#"[{"pc": 1, "op": "ADD"}]"#).unwrap();
+        assert_eq!(formatted, "00000001: ADD !! no instruction at pc\n");
+    }
+
+    #[rstest]
+    fn reject_call_tracer() {
+        let trace = r#"{"type": "CALL", "from": "0x1", "to": "0x2", "calls": []}"#;
+        let error = parse_trace(trace).unwrap_err();
+        assert!(error.to_string().contains("callTracer"));
+    }
+}
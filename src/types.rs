@@ -1,11 +1,15 @@
 //! Output types for Operation and Opcode
-use eyre::{eyre, Result};
-use std::fmt;
+use crate::error::{eyre, Result};
+use crate::link::LinkPlaceholder;
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::vec::Vec;
+use core::fmt;
 
 /// A single EVM operation
 ///
 /// For additional information on each operation see: https://www.evm.codes/
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[allow(missing_docs)]
 pub enum Opcode {
     STOP,
@@ -347,6 +351,372 @@ impl Opcode {
         }
     }
 
+    /// Look up an opcode by its mnemonic
+    ///
+    /// Accepts the names used by this crate as well as the aliases used by execution clients
+    /// (`KECCAK256` for `SHA3` and `PREVRANDAO` for `DIFFICULTY`). Names are case insensitive.
+    pub fn from_name(name: &str) -> Option<Opcode> {
+        let opcode = match name.to_ascii_uppercase().as_str() {
+            "KECCAK256" => Opcode::SHA3,
+            "PREVRANDAO" => Opcode::DIFFICULTY,
+            "STOP" => Opcode::STOP,
+            "ADD" => Opcode::ADD,
+            "MUL" => Opcode::MUL,
+            "SUB" => Opcode::SUB,
+            "DIV" => Opcode::DIV,
+            "SDIV" => Opcode::SDIV,
+            "MOD" => Opcode::MOD,
+            "SMOD" => Opcode::SMOD,
+            "ADDMOD" => Opcode::ADDMOD,
+            "MULMOD" => Opcode::MULMOD,
+            "EXP" => Opcode::EXP,
+            "SIGNEXTEND" => Opcode::SIGNEXTEND,
+            "LT" => Opcode::LT,
+            "GT" => Opcode::GT,
+            "SLT" => Opcode::SLT,
+            "SGT" => Opcode::SGT,
+            "EQ" => Opcode::EQ,
+            "ISZERO" => Opcode::ISZERO,
+            "AND" => Opcode::AND,
+            "OR" => Opcode::OR,
+            "XOR" => Opcode::XOR,
+            "NOT" => Opcode::NOT,
+            "BYTE" => Opcode::BYTE,
+            "SHL" => Opcode::SHL,
+            "SHR" => Opcode::SHR,
+            "SAR" => Opcode::SAR,
+            "SHA3" => Opcode::SHA3,
+            "ADDRESS" => Opcode::ADDRESS,
+            "BALANCE" => Opcode::BALANCE,
+            "ORIGIN" => Opcode::ORIGIN,
+            "CALLER" => Opcode::CALLER,
+            "CALLVALUE" => Opcode::CALLVALUE,
+            "CALLDATALOAD" => Opcode::CALLDATALOAD,
+            "CALLDATASIZE" => Opcode::CALLDATASIZE,
+            "CALLDATACOPY" => Opcode::CALLDATACOPY,
+            "CODESIZE" => Opcode::CODESIZE,
+            "CODECOPY" => Opcode::CODECOPY,
+            "GASPRICE" => Opcode::GASPRICE,
+            "EXTCODESIZE" => Opcode::EXTCODESIZE,
+            "EXTCODECOPY" => Opcode::EXTCODECOPY,
+            "RETURNDATASIZE" => Opcode::RETURNDATASIZE,
+            "RETURNDATACOPY" => Opcode::RETURNDATACOPY,
+            "EXTCODEHASH" => Opcode::EXTCODEHASH,
+            "BLOCKHASH" => Opcode::BLOCKHASH,
+            "COINBASE" => Opcode::COINBASE,
+            "TIMESTAMP" => Opcode::TIMESTAMP,
+            "NUMBER" => Opcode::NUMBER,
+            "DIFFICULTY" => Opcode::DIFFICULTY,
+            "GASLIMIT" => Opcode::GASLIMIT,
+            "CHAINID" => Opcode::CHAINID,
+            "SELFBALANCE" => Opcode::SELFBALANCE,
+            "BASEFEE" => Opcode::BASEFEE,
+            "POP" => Opcode::POP,
+            "MLOAD" => Opcode::MLOAD,
+            "MSTORE" => Opcode::MSTORE,
+            "MSTORE8" => Opcode::MSTORE8,
+            "SLOAD" => Opcode::SLOAD,
+            "SSTORE" => Opcode::SSTORE,
+            "JUMP" => Opcode::JUMP,
+            "JUMPI" => Opcode::JUMPI,
+            "PC" => Opcode::PC,
+            "MSIZE" => Opcode::MSIZE,
+            "GAS" => Opcode::GAS,
+            "JUMPDEST" => Opcode::JUMPDEST,
+            "MCOPY" => Opcode::MCOPY,
+            "TLOAD" => Opcode::TLOAD,
+            "TSTORE" => Opcode::TSTORE,
+            "PUSH0" => Opcode::PUSH0,
+            "PUSH1" => Opcode::PUSH1,
+            "PUSH2" => Opcode::PUSH2,
+            "PUSH3" => Opcode::PUSH3,
+            "PUSH4" => Opcode::PUSH4,
+            "PUSH5" => Opcode::PUSH5,
+            "PUSH6" => Opcode::PUSH6,
+            "PUSH7" => Opcode::PUSH7,
+            "PUSH8" => Opcode::PUSH8,
+            "PUSH9" => Opcode::PUSH9,
+            "PUSH10" => Opcode::PUSH10,
+            "PUSH11" => Opcode::PUSH11,
+            "PUSH12" => Opcode::PUSH12,
+            "PUSH13" => Opcode::PUSH13,
+            "PUSH14" => Opcode::PUSH14,
+            "PUSH15" => Opcode::PUSH15,
+            "PUSH16" => Opcode::PUSH16,
+            "PUSH17" => Opcode::PUSH17,
+            "PUSH18" => Opcode::PUSH18,
+            "PUSH19" => Opcode::PUSH19,
+            "PUSH20" => Opcode::PUSH20,
+            "PUSH21" => Opcode::PUSH21,
+            "PUSH22" => Opcode::PUSH22,
+            "PUSH23" => Opcode::PUSH23,
+            "PUSH24" => Opcode::PUSH24,
+            "PUSH25" => Opcode::PUSH25,
+            "PUSH26" => Opcode::PUSH26,
+            "PUSH27" => Opcode::PUSH27,
+            "PUSH28" => Opcode::PUSH28,
+            "PUSH29" => Opcode::PUSH29,
+            "PUSH30" => Opcode::PUSH30,
+            "PUSH31" => Opcode::PUSH31,
+            "PUSH32" => Opcode::PUSH32,
+            "DUP1" => Opcode::DUP1,
+            "DUP2" => Opcode::DUP2,
+            "DUP3" => Opcode::DUP3,
+            "DUP4" => Opcode::DUP4,
+            "DUP5" => Opcode::DUP5,
+            "DUP6" => Opcode::DUP6,
+            "DUP7" => Opcode::DUP7,
+            "DUP8" => Opcode::DUP8,
+            "DUP9" => Opcode::DUP9,
+            "DUP10" => Opcode::DUP10,
+            "DUP11" => Opcode::DUP11,
+            "DUP12" => Opcode::DUP12,
+            "DUP13" => Opcode::DUP13,
+            "DUP14" => Opcode::DUP14,
+            "DUP15" => Opcode::DUP15,
+            "DUP16" => Opcode::DUP16,
+            "SWAP1" => Opcode::SWAP1,
+            "SWAP2" => Opcode::SWAP2,
+            "SWAP3" => Opcode::SWAP3,
+            "SWAP4" => Opcode::SWAP4,
+            "SWAP5" => Opcode::SWAP5,
+            "SWAP6" => Opcode::SWAP6,
+            "SWAP7" => Opcode::SWAP7,
+            "SWAP8" => Opcode::SWAP8,
+            "SWAP9" => Opcode::SWAP9,
+            "SWAP10" => Opcode::SWAP10,
+            "SWAP11" => Opcode::SWAP11,
+            "SWAP12" => Opcode::SWAP12,
+            "SWAP13" => Opcode::SWAP13,
+            "SWAP14" => Opcode::SWAP14,
+            "SWAP15" => Opcode::SWAP15,
+            "SWAP16" => Opcode::SWAP16,
+            "LOG0" => Opcode::LOG0,
+            "LOG1" => Opcode::LOG1,
+            "LOG2" => Opcode::LOG2,
+            "LOG3" => Opcode::LOG3,
+            "LOG4" => Opcode::LOG4,
+            "CREATE" => Opcode::CREATE,
+            "CALL" => Opcode::CALL,
+            "CALLCODE" => Opcode::CALLCODE,
+            "RETURN" => Opcode::RETURN,
+            "DELEGATECALL" => Opcode::DELEGATECALL,
+            "CREATE2" => Opcode::CREATE2,
+            "STATICCALL" => Opcode::STATICCALL,
+            "REVERT" => Opcode::REVERT,
+            "INVALID" => Opcode::INVALID,
+            "SELFDESTRUCT" => Opcode::SELFDESTRUCT,
+            "BLOBBASEFEE" => Opcode::BLOBBASEFEE,
+            "BLOBHASH" => Opcode::BLOBHASH,
+            "CLZ" => Opcode::CLZ,
+            "DATALOAD" => Opcode::DATALOAD,
+            "DATALOADN" => Opcode::DATALOADN,
+            "DATASIZE" => Opcode::DATASIZE,
+            "DATACOPY" => Opcode::DATACOPY,
+            "RJUMP" => Opcode::RJUMP,
+            "RJUMPI" => Opcode::RJUMPI,
+            "RJUMPV" => Opcode::RJUMPV,
+            "CALLF" => Opcode::CALLF,
+            "RETF" => Opcode::RETF,
+            "JUMPF" => Opcode::JUMPF,
+            "DUPN" => Opcode::DUPN,
+            "SWAPN" => Opcode::SWAPN,
+            "EXCHANGE" => Opcode::EXCHANGE,
+            "EXTCODETYPE" => Opcode::EXTCODETYPE,
+            "EOFCREATE" => Opcode::EOFCREATE,
+            "TXCREATE" => Opcode::TXCREATE,
+            "RETURNCONTRACT" => Opcode::RETURNCONTRACT,
+            "RETURNDATALOAD" => Opcode::RETURNDATALOAD,
+            "EXTCALL" => Opcode::EXTCALL,
+            "EXTDELEGATECALL" => Opcode::EXTDELEGATECALL,
+            "EXTSTATICCALL" => Opcode::EXTSTATICCALL,
+            "PAY" => Opcode::PAY,
+            _ => return None,
+        };
+        Some(opcode)
+    }
+
+    /// Convert an Opcode back into its byte representation
+    ///
+    /// Inverse of `from_byte` and `from_byte_eof`. Undefined bytes decode to `INVALID`, which is
+    /// encoded as the designated invalid instruction `0xfe`.
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            Opcode::STOP => 0x00,
+            Opcode::ADD => 0x01,
+            Opcode::MUL => 0x02,
+            Opcode::SUB => 0x03,
+            Opcode::DIV => 0x04,
+            Opcode::SDIV => 0x05,
+            Opcode::MOD => 0x06,
+            Opcode::SMOD => 0x07,
+            Opcode::ADDMOD => 0x08,
+            Opcode::MULMOD => 0x09,
+            Opcode::EXP => 0x0a,
+            Opcode::SIGNEXTEND => 0x0b,
+            Opcode::LT => 0x10,
+            Opcode::GT => 0x11,
+            Opcode::SLT => 0x12,
+            Opcode::SGT => 0x13,
+            Opcode::EQ => 0x14,
+            Opcode::ISZERO => 0x15,
+            Opcode::AND => 0x16,
+            Opcode::OR => 0x17,
+            Opcode::XOR => 0x18,
+            Opcode::NOT => 0x19,
+            Opcode::BYTE => 0x1a,
+            Opcode::SHL => 0x1b,
+            Opcode::SHR => 0x1c,
+            Opcode::SAR => 0x1d,
+            Opcode::SHA3 => 0x20,
+            Opcode::ADDRESS => 0x30,
+            Opcode::BALANCE => 0x31,
+            Opcode::ORIGIN => 0x32,
+            Opcode::CALLER => 0x33,
+            Opcode::CALLVALUE => 0x34,
+            Opcode::CALLDATALOAD => 0x35,
+            Opcode::CALLDATASIZE => 0x36,
+            Opcode::CALLDATACOPY => 0x37,
+            Opcode::CODESIZE => 0x38,
+            Opcode::CODECOPY => 0x39,
+            Opcode::GASPRICE => 0x3a,
+            Opcode::EXTCODESIZE => 0x3b,
+            Opcode::EXTCODECOPY => 0x3c,
+            Opcode::RETURNDATASIZE => 0x3d,
+            Opcode::RETURNDATACOPY => 0x3e,
+            Opcode::EXTCODEHASH => 0x3f,
+            Opcode::BLOCKHASH => 0x40,
+            Opcode::COINBASE => 0x41,
+            Opcode::TIMESTAMP => 0x42,
+            Opcode::NUMBER => 0x43,
+            Opcode::DIFFICULTY => 0x44,
+            Opcode::GASLIMIT => 0x45,
+            Opcode::CHAINID => 0x46,
+            Opcode::SELFBALANCE => 0x47,
+            Opcode::BASEFEE => 0x48,
+            Opcode::POP => 0x50,
+            Opcode::MLOAD => 0x51,
+            Opcode::MSTORE => 0x52,
+            Opcode::MSTORE8 => 0x53,
+            Opcode::SLOAD => 0x54,
+            Opcode::SSTORE => 0x55,
+            Opcode::JUMP => 0x56,
+            Opcode::JUMPI => 0x57,
+            Opcode::PC => 0x58,
+            Opcode::MSIZE => 0x59,
+            Opcode::GAS => 0x5a,
+            Opcode::JUMPDEST => 0x5b,
+            Opcode::MCOPY => 0x5e,
+            Opcode::TLOAD => 0x5c,
+            Opcode::TSTORE => 0x5d,
+            Opcode::PUSH0 => 0x5f,
+            Opcode::PUSH1 => 0x60,
+            Opcode::PUSH2 => 0x61,
+            Opcode::PUSH3 => 0x62,
+            Opcode::PUSH4 => 0x63,
+            Opcode::PUSH5 => 0x64,
+            Opcode::PUSH6 => 0x65,
+            Opcode::PUSH7 => 0x66,
+            Opcode::PUSH8 => 0x67,
+            Opcode::PUSH9 => 0x68,
+            Opcode::PUSH10 => 0x69,
+            Opcode::PUSH11 => 0x6a,
+            Opcode::PUSH12 => 0x6b,
+            Opcode::PUSH13 => 0x6c,
+            Opcode::PUSH14 => 0x6d,
+            Opcode::PUSH15 => 0x6e,
+            Opcode::PUSH16 => 0x6f,
+            Opcode::PUSH17 => 0x70,
+            Opcode::PUSH18 => 0x71,
+            Opcode::PUSH19 => 0x72,
+            Opcode::PUSH20 => 0x73,
+            Opcode::PUSH21 => 0x74,
+            Opcode::PUSH22 => 0x75,
+            Opcode::PUSH23 => 0x76,
+            Opcode::PUSH24 => 0x77,
+            Opcode::PUSH25 => 0x78,
+            Opcode::PUSH26 => 0x79,
+            Opcode::PUSH27 => 0x7a,
+            Opcode::PUSH28 => 0x7b,
+            Opcode::PUSH29 => 0x7c,
+            Opcode::PUSH30 => 0x7d,
+            Opcode::PUSH31 => 0x7e,
+            Opcode::PUSH32 => 0x7f,
+            Opcode::DUP1 => 0x80,
+            Opcode::DUP2 => 0x81,
+            Opcode::DUP3 => 0x82,
+            Opcode::DUP4 => 0x83,
+            Opcode::DUP5 => 0x84,
+            Opcode::DUP6 => 0x85,
+            Opcode::DUP7 => 0x86,
+            Opcode::DUP8 => 0x87,
+            Opcode::DUP9 => 0x88,
+            Opcode::DUP10 => 0x89,
+            Opcode::DUP11 => 0x8a,
+            Opcode::DUP12 => 0x8b,
+            Opcode::DUP13 => 0x8c,
+            Opcode::DUP14 => 0x8d,
+            Opcode::DUP15 => 0x8e,
+            Opcode::DUP16 => 0x8f,
+            Opcode::SWAP1 => 0x90,
+            Opcode::SWAP2 => 0x91,
+            Opcode::SWAP3 => 0x92,
+            Opcode::SWAP4 => 0x93,
+            Opcode::SWAP5 => 0x94,
+            Opcode::SWAP6 => 0x95,
+            Opcode::SWAP7 => 0x96,
+            Opcode::SWAP8 => 0x97,
+            Opcode::SWAP9 => 0x98,
+            Opcode::SWAP10 => 0x99,
+            Opcode::SWAP11 => 0x9a,
+            Opcode::SWAP12 => 0x9b,
+            Opcode::SWAP13 => 0x9c,
+            Opcode::SWAP14 => 0x9d,
+            Opcode::SWAP15 => 0x9e,
+            Opcode::SWAP16 => 0x9f,
+            Opcode::LOG0 => 0xa0,
+            Opcode::LOG1 => 0xa1,
+            Opcode::LOG2 => 0xa2,
+            Opcode::LOG3 => 0xa3,
+            Opcode::LOG4 => 0xa4,
+            Opcode::CREATE => 0xf0,
+            Opcode::CALL => 0xf1,
+            Opcode::CALLCODE => 0xf2,
+            Opcode::RETURN => 0xf3,
+            Opcode::DELEGATECALL => 0xf4,
+            Opcode::CREATE2 => 0xf5,
+            Opcode::STATICCALL => 0xfa,
+            Opcode::REVERT => 0xfd,
+            Opcode::INVALID => 0xfe,
+            Opcode::SELFDESTRUCT => 0xff,
+            Opcode::BLOBBASEFEE => 0x4a,
+            Opcode::BLOBHASH => 0x49,
+            Opcode::CLZ => 0x1e,
+            Opcode::DATALOAD => 0xd0,
+            Opcode::DATALOADN => 0xd1,
+            Opcode::DATASIZE => 0xd2,
+            Opcode::DATACOPY => 0xd3,
+            Opcode::RJUMP => 0xe0,
+            Opcode::RJUMPI => 0xe1,
+            Opcode::RJUMPV => 0xe2,
+            Opcode::CALLF => 0xe3,
+            Opcode::RETF => 0xe4,
+            Opcode::JUMPF => 0xe5,
+            Opcode::DUPN => 0xe6,
+            Opcode::SWAPN => 0xe7,
+            Opcode::EXCHANGE => 0xe8,
+            Opcode::EXTCODETYPE => 0xe9,
+            Opcode::EOFCREATE => 0xec,
+            Opcode::TXCREATE => 0xed,
+            Opcode::RETURNCONTRACT => 0xee,
+            Opcode::RETURNDATALOAD => 0xf7,
+            Opcode::EXTCALL => 0xf8,
+            Opcode::EXTDELEGATECALL => 0xf9,
+            Opcode::EXTSTATICCALL => 0xfb,
+            Opcode::PAY => 0xfc,
+        }
+    }
+
     /// Convert a byte into an Opcode (EOF-aware version)
     ///
     /// This version includes EOF opcodes and should only be used for EOF containers.
@@ -386,13 +756,154 @@ impl Opcode {
             _ => Self::from_byte(byte),
         }
     }
+
+    /// Number of stack items consumed and produced by the opcode
+    ///
+    /// The arity of `DUPN`, `SWAPN` and `EXCHANGE` depends on their immediate, use
+    /// [`Operation::stack_arity`] to take it into account. `CALLF`, `RETF` and `JUMPF` depend on
+    /// the EOF type section and are reported as `(0, 0)`.
+    pub fn stack_arity(&self) -> (usize, usize) {
+        use Opcode::*;
+        match self {
+            PUSH0 | PUSH1 | PUSH2 | PUSH3 | PUSH4 | PUSH5 | PUSH6 | PUSH7 | PUSH8 | PUSH9
+            | PUSH10 | PUSH11 | PUSH12 | PUSH13 | PUSH14 | PUSH15 | PUSH16 | PUSH17 | PUSH18
+            | PUSH19 | PUSH20 | PUSH21 | PUSH22 | PUSH23 | PUSH24 | PUSH25 | PUSH26 | PUSH27
+            | PUSH28 | PUSH29 | PUSH30 | PUSH31 | PUSH32 => (0, 1),
+            DUP1 | DUP2 | DUP3 | DUP4 | DUP5 | DUP6 | DUP7 | DUP8 | DUP9 | DUP10 | DUP11
+            | DUP12 | DUP13 | DUP14 | DUP15 | DUP16 => {
+                let depth = (self.to_byte() - 0x7f) as usize;
+                (depth, depth + 1)
+            }
+            SWAP1 | SWAP2 | SWAP3 | SWAP4 | SWAP5 | SWAP6 | SWAP7 | SWAP8 | SWAP9 | SWAP10
+            | SWAP11 | SWAP12 | SWAP13 | SWAP14 | SWAP15 | SWAP16 => {
+                let depth = (self.to_byte() - 0x8e) as usize;
+                (depth, depth)
+            }
+            LOG0 | LOG1 | LOG2 | LOG3 | LOG4 => ((self.to_byte() - 0xa0) as usize + 2, 0),
+            STOP | JUMPDEST | INVALID | RJUMP | CALLF | RETF | JUMPF => (0, 0),
+            ADDRESS | ORIGIN | CALLER | CALLVALUE | CALLDATASIZE | CODESIZE | GASPRICE
+            | RETURNDATASIZE | COINBASE | TIMESTAMP | NUMBER | DIFFICULTY | GASLIMIT | CHAINID
+            | SELFBALANCE | BASEFEE | BLOBBASEFEE | PC | MSIZE | GAS | DATALOADN | DATASIZE => {
+                (0, 1)
+            }
+            POP | JUMP | SELFDESTRUCT | RJUMPI | RJUMPV => (1, 0),
+            ISZERO | NOT | CLZ | BALANCE | CALLDATALOAD | EXTCODESIZE | EXTCODEHASH | BLOCKHASH
+            | BLOBHASH | MLOAD | SLOAD | TLOAD | DATALOAD | EXTCODETYPE | RETURNDATALOAD => (1, 1),
+            MSTORE | MSTORE8 | SSTORE | TSTORE | JUMPI | RETURN | REVERT | RETURNCONTRACT => (2, 0),
+            ADD | MUL | SUB | DIV | SDIV | MOD | SMOD | EXP | SIGNEXTEND | LT | GT | SLT | SGT
+            | EQ | AND | OR | XOR | BYTE | SHL | SHR | SAR | SHA3 | PAY => (2, 1),
+            CALLDATACOPY | CODECOPY | RETURNDATACOPY | MCOPY | DATACOPY => (3, 0),
+            ADDMOD | MULMOD | CREATE | EXTDELEGATECALL | EXTSTATICCALL => (3, 1),
+            EXTCODECOPY => (4, 0),
+            CREATE2 | EOFCREATE | EXTCALL => (4, 1),
+            TXCREATE => (5, 1),
+            DELEGATECALL | STATICCALL => (6, 1),
+            CALL | CALLCODE => (7, 1),
+            DUPN => (1, 2),
+            SWAPN => (2, 2),
+            EXCHANGE => (3, 3),
+        }
+    }
+
+    /// Whether the opcode is a PUSH instruction (including PUSH0)
+    pub fn is_push(&self) -> bool {
+        (0x5f..=0x7f).contains(&self.to_byte())
+    }
+
+    /// Whether the opcode duplicates a stack item (`DUP1` to `DUP16` and `DUPN`)
+    pub fn is_dup(&self) -> bool {
+        *self == Opcode::DUPN || (0x80..=0x8f).contains(&self.to_byte())
+    }
+
+    /// Whether the opcode reorders stack items (`SWAP1` to `SWAP16`, `SWAPN` and `EXCHANGE`)
+    pub fn is_swap(&self) -> bool {
+        matches!(self, Opcode::SWAPN | Opcode::EXCHANGE) || (0x90..=0x9f).contains(&self.to_byte())
+    }
+
+    /// Whether the opcode calls another account (legacy `CALL` family and EOF `EXTCALL` family)
+    pub fn is_call(&self) -> bool {
+        matches!(
+            self,
+            Opcode::CALL
+                | Opcode::CALLCODE
+                | Opcode::DELEGATECALL
+                | Opcode::STATICCALL
+                | Opcode::EXTCALL
+                | Opcode::EXTDELEGATECALL
+                | Opcode::EXTSTATICCALL
+        )
+    }
+
+    /// Whether the opcode creates a contract
+    pub fn is_create(&self) -> bool {
+        matches!(
+            self,
+            Opcode::CREATE | Opcode::CREATE2 | Opcode::EOFCREATE | Opcode::TXCREATE
+        )
+    }
+
+    /// Whether the opcode ends a basic block
+    ///
+    /// This includes all jumps as well as all instructions halting execution.
+    pub fn is_block_terminator(&self) -> bool {
+        matches!(
+            self,
+            Opcode::STOP
+                | Opcode::JUMP
+                | Opcode::JUMPI
+                | Opcode::RETURN
+                | Opcode::REVERT
+                | Opcode::INVALID
+                | Opcode::SELFDESTRUCT
+                | Opcode::RJUMP
+                | Opcode::RJUMPI
+                | Opcode::RJUMPV
+                | Opcode::RETF
+                | Opcode::JUMPF
+                | Opcode::RETURNCONTRACT
+        )
+    }
+}
+
+/// An EIP-7702 delegation designator
+///
+/// Accounts with a delegation have the code `0xef0100` followed by the 20 byte address of the
+/// delegate, which is not executable code and must not be decoded as instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delegation {
+    /// The address whose code is executed in the context of the delegating account
+    pub address: [u8; 20],
+}
+
+impl Delegation {
+    /// The prefix identifying a delegation designator
+    pub const MAGIC: [u8; 3] = [0xef, 0x01, 0x00];
+
+    /// Parses a delegation designator, returning `None` for any other bytecode
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::MAGIC.len() + 20 || !bytes.starts_with(&Self::MAGIC) {
+            return None;
+        }
+        let mut address = [0u8; 20];
+        address.copy_from_slice(&bytes[Self::MAGIC.len()..]);
+        Some(Delegation { address })
+    }
+}
+
+/// The result of disassembling the code of an account
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Disassembly {
+    /// Regular (legacy or EOF) code decoded into operations
+    Operations(Vec<Operation>),
+    /// An EIP-7702 delegation designator
+    Delegation(Delegation),
 }
 
 /// A decoded operation
 ///
 /// An operation is represented by the combination of an opcode, the offset in the bytecode and any
 /// additional bytes that are part of the operation (only for PUSH operations).
-#[derive(PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct Operation {
     /// The opcode
     pub opcode: Opcode,
@@ -400,6 +911,8 @@ pub struct Operation {
     pub input: Vec<u8>,
     /// The offset in the bytecode
     pub offset: u32,
+    /// The library placeholder replaced by `input` (only for PUSH20 in unlinked bytecode)
+    pub link: Option<LinkPlaceholder>,
 }
 
 impl fmt::Debug for Operation {
@@ -409,7 +922,9 @@ impl fmt::Debug for Operation {
             format!("{:#x}", self.offset).trim_start_matches("0x"),
             self.opcode
         );
-        if !self.input.is_empty() {
+        if let Some(link) = &self.link {
+            formatted = format!("{formatted} {link}");
+        } else if !self.input.is_empty() {
             let encoded_bytes = hex::encode(&self.input);
             let mut formatted_bytes = encoded_bytes.trim_start_matches('0');
             if formatted_bytes.is_empty() {
@@ -428,6 +943,24 @@ impl Operation {
             opcode,
             offset,
             input: Vec::new(),
+            link: None,
+        }
+    }
+
+    /// Number of stack items consumed and produced by the operation
+    ///
+    /// Same as [`Opcode::stack_arity`] but resolves the depth of `DUPN`, `SWAPN` and `EXCHANGE`
+    /// from the immediate byte.
+    pub fn stack_arity(&self) -> (usize, usize) {
+        let immediate = self.input.first().copied().unwrap_or(0) as usize;
+        match self.opcode {
+            Opcode::DUPN => (immediate + 1, immediate + 2),
+            Opcode::SWAPN => (immediate + 2, immediate + 2),
+            Opcode::EXCHANGE => {
+                let depth = (immediate >> 4) + (immediate & 0x0f) + 3;
+                (depth, depth)
+            }
+            opcode => opcode.stack_arity(),
         }
     }
 
@@ -451,6 +984,7 @@ impl Operation {
             opcode: self.opcode,
             offset: self.offset,
             input: bytes.take(num_bytes as usize).collect(),
+            link: self.link,
         })
     }
 }
@@ -0,0 +1,476 @@
+//! Unsigned 256 bit integers with the wrapping semantics of EVM arithmetic
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, BitAnd, BitOr, BitXor, Mul, Not, Shl, Shr, Sub};
+
+/// An unsigned 256 bit integer
+///
+/// Arithmetic wraps around on overflow like EVM arithmetic. Signed operations interpret the value
+/// in two's complement.
+///
+/// # Examples
+///
+/// ```rust
+/// use evm_disassembler::u256::U256;
+///
+/// assert_eq!(U256::MAX + U256::ONE, U256::ZERO);
+/// assert_eq!(U256::from(10) * U256::from(20), U256::from(200));
+/// assert_eq!(U256::from(0xff).to_string(), "0xff");
+/// ```
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    /// The value 0
+    pub const ZERO: U256 = U256([0; 4]);
+    /// The value 1
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    /// The largest value, `2^256 - 1`
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    /// Interprets up to 32 big endian bytes as a number
+    ///
+    /// Longer slices are truncated to their last 32 bytes.
+    pub fn from_be_slice(bytes: &[u8]) -> Self {
+        let mut word = [0u8; 32];
+        let bytes = &bytes[bytes.len().saturating_sub(32)..];
+        word[32 - bytes.len()..].copy_from_slice(bytes);
+        Self::from_be_bytes(word)
+    }
+
+    /// Interprets 32 big endian bytes as a number
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 24 - 8 * i;
+            *limb = u64::from_be_bytes(bytes[start..start + 8].try_into().expect("8 bytes"));
+        }
+        U256(limbs)
+    }
+
+    /// The big endian bytes of the number
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            let start = 24 - 8 * i;
+            bytes[start..start + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// The number as `u64` if it fits
+    pub fn as_u64(&self) -> Option<u64> {
+        (self.0[1..] == [0; 3]).then_some(self.0[0])
+    }
+
+    /// The least significant 64 bits
+    pub fn low_u64(&self) -> u64 {
+        self.0[0]
+    }
+
+    /// Whether the number is 0
+    pub fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+
+    /// Whether the bit at the given index (0 is least significant) is set
+    pub fn bit(&self, index: usize) -> bool {
+        index < 256 && self.0[index / 64] >> (index % 64) & 1 == 1
+    }
+
+    /// The number of significant bits
+    pub fn bits(&self) -> usize {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i + 64 - self.0[i].leading_zeros() as usize;
+            }
+        }
+        0
+    }
+
+    /// Adds and reports whether the addition overflowed
+    pub fn overflowing_add(self, rhs: Self) -> (Self, bool) {
+        let mut limbs = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (sum, overflow_a) = self.0[i].overflowing_add(rhs.0[i]);
+            let (sum, overflow_b) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = overflow_a || overflow_b;
+        }
+        (U256(limbs), carry)
+    }
+
+    /// Subtracts and reports whether the subtraction underflowed
+    pub fn overflowing_sub(self, rhs: Self) -> (Self, bool) {
+        let mut limbs = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (difference, underflow_a) = self.0[i].overflowing_sub(rhs.0[i]);
+            let (difference, underflow_b) = difference.overflowing_sub(borrow as u64);
+            *limb = difference;
+            borrow = underflow_a || underflow_b;
+        }
+        (U256(limbs), borrow)
+    }
+
+    /// Divides and returns quotient and remainder, both are 0 for a divisor of 0
+    pub fn div_rem(self, divisor: Self) -> (Self, Self) {
+        if divisor.is_zero() {
+            return (Self::ZERO, Self::ZERO);
+        }
+        let mut quotient = Self::ZERO;
+        let mut remainder = Self::ZERO;
+        for i in (0..self.bits()).rev() {
+            let carry = remainder.bit(255);
+            remainder = remainder << 1;
+            remainder.0[0] |= self.bit(i) as u64;
+            if carry || remainder >= divisor {
+                remainder = remainder - divisor;
+                quotient.0[i / 64] |= 1 << (i % 64);
+            }
+        }
+        (quotient, remainder)
+    }
+
+    /// Raises to the given power, wrapping around on overflow
+    pub fn pow(self, exponent: Self) -> Self {
+        let mut result = Self::ONE;
+        for i in (0..exponent.bits()).rev() {
+            result = result * result;
+            if exponent.bit(i) {
+                result = result * self;
+            }
+        }
+        result
+    }
+
+    /// `(self + rhs) % modulus` without intermediate overflow, 0 for a modulus of 0
+    pub fn add_mod(self, rhs: Self, modulus: Self) -> Self {
+        if modulus.is_zero() {
+            return Self::ZERO;
+        }
+        let (a, b) = (self.div_rem(modulus).1, rhs.div_rem(modulus).1);
+        let (sum, overflow) = a.overflowing_add(b);
+        if overflow || sum >= modulus {
+            sum - modulus
+        } else {
+            sum
+        }
+    }
+
+    /// `(self * rhs) % modulus` without intermediate overflow, 0 for a modulus of 0
+    pub fn mul_mod(self, rhs: Self, modulus: Self) -> Self {
+        if modulus.is_zero() {
+            return Self::ZERO;
+        }
+        let a = self.div_rem(modulus).1;
+        let mut result = Self::ZERO;
+        for i in (0..rhs.bits()).rev() {
+            result = result.add_mod(result, modulus);
+            if rhs.bit(i) {
+                result = result.add_mod(a, modulus);
+            }
+        }
+        result
+    }
+
+    /// Whether the number is negative in two's complement
+    pub fn is_negative(&self) -> bool {
+        self.bit(255)
+    }
+
+    /// The two's complement negation
+    pub fn negate(self) -> Self {
+        Self::ZERO - self
+    }
+
+    fn abs(self) -> Self {
+        if self.is_negative() {
+            self.negate()
+        } else {
+            self
+        }
+    }
+
+    /// Signed division and remainder as done by `SDIV` and `SMOD`
+    ///
+    /// The quotient is rounded towards zero and the remainder has the sign of the dividend.
+    pub fn signed_div_rem(self, divisor: Self) -> (Self, Self) {
+        let (quotient, remainder) = self.abs().div_rem(divisor.abs());
+        let quotient = if self.is_negative() != divisor.is_negative() {
+            quotient.negate()
+        } else {
+            quotient
+        };
+        let remainder = if self.is_negative() {
+            remainder.negate()
+        } else {
+            remainder
+        };
+        (quotient, remainder)
+    }
+
+    /// Compares the numbers interpreted in two's complement
+    pub fn signed_cmp(&self, other: &Self) -> Ordering {
+        match (self.is_negative(), other.is_negative()) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => self.cmp(other),
+        }
+    }
+
+    /// Shifts right, filling with the sign bit as done by `SAR`
+    pub fn arithmetic_shr(self, shift: usize) -> Self {
+        if self.is_negative() {
+            !(!self >> shift)
+        } else {
+            self >> shift
+        }
+    }
+
+    /// Extends the sign of the number of the given size in bytes minus one as done by `SIGNEXTEND`
+    pub fn sign_extend(self, byte_index: Self) -> Self {
+        match byte_index.as_u64() {
+            Some(index) if index < 31 => {
+                let bit = index as usize * 8 + 7;
+                let mask = (Self::ONE << (bit + 1)) - Self::ONE;
+                if self.bit(bit) {
+                    self | !mask
+                } else {
+                    self & mask
+                }
+            }
+            _ => self,
+        }
+    }
+
+    /// The byte at the given index, counting from the most significant byte, as done by `BYTE`
+    pub fn byte(&self, index: Self) -> Self {
+        match index.as_u64() {
+            Some(index) if index < 32 => Self::from(self.to_be_bytes()[index as usize] as u64),
+            _ => Self::ZERO,
+        }
+    }
+
+    /// The shift amount of a shift instruction, shifts of 256 bits or more clear the value
+    pub fn as_shift(&self) -> usize {
+        self.as_u64().map_or(256, |shift| shift.min(256) as usize)
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> Self {
+        U256([value, 0, 0, 0])
+    }
+}
+
+impl From<bool> for U256 {
+    fn from(value: bool) -> Self {
+        U256::from(value as u64)
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for U256 {
+    type Output = U256;
+
+    /// Wrapping addition
+    fn add(self, rhs: Self) -> Self {
+        self.overflowing_add(rhs).0
+    }
+}
+
+impl Sub for U256 {
+    type Output = U256;
+
+    /// Wrapping subtraction
+    fn sub(self, rhs: Self) -> Self {
+        self.overflowing_sub(rhs).0
+    }
+}
+
+impl Mul for U256 {
+    type Output = U256;
+
+    /// Wrapping multiplication
+    fn mul(self, rhs: Self) -> Self {
+        let mut limbs = [0u64; 4];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 - i {
+                let product = limbs[i + j] as u128 + self.0[i] as u128 * rhs.0[j] as u128 + carry;
+                limbs[i + j] = product as u64;
+                carry = product >> 64;
+            }
+        }
+        U256(limbs)
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> Self {
+        U256(self.0.map(|limb| !limb))
+    }
+}
+
+macro_rules! bitwise {
+    ($trait:ident, $method:ident, $op:tt) => {
+        impl $trait for U256 {
+            type Output = U256;
+
+            fn $method(self, rhs: Self) -> Self {
+                let mut limbs = self.0;
+                for (limb, other) in limbs.iter_mut().zip(rhs.0) {
+                    *limb $op other;
+                }
+                U256(limbs)
+            }
+        }
+    };
+}
+
+bitwise!(BitAnd, bitand, &=);
+bitwise!(BitOr, bitor, |=);
+bitwise!(BitXor, bitxor, ^=);
+
+impl Shl<usize> for U256 {
+    type Output = U256;
+
+    fn shl(self, shift: usize) -> Self {
+        if shift >= 256 {
+            return Self::ZERO;
+        }
+        let (limbs, bits) = (shift / 64, shift % 64);
+        let mut shifted = [0u64; 4];
+        for (i, limb) in shifted.iter_mut().enumerate().skip(limbs) {
+            *limb = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                *limb |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        U256(shifted)
+    }
+}
+
+impl Shr<usize> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: usize) -> Self {
+        if shift >= 256 {
+            return Self::ZERO;
+        }
+        let (limbs, bits) = (shift / 64, shift % 64);
+        let mut shifted = [0u64; 4];
+        for (i, limb) in shifted.iter_mut().enumerate().take(4 - limbs) {
+            *limb = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs < 3 {
+                *limb |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        U256(shifted)
+    }
+}
+
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded = hex::encode(self.to_be_bytes());
+        let trimmed = encoded.trim_start_matches('0');
+        write!(f, "0x{}", if trimmed.is_empty() { "0" } else { trimmed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn word(hex: &str) -> U256 {
+        U256::from_be_slice(&hex::decode(format!("{hex:0>64}")).unwrap())
+    }
+
+    #[rstest]
+    #[case(U256::MAX, U256::from(2), U256::MAX - U256::ONE)]
+    #[case(
+        word("100000000000000000000000000000000"),
+        word("100000000000000000000000000000000"),
+        U256::ZERO
+    )]
+    #[case(
+        word("ffffffffffffffffffff"),
+        word("ffffffffffffffffffff"),
+        word("fffffffffffffffffffe00000000000000000001")
+    )]
+    fn wrapping_multiplication(#[case] a: U256, #[case] b: U256, #[case] expected: U256) {
+        assert_eq!(a * b, expected);
+    }
+
+    #[rstest]
+    #[case(U256::from(7), U256::from(2), U256::from(3), U256::ONE)]
+    #[case(U256::MAX, U256::from(0x10), U256::MAX >> 4, U256::from(0xf))]
+    #[case(U256::from(1), U256::ZERO, U256::ZERO, U256::ZERO)]
+    fn division(#[case] a: U256, #[case] b: U256, #[case] quotient: U256, #[case] remainder: U256) {
+        assert_eq!(a.div_rem(b), (quotient, remainder));
+    }
+
+    #[rstest]
+    fn signed_operations() {
+        let minus_seven = U256::from(7).negate();
+        let (quotient, remainder) = minus_seven.signed_div_rem(U256::from(2));
+        assert_eq!(quotient, U256::from(3).negate());
+        assert_eq!(remainder, U256::ONE.negate());
+        assert_eq!(minus_seven.signed_cmp(&U256::ONE), Ordering::Less);
+        assert_eq!(minus_seven.arithmetic_shr(1), U256::from(4).negate());
+        assert_eq!(
+            U256::from(0x80).sign_extend(U256::ZERO),
+            U256::from(0x80).negate()
+        );
+        assert_eq!(U256::from(0x7f).sign_extend(U256::ZERO), U256::from(0x7f));
+    }
+
+    #[rstest]
+    fn modular_arithmetic() {
+        assert_eq!(
+            U256::MAX.add_mod(U256::from(2), U256::from(3)),
+            U256::from(2)
+        );
+        assert_eq!(U256::MAX.mul_mod(U256::MAX, U256::from(12)), U256::from(9));
+        assert_eq!(U256::from(3).pow(U256::from(5)), U256::from(243));
+        assert_eq!(U256::from(2).pow(U256::from(256)), U256::ZERO);
+    }
+
+    #[rstest]
+    fn shifts_and_bytes() {
+        let value = word("0102030405060708090a0b0c0d0e0f10111213141516171819");
+        assert_eq!(value << 48 >> 48, value);
+        assert_eq!(value >> 64, word("0102030405060708090a0b0c0d0e0f1011"));
+        assert_eq!(value.byte(U256::from(31)), U256::from(0x19));
+        assert_eq!(value.bits(), 193);
+        assert_eq!(U256::ONE << 256, U256::ZERO);
+    }
+}
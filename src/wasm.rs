@@ -0,0 +1,203 @@
+//! WebAssembly bindings for disassembly in the browser
+//!
+//! Enabled with the `wasm` feature. All functions take hex encoded bytecode with or without
+//! `0x` prefix and return formatted text or a JSON string, errors are thrown as JavaScript
+//! `Error`s. Build the package with
+//!
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//! wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/evm_disassembler.wasm
+//! ```
+//!
+//! and use it from JavaScript:
+//!
+//! ```text
+//! import init, { disassemble, format, splitMetadata, splitEof } from "./pkg/evm_disassembler.js";
+//! await init();
+//! const { operations } = JSON.parse(disassemble("0x6080604052"));
+//! ```
+use crate::eof::{EofContainer, EofSection};
+use crate::metadata::split_metadata;
+use crate::types::{Delegation, Operation};
+use crate::{disassemble_str, format_operations};
+use eyre::Result;
+use serde_json::{json, Value};
+use wasm_bindgen::prelude::*;
+
+fn decode_hex(code: &str) -> Result<Vec<u8>> {
+    Ok(hex::decode(code.trim().trim_start_matches("0x"))?)
+}
+
+fn operations_json(operations: &[Operation]) -> Value {
+    operations
+        .iter()
+        .map(|operation| {
+            let mut value = json!({
+                "offset": operation.offset,
+                "opcode": format!("{:?}", operation.opcode),
+            });
+            if !operation.input.is_empty() {
+                value["input"] = json!(format!("0x{}", hex::encode(&operation.input)));
+            }
+            if let Some(link) = &operation.link {
+                value["link"] = json!(link.to_string());
+            }
+            value
+        })
+        .collect()
+}
+
+fn section_json(section: &EofSection) -> Value {
+    json!({
+        "offset": section.offset,
+        "code": format!("0x{}", hex::encode(&section.bytes)),
+    })
+}
+
+pub(crate) fn disassemble_json(code: &str) -> Result<String> {
+    let code = code.trim();
+    if let Some(delegation) = decode_hex(code)
+        .ok()
+        .and_then(|bytes| Delegation::from_bytes(&bytes))
+    {
+        let address = format!("0x{}", hex::encode(delegation.address));
+        return Ok(json!({ "delegation": address }).to_string());
+    }
+    let operations = disassemble_str(code)?;
+    Ok(json!({ "operations": operations_json(&operations) }).to_string())
+}
+
+pub(crate) fn split_metadata_json(code: &str) -> Result<String> {
+    let bytes = decode_hex(code)?;
+    let (code, metadata) = split_metadata(&bytes);
+    Ok(json!({
+        "code": format!("0x{}", hex::encode(code)),
+        "metadata": metadata.map(|metadata| format!("0x{}", hex::encode(metadata))),
+    })
+    .to_string())
+}
+
+pub(crate) fn split_eof_json(code: &str) -> Result<String> {
+    let container = EofContainer::parse(&decode_hex(code)?)?;
+    let code: Vec<Value> = container
+        .code
+        .iter()
+        .zip(container.code_operations())
+        .zip(&container.types)
+        .map(|((section, operations), code_type)| {
+            json!({
+                "offset": section.offset,
+                "inputs": code_type.inputs,
+                "outputs": code_type.outputs,
+                "maxStackHeight": code_type.max_stack_height,
+                "operations": operations_json(&operations),
+            })
+        })
+        .collect();
+    Ok(json!({
+        "version": container.version,
+        "headerSize": container.header_size,
+        "code": code,
+        "containers": container.containers.iter().map(section_json).collect::<Vec<_>>(),
+        "data": section_json(&container.data),
+        "dataSize": container.data_size,
+    })
+    .to_string())
+}
+
+fn js_error(error: eyre::Report) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+/// Disassembles hex encoded bytecode into JSON
+///
+/// Returns `{"operations": [{"offset", "opcode", "input"?, "link"?}]}` with `input` the hex
+/// encoded immediate and `link` the library placeholder of unlinked bytecode, or
+/// `{"delegation": "0x..."}` for EIP-7702 delegation designators.
+#[wasm_bindgen(js_name = disassemble)]
+pub fn wasm_disassemble(code: &str) -> Result<String, JsError> {
+    disassemble_json(code).map_err(js_error)
+}
+
+/// Disassembles hex encoded bytecode into the text format of `format_operations`
+#[wasm_bindgen(js_name = format)]
+pub fn wasm_format(code: &str) -> Result<String, JsError> {
+    disassemble_str(code.trim())
+        .and_then(format_operations)
+        .map_err(js_error)
+}
+
+/// Splits the Solidity or Vyper metadata trailer from hex encoded bytecode
+///
+/// Returns `{"code": "0x...", "metadata": "0x..." | null}`.
+#[wasm_bindgen(js_name = splitMetadata)]
+pub fn wasm_split_metadata(code: &str) -> Result<String, JsError> {
+    split_metadata_json(code).map_err(js_error)
+}
+
+/// Splits a hex encoded EOF container into its sections
+///
+/// Returns the `version`, `headerSize` and declared `dataSize`, the `code` sections with their
+/// `offset`, signature (`inputs`, `outputs`, `maxStackHeight`) and `operations` as in
+/// `disassemble`, and the `containers` and `data` sections with their `offset` and hex encoded
+/// `code`. Offsets are relative to the start of the container.
+#[wasm_bindgen(js_name = splitEof)]
+pub fn wasm_split_eof(code: &str) -> Result<String, JsError> {
+    split_eof_json(code).map_err(js_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("0x6080", json!({"operations": [{"offset": 0, "opcode": "PUSH1", "input": "0x80"}]}))]
+    #[case("5f00", json!({"operations": [{"offset": 0, "opcode": "PUSH0"}, {"offset": 1, "opcode": "STOP"}]}))]
+    #[case(
+        &format!("ef0100{}", "be".repeat(20)),
+        json!({"delegation": format!("0x{}", "be".repeat(20))})
+    )]
+    #[case(
+        &format!("73{:_<40}00", "__Lib"),
+        json!({"operations": [
+            {"offset": 0, "opcode": "PUSH20", "input": format!("0x{}", "00".repeat(20)), "link": format!("{:_<40}", "__Lib")},
+            {"offset": 21, "opcode": "STOP"}
+        ]})
+    )]
+    fn disassemble_to_json(#[case] code: &str, #[case] expected: Value) {
+        let json: Value = serde_json::from_str(&disassemble_json(code).unwrap()).unwrap();
+        assert_eq!(json, expected);
+    }
+
+    #[rstest]
+    fn split_metadata_to_json() {
+        // STOP followed by an ipfs metadata trailer of solc 0.8.20
+        let trailer = format!("a264697066735822{}64736f6c6343000814", "12".repeat(34));
+        let trailer = format!("{trailer}{:04x}", trailer.len() / 2);
+        let json: Value =
+            serde_json::from_str(&split_metadata_json(&format!("0x00{trailer}")).unwrap()).unwrap();
+        assert_eq!(json["code"], "0x00");
+        assert_eq!(json["metadata"], format!("0x{trailer}"));
+        let json: Value = serde_json::from_str(&split_metadata_json("6080").unwrap()).unwrap();
+        assert_eq!(json["metadata"], Value::Null);
+    }
+
+    #[rstest]
+    fn split_eof_to_json() {
+        // RJUMP 0 STOP and 2 bytes of data
+        let code = "ef000101000402000100040400020000800000e0000000aabb";
+        let json: Value = serde_json::from_str(&split_eof_json(code).unwrap()).unwrap();
+        assert_eq!(json["headerSize"], 15);
+        assert_eq!(json["code"][0]["outputs"], 0x80);
+        assert_eq!(
+            json["code"][0]["operations"],
+            json!([
+                {"offset": 19, "opcode": "RJUMP", "input": "0x0000"},
+                {"offset": 22, "opcode": "STOP"}
+            ])
+        );
+        assert_eq!(json["data"], json!({"offset": 23, "code": "0xaabb"}));
+        assert!(split_eof_json("6080").is_err());
+    }
+}
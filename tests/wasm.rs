@@ -0,0 +1,38 @@
+//! Tests of the wasm-bindgen exports in a headless browser
+//!
+//! Run with `wasm-pack test --headless --firefox -- --features wasm --test wasm`.
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use evm_disassembler::wasm::{wasm_disassemble, wasm_format, wasm_split_eof, wasm_split_metadata};
+use serde_json::{json, Value};
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+fn disassemble_to_json() {
+    let json: Value = serde_json::from_str(&wasm_disassemble("0x6080").unwrap()).unwrap();
+    assert_eq!(
+        json,
+        json!({"operations": [{"offset": 0, "opcode": "PUSH1", "input": "0x80"}]})
+    );
+    assert!(wasm_disassemble("0xzz").is_err());
+}
+
+#[wasm_bindgen_test]
+fn format_text() {
+    assert_eq!(
+        wasm_format("6080604052").unwrap(),
+        "00000000: PUSH1 0x80\n00000002: PUSH1 0x40\n00000004: MSTORE\n"
+    );
+}
+
+#[wasm_bindgen_test]
+fn split_sections() {
+    let metadata: Value = serde_json::from_str(&wasm_split_metadata("6080").unwrap()).unwrap();
+    assert_eq!(metadata, json!({"code": "0x6080", "metadata": null}));
+    let eof = wasm_split_eof("ef000101000402000100040400020000800000e0000000aabb").unwrap();
+    let eof: Value = serde_json::from_str(&eof).unwrap();
+    assert_eq!(eof["code"][0]["operations"][0]["opcode"], "RJUMP");
+    assert!(wasm_split_eof("6080").is_err());
+}